@@ -0,0 +1,505 @@
+use std::path::{Path, PathBuf};
+use crate::*;
+use uuid::Uuid;
+
+// stored in the data directory, edited from File > Autosave...
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub (crate) struct AutosaveConfig
+{
+    pub (crate) enabled : bool,
+    pub (crate) interval_secs : u64,
+    pub (crate) keep : usize, // recovery files, oldest are deleted first
+}
+
+impl Default for AutosaveConfig
+{
+    fn default() -> Self
+    {
+        Self { enabled : true, interval_secs : 300, keep : 5 }
+    }
+}
+
+pub (crate) struct Autosave
+{
+    pub (crate) dir : Option<PathBuf>,
+    pub (crate) config : AutosaveConfig,
+    // recovery files from sessions that didn't exit cleanly and aren't still running, newest first
+    pub (crate) offer : Vec<RecoveryFile>,
+    // restoring was asked for, but the current document has changes that it would throw away
+    pub (crate) confirm_restore : bool,
+    // names this run's recovery files, so that other instances leave them alone
+    pub (crate) session : String,
+    // held until exit, so that other instances can tell this session is still running
+    lock : Option<std::fs::File>,
+    started : bool,
+    start_progress : u128,
+    last_save : web_time::Instant,
+    last_progress : u128,
+    writer : Option<std::thread::JoinHandle<()>>,
+}
+
+impl Default for Autosave
+{
+    fn default() -> Self
+    {
+        Self {
+            dir : None,
+            config : AutosaveConfig::default(),
+            offer : vec!(),
+            confirm_restore : false,
+            session : Uuid::new_v4().simple().to_string(),
+            lock : None,
+            started : false,
+            start_progress : 0,
+            last_save : web_time::Instant::now(),
+            last_progress : 0,
+            writer : None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub (crate) struct RecoveryFile
+{
+    pub (crate) time : u64,
+    pub (crate) session : String,
+    pub (crate) path : PathBuf,
+}
+
+// per-user directory for recovery files and settings. WARPAINTER_DATA_DIR overrides it
+pub (crate) fn data_dir() -> Option<PathBuf>
+{
+    if let Some(dir) = std::env::var_os("WARPAINTER_DATA_DIR")
+    {
+        return Some(dir.into());
+    }
+    let base : Option<PathBuf>;
+    #[cfg(target_arch = "wasm32")]
+    {
+        base = None;
+    }
+    #[cfg(target_os = "android")]
+    {
+        base = unsafe { (*&raw const APP_CONTEXT).as_ref() }.and_then(|x| x.internal_data_path());
+    }
+    #[cfg(target_os = "windows")]
+    {
+        base = std::env::var_os("APPDATA").map(PathBuf::from);
+    }
+    #[cfg(target_os = "macos")]
+    {
+        base = std::env::var_os("HOME").map(|x| PathBuf::from(x).join("Library/Application Support"));
+    }
+    #[cfg(not(any(target_arch = "wasm32", target_os = "android", target_os = "windows", target_os = "macos")))]
+    {
+        base = std::env::var_os("XDG_DATA_HOME").filter(|x| !x.is_empty()).map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|x| PathBuf::from(x).join(".local/share")));
+    }
+    base.map(|x| x.join("warpainter"))
+}
+
+fn now_millis() -> u64
+{
+    web_time::SystemTime::now().duration_since(web_time::UNIX_EPOCH).map(|x| x.as_millis() as u64).unwrap_or(0)
+}
+
+fn write_atomic(path : &Path, data : &[u8]) -> Result<(), String>
+{
+    if let Some(parent) = path.parent()
+    {
+        std::fs::create_dir_all(parent).map_err(|x| x.to_string())?;
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        use std::io::Write;
+        use atomicwrites::{AtomicFile, AllowOverwrite};
+        AtomicFile::new(path, AllowOverwrite).write(|f| f.write_all(data)).map_err(|x| x.to_string())
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        std::fs::write(path, data).map_err(|x| x.to_string())
+    }
+}
+
+pub (crate) fn load_config(dir : &Path) -> AutosaveConfig
+{
+    std::fs::read(dir.join("autosave.cbor")).ok()
+        .and_then(|x| cbor4ii::serde::from_reader(std::io::Cursor::new(x)).ok())
+        .unwrap_or_default()
+}
+
+pub (crate) fn save_config(dir : &Path, config : &AutosaveConfig) -> Result<(), String>
+{
+    let mut data = vec!();
+    cbor4ii::serde::to_writer(&mut data, config).map_err(|x| x.to_string())?;
+    write_atomic(&dir.join("autosave.cbor"), &data)
+}
+
+// named by session and by when they were written, newest first
+pub (crate) fn recovery_files(dir : &Path) -> Vec<RecoveryFile>
+{
+    let Ok(entries) = std::fs::read_dir(dir.join("recovery")) else { return vec!() };
+    let mut files : Vec<_> = entries.flatten().filter_map(|entry|
+    {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let (session, time) = name.strip_prefix("recovery-")?.strip_suffix(".wpp")?.rsplit_once('-')?;
+        Some(RecoveryFile { time : time.parse().ok()?, session : session.to_string(), path : entry.path() })
+    }).collect();
+    files.sort_by(|a, b| b.time.cmp(&a.time).then_with(|| b.path.cmp(&a.path)));
+    files
+}
+
+fn session_path(dir : &Path, session : &str, ext : &str) -> PathBuf
+{
+    dir.join("recovery").join(format!("session-{}.{}", session, ext))
+}
+
+// the lock goes away with the process, even if it crashes
+pub (crate) fn lock_session(dir : &Path, session : &str) -> Result<std::fs::File, String>
+{
+    let path = session_path(dir, session, "lock");
+    std::fs::create_dir_all(path.parent().unwrap()).map_err(|x| x.to_string())?;
+    let mut file = std::fs::OpenOptions::new().create(true).truncate(false).write(true).open(path).map_err(|x| x.to_string())?;
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        use fs4::fs_std::FileExt;
+        if !file.try_lock_exclusive().map_err(|x| x.to_string())?
+        {
+            return Err("session is already locked".to_string());
+        }
+    }
+    use std::io::Write;
+    file.set_len(0).and_then(|_| write!(file, "{}", std::process::id())).map_err(|x| x.to_string())?;
+    Ok(file)
+}
+
+pub (crate) fn session_running(dir : &Path, session : &str) -> bool
+{
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        use fs4::fs_std::FileExt;
+        let Ok(file) = std::fs::OpenOptions::new().write(true).open(session_path(dir, session, "lock")) else { return false };
+        // getting the lock means nobody else has it; it's let go again when the file is closed
+        !file.try_lock_exclusive().unwrap_or(false)
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = (dir, session);
+        false
+    }
+}
+
+pub (crate) fn write_recovery(dir : &Path, session : &str, time : u64, data : &[u8], keep : usize) -> Result<PathBuf, String>
+{
+    let path = dir.join("recovery").join(format!("recovery-{}-{}.wpp", session, time));
+    write_atomic(&path, data)?;
+    for old in recovery_files(dir).into_iter().filter(|x| x.session == session).skip(keep.max(1))
+    {
+        let _ = std::fs::remove_file(old.path);
+    }
+    Ok(path)
+}
+
+pub (crate) fn mark_clean_exit(dir : &Path, session : &str, time : u64) -> Result<(), String>
+{
+    write_atomic(&session_path(dir, session, "clean"), time.to_string().as_bytes())
+}
+
+// recovery files from sessions that crashed or got killed. sessions that are still running don't count
+pub (crate) fn unclean_recovery_files(dir : &Path) -> Vec<RecoveryFile>
+{
+    let mut unclean = std::collections::HashMap::new();
+    recovery_files(dir).into_iter().filter(|file|
+    {
+        *unclean.entry(file.session.clone()).or_insert_with(||
+            !session_path(dir, &file.session, "clean").exists() && !session_running(dir, &file.session))
+    }).collect()
+}
+
+// called every frame
+pub (crate) fn autosave_think(app : &mut Warpainter)
+{
+    if !app.autosave.started
+    {
+        app.autosave.started = true;
+        app.autosave.start_progress = app.edit_progress;
+        app.autosave.last_progress = app.edit_progress;
+        app.autosave.dir = data_dir();
+        if let Some(dir) = &app.autosave.dir
+        {
+            app.autosave.config = load_config(dir);
+            app.autosave.offer = unclean_recovery_files(dir);
+            match lock_session(dir, &app.autosave.session)
+            {
+                Ok(lock) => app.autosave.lock = Some(lock),
+                Err(e) => println!("failed to lock autosave session: {}", e),
+            }
+        }
+    }
+    
+    let Some(dir) = app.autosave.dir.clone() else { return };
+    let config = &app.autosave.config;
+    if !config.enabled
+        || app.autosave.last_save.elapsed().as_secs() < config.interval_secs
+        || app.edit_progress == app.autosave.last_progress
+        || app.is_editing()
+        || app.autosave.writer.as_ref().is_some_and(|x| !x.is_finished())
+    {
+        return;
+    }
+    app.autosave.last_save = web_time::Instant::now();
+    app.autosave.last_progress = app.edit_progress;
+    
+    // copying the document is quick; compressing it and writing it out can take a while, so do that on another thread
+    let snapshot = wpp_snapshot(app);
+    let keep = app.autosave.config.keep;
+    let session = app.autosave.session.clone();
+    app.autosave.writer = Some(std::thread::spawn(move ||
+    {
+        let data = wpp_encode(snapshot);
+        if let Err(e) = write_recovery(&dir, &session, now_millis(), &data, keep)
+        {
+            println!("autosave failed: {}", e);
+        }
+    }));
+}
+
+pub (crate) fn autosave_clean_exit(app : &mut Warpainter)
+{
+    if let Some(writer) = app.autosave.writer.take()
+    {
+        let _ = writer.join();
+    }
+    if let Some(dir) = &app.autosave.dir
+    {
+        if let Err(e) = mark_clean_exit(dir, &app.autosave.session, now_millis())
+        {
+            println!("failed to record clean exit: {}", e);
+        }
+        if app.autosave.lock.take().is_some()
+        {
+            let _ = std::fs::remove_file(session_path(dir, &app.autosave.session, "lock"));
+        }
+    }
+}
+
+// the offer only comes up once; answering it in either direction counts as a clean exit for the sessions it came from
+pub (crate) fn autosave_dismiss_offer(app : &mut Warpainter)
+{
+    app.autosave.confirm_restore = false;
+    let offer = std::mem::take(&mut app.autosave.offer);
+    if let Some(dir) = &app.autosave.dir
+    {
+        let mut sessions : Vec<_> = offer.into_iter().map(|x| x.session).collect();
+        sessions.sort();
+        sessions.dedup();
+        for session in sessions
+        {
+            let _ = mark_clean_exit(dir, &session, now_millis());
+        }
+    }
+}
+
+// asks first (through confirm_restore) if that would throw away changes made since startup, unless confirmed is set
+pub (crate) fn autosave_restore(app : &mut Warpainter, confirmed : bool)
+{
+    if !confirmed && app.edit_progress != app.autosave.start_progress
+    {
+        app.autosave.confirm_restore = true;
+        return;
+    }
+    if let Some(file) = app.autosave.offer.first().cloned()
+    {
+        app.cancel_edit();
+        let result = std::fs::read(file.path).map_err(|x| WppError::Io(x.to_string())).and_then(|bytes| wpp_open(app, &bytes));
+        if let Err(e) = result
+        {
+            app.open_error = Some(format!("Failed to restore autosave: {}", e));
+        }
+        app.full_rerender();
+    }
+    autosave_dismiss_offer(app);
+}
+
+// restore prompt and the settings window from the file menu
+pub (crate) fn autosave_ui(app : &mut Warpainter, ctx : &egui::Context)
+{
+    if let Some(time) = app.autosave.offer.first().map(|x| x.time)
+    {
+        let confirming = app.autosave.confirm_restore;
+        let mut choice = None;
+        egui::Window::new("Recover Unsaved Work").collapsible(false).resizable(false)
+            .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0]).show(ctx, |ui|
+            {
+                if confirming
+                {
+                    ui.label("The current file has changes that restoring would throw away.");
+                    ui.label("Restore the autosave anyway?");
+                }
+                else
+                {
+                    ui.label("Warpainter didn't close cleanly last time.");
+                    ui.label(format!("An autosave from {} is available.", describe_age(time)));
+                    ui.label("Note: Restoring replaces the current file.");
+                }
+                ui.horizontal(|ui|
+                {
+                    if ui.button(if confirming { "Restore Anyway" } else { "Restore" }).clicked()
+                    {
+                        choice = Some(true);
+                    }
+                    if ui.button(if confirming { "Cancel" } else { "Discard" }).clicked()
+                    {
+                        choice = Some(false);
+                    }
+                });
+            });
+        match choice
+        {
+            Some(true) => autosave_restore(app, confirming),
+            // backing out of the confirmation goes back to the offer instead of throwing it away
+            Some(false) if confirming => app.autosave.confirm_restore = false,
+            Some(false) => autosave_dismiss_offer(app),
+            None => {}
+        }
+    }
+    
+    if app.open_dialog != "Autosave Settings"
+    {
+        return;
+    }
+    let mut still_open = true;
+    let mut config = app.autosave.config.clone();
+    egui::Window::new("Autosave").collapsible(false).resizable(false).open(&mut still_open)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0]).show(ctx, |ui|
+        {
+            ui.checkbox(&mut config.enabled, "Save recovery files in the background");
+            let mut minutes = config.interval_secs.div_ceil(60).max(1);
+            ui.horizontal(|ui|
+            {
+                ui.add_sized([50.0, 16.0], egui::DragValue::new(&mut minutes).range(1..=240));
+                ui.label("Minutes between saves");
+            });
+            config.interval_secs = minutes * 60;
+            ui.horizontal(|ui|
+            {
+                ui.add_sized([50.0, 16.0], egui::DragValue::new(&mut config.keep).range(1..=100));
+                ui.label("Recovery files to keep");
+            });
+            match &app.autosave.dir
+            {
+                Some(dir) => ui.label(format!("Saved to: {}", dir.join("recovery").display())),
+                None => ui.label("No data directory was found, so nothing will be saved."),
+            };
+        });
+    if config != app.autosave.config
+    {
+        if let Some(dir) = &app.autosave.dir
+        {
+            if let Err(e) = save_config(dir, &config)
+            {
+                println!("failed to save autosave settings: {}", e);
+            }
+        }
+        app.autosave.config = config;
+    }
+    if !still_open
+    {
+        app.open_dialog = "".to_string();
+    }
+}
+
+pub (crate) fn describe_age(time : u64) -> String
+{
+    let minutes = now_millis().saturating_sub(time) / 60000;
+    match minutes
+    {
+        0 => "less than a minute ago".to_string(),
+        1 => "1 minute ago".to_string(),
+        x if x < 120 => format!("{} minutes ago", x),
+        x if x < 48 * 60 => format!("{} hours ago", x / 60),
+        x => format!("{} days ago", x / (24 * 60)),
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    
+    #[test]
+    pub fn test_recovery_files()
+    {
+        let dir = std::env::temp_dir().join(format!("warpainter-autosave-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        
+        assert_eq!(load_config(&dir), AutosaveConfig::default());
+        let config = AutosaveConfig { enabled : false, interval_secs : 30, keep : 2 };
+        save_config(&dir, &config).unwrap();
+        assert_eq!(load_config(&dir), config);
+        
+        let mut app = Warpainter::default();
+        let mut img = Image::<4>::blank(3, 3);
+        img.set_pixel(1, 1, [1, 2, 3, 255]);
+        app.load_from_img(img);
+        let data = wpp_save(&mut app);
+        
+        // only the newest ones are kept, and pruning leaves other sessions' files alone
+        write_recovery(&dir, "other", 500, &data, config.keep).unwrap();
+        for time in [1000, 4000, 2000, 3000]
+        {
+            write_recovery(&dir, "crashed", time, &data, config.keep).unwrap();
+        }
+        let times : Vec<_> = recovery_files(&dir).into_iter().map(|x| (x.time, x.session)).collect();
+        assert_eq!(times, vec!((4000, "crashed".to_string()), (3000, "crashed".to_string()), (500, "other".to_string())));
+        
+        // a session that's still running isn't up for recovery, even though it hasn't exited yet
+        let lock = lock_session(&dir, "other").unwrap();
+        assert!(session_running(&dir, "other"));
+        assert!(lock_session(&dir, "other").is_err());
+        assert!(!session_running(&dir, "crashed"));
+        let unclean = unclean_recovery_files(&dir);
+        assert_eq!(unclean.iter().map(|x| x.time).collect::<Vec<_>>(), vec!(4000, 3000));
+        
+        // and once it goes away without exiting cleanly, it is
+        drop(lock);
+        assert!(!session_running(&dir, "other"));
+        assert_eq!(unclean_recovery_files(&dir).len(), 3);
+        
+        // a clean exit only covers its own session
+        mark_clean_exit(&dir, "other", 3500).unwrap();
+        let unclean = unclean_recovery_files(&dir);
+        assert_eq!(unclean.len(), 2);
+        assert_eq!(unclean[0].time, 4000);
+        
+        // the current document has been edited, so restoring asks first
+        let mut app2 = Warpainter::default();
+        app2.autosave.offer = unclean;
+        app2.autosave.dir = Some(dir.clone());
+        autosave_restore(&mut app2, false);
+        assert!(app2.autosave.confirm_restore);
+        assert_eq!(app2.autosave.offer.len(), 2);
+        assert_ne!(app2.flatten().get_pixel(1, 1), [1, 2, 3, 255]);
+        
+        autosave_restore(&mut app2, true);
+        assert!(app2.open_error.is_none());
+        assert!(app2.autosave.offer.is_empty());
+        assert!(!app2.autosave.confirm_restore);
+        assert_eq!(app2.flatten().get_pixel(1, 1), [1, 2, 3, 255]);
+        assert!(unclean_recovery_files(&dir).is_empty());
+        
+        // a document nobody has touched gets replaced without asking
+        write_recovery(&dir, "crashed2", 5000, &data, config.keep).unwrap();
+        let mut app3 = Warpainter::default();
+        app3.autosave.start_progress = app3.edit_progress;
+        app3.autosave.offer = unclean_recovery_files(&dir);
+        app3.autosave.dir = Some(dir.clone());
+        autosave_restore(&mut app3, false);
+        assert!(!app3.autosave.confirm_restore);
+        assert!(app3.autosave.offer.is_empty());
+        assert_eq!(app3.flatten().get_pixel(1, 1), [1, 2, 3, 255]);
+        
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
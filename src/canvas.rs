@@ -443,6 +443,36 @@ pub (crate) fn canvas(ui : &mut egui::Ui, app : &mut crate::Warpainter, focus_is
         }
     }
     
+    // guides
+    
+    let guide_stroke = egui::Stroke::new(1.0, egui::Color32::from_rgba_unmultiplied(0, 255, 255, 255));
+    let fx = response.rect.width()/2.0;
+    let fy = response.rect.height()/2.0;
+    let to_screen = |mut p : [f32; 2]|
+    {
+        p[0] = (p[0] + 1.0) * fx + response.rect.min.x;
+        p[1] = (p[1] + 1.0) * fy + response.rect.min.y;
+        p
+    };
+    for guide in app.metadata.guides.iter()
+    {
+        let (a, b, c, d, t) = if guide.horizontal
+        {
+            (vertices[0], vertices[2], vertices[1], vertices[3], guide.position / h)
+        }
+        else
+        {
+            (vertices[0], vertices[1], vertices[2], vertices[3], guide.position / w)
+        };
+        if !(0.0..=1.0).contains(&t)
+        {
+            continue;
+        }
+        let start = to_screen(vec_lerp(&a, &b, t));
+        let end = to_screen(vec_lerp(&c, &d, t));
+        painter.line_segment([start.into(), end.into()], guide_stroke);
+    }
+    
     let realtime = realstart.elapsed().as_secs_f32();
     if realtime > 0.001
     {
@@ -0,0 +1,215 @@
+use std::path::Path;
+use crate::*;
+
+pub (crate) const USAGE : &str = "usage:
+    warpainter [file]                   open the editor
+    warpainter render <input> <output>  flatten a document into an image (png, jpg, tga, ...)
+    warpainter convert <input> <output> convert between wpp, ora, psd, and image formats
+    warpainter info <input>             print the canvas size and layer tree";
+
+pub (crate) fn cli_open(path : &str) -> Result<Document, String>
+{
+    Document::open_path(path).map_err(|x| format!("failed to open {}: {}", path, x))
+}
+
+fn write_output(path : &str, data : &[u8]) -> Result<(), String>
+{
+    use std::io::Write;
+    use atomicwrites::{AtomicFile, AllowOverwrite};
+    AtomicFile::new(path, AllowOverwrite).write(|f| f.write_all(data)).map_err(|x| format!("failed to write {}: {}", path, x))
+}
+
+pub (crate) fn cli_render(doc : &mut Document, path : &str) -> Result<(), String>
+{
+    let img = image::RgbaImage::from_raw(doc.width() as u32, doc.height() as u32, doc.flatten()).ok_or("failed to flatten the document")?;
+    let img = image::DynamicImage::ImageRgba8(img);
+    // no alpha channel in these
+    let ext = Path::new(path).extension().map(|x| x.to_string_lossy().to_lowercase()).unwrap_or_default();
+    let img = match ext.as_str()
+    {
+        "jpg" | "jpeg" | "pnm" | "pbm" | "ppm" => image::DynamicImage::ImageRgb8(img.to_rgb8()),
+        _ => img,
+    };
+    img.save(path).map_err(|x| format!("failed to write {}: {}", path, x))
+}
+
+pub (crate) fn cli_convert(doc : &mut Document, path : &str) -> Result<(), String>
+{
+    match Format::from_path(path)
+    {
+        // any image format the image crate can write, not only png
+        Format::Image => cli_render(doc, path),
+        format => write_output(path, &doc.save(format).map_err(|x| format!("failed to write {}: {}", path, x))?),
+    }
+}
+
+fn describe_layer(layer : &Layer) -> String
+{
+    let kind = if let Some(adjustment) = &layer.adjustment
+    {
+        let name = format!("{:?}", adjustment);
+        format!("adjustment {}", name.split('(').next().unwrap_or_default())
+    }
+    else if layer.text.is_some()
+    {
+        "text".to_string()
+    }
+    else if layer.smart_object.is_some()
+    {
+        "smart object".to_string()
+    }
+    else if layer.is_group()
+    {
+        format!("group, {} children", layer.children.len())
+    }
+    else
+    {
+        "pixels".to_string()
+    };
+    let mut info = format!("\"{}\" ({}) {} {:.0}%", layer.name, kind, layer.blend_mode, layer.opacity * 100.0);
+    if layer.fill_opacity != 1.0
+    {
+        info += &format!(" fill {:.0}%", layer.fill_opacity * 100.0);
+    }
+    if let Some(data) = &layer.data
+    {
+        info += &format!(" {}x{} at {},{}", data.width, data.height, layer.offset[0], layer.offset[1]);
+        if data.is_float()
+        {
+            info += " float";
+        }
+    }
+    if layer.mask.is_some()
+    {
+        info += " masked";
+    }
+    if layer.vector_mask.is_some()
+    {
+        info += " vector-masked";
+    }
+    if layer.clipped
+    {
+        info += " clipped";
+    }
+    if !layer.effects.is_empty()
+    {
+        info += " fx";
+    }
+    if !layer.visible
+    {
+        info += " hidden";
+    }
+    info
+}
+
+pub (crate) fn cli_info(doc : &Document) -> String
+{
+    let mut out = format!("canvas {}x{}\n", doc.width(), doc.height());
+    for (depth, layer) in doc.walk()
+    {
+        out += &format!("{}{}\n", "  ".repeat(depth), describe_layer(layer.layer));
+    }
+    out
+}
+
+// returns None if the arguments aren't a subcommand, so the editor should open instead
+pub (crate) fn cli_main(args : &[String]) -> Option<i32>
+{
+    let command = args.get(1)?.as_str();
+    let inputs = &args[2.min(args.len())..];
+    let wanted = match command
+    {
+        "render" | "convert" => 2,
+        "info" => 1,
+        "help" | "--help" | "-h" =>
+        {
+            println!("{}", USAGE);
+            return Some(0);
+        }
+        _ => return None,
+    };
+    if inputs.len() != wanted
+    {
+        eprintln!("{}", USAGE);
+        return Some(2);
+    }
+    
+    let result = cli_open(&inputs[0]).and_then(|mut doc| match command
+    {
+        "render" => cli_render(&mut doc, &inputs[1]),
+        "convert" => cli_convert(&mut doc, &inputs[1]),
+        _ =>
+        {
+            print!("{}", cli_info(&doc));
+            Ok(())
+        }
+    });
+    match result
+    {
+        Ok(()) => Some(0),
+        Err(e) =>
+        {
+            eprintln!("{}", e);
+            Some(1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    
+    #[test]
+    pub fn test_cli()
+    {
+        let dir = std::env::temp_dir().join(format!("warpainter-cli-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name : &str| dir.join(name).to_string_lossy().to_string();
+        let args = |list : &[&str]| std::iter::once("warpainter").chain(list.iter().copied()).map(|x| x.to_string()).collect::<Vec<_>>();
+        
+        // plain file arguments go to the editor
+        assert_eq!(cli_main(&args(&[])), None);
+        assert_eq!(cli_main(&args(&["picture.png"])), None);
+        assert_eq!(cli_main(&args(&["render", "only_one.psd"])), Some(2));
+        assert_eq!(cli_main(&args(&["info", &path("missing.wpp")])), Some(1));
+        
+        let mut img = Image::<4>::blank(4, 3);
+        img.set_pixel(2, 1, [10, 20, 30, 255]);
+        img.to_imagebuffer().save(path("in.png")).unwrap();
+        
+        for ext in ["wpp", "ora", "psd"]
+        {
+            let converted = path(&format!("converted.{}", ext));
+            let rendered = path(&format!("rendered_{}.png", ext));
+            assert_eq!(cli_main(&args(&["convert", &path("in.png"), &converted])), Some(0));
+            assert_eq!(cli_main(&args(&["render", &converted, &rendered])), Some(0));
+            let out = image::open(&rendered).unwrap().to_rgba8();
+            assert_eq!(out.dimensions(), (4, 3));
+            assert_eq!(out.get_pixel(2, 1).0, [10, 20, 30, 255], "{}", ext);
+            
+            let info = cli_info(&cli_open(&converted).unwrap());
+            assert!(info.starts_with("canvas 4x3\n"), "{}", info);
+            assert!(info.contains("\"New Layer\" (pixels)"), "{}", info);
+        }
+        
+        let mut doc = cli_open(&path("in.png")).unwrap();
+        let group = doc.add_group(None, 0, "Group").unwrap();
+        let child = doc.add_layer(Some(group), 0, "Child", 2, 2, &[0; 16]).unwrap();
+        doc.set_visible(child, false).unwrap();
+        let info = cli_info(&doc);
+        let lines : Vec<_> = info.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].starts_with("\"Group\" (group, 1 children)"));
+        assert!(lines[2].starts_with("  \"Child\" (pixels)") && lines[2].ends_with(" hidden"));
+        
+        // the hidden layer survives a round trip through a project file as a layer, not a group
+        doc.save_path(path("tree.wpp")).unwrap();
+        let info = cli_info(&cli_open(&path("tree.wpp")).unwrap());
+        let lines : Vec<_> = info.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[2].starts_with("  \"Child\" (pixels)") && lines[2].ends_with(" hidden"), "{}", info);
+        
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
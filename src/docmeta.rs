@@ -0,0 +1,47 @@
+use serde::{Serialize, Deserialize};
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Resolution
+{
+    pub h_res : f64,
+    pub v_res : f64,
+    pub h_unit : u16, // 1 = pixels per inch, 2 = pixels per centimeter
+    pub v_unit : u16,
+    pub width_unit : u16, // display units: 1 = in, 2 = cm, 3 = pt, 4 = picas, 5 = columns
+    pub height_unit : u16,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Guide
+{
+    pub position : f32, // in canvas pixels
+    pub horizontal : bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Slice
+{
+    pub id : i32,
+    pub name : String,
+    pub left : i32,
+    pub top : i32,
+    pub right : i32,
+    pub bottom : i32,
+}
+
+// document-level information that isn't part of the layer stack
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DocumentMetadata
+{
+    #[serde(default)]
+    pub resolution : Option<Resolution>,
+    #[serde(default)]
+    pub guides : Vec<Guide>,
+    #[serde(default)]
+    pub slices : Vec<Slice>,
+    #[serde(default)]
+    #[serde(with = "serde_bytes")]
+    pub icc_profile : Option<Vec<u8>>,
+    #[serde(default)]
+    pub selected_layers : Vec<u128>, // uuids
+}
@@ -0,0 +1,537 @@
+//! Loading, editing, and saving documents without the editor.
+//!
+//! ```
+//! use libwarpainter::{Document, Format};
+//!
+//! let mut doc = Document::new(64, 32);
+//! let layer = doc.add_layer(None, 0, "Red", 64, 32, &[255, 0, 0, 255].repeat(64 * 32)).unwrap();
+//! doc.set_blend_mode(layer, "Multiply").unwrap();
+//! doc.set_opacity(layer, 0.5).unwrap();
+//!
+//! let bytes = doc.save(Format::Ora).unwrap();
+//! let mut reopened = Document::open(&bytes, Format::Ora).unwrap();
+//! assert_eq!(reopened.layers().len(), 2);
+//! assert_eq!(reopened.flatten().len(), 64 * 32 * 4);
+//! ```
+//!
+//! None of this needs the editor, so it also works with the default `gui` feature turned off
+//! (`default-features = false`), which leaves out eframe and the other windowing dependencies.
+
+use std::path::Path;
+use crate::*;
+
+/// File formats that documents can be loaded from and saved to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format
+{
+    /// Warpainter project file.
+    Wpp,
+    /// Photoshop document. PSB files can be opened, but are saved as PSD. Like in the editor,
+    /// opening one puts all of its layers in a single group.
+    Psd,
+    /// OpenRaster.
+    Ora,
+    /// Any raster image the `image` crate can detect. Opens as a single layer, saves as PNG.
+    Image,
+}
+
+impl Format
+{
+    /// Picks a format from a file extension, falling back to [`Format::Image`].
+    ///
+    /// ```
+    /// use libwarpainter::Format;
+    ///
+    /// assert_eq!(Format::from_path("art/scene.PSD"), Format::Psd);
+    /// assert_eq!(Format::from_path("scene.psb"), Format::Psd);
+    /// assert_eq!(Format::from_path("icon.webp"), Format::Image);
+    /// ```
+    pub fn from_path(path : impl AsRef<Path>) -> Format
+    {
+        let ext = path.as_ref().extension().map(|x| x.to_string_lossy().to_lowercase()).unwrap_or_default();
+        match ext.as_str()
+        {
+            "wpp" => Format::Wpp,
+            "psd" | "psb" => Format::Psd,
+            "ora" => Format::Ora,
+            _ => Format::Image,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DocumentError
+{
+    /// Reading or writing a file failed.
+    Io(String),
+    /// The file couldn't be decoded as the given format.
+    Decode(String),
+    /// The document can't be stored in the given format, e.g. too many layers for a PSD.
+    Encode(String),
+    /// No layer with this id is in the document.
+    NoSuchLayer,
+    /// Layers can only be added to or moved into groups, and only groups can be "Pass Through".
+    NotAGroup,
+    /// A group can't be moved into itself or one of its children.
+    InvalidMove,
+    /// Documents always keep at least one layer.
+    LastLayer,
+    /// The blend mode isn't one of [`Document::blend_modes`].
+    UnknownBlendMode(String),
+    /// The pixel buffer isn't `width * height * 4` bytes long.
+    BadPixels,
+}
+
+impl std::fmt::Display for DocumentError
+{
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self
+        {
+            DocumentError::Io(x) => write!(f, "{}", x),
+            DocumentError::Decode(x) => write!(f, "{}", x),
+            DocumentError::Encode(x) => write!(f, "{}", x),
+            DocumentError::NoSuchLayer => write!(f, "no such layer"),
+            DocumentError::NotAGroup => write!(f, "layer is not a group"),
+            DocumentError::InvalidMove => write!(f, "can't move a group into itself"),
+            DocumentError::LastLayer => write!(f, "can't remove the last layer"),
+            DocumentError::UnknownBlendMode(x) => write!(f, "unknown blend mode {:?}", x),
+            DocumentError::BadPixels => write!(f, "pixel buffer doesn't match the layer size"),
+        }
+    }
+}
+
+impl std::error::Error for DocumentError {}
+
+/// Identifies a layer within a [`Document`]. Stays the same when the layer is moved, and when the
+/// document is saved and reopened as [`Format::Wpp`] or [`Format::Ora`]. PSD and image files don't
+/// store ids, so opening one gives every layer a new id.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LayerId(u128);
+
+/// A layer stack, with the same loaders, savers, and compositing as the editor.
+///
+/// Layers are addressed by [`LayerId`], and positions within a group count from the top, so
+/// index 0 is drawn over everything else in that group. A parent of `None` means the top level.
+pub struct Document
+{
+    document : WppDocument,
+    // editor state from project files, so saving them again doesn't reset it
+    view : ViewState,
+}
+
+/// Read-only view of one layer.
+#[derive(Clone, Copy)]
+pub struct LayerRef<'a>
+{
+    pub (crate) layer : &'a Layer,
+}
+
+impl<'a> LayerRef<'a>
+{
+    pub fn id(&self) -> LayerId
+    {
+        LayerId(self.layer.uuid)
+    }
+    pub fn name(&self) -> &'a str
+    {
+        &self.layer.name
+    }
+    pub fn blend_mode(&self) -> &'a str
+    {
+        &self.layer.blend_mode
+    }
+    pub fn opacity(&self) -> f32
+    {
+        self.layer.opacity
+    }
+    pub fn visible(&self) -> bool
+    {
+        self.layer.visible
+    }
+    pub fn is_group(&self) -> bool
+    {
+        self.layer.is_group() && self.layer.adjustment.is_none()
+    }
+    /// Size of the layer's own pixels, `None` for groups and adjustment layers.
+    pub fn size(&self) -> Option<(usize, usize)>
+    {
+        self.layer.data.as_ref().map(|x| (x.width, x.height))
+    }
+    /// Where the layer's pixels start on the canvas.
+    pub fn offset(&self) -> (f32, f32)
+    {
+        (self.layer.offset[0], self.layer.offset[1])
+    }
+    /// Children from top to bottom. Empty unless this is a group.
+    pub fn children(&self) -> Vec<LayerRef<'a>>
+    {
+        self.layer.children.iter().map(|layer| LayerRef { layer }).collect()
+    }
+}
+
+impl Document
+{
+    // the same as loading an image in the editor
+    fn from_image(img : Image<4>) -> Self
+    {
+        let (canvas_width, canvas_height) = (img.width, img.height);
+        let mut layers = Layer::new_group("___root___");
+        layers.children = vec!(Layer::new_layer_from_image("New Layer", img));
+        let document = WppDocument {
+            current_layer : layers.children[0].uuid,
+            canvas_width,
+            canvas_height,
+            layers,
+            ..Default::default()
+        };
+        Self { document, view : ViewState::default() }
+    }
+    /// Makes a document with one transparent layer, like File > New in the editor.
+    pub fn new(width : usize, height : usize) -> Self
+    {
+        Self::from_image(Image::<4>::blank_white_transparent(width.max(1), height.max(1)))
+    }
+    /// Loads a document from the contents of a file.
+    pub fn open(bytes : &[u8], format : Format) -> Result<Self, DocumentError>
+    {
+        let document = match format
+        {
+            Format::Wpp =>
+            {
+                let (mut document, view) = wpp_read(bytes).map_err(|x| DocumentError::Decode(x.to_string()))?;
+                // nothing here is interactive, so there's no point in decoding lazily
+                wpp_decode_all(&mut document);
+                return Ok(Self { document, view });
+            }
+            Format::Psd => wpsd_read(bytes).map_err(|x| DocumentError::Decode(x.to_string()))?,
+            Format::Ora => wora_read(bytes).map_err(DocumentError::Decode)?,
+            Format::Image =>
+            {
+                let img = image::io::Reader::new(std::io::Cursor::new(bytes)).with_guessed_format().map_err(|x| DocumentError::Io(x.to_string()))?
+                    .decode().map_err(|x| DocumentError::Decode(x.to_string()))?.to_rgba8();
+                return Ok(Self::from_image(Image::<4>::from_rgbaimage(&img)));
+            }
+        };
+        Ok(Self { document, view : ViewState::default() })
+    }
+    /// Loads a file, picking the format from its extension.
+    pub fn open_path(path : impl AsRef<Path>) -> Result<Self, DocumentError>
+    {
+        let bytes = std::fs::read(path.as_ref()).map_err(|x| DocumentError::Io(x.to_string()))?;
+        Self::open(&bytes, Format::from_path(path))
+    }
+    pub fn save(&mut self, format : Format) -> Result<Vec<u8>, DocumentError>
+    {
+        Ok(match format
+        {
+            Format::Wpp => wpp_save_document(&mut self.document, self.view.clone()),
+            Format::Psd => wpsd_write(&mut self.document).map_err(DocumentError::Encode)?,
+            Format::Ora => self.document.build_ora_data(),
+            Format::Image =>
+            {
+                let mut bytes = vec!();
+                let img = self.document.flatten().to_imagebuffer();
+                img.write_to(&mut std::io::Cursor::new(&mut bytes), image::ImageOutputFormat::Png).map_err(|x| DocumentError::Encode(x.to_string()))?;
+                bytes
+            }
+        })
+    }
+    /// Saves to a file, picking the format from its extension.
+    pub fn save_path(&mut self, path : impl AsRef<Path>) -> Result<(), DocumentError>
+    {
+        let data = self.save(Format::from_path(path.as_ref()))?;
+        std::fs::write(path, data).map_err(|x| DocumentError::Io(x.to_string()))
+    }
+    pub fn width(&self) -> usize
+    {
+        self.document.canvas_width
+    }
+    pub fn height(&self) -> usize
+    {
+        self.document.canvas_height
+    }
+    /// Composites every visible layer into `width * height` straight-alpha RGBA pixels, row by row.
+    ///
+    /// ```
+    /// use libwarpainter::Document;
+    ///
+    /// let mut doc = Document::new(2, 1);
+    /// doc.add_layer(None, 0, "Blue", 1, 1, &[0, 0, 255, 255]).unwrap();
+    /// let pixels = doc.flatten();
+    /// assert_eq!(pixels[0..4], [0, 0, 255, 255]);
+    /// assert_eq!(pixels[7], 0);
+    /// ```
+    pub fn flatten(&mut self) -> Vec<u8>
+    {
+        self.document.flatten().to_imagebuffer().into_raw()
+    }
+    
+    /// Top-level layers, from top to bottom.
+    pub fn layers(&self) -> Vec<LayerRef<'_>>
+    {
+        self.document.layers.children.iter().map(|layer| LayerRef { layer }).collect()
+    }
+    pub fn layer(&self, id : LayerId) -> Option<LayerRef<'_>>
+    {
+        self.find(id).map(|layer| LayerRef { layer })
+    }
+    /// Every layer depth first, in drawing order from the top, with how deeply it's nested.
+    ///
+    /// ```
+    /// use libwarpainter::Document;
+    ///
+    /// let mut doc = Document::new(8, 8);
+    /// let group = doc.add_group(None, 0, "Group").unwrap();
+    /// doc.add_layer(Some(group), 0, "Inner", 1, 1, &[0; 4]).unwrap();
+    ///
+    /// let names : Vec<_> = doc.walk().iter().map(|(depth, layer)| (*depth, layer.name().to_string())).collect();
+    /// assert_eq!(names, vec!((0, "Group".to_string()), (1, "Inner".to_string()), (0, "New Layer".to_string())));
+    /// ```
+    pub fn walk(&self) -> Vec<(usize, LayerRef<'_>)>
+    {
+        fn visit<'a>(layers : &'a [Layer], depth : usize, ret : &mut Vec<(usize, LayerRef<'a>)>)
+        {
+            for layer in layers
+            {
+                ret.push((depth, LayerRef { layer }));
+                visit(&layer.children, depth + 1, ret);
+            }
+        }
+        let mut ret = vec!();
+        visit(&self.document.layers.children, 0, &mut ret);
+        ret
+    }
+    /// The group that holds the layer, `None` at the top level, and the layer's index in it.
+    pub fn parent(&self, id : LayerId) -> Option<(Option<LayerId>, usize)>
+    {
+        let parent = self.document.layers.find_layer_parent(id.0)?;
+        let index = parent.children.iter().position(|x| x.uuid == id.0)?;
+        let parent_id = if parent.uuid == self.document.layers.uuid { None } else { Some(LayerId(parent.uuid)) };
+        Some((parent_id, index))
+    }
+    
+    fn find(&self, id : LayerId) -> Option<&Layer>
+    {
+        if id.0 == self.document.layers.uuid
+        {
+            return None;
+        }
+        self.document.layers.find_layer(id.0)
+    }
+    fn find_mut(&mut self, id : LayerId) -> Result<&mut Layer, DocumentError>
+    {
+        if id.0 == self.document.layers.uuid
+        {
+            return Err(DocumentError::NoSuchLayer);
+        }
+        self.document.layers.find_layer_mut(id.0).ok_or(DocumentError::NoSuchLayer)
+    }
+    fn group_mut(&mut self, parent : Option<LayerId>) -> Result<&mut Layer, DocumentError>
+    {
+        let group = match parent
+        {
+            Some(id) => self.find_mut(id)?,
+            None => &mut self.document.layers,
+        };
+        if !group.is_group() || group.adjustment.is_some()
+        {
+            return Err(DocumentError::NotAGroup);
+        }
+        Ok(group)
+    }
+    // the tree changed shape, so every cached composite might be stale
+    fn changed(&mut self)
+    {
+        self.document.layers.visit_layers_mut(0, &mut |layer, _|
+        {
+            layer.dirtify_full_rect();
+            Some(())
+        });
+    }
+    fn insert(&mut self, parent : Option<LayerId>, index : usize, layer : Layer) -> Result<LayerId, DocumentError>
+    {
+        let id = LayerId(layer.uuid);
+        let group = self.group_mut(parent)?;
+        let index = index.min(group.children.len());
+        group.children.insert(index, layer);
+        self.changed();
+        Ok(id)
+    }
+    
+    /// Adds a layer from straight-alpha RGBA pixels, placed at the canvas origin.
+    pub fn add_layer(&mut self, parent : Option<LayerId>, index : usize, name : &str, width : usize, height : usize, rgba : &[u8]) -> Result<LayerId, DocumentError>
+    {
+        if width == 0 || height == 0 || rgba.len() != width * height * 4
+        {
+            return Err(DocumentError::BadPixels);
+        }
+        let img = image::RgbaImage::from_raw(width as u32, height as u32, rgba.to_vec()).ok_or(DocumentError::BadPixels)?;
+        self.insert(parent, index, Layer::new_layer_from_image(name, Image::<4>::from_rgbaimage(&img)))
+    }
+    pub fn add_group(&mut self, parent : Option<LayerId>, index : usize, name : &str) -> Result<LayerId, DocumentError>
+    {
+        self.insert(parent, index, Layer::new_group(name))
+    }
+    /// Removes a layer, along with its children if it's a group.
+    pub fn remove_layer(&mut self, id : LayerId) -> Result<(), DocumentError>
+    {
+        let layer = self.find(id).ok_or(DocumentError::NoSuchLayer)?;
+        if layer.count() + 1 >= self.document.layers.count()
+        {
+            return Err(DocumentError::LastLayer);
+        }
+        if layer.find_layer(self.document.current_layer).is_some()
+        {
+            let next = self.document.layers.uuid_of_next(id.0).or_else(|| self.document.layers.uuid_of_prev(id.0));
+            self.document.current_layer = next.unwrap_or_default();
+        }
+        self.document.layers.delete_layer(id.0);
+        if self.document.layers.find_layer(self.document.current_layer).is_none()
+        {
+            self.document.current_layer = self.document.layers.children[0].uuid;
+        }
+        self.changed();
+        Ok(())
+    }
+    /// Moves a layer into `parent` at `index`, counted after the layer has been taken out of its old place.
+    ///
+    /// ```
+    /// use libwarpainter::Document;
+    ///
+    /// let mut doc = Document::new(8, 8);
+    /// let group = doc.add_group(None, 0, "Group").unwrap();
+    /// let bottom = doc.layers()[1].id();
+    /// doc.move_layer(bottom, Some(group), 0).unwrap();
+    /// assert_eq!(doc.parent(bottom), Some((Some(group), 0)));
+    /// assert!(doc.move_layer(group, Some(group), 0).is_err());
+    /// ```
+    pub fn move_layer(&mut self, id : LayerId, parent : Option<LayerId>, index : usize) -> Result<(), DocumentError>
+    {
+        let layer = self.find(id).ok_or(DocumentError::NoSuchLayer)?;
+        if let Some(parent) = parent
+        {
+            if layer.find_layer(parent.0).is_some()
+            {
+                return Err(DocumentError::InvalidMove);
+            }
+        }
+        let layer = layer.clone();
+        self.group_mut(parent)?;
+        self.document.layers.delete_layer(id.0);
+        let group = self.group_mut(parent)?;
+        let index = index.min(group.children.len());
+        group.children.insert(index, layer);
+        self.changed();
+        Ok(())
+    }
+    /// Blend modes accepted by [`Document::set_blend_mode`], the same ones the editor offers.
+    /// "Pass Through" only applies to groups.
+    ///
+    /// ```
+    /// use libwarpainter::Document;
+    ///
+    /// assert!(Document::blend_modes().any(|x| x == "Multiply"));
+    /// ```
+    pub fn blend_modes() -> impl Iterator<Item = &'static str>
+    {
+        // custom modes need a formula, which isn't part of this api
+        blend_modes().filter(|x| !x.starts_with("Custom"))
+    }
+    /// Sets one of [`Document::blend_modes`] on a layer.
+    pub fn set_blend_mode(&mut self, id : LayerId, blend_mode : &str) -> Result<(), DocumentError>
+    {
+        let layer = self.find_mut(id)?;
+        if !Document::blend_modes().any(|x| x == blend_mode)
+        {
+            return Err(DocumentError::UnknownBlendMode(blend_mode.to_string()));
+        }
+        if blend_mode == "Pass Through" && !layer.is_group()
+        {
+            return Err(DocumentError::NotAGroup);
+        }
+        layer.blend_mode = blend_mode.to_string();
+        self.changed();
+        Ok(())
+    }
+    /// Sets the layer opacity, clamped to 0.0 to 1.0.
+    pub fn set_opacity(&mut self, id : LayerId, opacity : f32) -> Result<(), DocumentError>
+    {
+        self.find_mut(id)?.opacity = opacity.clamp(0.0, 1.0);
+        self.changed();
+        Ok(())
+    }
+    /// Shows or hides a layer, and everything in it if it's a group.
+    pub fn set_visible(&mut self, id : LayerId, visible : bool) -> Result<(), DocumentError>
+    {
+        self.find_mut(id)?.visible = visible;
+        self.changed();
+        Ok(())
+    }
+    pub fn set_name(&mut self, id : LayerId, name : &str) -> Result<(), DocumentError>
+    {
+        self.find_mut(id)?.name = name.to_string();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    
+    #[test]
+    pub fn test_document()
+    {
+        let mut doc = Document::new(4, 4);
+        let bottom = doc.layers()[0].id();
+        let group = doc.add_group(None, 0, "Group").unwrap();
+        let red = doc.add_layer(Some(group), 0, "Red", 2, 2, &[255, 0, 0, 255].repeat(4)).unwrap();
+        assert_eq!(doc.flatten()[0..4], [255, 0, 0, 255]);
+        assert_eq!(doc.flatten()[11], 0);
+        
+        assert_eq!(doc.add_layer(Some(red), 0, "x", 1, 1, &[0; 4]), Err(DocumentError::NotAGroup));
+        assert_eq!(doc.add_layer(None, 0, "x", 2, 2, &[0; 4]), Err(DocumentError::BadPixels));
+        assert_eq!(doc.set_blend_mode(red, "Pass Through"), Err(DocumentError::NotAGroup));
+        assert_eq!(doc.set_blend_mode(red, "Sideways"), Err(DocumentError::UnknownBlendMode("Sideways".to_string())));
+        assert_eq!(doc.move_layer(group, Some(group), 0), Err(DocumentError::InvalidMove));
+        
+        // edits show up in the composite without anything else having to be invalidated
+        doc.set_opacity(red, 0.5).unwrap();
+        assert_eq!(doc.flatten()[3], 128);
+        doc.set_visible(group, false).unwrap();
+        assert_eq!(doc.flatten()[3], 0);
+        doc.set_visible(group, true).unwrap();
+        doc.move_layer(red, None, 5).unwrap();
+        assert_eq!(doc.parent(red), Some((None, 2)));
+        assert_eq!(doc.flatten()[3], 128);
+        doc.set_blend_mode(red, "Erase").unwrap();
+        assert_eq!(doc.flatten()[3], 0);
+        doc.set_blend_mode(red, "Multiply").unwrap();
+        
+        for format in [Format::Wpp, Format::Psd, Format::Ora]
+        {
+            let mut reopened = Document::open(&doc.save(format).unwrap(), format).unwrap();
+            let mut names : Vec<_> = reopened.walk().iter().map(|(depth, layer)| (*depth, layer.name().to_string(), layer.blend_mode().to_string())).collect();
+            if format == Format::Psd
+            {
+                assert_eq!(names.remove(0), (0, "PSD File".to_string(), "Normal".to_string()));
+                names.iter_mut().for_each(|x| x.0 -= 1);
+            }
+            assert_eq!(names, vec!(
+                (0, "Group".to_string(), "Normal".to_string()),
+                (0, "New Layer".to_string(), "Normal".to_string()),
+                (0, "Red".to_string(), "Multiply".to_string()),
+            ), "{:?}", format);
+            assert_eq!(reopened.flatten(), doc.flatten(), "{:?}", format);
+            // psd files don't store ids
+            assert_eq!(reopened.layer(red).is_some(), format != Format::Psd, "{:?}", format);
+        }
+        
+        doc.remove_layer(group).unwrap();
+        doc.remove_layer(red).unwrap();
+        assert_eq!(doc.remove_layer(bottom), Err(DocumentError::LastLayer));
+        assert_eq!(doc.remove_layer(red), Err(DocumentError::NoSuchLayer));
+        assert_eq!(doc.layers().len(), 1);
+    }
+}
@@ -79,6 +79,11 @@ pub (crate) struct LayerInfo
     pub (crate) closed : bool,
     
     pub (crate) effects : HashMap<String, HashMap<String, Vec<FxData>>>,
+    
+    pub (crate) smart_transform : Option<[f32; 8]>,
+    pub (crate) text : Option<String>,
+    // a text layer that hasn't been edited yet, with its original rendering, so undoing the first edit can bring them back
+    pub (crate) unedited_text : Option<(TextLayer, Image<4>)>,
 }
 
 impl LayerInfo
@@ -102,6 +107,10 @@ impl LayerInfo
             closed : false,
             
             effects : HashMap::new(),
+            
+            smart_transform : None,
+            text : None,
+            unedited_text : None,
         }
     }
 }
@@ -117,16 +126,95 @@ pub (crate) enum Adjustment
     Levels(Vec<[f32; 5]>),
     Curves(Vec<Vec<[f32; 2]>>),
     BlackWhite(([f32; 6], bool, [f32; 3])),
+    GradientMap((Vec<[f32; 5]>, Vec<[f32; 3]>, bool, bool)), // color stops, alpha stops, reversed, dithered
+    PhotoFilter(([f32; 3], f32, bool)), // color, density (percent), preserve luminosity
+    Exposure([f32; 3]), // exposure, offset, gamma
+    Vibrance([f32; 2]), // vibrance, saturation
+    ChannelMixer(([[f32; 4]; 3], bool)), // red, green, blue outputs (r, g, b weights and constant, in percent), monochrome
+    ColorBalance(([[f32; 3]; 3], bool)), // shadows, midtones, highlights (cyan-red, magenta-green, yellow-blue), preserve luminosity
+    SelectiveColor((Vec<[f32; 4]>, bool)), // reds, yellows, greens, cyans, blues, magentas, whites, neutrals, blacks (c, m, y, k in percent), absolute
     #[default] Xxx,
 }
 
-pub (crate) trait CloneAny : Any
+// a layer whose pixels are a cached rendering of an embedded original file
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub (crate) struct SmartObject
+{
+    pub (crate) name : String,
+    #[serde(with = "serde_bytes")]
+    pub (crate) data : Vec<u8>,
+    // where the original's corners land (top left, top right, bottom right, bottom left), relative to the layer offset
+    pub (crate) transform : [f32; 8],
+}
+
+impl SmartObject
+{
+    pub (crate) fn decode(&self) -> Option<Image<4>>
+    {
+        if self.data.starts_with(b"8BPS")
+        {
+            return Some(crate::wpsd::wpsd_read(&self.data).ok()?.flatten().clone());
+        }
+        let img = image::load_from_memory(&self.data).ok()?.to_rgba8();
+        Some(Image::<4>::from_rgbaimage(&img))
+    }
+}
+
+#[derive(Clone, Debug, Default, Decode, Encode, Serialize, Deserialize)]
+pub (crate) struct TextRun
+{
+    pub (crate) length : usize, // in utf-16 code units, like photoshop counts them
+    pub (crate) font : String,
+    pub (crate) size : f32,
+    pub (crate) color : [f32; 4],
+}
+
+// a text layer's contents; its pixels stay the cached rendering from the original program until the text gets edited
+#[derive(Clone, Debug, Default, Decode, Encode, Serialize, Deserialize)]
+pub (crate) struct TextLayer
+{
+    pub (crate) text : String,
+    pub (crate) runs : Vec<TextRun>,
+    // xx, xy, yx, yy, then the first baseline's origin, relative to the layer offset
+    pub (crate) transform : [f32; 6],
+    pub (crate) edited : bool,
+    // the original TySh block, for writing back everything we don't understand
+    #[serde(with = "serde_bytes")]
+    pub (crate) psd_block : Vec<u8>,
+}
+
+// each knot takes the control point from the end of the segment before it
+fn segments_to_knots(segments : &[[[f32; 2]; 4]]) -> Vec<[[f32; 2]; 3]>
+{
+    let n = segments.len();
+    (0..n).map(|i| [segments[(i + n - 1) % n][2], segments[i][0], segments[i][1]]).collect()
+}
+
+impl TextLayer
+{
+    // the runs get cut or stretched to fit, with the last one taking up any new text
+    pub (crate) fn set_text(&mut self, text : &str)
+    {
+        let len = text.encode_utf16().count();
+        let mut start = 0;
+        self.runs.retain(|run| { let keep = start < len || start == 0; start += run.length; keep });
+        let before_last : usize = self.runs.iter().rev().skip(1).map(|x| x.length).sum();
+        if let Some(last) = self.runs.last_mut()
+        {
+            last.length = len - before_last;
+        }
+        self.text = text.to_string();
+    }
+}
+
+// Send, so that layer trees can be handed to other threads
+pub (crate) trait CloneAny : Any + Send
 { 
     fn any(&self) -> &dyn Any;
     fn mut_any(&mut self) -> &mut dyn Any;
     fn clone_box(&self) -> Box<dyn CloneAny>;
 }
-impl<T : Any + Clone> CloneAny for T
+impl<T : Any + Clone + Send> CloneAny for T
 {
     fn any(&self) -> &dyn Any
     {
@@ -182,6 +270,9 @@ pub (crate) struct Layer
     
     pub (crate) mask : Option<Image<1>>,
     pub (crate) mask_info : Option<MaskInfo>,
+    // rasterized from a vector mask on import, applied on top of the raster mask
+    #[serde(default)]
+    pub (crate) vector_mask : Option<(Image<1>, MaskInfo)>,
     
     pub (crate) flattened_data : Option<Image<4>>,
     pub (crate) flattened_dirty_rect : Option<[[f32; 2]; 2]>,
@@ -211,6 +302,23 @@ pub (crate) struct Layer
     
     pub (crate) effects : HashMap<String, HashMap<String, Vec<FxData>>>,
     
+    #[serde(default)]
+    pub (crate) smart_object : Option<SmartObject>,
+    #[serde(default)]
+    pub (crate) text : Option<TextLayer>,
+    
+    // the group wpsd_read puts a whole file in, which saving as PSD unwraps again
+    #[serde(default)]
+    pub (crate) psd_file_root : bool,
+    
+    // pixel chunks from a project file, still compressed until the layer is needed
+    #[serde(skip)]
+    pub (crate) pending : Option<std::sync::Arc<Vec<crate::wpp::WppChunk>>>,
+    
+    // whether anything under this group has float data; worked out once per flatten_as_root call
+    #[serde(skip)]
+    pub (crate) has_float : bool,
+    
     #[serde(skip)]
     pub (crate) _dummy_flattened_data : Option<Image<4>>,
     #[serde(skip)]
@@ -239,6 +347,10 @@ impl Layer
             closed : self.closed,
             
             effects : self.effects.clone(),
+            
+            smart_transform : self.smart_object.as_ref().map(|x| x.transform),
+            text : self.text.as_ref().map(|x| x.text.clone()),
+            unedited_text : self.text.as_ref().filter(|x| !x.edited).zip(self.data.clone()).map(|(text, data)| (text.clone(), data)),
         }
     }
     pub (crate) fn set_info(&mut self, info : &LayerInfo)
@@ -257,6 +369,29 @@ impl Layer
         
         self.effects = info.effects.clone();
         
+        if let (Some(smart), Some(transform)) = (&mut self.smart_object, info.smart_transform)
+        {
+            if smart.transform != transform
+            {
+                smart.transform = transform;
+                self.rasterize_smart_object();
+            }
+        }
+        if let (Some(text), Some(new_text)) = (&self.text, &info.text)
+        {
+            if let Some((original, data)) = info.unedited_text.as_ref().filter(|_| text.edited)
+            {
+                // back to before the first edit, so back to the original program's rendering
+                self.text = Some(original.clone());
+                self.data = Some(data.clone());
+                self.dirtify_all();
+            }
+            else if &text.text != new_text
+            {
+                self.edit_text(new_text);
+            }
+        }
+        
         self.commit_info();
     }
     pub (crate) fn commit_info(&mut self)
@@ -273,6 +408,7 @@ impl Layer
             data : Some(image),
             mask : None,
             mask_info : None,
+            vector_mask : None,
             adjustment : None,
             children : vec!(),
             
@@ -296,10 +432,16 @@ impl Layer
             
             effects : HashMap::new(),
             
+            smart_object : None,
+            text : None,
+            psd_file_root : false,
+            
+            pending : None,
             _dummy_flattened_data : None,
             _dummy_flattened_dirty_rect : None,
             thumbnail : None,
             mask_thumbnail : None,
+            has_float : false,
             
             old_info_for_undo : LayerInfo::new(name.to_string()),
         }
@@ -318,6 +460,7 @@ impl Layer
             data : None,
             mask : None,
             mask_info : None,
+            vector_mask : None,
             adjustment : None,
             children : vec!(),
             
@@ -341,21 +484,56 @@ impl Layer
             
             effects : HashMap::new(),
             
+            smart_object : None,
+            text : None,
+            psd_file_root : false,
+            
+            pending : None,
             _dummy_flattened_data : None,
             _dummy_flattened_dirty_rect : None,
             thumbnail : None,
             mask_thumbnail : None,
+            has_float : false,
             
             old_info_for_undo : LayerInfo::new(name.to_string()),
         }
     }
+    pub(crate) fn get_vector_mask(&self) -> Option<(&Image<1>, &MaskInfo)>
+    {
+        self.vector_mask.as_ref().map(|(mask, info)| (mask, info))
+    }
     pub(crate) fn is_drawable(&self) -> bool
     {
         self.data.is_some()
     }
     pub(crate) fn is_group(&self) -> bool
     {
-        self.data.is_none()
+        // pending layers have no data yet either, but they're not groups
+        self.data.is_none() && self.pending.is_none()
+    }
+    pub(crate) fn has_float_data(&self) -> bool
+    {
+        if let Some(data) = &self.data
+        {
+            return data.is_float();
+        }
+        for child in self.children.iter()
+        {
+            if child.has_float_data()
+            {
+                return true;
+            }
+        }
+        false
+    }
+    fn update_has_float(&mut self) -> bool
+    {
+        self.has_float = match &self.data
+        {
+            Some(data) => data.is_float(),
+            None => self.children.iter_mut().fold(false, |ret, child| child.update_has_float() || ret),
+        };
+        self.has_float
     }
     pub(crate) fn find_layer(&self, uuid : u128) -> Option<&Layer>
     {
@@ -544,9 +722,12 @@ impl Layer
     pub(crate) fn dirtify_all(&mut self)
     {
         let mut reference = None;
+        let mut has_adjustment = false;
         // FIXME cache somehow??? or is it not worth it
         self.visit_layers(0, &mut |layer, _|
         {
+            // adjustments inside groups (especially pass-through ones) can touch anything
+            has_adjustment |= layer.adjustment.is_some();
             if let Some(image) = &layer.data
             {
                 let rect = [layer.offset, vec_add(&layer.offset, &[image.width as f32, image.height as f32])];
@@ -561,7 +742,7 @@ impl Layer
             }
             Some(())
         });
-        if self.adjustment.is_some()
+        if has_adjustment
         {
             reference = Some([[0.0, 0.0], [1000000.0, 1000000.0]]);
         }
@@ -587,10 +768,16 @@ impl Layer
         }
         else
         {
-            self.flatten_as_root(canvas_width, canvas_height, override_uuid, override_data)
+            self.flatten_uncached(canvas_width, canvas_height, override_uuid, override_data)
         }
     }
     pub(crate) fn flatten_as_root<'a>(&'a mut self, canvas_width : usize, canvas_height : usize, override_uuid : Option<u128>, override_data : Option<&Image<4>>) -> &'a Image<4>
+    {
+        // one walk for the whole tree, instead of one per dirty group
+        self.update_has_float();
+        self.flatten_uncached(canvas_width, canvas_height, override_uuid, override_data)
+    }
+    fn flatten_uncached<'a>(&'a mut self, canvas_width : usize, canvas_height : usize, override_uuid : Option<u128>, override_data : Option<&Image<4>>) -> &'a Image<4>
     {
         let _start = web_time::Instant::now();
 
@@ -618,14 +805,24 @@ impl Layer
         {
             //println!("group is dirty, reflattening ({:?})", dirty_rect);
             
-            let mut new_dirty_rect;
+            let new_dirty_rect;
+            
+            // flatten into a float buffer if anything underneath has more precision than 8 bits
+            let want_float = self.has_float;
             
             #[allow(clippy::unnecessary_unwrap)] // broken lint
-            if self.flattened_data.is_none() || dirty_rect.is_none()
+            if self.flattened_data.is_none() || dirty_rect.is_none() || self.flattened_data.as_ref().unwrap().is_float() != want_float
             {
                 new_dirty_rect = [[0.0, 0.0], [canvas_width as f32, canvas_height as f32]];
                 //println!("new buffer...");
-                self.flattened_data = Some(Image::blank(canvas_width, canvas_height));
+                if want_float
+                {
+                    self.flattened_data = Some(Image::blank_float(canvas_width, canvas_height));
+                }
+                else
+                {
+                    self.flattened_data = Some(Image::blank(canvas_width, canvas_height));
+                }
             }
             else
             {
@@ -634,252 +831,528 @@ impl Layer
                 //println!("clearing rect {:?} (layer {})...", new_dirty_rect, self.name);
                 self.flattened_data.as_mut().unwrap().clear_rect_with_color_float(new_dirty_rect, [0.0, 0.0, 0.0, 0.0]);
             }
-            // We keep track of what's "first" (bottommost) in a given group to give it a special blend mode against the empty flattening target layer.
-            // This makes it so that "reveal" etc blend modes work more intuitively instead of having to choose
-            // between erased transparent data being lost or fully transparent higher layers overwriting fully transparent lower layers.
-            let mut first = true;
-            let mut _stash_is_first = false;
-            let mut stash = None;
-            let mut stash_offs = [0, 0];
-            let mut stash_clean = None;
-            let mut stash_mask = None;
-            let mut stash_mask_info = None;
-            let mut stash_opacity = 0.0;
-            let mut stash_fill_opacity = 0.0;
-            let mut stash_funny_flag = false;
-            let mut stash_blend_mode = "".to_string();
+            Self::flatten_children_into(&mut self.children, &mut self.flattened_data, new_dirty_rect, canvas_width, canvas_height, override_uuid, override_data);
+            //println!("--ASDFASDFASDF {:.6}ms", start.elapsed().as_secs_f64() * 1000.0);
+            self.flattened_dirty_rect = None;
+            return self.flattened_data.as_ref().unwrap();
+        }
+    }
+    // composites a list of children, bottom-most last, onto an existing buffer
+    fn flatten_children_into(children : &mut [Layer], target : &mut Option<Image<4>>, mut new_dirty_rect : [[f32; 2]; 2], canvas_width : usize, canvas_height : usize, override_uuid : Option<u128>, override_data : Option<&Image<4>>)
+    {
+        // We keep track of what's "first" (bottommost) in a given group to give it a special blend mode against the empty flattening target layer.
+        // This makes it so that "reveal" etc blend modes work more intuitively instead of having to choose
+        // between erased transparent data being lost or fully transparent higher layers overwriting fully transparent lower layers.
+        let mut first = true;
+        let mut _stash_is_first = false;
+        let mut stash = None;
+        let mut stash_offs = [0, 0];
+        let mut stash_clean = None;
+        let mut stash_mask = None;
+        let mut stash_mask_info = None;
+        let mut stash_vector_mask : Option<(Image<1>, MaskInfo)> = None;
+        let mut stash_opacity = 0.0;
+        let mut stash_fill_opacity = 0.0;
+        let mut stash_funny_flag = false;
+        let mut stash_blend_mode = "".to_string();
+        
+        for i in (0..children.len()).rev()
+        {
+            let (a, b) = children.split_at_mut(i);
+            let child = b.first_mut().unwrap();
+            // not decoded yet, so there's nothing to draw or to keep a buffer for
+            if child.pending.is_some()
+            {
+                continue;
+            }
+            if !child.visible
+            {
+                child.flatten(canvas_width, canvas_height, override_uuid, override_data);
+                continue;
+            }
+            let alen = a.len();
+            let mut above = a.last_mut();
+            let mut n = 0;
+            let skipped = |x : &Layer| !x.visible || x.pending.is_some();
+            while above.is_some() && skipped(above.as_ref().unwrap()) && n + 1 < alen
+            {
+                n += 1;
+                above = a.get_mut(alen - 1 - n);
+            }
+            if above.is_some() && skipped(above.as_ref().unwrap())
+            {
+                above = None;
+            }
             
-            for i in (0..self.children.len()).rev()
+            let above_clipped = above.as_ref().map(|x| x.clipped).unwrap_or(false);
+            if child.is_pass_through() && stash.is_none() && (!above_clipped || child.clipped)
             {
-                let (a, b) = self.children.split_at_mut(i);
-                let child = b.first_mut().unwrap();
-                if !child.visible
-                {
-                    child.flatten(canvas_width, canvas_height, override_uuid, override_data);
-                    continue;
-                }
-                let alen = a.len();
-                let mut above = a.last_mut();
-                let mut n = 0;
-                while above.is_some() && !above.as_ref().unwrap().visible && n + 1 < alen
-                {
-                    n += 1;
-                    above = a.get_mut(alen - 1 - n);
-                }
-                if above.is_some() && !above.as_ref().unwrap().visible
-                {
-                    above = None;
-                }
+                child.flatten_pass_through(target, new_dirty_rect, canvas_width, canvas_height, override_uuid, override_data);
+                first = false;
+                continue;
+            }
+            
+            let mut mode = child.blend_mode.clone();
+            if mode == "Custom"
+            {
+                mode += &("\n".to_string() + &child.custom_blend_mode);
+                
+                // example custom blend mode: hard mix
+                
+                // n = b + a*fill_opacity;
+                // n = n - 0.5;
+                // n = n - (fill_opacity*0.5);
+                // n = n / (1.0 - fill_opacity);
+                // n = n + 0.5;
+                // clamp(n, 0.0, 1.0)
+                
+                // bad hard mix (e.g. similar to the krita/gimp implementation)
+                
+                // n = b + a;
+                // n = n * 0.5;
+                // n = ~n;
+                // clamp(n, 0.0, 1.0)
                 
-                let mut mode = child.blend_mode.clone();
-                if mode == "Custom"
+                // photoshop-accurate linear dodge/add (TODO)
+                
+                // clamp(b+a*fill_opacity, 0, 1)
+            }
+            if mode == "Custom Tri"
+            {
+                mode = "TriCustom".to_string() + &("\n".to_string() + &child.custom_blend_mode);
+            }
+            if mode == "Custom Quad"
+            {
+                mode = "QuadCustom".to_string() + &("\n".to_string() + &child.custom_blend_mode);
+            }
+            let opacity = child.opacity;
+            let fill_opacity = child.fill_opacity;
+            let child_clipped = child.clipped;
+            let child_funny_flag = child.funny_flag;
+            let mut child_fx = child.effects.clone().into_iter().collect::<Vec<_>>();
+            child_fx.sort_by_key(|a| 
+                match a.0.as_str()
                 {
-                    mode += &("\n".to_string() + &child.custom_blend_mode);
-                    
-                    // example custom blend mode: hard mix
-                    
-                    // n = b + a*fill_opacity;
-                    // n = n - 0.5;
-                    // n = n - (fill_opacity*0.5);
-                    // n = n / (1.0 - fill_opacity);
-                    // n = n + 0.5;
-                    // clamp(n, 0.0, 1.0)
-                    
-                    // bad hard mix (e.g. similar to the krita/gimp implementation)
-                    
-                    // n = b + a;
-                    // n = n * 0.5;
-                    // n = ~n;
-                    // clamp(n, 0.0, 1.0)
-                    
-                    // photoshop-accurate linear dodge/add (TODO)
-                    
-                    // clamp(b+a*fill_opacity, 0, 1)
+                    "dropshadow" => 0,
+                    "outerglow" => 1,
+                    "patternfill" => 2,
+                    "gradfill" => 3,
+                    "colorfill" => 4,
+                    "satin" => 5,
+                    "innerglow" => 6,
+                    "stroke" => 7,
+                    "bevel" => 8,
+                    _ => 0,
                 }
-                if mode == "Custom Tri"
+            );
+            
+            //println!("???{:?}", self.offset);
+            let mut above_offset = [0, 0];
+            if child.data.is_some()
+            {
+                above_offset = [child.offset[0] as isize, child.offset[1] as isize];
+            }
+            
+            //let source_data = child.flatten(canvas_width, canvas_height, override_uuid, override_data);
+            child.flatten(canvas_width, canvas_height, override_uuid, override_data);
+            let source_data = if child.would_override(override_uuid, override_data)
+            {
+                override_data.unwrap()
+            }
+            else if child.flattened_data.is_some()
+            {
+                child.flattened_data.as_ref().unwrap()
+            }
+            else
+            {
+                child.data.as_ref().unwrap()
+            };
+            
+            #[allow(clippy::unnecessary_unwrap)] // broken lint
+            if above.is_some() && above.as_ref().unwrap().clipped && !child_clipped && !child.adjustment.is_some()
+            {
+                // child is a clip target, get into clip target mode
+                // for color
+                stash = Some(source_data.clone());
+                stash_mask = child.mask.clone();
+                stash_mask_info = child.mask_info.clone();
+                stash_vector_mask = child.vector_mask.clone();
+                stash_offs = above_offset;
+                // remove alpha
+                stash.as_mut().unwrap().clear_rect_alpha_float(new_dirty_rect, 1.0);
+                // for alpha, we restore the color bit's alpha with this later
+                stash_clean = Some(source_data.clone());
+                _stash_is_first = first;
+                stash_opacity = opacity;
+                stash_fill_opacity = fill_opacity;
+                stash_funny_flag = child_funny_flag;
+                stash_blend_mode = mode.clone();
+                
+                let mut rect = new_dirty_rect;
+                rect[0][0] -= above_offset[0] as f32;
+                rect[0][1] -= above_offset[1] as f32;
+                rect[1][0] -= above_offset[0] as f32;
+                rect[1][1] -= above_offset[1] as f32;
+                
+                // blend top into it
+                let above = above.unwrap();
+                above_offset[0] = above.offset[0] as isize - stash_offs[0];
+                above_offset[1] = above.offset[1] as isize - stash_offs[1];
+                let above_opacity = above.opacity;
+                let above_funny_flag = above.funny_flag;
+                let above_fill_opacity = above.fill_opacity;
+                let above_mode = &above.blend_mode.clone();
+                //let above_data = above.flatten(canvas_width, canvas_height, override_uuid, override_data);
+                
+                if let Some(adjustment) = &above.adjustment
                 {
-                    mode = "TriCustom".to_string() + &("\n".to_string() + &child.custom_blend_mode);
+                    stash.as_mut().unwrap().apply_adjustment(rect, &adjustment, above.mask.as_ref(), above.mask_info.as_ref(), above.get_vector_mask(), above_opacity, above_fill_opacity, above_funny_flag, above_offset, above_mode);
                 }
-                if mode == "Custom Quad"
+                else
                 {
-                    mode = "QuadCustom".to_string() + &("\n".to_string() + &child.custom_blend_mode);
-                }
-                let opacity = child.opacity;
-                let fill_opacity = child.fill_opacity;
-                let child_clipped = child.clipped;
-                let child_funny_flag = child.funny_flag;
-                let mut child_fx = child.effects.clone().into_iter().collect::<Vec<_>>();
-                child_fx.sort_by_key(|a| 
-                    match a.0.as_str()
+                    above.flatten(canvas_width, canvas_height, override_uuid, override_data);
+                    let above_data = if above.would_override(override_uuid, override_data)
                     {
-                        "dropshadow" => 0,
-                        "gradfill" => 1,
-                        "colorfill" => 2,
-                        "stroke" => 3,
-                        _ => 0,
+                        override_data.unwrap()
                     }
-                );
-                
-                //println!("???{:?}", self.offset);
-                let mut above_offset = [0, 0];
-                if child.data.is_some()
-                {
-                    above_offset = [child.offset[0] as isize, child.offset[1] as isize];
+                    else if above.flattened_data.is_some()
+                    {
+                        above.flattened_data.as_ref().unwrap()
+                    }
+                    else
+                    {
+                        above.data.as_ref().unwrap()
+                    };
+                    
+                    stash.as_mut().unwrap().blend_rect_from(rect, above_data, above.mask.as_ref(), above.mask_info.as_ref(), above.get_vector_mask(), above_opacity, above_fill_opacity, above_funny_flag, above_offset, above_mode);
                 }
+            }
+            else if stash.is_some() && (above.is_none() || !above.as_ref().unwrap().clipped)
+            {
+                // done with the clipping mask sequence, blend into rest of group
+                let mut rect = new_dirty_rect;
+                rect[0][0] -= stash_offs[0] as f32;
+                rect[0][1] -= stash_offs[1] as f32;
+                rect[1][0] -= stash_offs[0] as f32;
+                rect[1][1] -= stash_offs[1] as f32;
                 
-                //let source_data = child.flatten(canvas_width, canvas_height, override_uuid, override_data);
-                child.flatten(canvas_width, canvas_height, override_uuid, override_data);
-                let source_data = if child.would_override(override_uuid, override_data)
-                {
-                    override_data.unwrap()
-                }
-                else if child.flattened_data.is_some()
+                // restore original alpha
+                stash.as_mut().unwrap().blend_rect_from(rect, stash_clean.as_ref().unwrap(), None, None, None, stash_opacity, stash_fill_opacity, stash_funny_flag, [0, 0], "Clip Alpha");
+                //let s2 = stash.as_mut().unwrap().clone();
+                //stash.as_mut().unwrap().apply_fx_dummy_outline(rect, Some(s2).as_ref(), None, None, stash_opacity, stash_fill_opacity, stash_funny_flag, [0, 0], "Normal");
+                
+                above_offset = stash_offs;
+                
+                target.as_mut().unwrap().blend_rect_from(new_dirty_rect, stash.as_ref().unwrap(), stash_mask.as_ref(), stash_mask_info.as_ref(), stash_vector_mask.as_ref().map(|(mask, info)| (mask, info)), stash_opacity, stash_fill_opacity, stash_funny_flag, above_offset, &stash_blend_mode);
+                
+                stash = None;
+                stash_clean = None;
+                stash_mask = None;
+                stash_mask_info = None;
+                stash_vector_mask = None;
+            }
+            else if let (Some(above), Some(ref mut stash)) = (above, stash.as_mut()) // above.is_some() is redundant with the above if branch, but left in for clarity
+            {
+                // continuing a clip mask blend
+                let above_opacity = above.opacity;
+                let above_fill_opacity = above.fill_opacity;
+                let above_funny_flag = above.funny_flag;
+                above_offset[0] = above.offset[0] as isize - stash_offs[0];
+                above_offset[1] = above.offset[1] as isize - stash_offs[1];
+                let above_mode = &above.blend_mode.clone();
+                
+                let mut rect = new_dirty_rect;
+                rect[0][0] -= stash_offs[0] as f32;
+                rect[0][1] -= stash_offs[1] as f32;
+                rect[1][0] -= stash_offs[0] as f32;
+                rect[1][1] -= stash_offs[1] as f32;
+                
+                if let Some(adjustment) = &above.adjustment
                 {
-                    child.flattened_data.as_ref().unwrap()
+                    stash.apply_adjustment(rect, &adjustment, above.mask.as_ref(), above.mask_info.as_ref(), above.get_vector_mask(), above_opacity, above_fill_opacity, above_funny_flag, above_offset, above_mode);
                 }
                 else
                 {
-                    child.data.as_ref().unwrap()
-                };
-                
-                #[allow(clippy::unnecessary_unwrap)] // broken lint
-                if above.is_some() && above.as_ref().unwrap().clipped && !child_clipped && !child.adjustment.is_some()
-                {
-                    // child is a clip target, get into clip target mode
-                    // for color
-                    stash = Some(source_data.clone());
-                    stash_mask = child.mask.clone();
-                    stash_mask_info = child.mask_info.clone();
-                    stash_offs = above_offset;
-                    // remove alpha
-                    stash.as_mut().unwrap().clear_rect_alpha_float(new_dirty_rect, 1.0);
-                    // for alpha, we restore the color bit's alpha with this later
-                    stash_clean = Some(source_data.clone());
-                    _stash_is_first = first;
-                    stash_opacity = opacity;
-                    stash_fill_opacity = fill_opacity;
-                    stash_funny_flag = child_funny_flag;
-                    stash_blend_mode = mode.clone();
-                    
-                    let mut rect = new_dirty_rect;
-                    rect[0][0] -= above_offset[0] as f32;
-                    rect[0][1] -= above_offset[1] as f32;
-                    rect[1][0] -= above_offset[0] as f32;
-                    rect[1][1] -= above_offset[1] as f32;
-                    
-                    // blend top into it
-                    let above = above.unwrap();
-                    above_offset[0] = above.offset[0] as isize - stash_offs[0];
-                    above_offset[1] = above.offset[1] as isize - stash_offs[1];
-                    let above_opacity = above.opacity;
-                    let above_funny_flag = above.funny_flag;
-                    let above_fill_opacity = above.fill_opacity;
-                    let above_mode = &above.blend_mode.clone();
-                    //let above_data = above.flatten(canvas_width, canvas_height, override_uuid, override_data);
-                    
-                    if let Some(adjustment) = &above.adjustment
+                    above.flatten(canvas_width, canvas_height, override_uuid, override_data);
+                    let above_data = if above.would_override(override_uuid, override_data)
                     {
-                        stash.as_mut().unwrap().apply_adjustment(rect, &adjustment, above.mask.as_ref(), above.mask_info.as_ref(), above_opacity, above_fill_opacity, above_funny_flag, above_offset, above_mode);
+                        override_data.unwrap()
                     }
-                    else
+                    else if above.flattened_data.is_some()
                     {
-                        above.flatten(canvas_width, canvas_height, override_uuid, override_data);
-                        let above_data = if above.would_override(override_uuid, override_data)
-                        {
-                            override_data.unwrap()
-                        }
-                        else if above.flattened_data.is_some()
-                        {
-                            above.flattened_data.as_ref().unwrap()
-                        }
-                        else
-                        {
-                            above.data.as_ref().unwrap()
-                        };
-                        
-                        stash.as_mut().unwrap().blend_rect_from(rect, above_data, above.mask.as_ref(), above.mask_info.as_ref(), above_opacity, above_fill_opacity, above_funny_flag, above_offset, above_mode);
+                        above.flattened_data.as_ref().unwrap()
                     }
+                    else
+                    {
+                        above.data.as_ref().unwrap()
+                    };
+                    stash.blend_rect_from(rect, above_data, above.mask.as_ref(), above.mask_info.as_ref(), above.get_vector_mask(), above_opacity, above_fill_opacity, above_funny_flag, above_offset, above_mode);
                 }
-                else if stash.is_some() && (above.is_none() || !above.as_ref().unwrap().clipped)
+            }
+            else
+            {
+                if let Some(adjustment) = &child.adjustment
                 {
-                    // done with the clipping mask sequence, blend into rest of group
-                    let mut rect = new_dirty_rect;
-                    rect[0][0] -= stash_offs[0] as f32;
-                    rect[0][1] -= stash_offs[1] as f32;
-                    rect[1][0] -= stash_offs[0] as f32;
-                    rect[1][1] -= stash_offs[1] as f32;
-                    
-                    // restore original alpha
-                    stash.as_mut().unwrap().blend_rect_from(rect, stash_clean.as_ref().unwrap(), None, None, stash_opacity, stash_fill_opacity, stash_funny_flag, [0, 0], "Clip Alpha");
-                    //let s2 = stash.as_mut().unwrap().clone();
-                    //stash.as_mut().unwrap().apply_fx_dummy_outline(rect, Some(s2).as_ref(), None, None, stash_opacity, stash_fill_opacity, stash_funny_flag, [0, 0], "Normal");
-                    
-                    above_offset = stash_offs;
-                    
-                    self.flattened_data.as_mut().unwrap().blend_rect_from(new_dirty_rect, stash.as_ref().unwrap(), stash_mask.as_ref(), stash_mask_info.as_ref(), stash_opacity, stash_fill_opacity, stash_funny_flag, above_offset, &stash_blend_mode);
-                    
-                    stash = None;
-                    stash_clean = None;
-                    stash_mask = None;
-                    stash_mask_info = None;
+                    target.as_mut().unwrap().apply_adjustment(new_dirty_rect, &adjustment, child.mask.as_ref(), child.mask_info.as_ref(), child.get_vector_mask(), opacity, fill_opacity, child.funny_flag, above_offset, &mode);
                 }
-                else if let (Some(above), Some(ref mut stash)) = (above, stash.as_mut()) // above.is_some() is redundant with the above if branch, but left in for clarity
+                else
                 {
-                    // continuing a clip mask blend
-                    let above_opacity = above.opacity;
-                    let above_fill_opacity = above.fill_opacity;
-                    let above_funny_flag = above.funny_flag;
-                    above_offset[0] = above.offset[0] as isize - stash_offs[0];
-                    above_offset[1] = above.offset[1] as isize - stash_offs[1];
-                    let above_mode = &above.blend_mode.clone();
-                    
-                    let mut rect = new_dirty_rect;
-                    rect[0][0] -= stash_offs[0] as f32;
-                    rect[0][1] -= stash_offs[1] as f32;
-                    rect[1][0] -= stash_offs[0] as f32;
-                    rect[1][1] -= stash_offs[1] as f32;
-                    
-                    if let Some(adjustment) = &above.adjustment
+                    use crate::layers_fxblend::*;
+                    blend_with_fx(target, &mut new_dirty_rect, above_offset, source_data,
+                        child, child_fx, opacity, fill_opacity, child_clipped, child_funny_flag, mode);
+                }
+            }
+            first = false;
+        }
+    }
+    pub(crate) fn is_pass_through(&self) -> bool
+    {
+        // groups with effects still need an isolated buffer to draw the effects from
+        self.is_group() && self.adjustment.is_none() && self.blend_mode == "Pass Through" && self.effects.is_empty()
+    }
+    // composites a pass-through group's children directly onto the backdrop under it, like photoshop does
+    fn flatten_pass_through(&mut self, target : &mut Option<Image<4>>, new_dirty_rect : [[f32; 2]; 2], canvas_width : usize, canvas_height : usize, override_uuid : Option<u128>, override_data : Option<&Image<4>>)
+    {
+        // never used as an isolated buffer, so don't keep one around
+        self.flattened_data = None;
+        if self.opacity >= 1.0 && self.mask.is_none() && self.vector_mask.is_none()
+        {
+            Self::flatten_children_into(&mut self.children, target, new_dirty_rect, canvas_width, canvas_height, override_uuid, override_data);
+        }
+        else
+        {
+            // opacity and masks fade between the untouched backdrop and the composited one
+            let mut composite = target.clone();
+            Self::flatten_children_into(&mut self.children, &mut composite, new_dirty_rect, canvas_width, canvas_height, override_uuid, override_data);
+            target.as_mut().unwrap().blend_rect_from(new_dirty_rect, composite.as_ref().unwrap(), self.mask.as_ref(), self.mask_info.as_ref(), self.get_vector_mask(), self.opacity, 1.0, self.funny_flag, [0, 0], "Interpolate");
+        }
+        self.flattened_dirty_rect = None;
+    }
+    // renders a smart object's original at its current transform, instead of resampling already-resampled pixels
+    pub(crate) fn rasterize_smart_object(&mut self) -> bool
+    {
+        let Some(src) = self.smart_object.as_ref().and_then(|x| x.decode()) else { return false };
+        let smart = self.smart_object.as_mut().unwrap();
+        let t = smart.transform;
+        let corners = [[t[0], t[1]], [t[2], t[3]], [t[4], t[5]], [t[6], t[7]]];
+        let mut rect = [corners[0], corners[0]];
+        for c in corners
+        {
+            rect = rect_enclose_point(rect, c);
+        }
+        let min = vec_floor(&rect[0]);
+        let w = (rect[1][0] - min[0]).ceil().max(1.0) as usize;
+        let h = (rect[1][1] - min[1]).ceil().max(1.0) as usize;
+        
+        // affine, from the top left, top right and bottom left corners
+        let u = [(corners[1][0] - corners[0][0]) / src.width as f32, (corners[1][1] - corners[0][1]) / src.width as f32];
+        let v = [(corners[3][0] - corners[0][0]) / src.height as f32, (corners[3][1] - corners[0][1]) / src.height as f32];
+        let det = u[0] * v[1] - u[1] * v[0];
+        if det.abs() < 0.000001
+        {
+            return false;
+        }
+        // supersample when shrinking
+        let n = (1.0 / length(&u).min(length(&v))).ceil().clamp(1.0, 4.0) as usize;
+        let origin = vec_sub(&corners[0], &min);
+        
+        let mut img = if src.is_float() { Image::<4>::blank_float(w, h) } else { Image::<4>::blank(w, h) };
+        img.loop_rect_threaded([[0.0, 0.0], [w as f32, h as f32]], &|x, y, _color : [f32; 4]|
+        {
+            let mut sum = [0.0; 4];
+            for sy in 0..n
+            {
+                for sx in 0..n
+                {
+                    let px = x as f32 + (sx as f32 + 0.5) / n as f32 - origin[0];
+                    let py = y as f32 + (sy as f32 + 0.5) / n as f32 - origin[1];
+                    let src_x = (px * v[1] - py * v[0]) / det;
+                    let src_y = (u[0] * py - u[1] * px) / det;
+                    if src_x < 0.0 || src_y < 0.0 || src_x > src.width as f32 || src_y > src.height as f32
                     {
-                        stash.apply_adjustment(rect, &adjustment, above.mask.as_ref(), above.mask_info.as_ref(), above_opacity, above_fill_opacity, above_funny_flag, above_offset, above_mode);
+                        continue;
                     }
-                    else
+                    // clamped so the edges don't fade out when enlarging
+                    let c = src.get_pixel_float_lerped((src_x - 0.5).clamp(0.0, src.width as f32 - 1.0), (src_y - 0.5).clamp(0.0, src.height as f32 - 1.0));
+                    for i in 0..3
                     {
-                        above.flatten(canvas_width, canvas_height, override_uuid, override_data);
-                        let above_data = if above.would_override(override_uuid, override_data)
-                        {
-                            override_data.unwrap()
-                        }
-                        else if above.flattened_data.is_some()
-                        {
-                            above.flattened_data.as_ref().unwrap()
-                        }
-                        else
-                        {
-                            above.data.as_ref().unwrap()
-                        };
-                        stash.blend_rect_from(rect, above_data, above.mask.as_ref(), above.mask_info.as_ref(), above_opacity, above_fill_opacity, above_funny_flag, above_offset, above_mode);
+                        sum[i] += c[i] * c[3];
                     }
+                    sum[3] += c[3];
                 }
-                else
+            }
+            if sum[3] <= 0.0
+            {
+                return [0.0; 4];
+            }
+            [sum[0] / sum[3], sum[1] / sum[3], sum[2] / sum[3], sum[3] / (n * n) as f32]
+        });
+        
+        for i in 0..4
+        {
+            smart.transform[i * 2] -= min[0];
+            smart.transform[i * 2 + 1] -= min[1];
+        }
+        self.offset = vec_add(&self.offset, &min);
+        self.data = Some(img);
+        self.dirtify_full_rect();
+        true
+    }
+    // scales a smart object around its center and re-renders it from the original
+    pub(crate) fn scale_smart_object(&mut self, scale : [f32; 2]) -> bool
+    {
+        let Some(smart) = &mut self.smart_object else { return false };
+        let t = &mut smart.transform;
+        let center = [(t[0] + t[2] + t[4] + t[6]) * 0.25, (t[1] + t[3] + t[5] + t[7]) * 0.25];
+        for i in 0..4
+        {
+            t[i * 2] = (t[i * 2] - center[0]) * scale[0] + center[0];
+            t[i * 2 + 1] = (t[i * 2 + 1] - center[1]) * scale[1] + center[1];
+        }
+        self.dirtify_all();
+        let ret = self.rasterize_smart_object();
+        self.dirtify_all();
+        ret
+    }
+    // changes a text layer's text, and re-renders it with the bundled font
+    pub(crate) fn edit_text(&mut self, text : &str) -> bool
+    {
+        let Some(info) = &mut self.text else { return false };
+        info.set_text(text);
+        info.edited = true;
+        self.dirtify_all();
+        let ret = self.rasterize_text();
+        self.dirtify_all();
+        ret
+    }
+    pub(crate) fn rasterize_text(&mut self) -> bool
+    {
+        use ab_glyph::{Font, FontRef, OutlineCurve};
+        let Some(info) = &mut self.text else { return false };
+        let Ok(font) = FontRef::try_from_slice(crate::bundled_font()) else { return false };
+        let units = font.units_per_em().unwrap_or(1000.0);
+        let t = info.transform;
+        let apply = |p : [f32; 2]| [t[0] * p[0] + t[2] * p[1] + t[4], t[1] * p[0] + t[3] * p[1] + t[5]];
+        
+        // glyph outlines as bezier loops relative to the layer offset, grouped by color
+        let mut shapes : Vec<([f32; 4], Vec<BezierShape>)> = vec!();
+        let default = TextRun { length : 0, font : "".to_string(), size : 12.0, color : [0.0, 0.0, 0.0, 1.0] };
+        let mut run_index = 0;
+        let mut run_end = info.runs.first().map(|x| x.length).unwrap_or(usize::MAX);
+        let mut offset = 0;
+        let mut baseline = 0.0;
+        let mut pen = 0.0;
+        let mut line_size : f32 = 0.0;
+        let mut prev = None;
+        for c in info.text.chars().chain(std::iter::once('\n'))
+        {
+            while offset >= run_end && run_index + 1 < info.runs.len()
+            {
+                run_index += 1;
+                run_end += info.runs[run_index].length;
+            }
+            let run = info.runs.get(run_index).unwrap_or(&default);
+            offset += c.len_utf16();
+            line_size = line_size.max(run.size);
+            if c == '\n'
+            {
+                // photoshop's automatic leading
+                baseline += line_size * 1.2;
+                (pen, line_size, prev) = (0.0, 0.0, None);
+                continue;
+            }
+            
+            let scale = run.size / units;
+            let id = font.glyph_id(c);
+            if let Some(prev) = prev
+            {
+                pen += font.kern_unscaled(prev, id) * scale;
+            }
+            prev = Some(id);
+            if let Some(outline) = font.outline(id)
+            {
+                // font units point up
+                let to_canvas = |p : ab_glyph::Point| apply([pen + p.x * scale, baseline - p.y * scale]);
+                let mut loops = vec!();
+                let mut segments : Vec<[[f32; 2]; 4]> = vec!();
+                let mut last = None;
+                for curve in outline.curves.iter()
                 {
-                    if let Some(adjustment) = &child.adjustment
+                    // everything as cubics
+                    let (start, end, segment) = match *curve
                     {
-                        self.flattened_data.as_mut().unwrap().apply_adjustment(new_dirty_rect, &adjustment, child.mask.as_ref(), child.mask_info.as_ref(), opacity, fill_opacity, child.funny_flag, above_offset, &mode);
-                    }
-                    else
+                        OutlineCurve::Line(a, b) => (a, b, [to_canvas(a), to_canvas(a), to_canvas(b), to_canvas(b)]),
+                        OutlineCurve::Quad(a, b, c) =>
+                        {
+                            let (p, q, r) = (to_canvas(a), to_canvas(b), to_canvas(c));
+                            let lerp = |p : [f32; 2], q : [f32; 2]| [p[0] + (q[0] - p[0]) * 2.0 / 3.0, p[1] + (q[1] - p[1]) * 2.0 / 3.0];
+                            (a, c, [p, lerp(p, q), lerp(r, q), r])
+                        }
+                        OutlineCurve::Cubic(a, b, c, d) => (a, d, [to_canvas(a), to_canvas(b), to_canvas(c), to_canvas(d)]),
+                    };
+                    // a new contour starts wherever the last one didn't end
+                    if last != Some(start) && !segments.is_empty()
                     {
-                        use crate::layers_fxblend::*;
-                        blend_with_fx(&mut self.flattened_data, &mut new_dirty_rect, above_offset, source_data,
-                            child, child_fx, opacity, fill_opacity, child_clipped, child_funny_flag, mode);
+                        loops.push(segments_to_knots(&segments));
+                        segments.clear();
                     }
+                    last = Some(end);
+                    segments.push(segment);
+                }
+                if !segments.is_empty()
+                {
+                    loops.push(segments_to_knots(&segments));
+                }
+                match shapes.iter_mut().find(|x| x.0 == run.color)
+                {
+                    Some(x) => x.1.push((1, loops)),
+                    None => shapes.push((run.color, vec!((1, loops)))),
                 }
-                first = false;
             }
-            //println!("--ASDFASDFASDF {:.6}ms", start.elapsed().as_secs_f64() * 1000.0);
-            self.flattened_dirty_rect = None;
-            return self.flattened_data.as_ref().unwrap();
+            pen += font.h_advance_unscaled(id) * scale;
         }
+        
+        let points = shapes.iter().flat_map(|x| x.1.iter()).flat_map(|x| x.1.iter()).flatten().flatten();
+        let mut rect = [[f32::INFINITY; 2], [f32::NEG_INFINITY; 2]];
+        for p in points
+        {
+            rect = rect_enclose_point(rect, *p);
+        }
+        if rect[0][0] > rect[1][0]
+        {
+            rect = [[0.0, 0.0], [0.0, 0.0]];
+        }
+        let min = vec_floor(&rect[0]);
+        let w = (rect[1][0] - min[0]).ceil().max(1.0) as usize;
+        let h = (rect[1][1] - min[1]).ceil().max(1.0) as usize;
+        
+        let mut img = Image::<4>::blank(w, h);
+        for (color, mut loops) in shapes
+        {
+            for knot in loops.iter_mut().flat_map(|x| x.1.iter_mut()).flatten().flatten()
+            {
+                *knot = vec_sub(knot, &min);
+            }
+            let coverage = Image::<1>::from_bezier_loops(w, h, &loops, false);
+            img.loop_rect_threaded([[0.0, 0.0], [w as f32, h as f32]], &|x, y, under : [f32; 4]|
+            {
+                let a = coverage.get_pixel_float(x as isize, y as isize)[0] * color[3];
+                let out_a = a + under[3] * (1.0 - a);
+                if out_a <= 0.0
+                {
+                    return [0.0; 4];
+                }
+                let mix = |i : usize| (color[i] * a + under[i] * under[3] * (1.0 - a)) / out_a;
+                [mix(0), mix(1), mix(2), out_a]
+            });
+        }
+        
+        info.transform[4] -= min[0];
+        info.transform[5] -= min[1];
+        self.offset = vec_add(&self.offset, &min);
+        self.data = Some(img);
+        self.dirtify_full_rect();
+        true
     }
     pub(crate) fn flatten_get_cached(&self) -> Option<&Image<4>>
     {
@@ -1145,3 +1618,104 @@ impl Layer
         });
     }
 }
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    
+    #[test]
+    pub fn test_pass_through()
+    {
+        let mut img = Image::<4>::blank(4, 4);
+        img.clear_rect_with_color_float([[0.0, 0.0], [4.0, 4.0]], [0.25, 0.5, 1.0, 1.0]);
+        let bottom = Layer::new_layer_from_image("Bottom", img);
+        let mut invert = Layer::new_layer("Invert", 0, 0);
+        invert.adjustment = Some(Adjustment::Invert);
+        let mut group = Layer::new_group("Group");
+        group.children.push(invert);
+        
+        let mut root = Layer::new_group("___root___");
+        root.children = vec!(group, bottom);
+        
+        // an isolated group's adjustments only see the group's own (empty) contents
+        assert_eq!(root.flatten_as_root(4, 4, None, None).get_pixel(1, 1), [64, 128, 255, 255]);
+        
+        root.children[0].blend_mode = "Pass Through".to_string();
+        root.children[0].dirtify_all();
+        assert_eq!(root.flatten_as_root(4, 4, None, None).get_pixel(1, 1), [191, 127, 0, 255]);
+        
+        // edits below the group get redrawn through it
+        let bottom = &mut root.children[1];
+        bottom.data.as_mut().unwrap().set_pixel(2, 2, [0, 0, 0, 255]);
+        bottom.dirtify_point([2.0, 2.0]);
+        let out = root.flatten_as_root(4, 4, None, None);
+        assert_eq!(out.get_pixel(2, 2), [255, 255, 255, 255]);
+        assert_eq!(out.get_pixel(1, 1), [191, 127, 0, 255]);
+        
+        root.children[0].opacity = 0.5;
+        root.children[0].dirtify_all();
+        let c = root.flatten_as_root(4, 4, None, None).get_pixel(1, 1);
+        assert!(c.iter().zip([128, 128, 128, 255]).all(|(a, b)| (*a as i32 - b).abs() <= 1), "{:?}", c);
+    }
+    
+    #[test]
+    pub fn test_float_groups()
+    {
+        let mut inner = Layer::new_group("Inner");
+        inner.children.push(Layer::new_layer_from_image("Float", Image::<4>::blank_float(4, 4)));
+        let mut outer = Layer::new_group("Outer");
+        outer.children.push(inner);
+        let mut root = Layer::new_group("___root___");
+        root.children = vec!(outer, Layer::new_layer("Bottom", 4, 4));
+        
+        // every group the float layer is in gets a float buffer
+        assert!(root.flatten_as_root(4, 4, None, None).is_float());
+        assert!(root.children[0].flattened_data.as_ref().unwrap().is_float());
+        assert!(root.children[0].children[0].flattened_data.as_ref().unwrap().is_float());
+        
+        // and goes back to 8 bits once it's gone
+        let float = &mut root.children[0].children[0].children[0];
+        float.data = Some(Image::<4>::blank(4, 4));
+        float.dirtify_all();
+        assert!(!root.flatten_as_root(4, 4, None, None).is_float());
+        assert!(!root.children[0].has_float);
+        assert!(!root.children[0].children[0].flattened_data.as_ref().unwrap().is_float());
+    }
+    
+    #[test]
+    pub fn test_smart_object_scaling()
+    {
+        let mut src = image::RgbaImage::new(8, 8);
+        for (x, y, px) in src.enumerate_pixels_mut()
+        {
+            *px = image::Rgba([(x * 32) as u8, (y * 32) as u8, ((x ^ y) * 32) as u8, 255]);
+        }
+        let mut png = vec!();
+        src.write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png).unwrap();
+        
+        let mut layer = Layer::new_layer("Smart", 8, 8);
+        layer.offset = [10.0, 20.0];
+        layer.smart_object = Some(SmartObject { name : "src.png".to_string(), data : png, transform : [0.0, 0.0, 8.0, 0.0, 8.0, 8.0, 0.0, 8.0] });
+        assert!(layer.rasterize_smart_object());
+        let original = layer.data.as_ref().unwrap().clone();
+        assert_eq!(original.get_pixel(3, 5), [96, 160, 192, 255]);
+        
+        assert!(layer.scale_smart_object([0.5, 0.5]));
+        assert_eq!(layer.offset, [12.0, 22.0]);
+        assert_eq!(layer.data.as_ref().unwrap().width, 4);
+        
+        // growing back renders from the original, so nothing is lost
+        assert!(layer.scale_smart_object([2.0, 2.0]));
+        assert_eq!(layer.offset, [10.0, 20.0]);
+        assert_eq!(layer.data.as_ref().unwrap().bytes(), original.bytes());
+        
+        // and undoing a scale through the layer info re-renders too
+        let mut info = layer.get_info();
+        layer.scale_smart_object([2.0, 1.0]);
+        assert_eq!(layer.data.as_ref().unwrap().width, 16);
+        info.offset = [10.0, 20.0];
+        layer.set_info(&info);
+        assert_eq!(layer.data.as_ref().unwrap().bytes(), original.bytes());
+    }
+}
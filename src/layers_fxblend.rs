@@ -40,6 +40,8 @@ pub (crate) fn blend_with_fx(
     }
     if real_count > 0
     {
+        let child_fx = child_fx.into_iter().flat_map(fx_expand).collect::<Vec<_>>();
+        
         // CLONE
         let mut fill = flattened_data.clone().unwrap();
         let mut dropshadow = None;
@@ -50,10 +52,10 @@ pub (crate) fn blend_with_fx(
         
         let mut rect = *new_dirty_rect;
         
-        // dropshadow needs special handling; the basic layer blend mode blends on top of it instead of below
+        // dropshadow, outer glow and outer bevels need special handling; the basic layer blend mode blends on top of them instead of below
         for fx in child_fx.iter()
         {
-            if fx.0 != "dropshadow".to_string()
+            if !fx_is_underlay(fx)
             {
                 continue;
             }
@@ -84,16 +86,15 @@ pub (crate) fn blend_with_fx(
             let mut overlay = fill.clone();
             
             let mut data = source_data.alike_grown(r_int as usize);
-            data.apply_fx(rect_shifted, &fx, Some(source_data), child.mask.as_ref(), child.mask_info.as_ref(), 1.0, 1.0, child.funny_flag, [r_int, r_int], "Normal");
-            overlay.blend_rect_from(rect, &data, None, None, 1.0, 1.0, false, offset2, &fx_mode);
+            data.apply_fx(rect_shifted, &fx, Some(source_data), child.mask.as_ref(), child.mask_info.as_ref(), child.get_vector_mask(), 1.0, 1.0, child.funny_flag, [r_int, r_int], "Normal");
+            overlay.blend_rect_from(rect, &data, None, None, None, 1.0, 1.0, false, offset2, &fx_mode);
             
             // FIXME: use separate alpha and mask
-            full_mask.blend_rect_from(rect, &data, None, None, 1.0, 1.0, false, offset2, "Weld");
-            fill.blend_rect_from(rect, &overlay, None, None, fx_opacity, 1.0, false, [0, 0], &weld_func);
+            full_mask.blend_rect_from(rect, &data, None, None, None, 1.0, 1.0, false, offset2, "Weld");
+            fill.blend_rect_from(rect, &overlay, None, None, None, fx_opacity, 1.0, false, [0, 0], &weld_func);
             
             // CLONE
             dropshadow = Some(fill.clone());
-            break;
         }
         
         let rect_shifted = rect_translate(rect, [-above_offset[0] as f32, -above_offset[1] as f32]);
@@ -101,16 +102,16 @@ pub (crate) fn blend_with_fx(
         let mut source = source_data.clone();
         source.clear_rect_alpha_float(rect_shifted, 1.0);
         
-        fill.blend_rect_from(rect, &source, None, None, 1.0, fill_opacity, child.funny_flag, above_offset, &mode);
+        fill.blend_rect_from(rect, &source, None, None, None, 1.0, fill_opacity, child.funny_flag, above_offset, &mode);
         
-        fill_mask.blend_rect_from(rect, &source_data, child.mask.as_ref(), child.mask_info.as_ref(), 1.0, 1.0, false, above_offset, "Copy");
-        full_mask.blend_rect_from(rect, &source_data, child.mask.as_ref(), child.mask_info.as_ref(), 1.0, 1.0, false, above_offset, "Normal");
+        fill_mask.blend_rect_from(rect, &source_data, child.mask.as_ref(), child.mask_info.as_ref(), child.get_vector_mask(), 1.0, 1.0, false, above_offset, "Copy");
+        full_mask.blend_rect_from(rect, &source_data, child.mask.as_ref(), child.mask_info.as_ref(), child.get_vector_mask(), 1.0, 1.0, false, above_offset, "Normal");
         
         let mut fill_masking_performed = false;
         
         for mut fx in child_fx
         {
-            if fx.0 == "_enabled".to_string() || fx.0 == "_scale".to_string() || fx.0 == "dropshadow".to_string()
+            if fx.0 == "_enabled".to_string() || fx.0 == "_scale".to_string() || fx_is_underlay(&fx)
             {
                 continue;
             }
@@ -138,7 +139,7 @@ pub (crate) fn blend_with_fx(
             
             // CLONE
             let mut data = source_data.alike_grown(r_int as usize);
-            data.apply_fx(rect_shifted, &fx, Some(source_data), child.mask.as_ref(), child.mask_info.as_ref(), 1.0, 1.0, child.funny_flag, [r_int, r_int], "Normal");
+            data.apply_fx(rect_shifted, &fx, Some(source_data), child.mask.as_ref(), child.mask_info.as_ref(), child.get_vector_mask(), 1.0, 1.0, child.funny_flag, [r_int, r_int], "Normal");
             
             let offset2 = [above_offset[0] - r_int, above_offset[1] - r_int];
             
@@ -153,80 +154,256 @@ pub (crate) fn blend_with_fx(
             let mut overlay = if !fx_is_fill(&fx) { if let Some(ds) = &dropshadow { ds.clone() } else { flattened_data.clone().unwrap() } } else { fill.clone() };
             // CLONE
             let mut overlay_mask = overlay.alike();
-            overlay_mask.blend_rect_from(rect, &data, child.mask.as_ref(), child.mask_info.as_ref(), 1.0, 1.0, false, offset2, "Copy");
+            overlay_mask.blend_rect_from(rect, &data, child.mask.as_ref(), child.mask_info.as_ref(), child.get_vector_mask(), 1.0, 1.0, false, offset2, "Copy");
             
             if !fx_is_fill(&fx)
             {
                 data.clear_rect_alpha_float(rect_shifted, 1.0);
             }
             //data.clear_rect_alpha_float(rect_shifted, 1.0);
-            overlay.blend_rect_from(rect, &data, child.mask.as_ref(), child.mask_info.as_ref(), 1.0, 1.0, true, offset2, &fx_mode);
+            overlay.blend_rect_from(rect, &data, child.mask.as_ref(), child.mask_info.as_ref(), child.get_vector_mask(), 1.0, 1.0, true, offset2, &fx_mode);
             
             if !fx_is_fill(&fx)
             {
                 if !fill_masking_performed
                 {
                     fill_masking_performed = true;
-                    fill.blend_rect_from(rect, &fill_mask, None, None, 1.0, 1.0, false, [0, 0], "Merge Alpha");
+                    fill.blend_rect_from(rect, &fill_mask, None, None, None, 1.0, 1.0, false, [0, 0], "Merge Alpha");
                     if let Some(ds) = &dropshadow
                     {
                         let mut d2 = ds.clone();
-                        d2.blend_rect_from(rect, &fill, None, None, 1.0, 1.0, false, [0, 0], "Erase");
-                        d2.blend_rect_from(rect, &full_mask, None, None, 1.0, 1.0, false, [0, 0], "Merge Alpha");
-                        fill.blend_rect_from(rect, &d2, None, None, 1.0, 1.0, false, [0, 0], "Normal");
+                        d2.blend_rect_from(rect, &fill, None, None, None, 1.0, 1.0, false, [0, 0], "Erase");
+                        d2.blend_rect_from(rect, &full_mask, None, None, None, 1.0, 1.0, false, [0, 0], "Merge Alpha");
+                        fill.blend_rect_from(rect, &d2, None, None, None, 1.0, 1.0, false, [0, 0], "Normal");
                     }
                 }
             }
             else
             {
                 //overlay.blend_rect_from(rect, &overlay_mask, None, None, 1.0, 1.0, false, [0, 0], "Merge Alpha");
-                fill.blend_rect_from(rect, &overlay, None, None, 1.0, 1.0, false, [0, 0], "Interpolate");
+                fill.blend_rect_from(rect, &overlay, None, None, None, 1.0, 1.0, false, [0, 0], "Interpolate");
                 continue;
             }
             
             if !fx_is_fill(&fx)
             {
-                overlay.blend_rect_from(rect, &overlay_mask, None, None, 1.0, 1.0, false, [0, 0], "Merge Alpha");
+                overlay.blend_rect_from(rect, &overlay_mask, None, None, None, 1.0, 1.0, false, [0, 0], "Merge Alpha");
             }
             
             if !fx_is_fill(&fx)
             {
                 let mut fill2 = fill.clone();
-                full_mask.blend_rect_from(rect, &overlay, None, None, 1.0, 1.0, false, [0, 0], "Erase");
-                full_mask.blend_rect_from(rect, &overlay, None, None, fx_opacity, 1.0, false, [0, 0], &mask_func);
-                fill.blend_rect_from(rect, &overlay, None, None, 1.0, 1.0, false, [0, 0], "Erase");
+                full_mask.blend_rect_from(rect, &overlay, None, None, None, 1.0, 1.0, false, [0, 0], "Erase");
+                full_mask.blend_rect_from(rect, &overlay, None, None, None, fx_opacity, 1.0, false, [0, 0], &mask_func);
+                fill.blend_rect_from(rect, &overlay, None, None, None, 1.0, 1.0, false, [0, 0], "Erase");
                 if let Some(ds) = &dropshadow
                 {
                     let mut d2 = ds.clone();
-                    d2.blend_rect_from(rect, &overlay, None, None, 1.0, 1.0, false, [0, 0], "Clip Alpha");
-                    fill.blend_rect_from(rect, &d2, None, None, 1.0, 1.0, false, [0, 0], "Weld");
+                    d2.blend_rect_from(rect, &overlay, None, None, None, 1.0, 1.0, false, [0, 0], "Clip Alpha");
+                    fill.blend_rect_from(rect, &d2, None, None, None, 1.0, 1.0, false, [0, 0], "Weld");
                 }
-                fill2.blend_rect_from(rect, &overlay, None, None, 1.0, 1.0, false, [0, 0], &weld_func);
-                fill.blend_rect_from(rect, &fill2, None, None, fx_opacity, 1.0, false, [0, 0], "Interpolate");
+                fill2.blend_rect_from(rect, &overlay, None, None, None, 1.0, 1.0, false, [0, 0], &weld_func);
+                fill.blend_rect_from(rect, &fill2, None, None, None, fx_opacity, 1.0, false, [0, 0], "Interpolate");
             }
             else
             {
-                full_mask.blend_rect_from(rect, &overlay, None, None, fx_opacity, 1.0, false, [0, 0], &mask_func);
-                fill.blend_rect_from(rect, &overlay, None, None, fx_opacity, 1.0, false, [0, 0], &weld_func);
+                full_mask.blend_rect_from(rect, &overlay, None, None, None, fx_opacity, 1.0, false, [0, 0], &mask_func);
+                fill.blend_rect_from(rect, &overlay, None, None, None, fx_opacity, 1.0, false, [0, 0], &weld_func);
             }
         }
         
         if !fill_masking_performed
         {
-            fill.blend_rect_from(rect, &fill_mask, None, None, 1.0, 1.0, false, [0, 0], "Merge Alpha");
+            fill.blend_rect_from(rect, &fill_mask, None, None, None, 1.0, 1.0, false, [0, 0], "Merge Alpha");
             if let Some(mut ds) = dropshadow
             {
-                ds.blend_rect_from(rect, &fill, None, None, 1.0, 1.0, false, [0, 0], "Erase");
-                ds.blend_rect_from(rect, &full_mask, None, None, 1.0, 1.0, false, [0, 0], "Merge Alpha");
-                fill.blend_rect_from(rect, &ds, None, None, 1.0, 1.0, false, [0, 0], "Weld");
+                ds.blend_rect_from(rect, &fill, None, None, None, 1.0, 1.0, false, [0, 0], "Erase");
+                ds.blend_rect_from(rect, &full_mask, None, None, None, 1.0, 1.0, false, [0, 0], "Merge Alpha");
+                fill.blend_rect_from(rect, &ds, None, None, None, 1.0, 1.0, false, [0, 0], "Weld");
             }
         }
         
-        flattened_data.as_mut().unwrap().blend_rect_from(rect, &fill, None, None, opacity, 1.0, false, [0, 0], "Alpha Antiblend");
-        flattened_data.as_mut().unwrap().blend_rect_from(rect, &fill, None, None, opacity, 1.0, false, [0, 0], "Blend Weld");
+        flattened_data.as_mut().unwrap().blend_rect_from(rect, &fill, None, None, None, opacity, 1.0, false, [0, 0], "Alpha Antiblend");
+        flattened_data.as_mut().unwrap().blend_rect_from(rect, &fill, None, None, None, opacity, 1.0, false, [0, 0], "Blend Weld");
     }
     else
     {
-        flattened_data.as_mut().unwrap().blend_rect_from(*new_dirty_rect, source_data, child.mask.as_ref(), child.mask_info.as_ref(), opacity, fill_opacity, child.funny_flag, above_offset, &mode);
+        flattened_data.as_mut().unwrap().blend_rect_from(*new_dirty_rect, source_data, child.mask.as_ref(), child.mask_info.as_ref(), child.get_vector_mask(), opacity, fill_opacity, child.funny_flag, above_offset, &mode);
+    }
+}
+// these all go through the editor
+#[cfg(all(test, feature = "gui"))]
+mod tests
+{
+    use super::*;
+    use crate::Warpainter;
+    
+    fn glow_fx(source : &str) -> HashMap<String, Vec<FxData>>
+    {
+        let mut hm = HashMap::new();
+        hm.insert("enabled".to_string(), vec!(true.into()));
+        hm.insert("mode".to_string(), vec!("Normal".to_string().into()));
+        hm.insert("opacity".to_string(), vec!(100.0.into()));
+        hm.insert("color".to_string(), vec!(1.0.into(), 0.0.into(), 0.0.into(), 1.0.into()));
+        hm.insert("size".to_string(), vec!(4.0.into()));
+        hm.insert("choke".to_string(), vec!(0.0.into()));
+        hm.insert("technique".to_string(), vec!("precise".to_string().into()));
+        hm.insert("source".to_string(), vec!(source.to_string().into()));
+        hm
+    }
+    
+    #[test]
+    pub fn test_glows()
+    {
+        let mut img = Image::<4>::blank(21, 21);
+        for y in 6..15
+        {
+            for x in 6..15
+            {
+                img.set_pixel(x, y, [0, 0, 255, 255]);
+            }
+        }
+        
+        let mut app = Warpainter::default();
+        app.load_from_img(img.clone());
+        app.layers.children[0].effects.insert("outerglow".to_string(), glow_fx("edge"));
+        let out = app.flatten().clone();
+        assert_eq!(out.get_pixel(10, 10), [0, 0, 255, 255]);
+        let near = out.get_pixel(5, 10);
+        assert!(near[0] > 128 && near[3] > 128, "{:?}", near);
+        assert_eq!(out.get_pixel(0, 10)[3], 0);
+        
+        let mut app = Warpainter::default();
+        app.load_from_img(img);
+        app.layers.children[0].effects.insert("innerglow".to_string(), glow_fx("edge"));
+        let out = app.flatten().clone();
+        let edge = out.get_pixel(6, 10);
+        assert!(edge[0] > 128 && edge[3] == 255, "{:?}", edge);
+        assert_eq!(out.get_pixel(10, 10), [0, 0, 255, 255]);
+        assert_eq!(out.get_pixel(0, 10)[3], 0);
+    }
+    
+    #[test]
+    pub fn test_bevel_and_satin()
+    {
+        let mut img = Image::<4>::blank(31, 31);
+        for y in 8..23
+        {
+            for x in 8..23
+            {
+                img.set_pixel(x, y, [128, 128, 128, 255]);
+            }
+        }
+        
+        // light comes from the top left by default, so that edge is lit and the bottom right one is shaded
+        let mut bevel = fx_default("bevel").unwrap();
+        bevel.insert("highlight opacity".to_string(), vec!(100.0.into()));
+        bevel.insert("shadow opacity".to_string(), vec!(100.0.into()));
+        let mut app = Warpainter::default();
+        app.load_from_img(img.clone());
+        app.layers.children[0].effects.insert("bevel".to_string(), bevel.clone());
+        let out = app.flatten().clone();
+        assert!(out.get_pixel(9, 15)[0] > 160, "{:?}", out.get_pixel(9, 15));
+        assert!(out.get_pixel(21, 15)[0] < 96, "{:?}", out.get_pixel(21, 15));
+        assert_eq!(out.get_pixel(15, 15), [128, 128, 128, 255]);
+        assert_eq!(out.get_pixel(4, 15)[3], 0);
+        
+        // outer bevels shade the area around the layer instead
+        bevel.insert("style".to_string(), vec!("outer".to_string().into()));
+        let mut app = Warpainter::default();
+        app.load_from_img(img.clone());
+        app.layers.children[0].effects.insert("bevel".to_string(), bevel);
+        let out = app.flatten().clone();
+        assert_eq!(out.get_pixel(9, 15), [128, 128, 128, 255]);
+        assert!(out.get_pixel(24, 15)[3] > 0, "{:?}", out.get_pixel(24, 15));
+        
+        // satin compares offset copies of the shape, so the middle of a symmetric shape is untouched when inverted
+        let mut satin = fx_default("satin").unwrap();
+        satin.insert("opacity".to_string(), vec!(100.0.into()));
+        satin.insert("color".to_string(), vec!(1.0.into(), 0.0.into(), 0.0.into(), 1.0.into()));
+        satin.insert("mode".to_string(), vec!("Normal".to_string().into()));
+        satin.insert("angle".to_string(), vec!(0.0.into()));
+        satin.insert("distance".to_string(), vec!(4.0.into()));
+        satin.insert("size".to_string(), vec!(2.0.into()));
+        satin.insert("invert".to_string(), vec!(false.into()));
+        let mut app = Warpainter::default();
+        app.load_from_img(img);
+        app.layers.children[0].effects.insert("satin".to_string(), satin);
+        let out = app.flatten().clone();
+        assert_eq!(out.get_pixel(15, 15), [128, 128, 128, 255]);
+        let edge = out.get_pixel(9, 15);
+        assert!(edge[0] > 200 && edge[1] < 64, "{:?}", edge);
+        assert_eq!(out.get_pixel(4, 15)[3], 0);
+    }
+    
+    fn fill_app(fx_name : &str, fx : HashMap<String, Vec<FxData>>) -> Image<4>
+    {
+        let mut img = Image::<4>::blank(21, 21);
+        for y in 0..21
+        {
+            for x in 0..21
+            {
+                img.set_pixel(x, y, [255, 0, 0, 255]);
+            }
+        }
+        let mut app = Warpainter::default();
+        app.load_from_img(img);
+        app.layers.children[0].effects.insert(fx_name.to_string(), fx);
+        app.flatten().clone()
+    }
+    
+    #[test]
+    pub fn test_gradient_styles_and_patterns()
+    {
+        let gradient = |style : &str|
+        {
+            let mut hm = HashMap::new();
+            hm.insert("enabled".to_string(), vec!(true.into()));
+            hm.insert("mode".to_string(), vec!("Normal".to_string().into()));
+            hm.insert("opacity".to_string(), vec!(100.0.into()));
+            hm.insert("angle".to_string(), vec!(90.0.into()));
+            hm.insert("scale".to_string(), vec!(100.0.into()));
+            hm.insert("type".to_string(), vec!(style.to_string().into()));
+            let colors = vec!(vec!(0.0, 0.0, 0.0, 0.0, 0.5), vec!(1.0, 1.0, 1.0, 1.0, 0.5));
+            let alphas = vec!(vec!(1.0, 0.0, 0.5), vec!(1.0, 1.0, 0.5));
+            hm.insert("gradient".to_string(), vec!(colors.into(), alphas.into()));
+            hm
+        };
+        
+        let out = fill_app("gradfill", gradient("radial"));
+        assert!(out.get_pixel(10, 10)[0] < 16, "{:?}", out.get_pixel(10, 10));
+        assert!(out.get_pixel(0, 0)[0] > 240, "{:?}", out.get_pixel(0, 0));
+        
+        let out = fill_app("gradfill", gradient("reflected"));
+        assert!(out.get_pixel(10, 10)[0] < 16, "{:?}", out.get_pixel(10, 10));
+        assert!(out.get_pixel(10, 0)[0] > 200, "{:?}", out.get_pixel(10, 0));
+        assert!(out.get_pixel(10, 20)[0] > 200, "{:?}", out.get_pixel(10, 20));
+        
+        let out = fill_app("gradfill", gradient("diamond"));
+        assert!(out.get_pixel(10, 0)[0] > 200, "{:?}", out.get_pixel(10, 0));
+        assert!(out.get_pixel(5, 10)[0] < 160, "{:?}", out.get_pixel(5, 10));
+        
+        let mut reversed = gradient("radial");
+        reversed.insert("reverse".to_string(), vec!(true.into()));
+        reversed.insert("opacity".to_string(), vec!(50.0.into()));
+        let out = fill_app("gradfill", reversed);
+        let c = out.get_pixel(10, 10);
+        assert!(c[0] > 240 && c[1] > 112 && c[1] < 144, "{:?}", c);
+        
+        // 2x2 checkerboard of blue and transparent
+        let mut pattern = HashMap::new();
+        pattern.insert("enabled".to_string(), vec!(true.into()));
+        pattern.insert("mode".to_string(), vec!("Normal".to_string().into()));
+        pattern.insert("opacity".to_string(), vec!(100.0.into()));
+        pattern.insert("scale".to_string(), vec!(100.0.into()));
+        pattern.insert("link".to_string(), vec!(true.into()));
+        pattern.insert("phase".to_string(), vec!(0.0.into(), 0.0.into()));
+        let pixels = vec!(0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0);
+        pattern.insert("pattern".to_string(), vec!(vec!(2.0, 2.0).into(), pixels.into()));
+        let out = fill_app("patternfill", pattern);
+        assert_eq!(out.get_pixel(0, 0), [0, 0, 255, 255]);
+        assert_eq!(out.get_pixel(1, 0), [255, 0, 0, 255]);
+        assert_eq!(out.get_pixel(2, 0), [0, 0, 255, 255]);
+        assert_eq!(out.get_pixel(3, 1), [0, 0, 255, 255]);
     }
-}
\ No newline at end of file
+}
@@ -5,12 +5,18 @@ extern crate alloc;
 
 use std::io::{Write as _};
 
+#[cfg(feature = "gui")]
 use eframe::egui;
+#[cfg(feature = "gui")]
 use alloc::sync::Arc;
+#[cfg(feature = "gui")]
 use egui::mutex::Mutex;
+#[cfg(feature = "gui")]
 use egui::{Ui, SliderClamping};
+#[cfg(feature = "gui")]
 use eframe::egui_glow::glow;
 
+#[cfg(feature = "gui")]
 use glow::HasContext;
 use serde::{Serialize, Deserialize};
 
@@ -18,31 +24,59 @@ use serde::{Serialize, Deserialize};
 use wasm_bindgen::prelude::wasm_bindgen;
 
 mod wpsd;
+mod wora;
+mod wpp;
+mod document;
+#[cfg(feature = "gui")]
+mod autosave;
+#[cfg(not(target_arch = "wasm32"))]
+mod cli;
 mod rle16;
 mod wpsd_raw;
+mod wpsd_text;
+mod docmeta;
 mod warimage;
 mod transform;
+#[cfg(feature = "gui")]
 mod widgets;
+#[cfg(feature = "gui")]
 mod canvas;
+#[cfg(feature = "gui")]
 mod gizmos;
+#[cfg(feature = "gui")]
 mod tools;
 mod layers;
 mod layers_fxblend;
+#[cfg(feature = "gui")]
 mod quadrender;
 mod vecmap;
 mod pixelmath;
 mod spline;
 mod wabl;
+#[cfg(feature = "gui")]
 mod hwaccel;
 
 use wpsd::*;
+use wora::*;
+use wpp::*;
+pub use document::{Document, DocumentError, Format, LayerId, LayerRef};
+#[cfg(feature = "gui")]
+use autosave::*;
+#[cfg(not(target_arch = "wasm32"))]
+use cli::*;
 use warimage::*;
+use docmeta::*;
 use transform::*;
+#[cfg(feature = "gui")]
 use widgets::*;
+#[cfg(feature = "gui")]
 use canvas::*;
+#[cfg(feature = "gui")]
 use tools::*;
 use layers::*;
+#[cfg(feature = "gui")]
 use quadrender::*;
+#[cfg(feature = "gui")]
 use vecmap::*;
 use pixelmath::*;
 
@@ -174,15 +208,11 @@ impl UndoEvent
     }
 }
 
-#[allow(non_snake_case)]
+#[cfg(feature = "gui")]
 #[allow(unused)]
-#[derive(Serialize, Deserialize)]
 struct Warpainter
 {
-    #[serde(default)]
-    // for detection of warpainter project/document files as different from other CBOR files
-    WarpainterDocumentCBOR : (),
-    
+    // saved in project files, see wpp.rs
     layers : Layer, // tree, layers contain other layers
     current_layer : u128, // uuid
     
@@ -195,7 +225,6 @@ struct Warpainter
     sub_color_rgb : [f32; 4],
     sub_color_hsv : [f32; 4],
     
-    #[serde(default)]
     current_tool : usize, // FIXME change to &'static str
     
     xform : Transform, // view/camera. FIXME: support mirroring
@@ -203,91 +232,80 @@ struct Warpainter
     selection_mask : Option<Image<1>>,
     selection_poly : Vec<Vec<[f32; 2]>>,
     
-    // unsaved
-    #[serde(skip)]
+    metadata : DocumentMetadata, // resolution, guides, etc. from imported files
+    
+    // not saved
     cache_rect : [[f32; 2]; 2],
     
-    #[serde(skip)]
     redo_buffer : Vec<Vec<u8>>,
-    #[serde(skip)]
     undo_buffer : Vec<Vec<u8>>,
     
-    #[serde(skip)]
     debug_text : Vec<String>,
     
-    #[serde(skip)]
     tools : Vec<Box<dyn Tool>>, // FIXME change to VecMap<&'static str, ....
     
-    #[serde(skip)]
     edit_is_direct : bool,
-    #[serde(skip)]
     edit_ignores_selection : bool,
-    #[serde(skip)]
     in_state_edit : bool,
-    #[serde(skip)]
     editing_image : Option<Image<4>>,
-    #[serde(skip)]
     editing_image_stash : Option<Image<4>>,
-    #[serde(skip)]
     editing_image_display : Option<Image<4>>,
-    #[serde(skip)]
     editing_image_display_stash : Option<Image<4>>,
-    #[serde(skip)]
     editing_offset : [f32; 2],
     
-    #[serde(skip)]
     loaded_shaders : bool,
-    #[serde(skip)]
     shaders : VecMap<&'static str, Arc<Mutex<ShaderQuad>>>,
     
-    #[serde(skip)]
     loaded_fonts : bool,
-    #[serde(skip)]
     loaded_icons : bool,
-    #[serde(skip)]
     icons : VecMap<&'static str, (egui::TextureHandle, Image<4>)>,
     
-    #[serde(skip)]
     did_event_setup : bool,
     
-    #[serde(skip)]
     open_dialog : String,
+    open_error : Option<String>,
+    
+    autosave : Autosave,
     
-    #[serde(skip)]
     edit_progress : u128,
     
-    #[serde(skip)]
     file_open_promise : Option<poll_promise::Promise<Option<(String, Vec<u8>)>>>,
     
-    #[serde(skip)]
     max_texture_size : i32,
     
-    #[serde(skip)]
     #[allow(unused)]
     dummy_text : String,
     
-    #[serde(skip)]
     auto_open : String,
     
-    #[serde(skip)]
     canvas_view_x : usize,
-    #[serde(skip)]
     canvas_view_y : usize,
-    #[serde(skip)]
     canvas_view_w : usize,
-    #[serde(skip)]
     canvas_view_h : usize,
     
-    #[serde(skip)]
     queue_fit : bool,
     
-    #[serde(skip)]
     last_input : CanvasInputState,
     
-    #[serde(skip)]
     shared : Arc<Mutex<f32>>,
 }
 
+// used by the UI, and for rendering edited text layers
+pub (crate) fn bundled_font() -> &'static [u8]
+{
+    static FONT : std::sync::OnceLock<Vec<u8>> = std::sync::OnceLock::new();
+    FONT.get_or_init(||
+    {
+        use std::io::Read;
+        const GZ_BYTES : &[u8] = include_bytes!("data/IBMPlexSansJP-Regular.ttf.gz");
+        let mut decoder = libflate::gzip::Decoder::new(GZ_BYTES).expect("Invalid Gzip header");
+        let mut decompressed = Vec::new();
+        decoder.read_to_end(&mut decompressed).expect("Failed to decompress");
+        decompressed
+    })
+}
+
+#[cfg(feature = "gui")]
 fn default_tools() -> Vec<Box<dyn Tool>>
 {
     vec!(
@@ -301,6 +319,7 @@ fn default_tools() -> Vec<Box<dyn Tool>>
     )
 }
 
+#[cfg(feature = "gui")]
 impl Default for Warpainter
 {
     fn default() -> Self
@@ -323,7 +342,6 @@ impl Default for Warpainter
         
         use rand::Rng;
         Self {
-            WarpainterDocumentCBOR : (),
             layers : root_layer,
             current_layer : image_layer_uuid,
             
@@ -365,11 +383,16 @@ impl Default for Warpainter
             selection_mask : None,
             selection_poly : Vec::new(),
             
+            metadata : DocumentMetadata::default(),
+            
             cache_rect : [[0.0, 0.0], [0.0, 0.0]],
             
             did_event_setup : false,
             
             open_dialog : "".to_string(),
+            open_error : None,
+            
+            autosave : Autosave::default(),
             
             edit_progress : rand::thread_rng().gen(),
             in_state_edit : false,
@@ -394,33 +417,9 @@ impl Default for Warpainter
     }
 }
 
+#[cfg(feature = "gui")]
 impl Warpainter
 {
-    fn load_from(&mut self, other : Self)
-    {
-        self.layers = other.layers;
-        self.current_layer = other.current_layer;
-        
-        self.canvas_width = other.canvas_width;
-        self.canvas_height = other.canvas_height;
-        
-        self.eraser_mode = other.eraser_mode;
-        self.main_color_rgb = other.main_color_rgb;
-        self.main_color_hsv = other.main_color_hsv;
-        self.sub_color_rgb = other.sub_color_rgb;
-        self.sub_color_hsv = other.sub_color_hsv;
-        
-        self.current_tool = other.current_tool;
-        
-        self.xform = other.xform;
-        
-        self.selection_mask = other.selection_mask;
-        self.selection_poly = other.selection_poly;
-        
-        self.layers.visit_layers_mut(0, &mut |layer, _| { layer.commit_info(); Some(()) });
-        
-        self.queue_fit = true;
-    }
     fn load_shaders(&mut self, frame : &mut eframe::Frame)
     {
         if self.loaded_shaders || frame.gl().is_none()
@@ -464,16 +463,7 @@ impl Warpainter
         }
         self.loaded_fonts = true;
         
-        const GZ_BYTES: &[u8] = include_bytes!("data/IBMPlexSansJP-Regular.ttf.gz");
-        fn decompress_gz(data: &[u8]) -> Vec<u8> {
-            use std::io::Read;
-            let mut decoder = libflate::gzip::Decoder::new(data).expect("Invalid Gzip header");
-            let mut decompressed = Vec::new();
-            decoder.read_to_end(&mut decompressed).expect("Failed to decompress");
-            decompressed
-        }
-        
-        let dec = decompress_gz(GZ_BYTES);
+        let dec = bundled_font().to_vec();
         
         let mut fonts = egui::FontDefinitions::default();
         fonts.font_data.insert("IBM Plex JP".to_owned(), egui::FontData::from_owned(dec).into());
@@ -600,6 +590,7 @@ impl Warpainter
     }
 }
 
+#[cfg(feature = "gui")]
 impl Warpainter
 {
     fn load_from_img(&mut self, img : Image<4>)
@@ -608,6 +599,7 @@ impl Warpainter
         
         self.canvas_width = img.width;
         self.canvas_height = img.height;
+        self.metadata = DocumentMetadata::default();
         
         let image_layer = Layer::new_layer_from_image("New Layer", img);
         let image_layer_uuid = image_layer.uuid;
@@ -617,8 +609,32 @@ impl Warpainter
         
         self.queue_fit = true;
     }
+    // moves the image out, for the loaders and savers that work without the editor
+    fn take_document(&mut self) -> WppDocument
+    {
+        WppDocument {
+            layers : std::mem::take(&mut self.layers),
+            current_layer : self.current_layer,
+            canvas_width : self.canvas_width,
+            canvas_height : self.canvas_height,
+            selection_mask : self.selection_mask.take(),
+            selection_poly : std::mem::take(&mut self.selection_poly),
+            metadata : std::mem::take(&mut self.metadata),
+        }
+    }
+    fn set_document(&mut self, document : WppDocument)
+    {
+        self.layers = document.layers;
+        self.current_layer = document.current_layer;
+        self.canvas_width = document.canvas_width;
+        self.canvas_height = document.canvas_height;
+        self.selection_mask = document.selection_mask;
+        self.selection_poly = document.selection_poly;
+        self.metadata = document.metadata;
+    }
 }
 
+#[cfg(feature = "gui")]
 impl Warpainter
 {
     fn tool_think(&mut self, inputstate : &CanvasInputState)
@@ -673,6 +689,7 @@ impl Warpainter
     }
 }
 
+#[cfg(feature = "gui")]
 impl Warpainter
 {
     fn sample_poly_sdf(mut c : [f32; 2], points : &[[f32; 2]]) -> f32
@@ -766,6 +783,7 @@ impl Warpainter
     }
 }
 
+#[cfg(feature = "gui")]
 impl Warpainter
 {
     fn begin_state_edit(&mut self)
@@ -863,7 +881,7 @@ impl Warpainter
     {
         if let Some(layer) = self.layers.find_layer_mut(self.current_layer)
         {
-            Some(layer.flatten(self.canvas_width, self.canvas_height, None, None))
+            Some(layer.flatten_as_root(self.canvas_width, self.canvas_height, None, None))
         }
         else
         {
@@ -887,6 +905,7 @@ impl Warpainter
     }
     fn flatten(&mut self) -> &Image<4>
     {
+        wpp_load_needed(self);
         if self.get_temp_edit_image()
         {
             // FIXME convey whether the edit is a direct edit
@@ -918,27 +937,27 @@ impl Warpainter
                         {
                             if let (Some(selection_mask), false) = (&self.selection_mask, self.edit_ignores_selection)
                             {
-                                self.editing_image_display.as_mut().unwrap().blend_rect_from(rect, edit_image, None, None, 1.0, 1.0, false, [0, 0], "Copy");
-                                self.editing_image_display.as_mut().unwrap().blend_rect_from(rect, edit_image, Some(selection_mask), None, 1.0, 1.0, false, [0, 0], "Clamp Erase");
-                                self.editing_image_display.as_mut().unwrap().blend_rect_from(rect, current_image, Some(selection_mask), None, 1.0, 1.0, false, [0, 0], "Weld");
+                                self.editing_image_display.as_mut().unwrap().blend_rect_from(rect, edit_image, None, None, None, 1.0, 1.0, false, [0, 0], "Copy");
+                                self.editing_image_display.as_mut().unwrap().blend_rect_from(rect, edit_image, Some(selection_mask), None, None, 1.0, 1.0, false, [0, 0], "Clamp Erase");
+                                self.editing_image_display.as_mut().unwrap().blend_rect_from(rect, current_image, Some(selection_mask), None, None, 1.0, 1.0, false, [0, 0], "Weld");
                             }
                             else
                             {
-                                self.editing_image_display.as_mut().unwrap().blend_rect_from(rect, edit_image, None, None, 1.0, 1.0, false, [0, 0], "Copy");
+                                self.editing_image_display.as_mut().unwrap().blend_rect_from(rect, edit_image, None, None, None, 1.0, 1.0, false, [0, 0], "Copy");
                             }
                             return true;
                         }
                         else
                         {
-                            self.editing_image_display.as_mut().unwrap().blend_rect_from(rect, current_image, None, None, 1.0, 1.0, false, [0, 0], "Copy");
+                            self.editing_image_display.as_mut().unwrap().blend_rect_from(rect, current_image, None, None, None, 1.0, 1.0, false, [0, 0], "Copy");
                             
                             if let (Some(selection_mask), false) = (&self.selection_mask, self.edit_ignores_selection)
                             {
-                                self.editing_image_display.as_mut().unwrap().blend_rect_from(rect, edit_image, Some(selection_mask), None, 1.0, 1.0, false, [0, 0], "Normal");
+                                self.editing_image_display.as_mut().unwrap().blend_rect_from(rect, edit_image, Some(selection_mask), None, None, 1.0, 1.0, false, [0, 0], "Normal");
                             }
                             else
                             {
-                                self.editing_image_display.as_mut().unwrap().blend_rect_from(rect, edit_image, None, None, 1.0, 1.0, false, [0, 0], "Normal");
+                                self.editing_image_display.as_mut().unwrap().blend_rect_from(rect, edit_image, None, None, None, 1.0, 1.0, false, [0, 0], "Normal");
                             }
                             
                             return true;
@@ -1286,6 +1305,7 @@ impl Warpainter
 }
 
 
+#[cfg(feature = "gui")]
 impl Warpainter
 {
     fn get_zoom(&self) -> f32
@@ -1339,6 +1359,7 @@ impl Warpainter
         self.debug_text.push(text.to_string());
     }
 }
+#[cfg(feature = "gui")]
 impl Warpainter
 {
     fn set_main_color_rgb8(&mut self, new : [u8; 4])
@@ -1380,12 +1401,25 @@ impl Warpainter
     }
 }
 
+#[cfg(feature = "gui")]
 impl Warpainter
 {
     fn build_ora_data(&mut self) -> Vec<u8>
     {
         self.cancel_edit();
+        wpp_load_all(self);
         
+        let mut document = self.take_document();
+        let data = document.build_ora_data();
+        self.set_document(document);
+        data
+    }
+}
+
+impl WppDocument
+{
+    fn build_ora_data(&mut self) -> Vec<u8>
+    {
         use xot::Xot;
         let mut xot = Xot::new();
         
@@ -1410,10 +1444,24 @@ impl Warpainter
         let real_opacity_name = xot.add_name("real-opacity");
         let clipped_name = xot.add_name("clipped");
         let uuidhex_name = xot.add_name("uuidhex");
-        let _masks_name = xot.add_name("masks"); // TODO
-        let _mask_name = xot.add_name("mask"); // TODO
+        let default_color_name = xot.add_name("default-color");
+        let relative_name = xot.add_name("relative");
+        let disabled_name = xot.add_name("disabled");
+        let invert_name = xot.add_name("invert");
+        let kind_name = xot.add_name("kind");
+        
+        // namespaced extension elements
+        let wp_ns = xot.add_namespace(WARPAINTER_ORA_NS);
+        let wp_prefix = xot.add_prefix("warpainter");
+        let mask_name = xot.add_name_ns("mask", wp_ns);
+        let adjustment_name = xot.add_name_ns("adjustment", wp_ns);
+        let effect_name = xot.add_name_ns("effect", wp_ns);
+        // exact blend mode, since most of ours have no standard composite-op
+        let wp_composite_op_name = xot.add_name_ns("composite-op", wp_ns);
+        let wp_custom_blend_mode_name = xot.add_name_ns("custom-blend-mode", wp_ns);
         
         let root = xot.new_element(image_name);
+        xot.namespaces_mut(root).insert(wp_prefix, wp_ns);
         //xot.attributes_mut(root).insert(version_name, "0.0.6-wp.1".to_string());
         xot.attributes_mut(root).insert(version_name, "0.0.6".to_string());
         xot.attributes_mut(root).insert(w_name, format!("{}", self.canvas_width));
@@ -1431,12 +1479,12 @@ impl Warpainter
         let zip_options = _zip_options.clone();
         
         zipref.start_file("mimetype", zip_options).unwrap();
-        zipref.write(b"image/openraster").unwrap();
+        zipref.write_all(b"image/openraster").unwrap();
         
         let mut img = self.flatten().to_imagebuffer();
         let bytes = save_image_to_vec(&img);
         zipref.start_file("mergedimage.png", zip_options).unwrap();
-        zipref.write(&bytes).unwrap();
+        zipref.write_all(&bytes).unwrap();
         
         if img.width() > 256 || img.height() > 256
         {
@@ -1452,7 +1500,7 @@ impl Warpainter
         
         let bytes = save_image_to_vec(&img);
         zipref.start_file("Thumbnails/thumbnail.png", zip_options).unwrap();
-        zipref.write(&bytes).unwrap();
+        zipref.write_all(&bytes).unwrap();
         
         fn save_image_to_vec(buffer : &image::ImageBuffer<image::Rgba<u8>, Vec<u8>>) -> Vec<u8>
         {
@@ -1468,85 +1516,18 @@ impl Warpainter
             }
             ret
         }
-        
-        fn get_svg_composite_op(s : &str) -> &'static str
+        fn save_gray_image_to_vec(buffer : &image::GrayImage) -> Vec<u8>
         {
-            match s
+            let mut ret = Vec::new();
             {
-                "Normal" => "svg:src-over",
-                "Composite" => "svg:src-over",
-                "Multiply" => "svg:multiply",
-                "Divide" => "svg:difference",
-                "Screen" => "svg:multiply",
-                "Add" => "svg:plus",
-                "Glow Add" => "svg:plus",
-                "Subtract" => "svg:difference",
-                "Difference" => "svg:difference",
-                "Signed Diff" => "svg:difference",
-                "Signed Add" => "svg:plus",
-                "Negation" => "svg:difference",
-                "Lighten" => "svg:lighten",
-                "Darken" => "svg:darken",
-                "Linear Burn" => "svg:multiply",
-                "Color Burn" => "svg:color-burn",
-                "Color Dodge" => "svg:color-dodge",
-                "Glow Dodge" => "svg:color-dodge",
-                "Glow" => "svg:color-dodge", // or hard light
-                "Reflect" => "svg:hard-light",
-                "Overlay" => "svg:overlay",
-                
-                "Soft Light" => "svg:soft-light",
-                
-                "Hard Light" => "svg:hard-light",
-                "Vivid Light" => "svg:hard-light",
-                "Linear Light" => "svg:hard-light",
-                "Pin Light" => "svg:hard-light",
-                "Hard Mix" => "svg:hard-light",
-                
-                "Exclusion" => "svg:difference",
-                
-                "Hue" => "svg:hue",
-                "Saturation" => "svg:saturation",
-                "Color" => "svg:color",
-                "Luminosity" => "svg:luminosity",
-                "Flat Hue" => "svg:hue",
-                "Flat Sat" => "svg:saturation",
-                "Flat Color" => "svg:luminosity",
-                "Value" => "svg:luminosity",
-                "Hard Sat" => "svg:saturation",
-                "Hard Color" => "svg:color",
-                "Lightness" => "svg:luminosity",
-                
-                "Erase" => "svg:dst-out",
-                "Reveal" => "svg:dst-atop",
-                "Alpha Mask" => "svg:dst-in",
-                "Alpha Reject" => "svg:dst-out",
-                "Under" => "svg:src-over", // svg:dst-over
-                "Interpolate" => "svg:src-over", // svg:src-in
-                
-                "Dither" => "svg:src-over",
-                
-                // internal
-                "Hard Interpolate" => "FIXME",
-                "Clamp Erase" => "FIXME",
-                "Merge Alpha" => "FIXME",
-                "Clip Alpha" => "FIXME",
-                "Max Alpha" => "FIXME",
-                "Copy Alpha" => "FIXME",
-                
-                "Copy" => "FIXME",
-                "Weld Under" => "FIXME",
-                "Alpha Antiblend" => "FIXME",
-                "Blend Weld" => "FIXME",
-                "Sum Weld" => "FIXME",
-                "Weld" => "FIXME",
-                "Soft Weld" => "FIXME",
-                "Hard Weld" => "FIXME",
-                "Clip Weld" => "FIXME",
-                
-                // fallback
-                _ => "svg:src-over",
+                let mut encoder = png::Encoder::new(&mut ret, buffer.width(), buffer.height());
+                encoder.set_color(png::ColorType::Grayscale);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive);
+                let mut writer = encoder.write_header().unwrap();
+                writer.write_image_data(buffer.as_raw()).unwrap();
             }
+            ret
         }
         
         use std::rc::Rc;
@@ -1555,13 +1536,14 @@ impl Warpainter
             : Rc<dyn Fn(&mut Zw, Rc<dyn Any>, &mut Xot, xot::Node, &Layer) -> ()>
             = Rc::new(move |zipref : &mut Zw, selfie : Rc<dyn Any>, xot : &mut Xot, node : xot::Node, layer : &Layer| -> ()
         {
-            let d = if layer.is_group()
+            // adjustment layers are written as empty stacks below, whether they have a (usually empty) image or not
+            let d = if layer.is_group() && layer.adjustment.is_none()
             {
                 let d = xot.new_element(stack_name);
                 xot.append(node, d).unwrap();
                 xot.attributes_mut(d).insert(name_name, layer.name.clone());
                 xot.attributes_mut(d).insert(composite_op_name, get_svg_composite_op(&layer.blend_mode).to_string());
-                xot.attributes_mut(d).insert(isolation_name, "isolate".to_string());
+                xot.attributes_mut(d).insert(isolation_name, if layer.blend_mode == "Pass Through" { "auto" } else { "isolate" }.to_string());
                 
                 let f = selfie.clone().downcast::<Rc<dyn Fn(&mut Zw, Rc<dyn Any>, &mut Xot, xot::Node, &Layer)>>().unwrap();
                 for c in layer.children.iter()
@@ -1571,7 +1553,7 @@ impl Warpainter
                 
                 d
             }
-            else if let Some(data) = &layer.data
+            else if let (Some(data), None) = (&layer.data, &layer.adjustment)
             {
                 let d = xot.new_element(layer_name);
                 xot.append(node, d).unwrap();
@@ -1582,7 +1564,7 @@ impl Warpainter
                 let bytes = save_image_to_vec(&img);
                 let fname = format!("data/{}.png", layer.uuid);
                 zipref.start_file(&fname, zip_options).unwrap();
-                zipref.write(&bytes).unwrap();
+                zipref.write_all(&bytes).unwrap();
                 
                 xot.attributes_mut(d).insert(x_name, format!("{}", layer.offset[0] as i64));
                 xot.attributes_mut(d).insert(y_name, format!("{}", layer.offset[1] as i64));
@@ -1600,12 +1582,56 @@ impl Warpainter
             };
             
             xot.attributes_mut(d).insert(uuidhex_name, format!("{}", layer.uuid));
+            xot.attributes_mut(d).insert(wp_composite_op_name, layer.blend_mode.clone());
+            if layer.blend_mode.starts_with("Custom")
+            {
+                // an element and not an attribute, because attribute values lose their newlines
+                let c = xot.new_element(wp_custom_blend_mode_name);
+                xot.append(d, c).unwrap();
+                let text = xot.new_text(&layer.custom_blend_mode);
+                xot.append(c, text).unwrap();
+            }
             xot.attributes_mut(d).insert(opacity_name, format!("{}", layer.opacity * layer.fill_opacity));
             xot.attributes_mut(d).insert(visibility_name, if layer.visible { "visible" } else { "hidden" }.to_string() );
             
             xot.attributes_mut(d).insert(fill_opacity_name, format!("{}", layer.fill_opacity));
-            xot.attributes_mut(d).insert(real_opacity_name, format!("{}", layer.fill_opacity));
+            xot.attributes_mut(d).insert(real_opacity_name, format!("{}", layer.opacity));
             xot.attributes_mut(d).insert(clipped_name, if layer.clipped { "true" } else { "false" }.to_string() );
+            
+            if let (Some(mask), Some(info)) = (&layer.mask, &layer.mask_info)
+            {
+                let bytes = save_gray_image_to_vec(&mask.to_yimage());
+                let fname = format!("mask/{}.png", layer.uuid);
+                zipref.start_file(&fname, zip_options).unwrap();
+                zipref.write_all(&bytes).unwrap();
+                
+                let m = xot.new_element(mask_name);
+                xot.append(d, m).unwrap();
+                xot.attributes_mut(m).insert(src_name, fname);
+                xot.attributes_mut(m).insert(x_name, format!("{}", info.x));
+                xot.attributes_mut(m).insert(y_name, format!("{}", info.y));
+                xot.attributes_mut(m).insert(default_color_name, format!("{}", info.default_color));
+                xot.attributes_mut(m).insert(relative_name, if info.relative { "true" } else { "false" }.to_string());
+                xot.attributes_mut(m).insert(disabled_name, if info.disabled { "true" } else { "false" }.to_string());
+                xot.attributes_mut(m).insert(invert_name, if info.invert { "true" } else { "false" }.to_string());
+            }
+            if let Some(adjustment) = &layer.adjustment
+            {
+                let a = xot.new_element(adjustment_name);
+                xot.append(d, a).unwrap();
+                let text = xot.new_text(&ora_encode_blob(adjustment));
+                xot.append(a, text).unwrap();
+            }
+            let mut effects = layer.effects.iter().collect::<Vec<_>>();
+            effects.sort_by(|a, b| a.0.cmp(b.0));
+            for (kind, fx) in effects
+            {
+                let e = xot.new_element(effect_name);
+                xot.append(d, e).unwrap();
+                xot.attributes_mut(e).insert(kind_name, kind.clone());
+                let text = xot.new_text(&ora_encode_blob(fx));
+                xot.append(e, text).unwrap();
+            }
         });
         
         for c in self.layers.children.iter()
@@ -1618,12 +1644,17 @@ impl Warpainter
         let zip_options = _zip_options.clone();
         zipref.start_file("stack.xml", zip_options).unwrap();
         let xml = xot.to_string(doc).unwrap();
-        zipref.write(xml.as_bytes()).unwrap();
+        zipref.write_all(xml.as_bytes()).unwrap();
         
         drop(zip);
         
         zipbuf
     }
+}
+
+#[cfg(feature = "gui")]
+impl Warpainter
+{
     fn new_layer(&mut self)
     {
         let layer = Layer::new_layer("New Layer", self.canvas_width, self.canvas_height);
@@ -1685,34 +1716,43 @@ impl Warpainter
     }
 }
 
-fn show_modal_warning(ctx : &egui::Context, text : String)
+// returns true on the frame that the warning gets closed
+#[cfg(feature = "gui")]
+fn show_modal_warning(ctx : &egui::Context, id : &str, text : String) -> bool
 {
-    let is_open = ctx.data_mut(|d| d.get_persisted::<bool>(egui::Id::new("modal_warning_state"))).unwrap_or(true);
+    let state_id = egui::Id::new(format!("{}_state", id));
+    let is_open = ctx.data_mut(|d| d.get_persisted::<bool>(state_id)).unwrap_or(true);
     
     if !is_open
     {
-        return;
+        return false;
     }
     
+    let mut closed = false;
     egui::Window::new("Warning").collapsible(false).resizable(false)
         .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0]).title_bar(true).interactable(true)
-        .enabled(true).id(egui::Id::new("modal_warning")).show(ctx, |ui|
+        .enabled(true).id(egui::Id::new(id)).show(ctx, |ui|
         {
             ui.label(text);
             if ui.button("Close").clicked()
             {
                 ui.memory_mut(|mem|
                 {
-                    mem.data.insert_persisted(egui::Id::new("modal_warning_state"), false);
+                    mem.data.insert_persisted(state_id, false);
                 });
+                closed = true;
             }
         });
+    closed
 }
 
+#[cfg(feature = "gui")]
 static mut GL : Option<Arc<glow::Context>> = None;
 
+#[cfg(feature = "gui")]
 use egui::{Margin, Frame};
 
+#[cfg(feature = "gui")]
 impl eframe::App for Warpainter
 {
     fn update(&mut self, ctx : &egui::Context, frame : &mut eframe::Frame)
@@ -1732,6 +1772,11 @@ impl eframe::App for Warpainter
         self.load_font(ctx);
         self.load_shaders(frame);
         
+        // layers from project files that were just shown or selected
+        wpp_load_needed(self);
+        
+        autosave_think(self);
+        
         //println!("app still running! time: {:?}", web_time::Instant::now());
         
         unsafe
@@ -1759,26 +1804,30 @@ impl eframe::App for Warpainter
         {
             let fname = self.auto_open.clone();
             self.auto_open = "".to_string();
-            if fname.ends_with(".psd")
+            if fname.ends_with(".psd") || fname.ends_with(".psb")
             {
                 let bytes = std::fs::read(fname).unwrap();
-                wpsd_open(self, &bytes);
+                if let Err(e) = wpsd_open(self, &bytes)
+                {
+                    self.open_error = Some(format!("Failed to open PSD file: {}", e));
+                }
+            }
+            else if fname.ends_with(".ora")
+            {
+                let bytes = std::fs::read(fname).unwrap();
+                if let Err(e) = wora_open(self, &bytes)
+                {
+                    self.open_error = Some(format!("Failed to open ORA file: {}", e));
+                }
             }
             else if fname.ends_with(".wpp")
             {
-                //let bytes = std::fs::read(&fname).unwrap();
                 let start = web_time::Instant::now();
-                
-                fn load(path : &String) -> Result<Warpainter, String>
+                let result = std::fs::read(fname).map_err(|x| WppError::Io(x.to_string())).and_then(|bytes| wpp_open(self, &bytes));
+                if let Err(e) = result
                 {
-                    let file = std::fs::File::open(path).map_err(|x| x.to_string())?;
-                    let reader = std::io::BufReader::new(file);
-                    let data : Warpainter = cbor4ii::serde::from_reader(reader).map_err(|x| x.to_string())?;
-                    Ok(data)
+                    self.open_error = Some(format!("Failed to open project file: {}", e));
                 }
-                
-                let new = load(&fname).unwrap();
-                self.load_from(new);
                 println!("WPP load time: {:.3}", start.elapsed().as_secs_f64() * 1000.0);
             }
             else if fname != ""
@@ -1832,7 +1881,7 @@ This warning will only be shown once.", self.max_texture_size);
         
         if self.canvas_width > self.max_texture_size as usize || self.canvas_height > self.max_texture_size as usize
         {
-            show_modal_warning(ctx, sizewarn.clone());
+            show_modal_warning(ctx, "modal_warning", sizewarn.clone());
         }
         
         self.layers.visit_layers_mut(0, &mut |l, _depth|
@@ -1841,13 +1890,25 @@ This warning will only be shown once.", self.max_texture_size);
             {
                 if data.width > self.max_texture_size as usize || data.height > self.max_texture_size as usize
                 {
-                    show_modal_warning(ctx, sizewarn.clone());
+                    show_modal_warning(ctx, "modal_warning", sizewarn.clone());
                     return None;
                 }
             }
             Some(())
         });
         
+        if let Some(text) = self.open_error.clone()
+        {
+            if show_modal_warning(ctx, "open_error_warning", text)
+            {
+                // let the next failure show up again
+                self.open_error = None;
+                ctx.data_mut(|d| d.remove::<bool>(egui::Id::new("open_error_warning_state")));
+            }
+        }
+        
+        autosave_ui(self, ctx);
+        
         let mut focus_is_global = true;
         let mut new_dialog_opened = &self.open_dialog == "New Window";
         if new_dialog_opened
@@ -1935,21 +1996,32 @@ This warning will only be shown once.", self.max_texture_size);
                 let pattern = b"WarpainterDocumentCBOR";
                 let force_wpp = std::iter::Iterator::any(&mut bytes.windows(pattern.len()).take(bytes.len().min(0x20) - pattern.len() + 1), |window| window == pattern);
                 
-                if ext == "psd" && !force_wpp
+                if (ext == "psd" || ext == "psb") && !force_wpp
                 {
                     let start = web_time::Instant::now();
-                    wpsd_open(self, &bytes);
+                    if let Err(e) = wpsd_open(self, &bytes)
+                    {
+                        self.open_error = Some(format!("Failed to open PSD file: {}", e));
+                    }
                     println!("PSD load time: {:.3}", start.elapsed().as_secs_f64() * 1000.0);
                 }
+                else if ext == "ora" && !force_wpp
+                {
+                    if let Err(e) = wora_open(self, &bytes)
+                    {
+                        self.open_error = Some(format!("Failed to open ORA file: {}", e));
+                    }
+                }
                 else if ext == "wpp" || force_wpp
                 {
                     self.cancel_edit();
                     
                     let start = web_time::Instant::now();
                     
-                    let reader = std::io::BufReader::new(std::io::Cursor::new(bytes));
-                    let new : Warpainter = cbor4ii::serde::from_reader(reader).map_err(|x| x.to_string()).unwrap();
-                    self.load_from(new);
+                    if let Err(e) = wpp_open(self, &bytes)
+                    {
+                        self.open_error = Some(format!("Failed to open project file: {}", e));
+                    }
                     
                     println!("WPP load time: {:.3}", start.elapsed().as_secs_f64() * 1000.0);
                 }
@@ -1976,43 +2048,15 @@ This warning will only be shown once.", self.max_texture_size);
                         self.open_dialog = "New Window".to_string();
                         ui.close_menu();
                     }
+                    #[cfg(not(target_arch = "wasm32"))]
+                    if ui.button("Autosave...").clicked()
+                    {
+                        self.open_dialog = "Autosave Settings".to_string();
+                        ui.close_menu();
+                    }
                     
                     // FIXME: highly duplicated grabage. deduplicate!!!
                     
-                    macro_rules! get_state { () => {
-                    {
-                        self.cancel_edit();
-                        
-                        
-                        //for l in self.layers.children.iter_mut()
-                        //if false
-                        {
-                            //l.visit_layers_mut(0, &mut |l, _depth|
-                            self.layers.visit_layers_mut(0, &mut |l, _depth|
-                            {
-                                std::mem::swap(&mut l.flattened_data, &mut l._dummy_flattened_data);
-                                std::mem::swap(&mut l.flattened_dirty_rect, &mut l._dummy_flattened_dirty_rect);
-                                Some(())
-                            });
-                        }
-                        
-                        let mut data = Vec::new();
-                        cbor4ii::serde::to_writer(&mut data, self).unwrap();
-                        
-                        //for l in self.layers.children.iter_mut()
-                        //if false
-                        {
-                            //l.visit_layers_mut(0, &mut |l, _depth|
-                            self.layers.visit_layers_mut(0, &mut |l, _depth|
-                            {
-                                std::mem::swap(&mut l.flattened_data, &mut l._dummy_flattened_data);
-                                std::mem::swap(&mut l.flattened_dirty_rect, &mut l._dummy_flattened_dirty_rect);
-                                Some(())
-                            });
-                        }
-                        
-                        data
-                    } } }
                     
                     
                     #[cfg(target_os = "android")]
@@ -2032,10 +2076,9 @@ This warning will only be shown once.", self.max_texture_size);
                         {
                             if let Some(path) = rfd::FileDialog::new()
                                 .add_filter("Supported Formats",
-                                    &["wpp", "png", "jpg", "jpeg", "gif", "bmp", "tga", "tiff", "webp", "ico", "pnm", "pbm", "ppm", "avif", "dds", "qoi", "psd"])
+                                    &["wpp", "png", "jpg", "jpeg", "gif", "bmp", "tga", "tiff", "webp", "ico", "pnm", "pbm", "ppm", "avif", "dds", "qoi", "psd", "psb", "ora"])
                                 .add_filter("Warpainter Project", &["wpp"])
-                                .add_filter("Other Projects", &["psd"])
-                                //.add_filter("Other Projects", &["psd", "ora"])
+                                .add_filter("Other Projects", &["psd", "psb", "ora"])
                                 .add_filter("Images",
                                     &["png", "jpg", "jpeg", "gif", "bmp", "tga", "tiff", "webp", "ico", "pnm", "pbm", "ppm", "avif", "dds", "qoi"])
                                 //.add_filter("Warpainter Project",
@@ -2045,29 +2088,35 @@ This warning will only be shown once.", self.max_texture_size);
                                 self.cancel_edit();
                                 
                                 println!("{}", path.extension().unwrap().to_string_lossy());
-                                if path.extension().unwrap().to_string_lossy() == "psd"
+                                if path.extension().unwrap().to_string_lossy() == "psd" || path.extension().unwrap().to_string_lossy() == "psb"
                                 {
                                     let start = web_time::Instant::now();
                                     let bytes = std::fs::read(path).unwrap();
-                                    wpsd_open(self, &bytes);
+                                    if let Err(e) = wpsd_open(self, &bytes)
+                                    {
+                                        self.open_error = Some(format!("Failed to open PSD file: {}", e));
+                                    }
                                     println!("PSD load time: {:.3}", start.elapsed().as_secs_f64() * 1000.0);
                                 }
+                                else if path.extension().unwrap().to_string_lossy() == "ora"
+                                {
+                                    let start = web_time::Instant::now();
+                                    let bytes = std::fs::read(path).unwrap();
+                                    if let Err(e) = wora_open(self, &bytes)
+                                    {
+                                        self.open_error = Some(format!("Failed to open ORA file: {}", e));
+                                    }
+                                    println!("ORA load time: {:.3}", start.elapsed().as_secs_f64() * 1000.0);
+                                }
                                 else if path.extension().unwrap().to_string_lossy() == "wpp"
                                 {
                                     let start = web_time::Instant::now();
                                     
-                                    fn load(path : &std::path::Path) -> Result<Warpainter, String>
+                                    let result = std::fs::read(path).map_err(|x| WppError::Io(x.to_string())).and_then(|bytes| wpp_open(self, &bytes));
+                                    if let Err(e) = result
                                     {
-                                        let file = std::fs::File::open(path).map_err(|x| x.to_string())?;
-                                        
-                                        let reader = std::io::BufReader::new(file);
-                                        
-                                        let data : Warpainter = cbor4ii::serde::from_reader(reader).map_err(|x| x.to_string())?;
-                                        Ok(data)
+                                        self.open_error = Some(format!("Failed to open project file: {}", e));
                                     }
-                                    
-                                    let new = load(&path).unwrap();
-                                    self.load_from(new);
                                     println!("WPP load time: {:.3}", start.elapsed().as_secs_f64() * 1000.0);
                                 }
                                 else
@@ -2094,7 +2143,7 @@ This warning will only be shown once.", self.max_texture_size);
                                 .save_file()
                             {
                                 // FIXME handle error
-                                save_vec_u8_atomic(&path, &get_state!()).unwrap();
+                                save_vec_u8_atomic(&path, &wpp_save(self)).unwrap();
                             }
                             ui.close_menu();
                         }
@@ -2123,6 +2172,20 @@ This warning will only be shown once.", self.max_texture_size);
                             }
                             ui.close_menu();
                         }
+                        if ui.button("Save PSD...").clicked()
+                        {
+                            if let Some(path) = rfd::FileDialog::new()
+                                .add_filter("Photoshop Document", &["psd"])
+                                .save_file()
+                            {
+                                match wpsd_save(self)
+                                {
+                                    Ok(data) => save_vec_u8_atomic(&path, &data).unwrap(),
+                                    Err(e) => self.open_error = Some(format!("Failed to save PSD file: {}", e)),
+                                }
+                            }
+                            ui.close_menu();
+                        }
                     }
                     #[cfg(target_arch = "wasm32")]
                     {
@@ -2132,10 +2195,9 @@ This warning will only be shown once.", self.max_texture_size);
                             {
                                 let file = rfd::AsyncFileDialog::new()
                                     .add_filter("Supported Formats",
-                                                &["wpp", "png", "jpg", "jpeg", "gif", "bmp", "tga", "tiff", "webp", "ico", "pnm", "pbm", "ppm", "avif", "dds", "psd"])
+                                                &["wpp", "png", "jpg", "jpeg", "gif", "bmp", "tga", "tiff", "webp", "ico", "pnm", "pbm", "ppm", "avif", "dds", "psd", "psb", "ora"])
                                     .add_filter("Warpainter Project", &["wpp"])
-                                    .add_filter("Other Projects", &["psd"])
-                                    //.add_filter("Other Projects", &["psd", "ora"])
+                                    .add_filter("Other Projects", &["psd", "psb", "ora"])
                                     .add_filter("Images",
                                         &["png", "jpg", "jpeg", "gif", "bmp", "tga", "tiff", "webp", "ico", "pnm", "pbm", "ppm", "avif", "dds", "qoi"])
                                     .pick_file().await;
@@ -2158,7 +2220,7 @@ This warning will only be shown once.", self.max_texture_size);
                         }
                         if ui.button("Save As...").clicked()
                         {
-                            let data = get_state!();
+                            let data = wpp_save(self);
                             
                             let future = async move
                             {
@@ -2206,6 +2268,26 @@ This warning will only be shown once.", self.max_texture_size);
                             wasm_bindgen_futures::spawn_local(future);
                             ui.close_menu();
                         }
+                        if ui.button("Save PSD...").clicked()
+                        {
+                            match wpsd_save(self)
+                            {
+                                Ok(data) =>
+                                {
+                                    let future = async move
+                                    {
+                                        if let Some(file_handle) = rfd::AsyncFileDialog::new()
+                                            .set_file_name("WpProject.psd").save_file().await
+                                        {
+                                            file_handle.write(&data).await.unwrap();
+                                        }
+                                    };
+                                    wasm_bindgen_futures::spawn_local(future);
+                                }
+                                Err(e) => self.open_error = Some(format!("Failed to save PSD file: {}", e)),
+                            }
+                            ui.close_menu();
+                        }
                     }
                     }
                 });
@@ -2222,19 +2304,28 @@ This warning will only be shown once.", self.max_texture_size);
                         let name = name.clone();
                         let data = data.clone();
                         println!("{}", name);
-                        if name.ends_with(".psd")
+                        if name.ends_with(".psd") || name.ends_with(".psb")
                         {
-                            wpsd_open(self, &data);
+                            if let Err(e) = wpsd_open(self, &data)
+                            {
+                                self.open_error = Some(format!("Failed to open PSD file: {}", e));
+                            }
+                        }
+                        else if name.ends_with(".ora")
+                        {
+                            self.cancel_edit();
+                            if let Err(e) = wora_open(self, &data)
+                            {
+                                self.open_error = Some(format!("Failed to open ORA file: {}", e));
+                            }
                         }
                         else if name.ends_with(".wpp")
                         {
                             self.cancel_edit();
-                            // FIXME handle error
-                            
-                            let reader = std::io::BufReader::new(std::io::Cursor::new(data));
-                            
-                            let new = cbor4ii::serde::from_reader(reader).map_err(|x| x.to_string()).unwrap();
-                            self.load_from(new);
+                            if let Err(e) = wpp_open(self, &data)
+                            {
+                                self.open_error = Some(format!("Failed to open project file: {}", e));
+                            }
                         }
                         else
                         {
@@ -2324,89 +2415,23 @@ This warning will only be shown once.", self.max_texture_size);
                 if let Some(layer) = self.layers.find_layer_mut(self.current_layer)
                 {
                     let old_blend_mode = layer.blend_mode.clone();
+                    let is_group = layer.is_group() && layer.adjustment.is_none();
                     egui::ComboBox::from_id_salt("blend_mode_dropdown")
                         .selected_text(&layer.blend_mode)
                         .width(150.0)
                         .show_ui(ui, |ui|
                     {
-                        ui.selectable_value(&mut layer.blend_mode, "Normal".to_string(), "Normal");
-                        ui.selectable_value(&mut layer.blend_mode, "Dither".to_string(), "Dither");
-                        
-                        ui.separator();
-                        
-                        ui.selectable_value(&mut layer.blend_mode, "Darken".to_string(), "Darken");
-                        ui.selectable_value(&mut layer.blend_mode, "Multiply".to_string(), "Multiply");
-                        ui.selectable_value(&mut layer.blend_mode, "Color Burn".to_string(), "Color Burn");
-                        ui.selectable_value(&mut layer.blend_mode, "Linear Burn".to_string(), "Linear Burn");
-                        ui.selectable_value(&mut layer.blend_mode, "Subtract".to_string(), "Subtract");
-                        
-                        ui.separator();
-                        
-                        ui.selectable_value(&mut layer.blend_mode, "Lighten".to_string(), "Lighten");
-                        ui.selectable_value(&mut layer.blend_mode, "Screen".to_string(), "Screen");
-                        ui.selectable_value(&mut layer.blend_mode, "Color Dodge".to_string(), "Color Dodge");
-                        ui.selectable_value(&mut layer.blend_mode, "Glow Dodge".to_string(), "Glow Dodge");
-                        ui.selectable_value(&mut layer.blend_mode, "Add".to_string(), "Add"); // aka linear dodge
-                        ui.selectable_value(&mut layer.blend_mode, "Glow Add".to_string(), "Glow Add");
-                        ui.selectable_value(&mut layer.blend_mode, "Divide".to_string(), "Divide");
-                        
-                        ui.separator();
-                        
-                        ui.selectable_value(&mut layer.blend_mode, "Glow".to_string(), "Glow");
-                        ui.selectable_value(&mut layer.blend_mode, "Reflect".to_string(), "Reflect");
-                        
-                        ui.separator();
-                        
-                        ui.selectable_value(&mut layer.blend_mode, "Overlay".to_string(), "Overlay");
-                        ui.selectable_value(&mut layer.blend_mode, "Soft Light".to_string(), "Soft Light");
-                        ui.selectable_value(&mut layer.blend_mode, "Hard Light".to_string(), "Hard Light");
-                        ui.selectable_value(&mut layer.blend_mode, "Vivid Light".to_string(), "Vivid Light");
-                        ui.selectable_value(&mut layer.blend_mode, "Linear Light".to_string(), "Linear Light");
-                        ui.selectable_value(&mut layer.blend_mode, "Pin Light".to_string(), "Pin Light");
-                        ui.selectable_value(&mut layer.blend_mode, "Hard Mix".to_string(), "Hard Mix");
-                        
-                        ui.separator();
-                        
-                        ui.selectable_value(&mut layer.blend_mode, "Signed Add".to_string(), "Signed Add");
-                        ui.selectable_value(&mut layer.blend_mode, "Signed Diff".to_string(), "Signed Diff");
-                        ui.selectable_value(&mut layer.blend_mode, "Negation".to_string(), "Negation");
-                        ui.selectable_value(&mut layer.blend_mode, "Difference".to_string(), "Difference");
-                        ui.selectable_value(&mut layer.blend_mode, "Exclusion".to_string(), "Exclusion");
-                        
-                        ui.separator();
-                        
-                        ui.selectable_value(&mut layer.blend_mode, "Hue".to_string(), "Hue");
-                        ui.selectable_value(&mut layer.blend_mode, "Saturation".to_string(), "Saturation");
-                        ui.selectable_value(&mut layer.blend_mode, "Color".to_string(), "Color");
-                        ui.selectable_value(&mut layer.blend_mode, "Brightness".to_string(), "Brightness");
-                        
-                        ui.separator();
-                        
-                        ui.selectable_value(&mut layer.blend_mode, "Flat Hue".to_string(), "Flat Hue");
-                        ui.selectable_value(&mut layer.blend_mode, "Flat Sat".to_string(), "Flat Sat");
-                        ui.selectable_value(&mut layer.blend_mode, "Flat Color".to_string(), "Flat Color");
-                        ui.selectable_value(&mut layer.blend_mode, "Value".to_string(), "Value");
-                        
-                        ui.separator();
-                        
-                        ui.selectable_value(&mut layer.blend_mode, "Hard Sat".to_string(), "Hard Sat");
-                        ui.selectable_value(&mut layer.blend_mode, "Hard Color".to_string(), "Hard Color");
-                        ui.selectable_value(&mut layer.blend_mode, "Lightness".to_string(), "Lightness");
-                        
-                        ui.separator();
-                        
-                        ui.selectable_value(&mut layer.blend_mode, "Erase".to_string(), "Erase");
-                        ui.selectable_value(&mut layer.blend_mode, "Reveal".to_string(), "Reveal");
-                        ui.selectable_value(&mut layer.blend_mode, "Alpha Mask".to_string(), "Alpha Mask");
-                        ui.selectable_value(&mut layer.blend_mode, "Alpha Reject".to_string(), "Alpha Reject");
-                        
-                        ui.selectable_value(&mut layer.blend_mode, "Interpolate".to_string(), "Interpolate");
-                        
-                        ui.separator();
-                        
-                        ui.selectable_value(&mut layer.blend_mode, "Custom".to_string(), "Custom");
-                        ui.selectable_value(&mut layer.blend_mode, "Custom Tri".to_string(), "Custom Tri");
-                        ui.selectable_value(&mut layer.blend_mode, "Custom Quad".to_string(), "Custom Quad");
+                        for (i, group) in BLEND_MODE_GROUPS.iter().enumerate()
+                        {
+                            if i > 0
+                            {
+                                ui.separator();
+                            }
+                            for mode in group.iter().filter(|x| is_group || **x != "Pass Through")
+                            {
+                                ui.selectable_value(&mut layer.blend_mode, mode.to_string(), *mode);
+                            }
+                        }
                     });
                     
                     let mut rerender = false;
@@ -2439,11 +2464,61 @@ This warning will only be shown once.", self.max_texture_size);
                     let slider_response2 = ui.add(egui::Slider::new(&mut fill_opacity, 0.0..=100.0).clamping(SliderClamping::Always));
                     layer.opacity = opacity/100.0;
                     layer.fill_opacity = fill_opacity/100.0;
+                    
+                    // checked entries are present on the layer; picking one toggles it
+                    let mut effects_changed = false;
+                    egui::ComboBox::from_id_salt("effects_dropdown").selected_text("Effects").show_ui(ui, |ui|
+                    {
+                        for (kind, name) in [("bevel", "Bevel & Emboss"), ("satin", "Satin"), ("innerglow", "Inner Glow"), ("outerglow", "Outer Glow")]
+                        {
+                            if ui.selectable_label(layer.effects.contains_key(kind), name).clicked()
+                            {
+                                if layer.effects.remove(kind).is_none()
+                                {
+                                    layer.effects.insert(kind.to_string(), fx_default(kind).unwrap());
+                                }
+                                effects_changed = true;
+                            }
+                        }
+                    });
+                    // smart objects get re-rendered from their original, so resizing them doesn't lose detail
+                    let mut smart_changed = false;
+                    if layer.smart_object.is_some()
+                    {
+                        ui.horizontal(|ui|
+                        {
+                            ui.label("Smart Object");
+                            for (label, scale) in [("Shrink", 0.8), ("Grow", 1.25)]
+                            {
+                                if ui.button(label).clicked() && layer.scale_smart_object([scale, scale])
+                                {
+                                    smart_changed = true;
+                                }
+                            }
+                        });
+                    }
+                    // text layers keep their original rendering until their text gets changed here
+                    let mut text_changed = false;
+                    if let Some(text) = &layer.text
+                    {
+                        let mut new_text = text.text.clone();
+                        ui.label("Text");
+                        let response = ui.add(egui::TextEdit::multiline(&mut new_text).desired_rows(2));
+                        if response.changed()
+                        {
+                            text_changed = layer.edit_text(&new_text);
+                        }
+                        // one undo step per edit, rather than one per keystroke
+                        if response.lost_focus() && layer.old_info_for_undo.text != layer.get_info().text
+                        {
+                            smart_changed = true;
+                        }
+                    }
                     let id = layer.uuid;
                     
                     #[allow(clippy::if_same_then_else)]
                     
-                    if old_blend_mode != layer.blend_mode
+                    if old_blend_mode != layer.blend_mode || effects_changed || smart_changed
                     {
                         self.log_layer_info_change(self.current_layer);
                         rerender = true;
@@ -2462,7 +2537,7 @@ This warning will only be shown once.", self.max_texture_size);
                         self.log_layer_info_change(self.current_layer);
                     }
                     
-                    if old_opacity != opacity || old_fill_opacity != fill_opacity || rerender
+                    if old_opacity != opacity || old_fill_opacity != fill_opacity || rerender || text_changed
                     {
                         self.full_rerender_with(id);
                     }
@@ -3229,6 +3304,7 @@ This warning will only be shown once.", self.max_texture_size);
     }
     fn on_exit(&mut self, gl : Option<&glow::Context>)
     {
+        autosave_clean_exit(self);
         if let Some(gl) = gl
         {
             for shader in self.shaders.values()
@@ -3239,10 +3315,18 @@ This warning will only be shown once.", self.max_texture_size);
     }
 }
 
+#[cfg(feature = "gui")]
 #[allow(clippy::field_reassign_with_default)]
 #[cfg(not(target_arch = "wasm32"))]
 pub fn do_main()
 {
+    // render/convert/info run without opening a window
+    let args : Vec<String> = std::env::args().collect();
+    if let Some(code) = cli_main(&args)
+    {
+        std::process::exit(code);
+    }
+    
     let mut options = eframe::NativeOptions::default();
     
     let icon = eframe::icon_data::from_png_bytes(include_bytes!("data/warpaint logo.png")).unwrap();
@@ -3261,7 +3345,7 @@ pub fn do_main()
     
     let shared_state = Arc::clone(&wp.shared);
     
-    let fname = std::env::args().nth(1).unwrap_or_default();
+    let fname = args.get(1).cloned().unwrap_or_default();
     
     wp.auto_open = fname;
     
@@ -3324,6 +3408,18 @@ pub fn do_main()
     eventloop.run_app(&mut w).unwrap();
 }
 
+// built without the editor, so the command line tools are all there is
+#[cfg(all(not(target_arch = "wasm32"), not(feature = "gui")))]
+pub fn do_main()
+{
+    let args : Vec<String> = std::env::args().collect();
+    std::process::exit(cli_main(&args).unwrap_or_else(||
+    {
+        eprintln!("{}", USAGE);
+        2
+    }));
+}
+
 #[cfg(target_arch = "wasm32")]
 #[wasm_bindgen]
 extern "C" {
@@ -3348,7 +3444,7 @@ pub fn web_pressure_update(pressure : f32)
 }
 
 // when compiling to web using trunk.
-#[cfg(target_arch = "wasm32")]
+#[cfg(all(target_arch = "wasm32", feature = "gui"))]
 pub fn do_main()
 {
     // Make sure panics are logged using `console.error`.
@@ -3440,10 +3536,10 @@ pub fn do_main()
 }
 
 
-#[cfg(target_os = "android")]
+#[cfg(all(target_os = "android", feature = "gui"))]
 static mut APP_CONTEXT : Option<egui_winit::winit::platform::android::activity::AndroidApp> = None;
 
-#[cfg(target_os = "android")]
+#[cfg(all(target_os = "android", feature = "gui"))]
 pub fn show_soft_input(show_implicit : bool)
 {
     // https://github.com/rust-mobile/android-activity/pull/178
@@ -3490,12 +3586,13 @@ pub fn show_soft_input(show_implicit : bool)
     if env.exception_check().unwrap() { env.exception_clear(); }
 }
 
+#[cfg(feature = "gui")]
 fn check_ui_keyboard_focus(ctx : &egui::Context) -> bool
 {
     ctx.memory(|mem| mem.focused()).is_some()
 }
 
-#[cfg(target_os = "android")]
+#[cfg(all(target_os = "android", feature = "gui"))]
 fn get_insets(app : egui_winit::winit::platform::android::activity::AndroidApp) -> (f32, f32)
 {
     use jni::objects::JObject;
@@ -3524,7 +3621,7 @@ fn get_insets(app : egui_winit::winit::platform::android::activity::AndroidApp)
     (top as f32, bottom as f32)
 }
 
-#[cfg(target_os = "android")]
+#[cfg(all(target_os = "android", feature = "gui"))]
 fn android_get_file()
 {
     use jni::objects::JClass;
@@ -3569,7 +3666,7 @@ fn android_get_file()
         panic!();
     }
 }
-#[cfg(target_os = "android")]
+#[cfg(all(target_os = "android", feature = "gui"))]
 fn android_check_file(app : &mut crate::Warpainter) -> Option<(Vec<u8>, String, String)>
 {
     use jni::objects::JClass;
@@ -3611,7 +3708,7 @@ fn android_check_file(app : &mut crate::Warpainter) -> Option<(Vec<u8>, String,
     None
 }
 
-#[cfg(target_os = "android")]
+#[cfg(all(target_os = "android", feature = "gui"))]
 #[no_mangle]
 fn android_main(app : egui_winit::winit::platform::android::activity::AndroidApp)
 {
@@ -3633,19 +3730,19 @@ fn android_main(app : egui_winit::winit::platform::android::activity::AndroidApp
         Box::new(|_cc| Ok(Box::<Warpainter>::default())),
     ).unwrap();
 }
-#[cfg(target_os = "android")]
+#[cfg(all(target_os = "android", feature = "gui"))]
 use jni::objects::{JObject, JString, JValue};
 
-#[cfg(target_os = "android")]
+#[cfg(all(target_os = "android", feature = "gui"))]
 use jni::sys::{jobject, jstring};
-#[cfg(target_os = "android")]
+#[cfg(all(target_os = "android", feature = "gui"))]
 use jni::JNIEnv;
 
-#[cfg(target_os = "android")]
+#[cfg(all(target_os = "android", feature = "gui"))]
 use std::fs::File;
-#[cfg(target_os = "android")]
+#[cfg(all(target_os = "android", feature = "gui"))]
 use std::io::Write;
-#[cfg(target_os = "android")]
+#[cfg(all(target_os = "android", feature = "gui"))]
 use std::path::Path;
-#[cfg(target_os = "android")]
+#[cfg(all(target_os = "android", feature = "gui"))]
 use std::ptr::null_mut;
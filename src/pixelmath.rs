@@ -592,6 +592,25 @@ impl BlendModeTriad for BlendModeLuminosity
     }
 }
 
+// photoshop style, picking whichever whole color has the lower/higher luma
+
+pub (crate) struct BlendModeDarkerColor;
+impl BlendModeTriad for BlendModeDarkerColor
+{
+    fn blend(top : [f32; 3], bottom : [f32; 3]) -> [f32; 3]
+    {
+        if calc_y(top) < calc_y(bottom) { top } else { bottom }
+    }
+}
+pub (crate) struct BlendModeLighterColor;
+impl BlendModeTriad for BlendModeLighterColor
+{
+    fn blend(top : [f32; 3], bottom : [f32; 3]) -> [f32; 3]
+    {
+        if calc_y(top) > calc_y(bottom) { top } else { bottom }
+    }
+}
+
 // "soft" versions, using HSV
 
 pub (crate) struct BlendModeFlatHue;
@@ -838,6 +857,28 @@ impl BlendModeFull for BlendModeGlowDodge
     }
 }
 
+// the blend modes users can pick, in the sections the layer panel shows them in. the rest of the
+// names below are for internal use. "Pass Through" only applies to groups, and the custom modes
+// take their formula from the layer's custom_blend_mode
+pub (crate) const BLEND_MODE_GROUPS : &[&[&str]] = &[
+    &["Pass Through", "Normal", "Dither"],
+    &["Darken", "Multiply", "Color Burn", "Linear Burn", "Subtract", "Darker Color"],
+    &["Lighten", "Screen", "Color Dodge", "Glow Dodge", "Add", "Glow Add", "Divide", "Lighter Color"],
+    &["Glow", "Reflect"],
+    &["Overlay", "Soft Light", "Hard Light", "Vivid Light", "Linear Light", "Pin Light", "Hard Mix"],
+    &["Signed Add", "Signed Diff", "Negation", "Difference", "Exclusion"],
+    &["Hue", "Saturation", "Color", "Luminosity"],
+    &["Flat Hue", "Flat Sat", "Flat Color", "Value"],
+    &["Hard Sat", "Hard Color", "Lightness"],
+    &["Erase", "Reveal", "Alpha Mask", "Alpha Reject", "Interpolate"],
+    &["Custom", "Custom Tri", "Custom Quad"],
+];
+
+pub (crate) fn blend_modes() -> impl Iterator<Item = &'static str>
+{
+    BLEND_MODE_GROUPS.iter().flat_map(|x| x.iter().copied())
+}
+
 type FloatBlendFn = dyn Fn([f32; 4], [f32; 4], f32, f32, bool) -> [f32; 4];
 type IntBlendFn = fn([u8; 4], [u8; 4], f32, f32, bool) -> [u8; 4];
 
@@ -859,6 +900,8 @@ pub (crate) fn find_blend_func_float(blend_mode : &str) -> Box<FloatBlendFn>
         "Negation" => px_func_float::<BlendModeNegation>,
         "Lighten" => px_func_float::<BlendModeLighten>,
         "Darken" => px_func_float::<BlendModeDarken>,
+        "Darker Color" => px_func_triad_float::<BlendModeDarkerColor>,
+        "Lighter Color" => px_func_triad_float::<BlendModeLighterColor>,
         "Linear Burn" => px_func_float::<BlendModeLinearBurn>,
         "Color Burn" => px_func_float::<BlendModeColorBurn>,
         "Color Dodge" => px_func_float::<BlendModeColorDodge>,
@@ -982,6 +1025,8 @@ pub (crate) fn find_blend_func(blend_mode : &str) -> IntBlendFn
         "Negation" => px_func::<BlendModeNegation>,
         "Lighten" => px_func::<BlendModeLighten>,
         "Darken" => px_func::<BlendModeDarken>,
+        "Darker Color" => px_func_triad::<BlendModeDarkerColor>,
+        "Lighter Color" => px_func_triad::<BlendModeLighterColor>,
         "Linear Burn" => px_func::<BlendModeLinearBurn>,
         "Color Burn" => px_func::<BlendModeColorBurn>,
         "Color Dodge" => px_func::<BlendModeColorDodge>,
@@ -1303,3 +1348,40 @@ pub (crate) fn hsl_to_rgb(hsla : [f32; 4]) -> [f32; 4]
     
     hsv_to_rgb(hsva)
 }
+#[inline]
+pub (crate) fn linear_to_srgb(x : f32) -> f32
+{
+    if x <= 0.0031308
+    {
+        x * 12.92
+    }
+    else
+    {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+#[inline]
+pub (crate) fn srgb_to_linear(x : f32) -> f32
+{
+    if x <= 0.04045
+    {
+        x / 12.92
+    }
+    else
+    {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+// CIE L*a*b* (D65) to sRGB
+pub (crate) fn lab_to_rgb(l : f64, a : f64, b : f64) -> [f64; 3]
+{
+    let fy = (l + 16.0) / 116.0;
+    let fx = fy + a / 500.0;
+    let fz = fy - b / 200.0;
+    let f = |t : f64| if t > 6.0 / 29.0 { t * t * t } else { 3.0 * (6.0f64 / 29.0).powi(2) * (t - 4.0 / 29.0) };
+    let (x, y, z) = (f(fx) * 0.95047, f(fy), f(fz) * 1.08883);
+    let r = 3.2406 * x - 1.5372 * y - 0.4986 * z;
+    let g = -0.9689 * x + 1.8758 * y + 0.0415 * z;
+    let b = 0.0557 * x - 0.2040 * y + 1.0570 * z;
+    [r, g, b].map(|c| linear_to_srgb(c as f32).clamp(0.0, 1.0) as f64)
+}
@@ -1,3 +1,4 @@
+#[cfg(feature = "gui")]
 use eframe::egui;
 
 use std::collections::HashMap;
@@ -399,7 +400,7 @@ fn get_thread_count() -> usize
 }
 use std::sync::OnceLock;
 static THREAD_POOL: OnceLock<rayon::ThreadPool> = OnceLock::new();
-fn get_pool() -> &'static rayon::ThreadPool
+pub (crate) fn get_pool() -> &'static rayon::ThreadPool
 {
     THREAD_POOL.get_or_init(|| rayon::ThreadPoolBuilder::new().num_threads(get_thread_count()).build().unwrap())
 }
@@ -568,6 +569,9 @@ impl<const N : usize> Image<N>
     }
 }
 
+// a combining operation, then closed loops of (preceding control, anchor, leaving control) knots
+pub (crate) type BezierShape = (i16, Vec<Vec<[[f32; 2]; 3]>>);
+
 impl Image<1>
 {
     pub (crate) fn from_yimage(input : &image::GrayImage, inverted : bool) -> Self
@@ -589,6 +593,246 @@ impl Image<1>
         }
         ret
     }
+    pub (crate) fn to_yimage(&self) -> image::GrayImage
+    {
+        image::GrayImage::from_vec(self.width as u32, self.height as u32, self.data.to_int()).unwrap()
+    }
+    // antialiased fill of shapes made of closed cubic bezier loops, filled by nonzero winding
+    // each shape gets combined with what's under it by its own operation (0 xor, 1 union, 2 subtract, 3 intersect)
+    // knots are (preceding control, anchor, leaving control), in pixel coordinates
+    pub (crate) fn from_bezier_loops(w : usize, h : usize, shapes : &[BezierShape], initial_fill : bool) -> Self
+    {
+        const SUBROWS : usize = 4;
+        
+        let mut coverage = vec!(if initial_fill { 1.0 } else { 0.0 }; w*h);
+        let mut shape_coverage = vec!(0.0f32; w*h);
+        let mut crossings = vec!();
+        for (op, loops) in shapes
+        {
+            // flatten into polygons, with roughly one point per pixel
+            let mut polygons = vec!();
+            for knots in loops.iter().filter(|x| !x.is_empty())
+            {
+                let mut points = vec!();
+                for i in 0..knots.len()
+                {
+                    let (a, b) = (knots[i], knots[(i + 1) % knots.len()]);
+                    let p = [a[1], a[2], b[0], b[1]];
+                    let dist = |a : [f32; 2], b : [f32; 2]| ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2)).sqrt();
+                    let len = dist(p[0], p[1]) + dist(p[1], p[2]) + dist(p[2], p[3]);
+                    let steps = len.ceil().clamp(1.0, 256.0) as usize;
+                    for s in 0..steps
+                    {
+                        let t = s as f32 / steps as f32;
+                        let u = 1.0 - t;
+                        let f = |c : usize| u*u*u*p[0][c] + 3.0*u*u*t*p[1][c] + 3.0*u*t*t*p[2][c] + t*t*t*p[3][c];
+                        points.push([f(0), f(1)]);
+                    }
+                }
+                polygons.push(points);
+            }
+            
+            let ys = polygons.iter().flatten().map(|p| p[1]);
+            let mut min_y = (ys.clone().fold(f32::INFINITY, f32::min).floor().max(0.0) as usize).min(h);
+            let mut max_y = (ys.fold(f32::NEG_INFINITY, f32::max).ceil().max(0.0) as usize).min(h);
+            if *op == 3
+            {
+                // intersecting also clears everything outside of the shape
+                (min_y, max_y) = (0, h);
+            }
+            if min_y >= max_y
+            {
+                continue;
+            }
+            shape_coverage[min_y*w..max_y*w].iter_mut().for_each(|c| *c = 0.0);
+            for y in min_y..max_y
+            {
+                let row = &mut shape_coverage[y*w..(y + 1)*w];
+                // several sub-scanlines per row for vertical antialiasing, with exact horizontal coverage along each
+                for s in 0..SUBROWS
+                {
+                    let sy = y as f32 + (s as f32 + 0.5) / SUBROWS as f32;
+                    crossings.clear();
+                    for points in polygons.iter()
+                    {
+                        for i in 0..points.len()
+                        {
+                            let (p, q) = (points[i], points[(i + 1) % points.len()]);
+                            if (p[1] <= sy) != (q[1] <= sy)
+                            {
+                                let t = (sy - p[1]) / (q[1] - p[1]);
+                                crossings.push((p[0] + t * (q[0] - p[0]), if q[1] > p[1] { 1 } else { -1 }));
+                            }
+                        }
+                    }
+                    crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+                    
+                    let weight = 1.0 / SUBROWS as f32;
+                    let mut winding = 0;
+                    let mut start = 0.0;
+                    for &(x, dir) in crossings.iter()
+                    {
+                        let was_inside = winding != 0;
+                        winding += dir;
+                        if !was_inside && winding != 0
+                        {
+                            start = x;
+                            continue;
+                        }
+                        if !was_inside || winding != 0
+                        {
+                            continue;
+                        }
+                        let x0 = start.clamp(0.0, w as f32);
+                        let x1 = x.clamp(0.0, w as f32);
+                        if x1 <= x0
+                        {
+                            continue;
+                        }
+                        let (i0, i1) = (x0 as usize, x1 as usize);
+                        if i0 == i1
+                        {
+                            row[i0] += (x1 - x0) * weight;
+                            continue;
+                        }
+                        row[i0] += (i0 as f32 + 1.0 - x0) * weight;
+                        for c in &mut row[i0 + 1..i1]
+                        {
+                            *c += weight;
+                        }
+                        if i1 < w
+                        {
+                            row[i1] += (x1 - i1 as f32) * weight;
+                        }
+                    }
+                }
+            }
+            
+            for (c, b) in coverage[min_y*w..max_y*w].iter_mut().zip(shape_coverage[min_y*w..max_y*w].iter())
+            {
+                let (a, b) = (*c, b.min(1.0));
+                *c = match op
+                {
+                    1 => a + b - a * b,
+                    2 => a * (1.0 - b),
+                    3 => a * b,
+                    _ => a + b - 2.0 * a * b,
+                };
+            }
+        }
+        
+        let mut ret = Self::blank(w, h);
+        for y in 0..h
+        {
+            for x in 0..w
+            {
+                ret.set_pixel(x as isize, y as isize, [(coverage[y*w + x].clamp(0.0, 1.0) * 255.0).round() as u8]);
+            }
+        }
+        ret
+    }
+}
+
+// splits a color into how much of it is red, yellow, green, cyan, blue, magenta, plus the gray floor under them
+pub (crate) fn rgb_to_rygcbml(rgb: &[f32]) -> [f32; 7]
+{
+    let [r, g, b] = [rgb[0], rgb[1], rgb[2]];
+    
+    let l = r.min(g).min(b);
+    
+    let r = r - l;
+    let g = g - l;
+    let b = b - l;
+    
+    let y = r.min(g);
+    let c = g.min(b);
+    let m = r.min(b);
+    let r2 = r - y - m;
+    let g2 = g - y - c;
+    let b2 = b - c - m;
+    
+    [r2, y, g2, c, b2, m, l]
+}
+
+// samples a gradient made of color stops ([r, g, b, location, midpoint]) and alpha stops ([a, location, midpoint])
+pub (crate) fn sample_gradient(colors : &[[f32; 5]], alphas : &[[f32; 3]], t : f32) -> [f32; 4]
+{
+    if colors.is_empty() || alphas.is_empty()
+    {
+        return [0.0, 0.0, 0.0, 0.0];
+    }
+    let mut nc = 0;
+    while nc + 1 < colors.len() && colors[nc+1][3] < t
+    {
+        nc += 1;
+    }
+    let nc2 = (nc+1).min(colors.len()-1);
+    // clamped, so stops that don't start at 0 or end at 1 don't extrapolate
+    let mut tc = if colors[nc2][3] > colors[nc][3] { unlerp(colors[nc][3], colors[nc2][3], t).clamp(0.0, 1.0) } else { 0.0 };
+    let biascraw = colors[nc2][3+1].clamp(0.0001, 0.9999);
+    let biasc = biascraw * 2.0 - 1.0;
+    if tc > biascraw
+    {
+        tc = (tc - 1.0) / (1.0 - biasc) + 1.0;
+    }
+    else
+    {
+        tc /= 1.0 + biasc;
+    }
+    
+    let mut c = [colors[nc][0], colors[nc][1], colors[nc][2]];
+    for (i, c) in c.iter_mut().enumerate()
+    {
+        *c = lerp(*c, colors[nc2][i], tc);
+    }
+    
+    let mut na = 0;
+    while na + 1 < alphas.len() && alphas[na+1][1] < t
+    {
+        na += 1;
+    }
+    let na2 = (na+1).min(alphas.len()-1);
+    let mut ta = if alphas[na2][1] > alphas[na][1] { unlerp(alphas[na][1], alphas[na2][1], t).clamp(0.0, 1.0) } else { 0.0 };
+    let biasaraw = alphas[na2][1+1].clamp(0.0001, 0.9999);
+    let biasa = biasaraw * 2.0 - 1.0;
+    if ta > biasaraw
+    {
+        ta = (ta - 1.0) / (1.0 - biasa) + 1.0;
+    }
+    else
+    {
+        ta /= 1.0 + biasa;
+    }
+    
+    let a = lerp(alphas[na][0], alphas[na2][0], ta);
+    
+    [c[0], c[1], c[2], a]
+}
+
+// vector masks apply on top of whatever the raster mask already gives
+fn with_vector_mask<'a>(get_opacity : Box<dyn Fn(usize, usize) -> f32 + Send + Sync + 'a>, vector_mask : Option<(&'a Image<1>, &MaskInfo)>, top_offset : [isize; 2]) -> Box<dyn Fn(usize, usize) -> f32 + Send + Sync + 'a>
+{
+    match vector_mask
+    {
+        Some((mask, info)) if !info.disabled =>
+        {
+            let xoffs = info.x as isize + top_offset[0];
+            let yoffs = info.y as isize + top_offset[1];
+            let default = info.default_color as f32 / 255.0;
+            Box::new(move |x : usize, y : usize| get_opacity(x, y) * mask.get_pixel_float_default(x as isize - xoffs, y as isize - yoffs, default)[0])
+        }
+        _ => get_opacity,
+    }
+}
+
+// about one 8-bit step of noise, to break up banding in gradients
+pub (crate) fn dither_noise(x : usize, y : usize) -> f32
+{
+    let mut n = (x as u32).wrapping_mul(0x9E3779B1) ^ (y as u32).wrapping_mul(0x85EBCA77);
+    n ^= n >> 15;
+    n = n.wrapping_mul(0x2C1B3C6D);
+    n ^= n >> 12;
+    ((n & 0xFFFF) as f32 / 65535.0 - 0.5) / 255.0
 }
 
 pub (crate) fn fx_get_radius(fx : &(String, HashMap<String, Vec<crate::FxData>>)) -> f32
@@ -598,7 +842,12 @@ pub (crate) fn fx_get_radius(fx : &(String, HashMap<String, Vec<crate::FxData>>)
         "stroke" => fx.1["size"][0].f() as f32 + 2.0,
         "colorfill" => 0.0,
         "gradfill" => 0.0,
+        "patternfill" => 0.0,
         "dropshadow" => fx.1["distance"][0].f() as f32,
+        "outerglow" => fx.1["size"][0].f() as f32 + 2.0,
+        "innerglow" => 0.0,
+        "bevel" => if fx.1["_region"][0].s() == "outside" { fx.1["size"][0].f() as f32 + 2.0 } else { 0.0 },
+        "satin" => 0.0,
         _ => panic!()
     }
 }
@@ -610,6 +859,10 @@ pub (crate) fn fx_get_mask_func(fx : &(String, HashMap<String, Vec<crate::FxData
         "stroke" => "Weld".to_string(),
         "colorfill" => "None".to_string(),
         "gradfill" => "None".to_string(),
+        "patternfill" => "None".to_string(),
+        "innerglow" => "None".to_string(),
+        "bevel" => "None".to_string(),
+        "satin" => "None".to_string(),
         _ => "Weld".to_string()
     }
 }
@@ -630,7 +883,12 @@ pub (crate) fn fx_get_early_blend_mode(fx : &(String, HashMap<String, Vec<crate:
         "stroke" => fx.1["mode"][0].s(),
         "colorfill" => fx.1["mode"][0].s(),
         "gradfill" => fx.1["mode"][0].s(),
+        "patternfill" => fx.1["mode"][0].s(),
         "dropshadow" => fx.1["mode"][0].s(),
+        "outerglow" => fx.1["mode"][0].s(),
+        "innerglow" => fx.1["mode"][0].s(),
+        "bevel" => fx.1["mode"][0].s(),
+        "satin" => fx.1["mode"][0].s(),
         _ => "Copy".to_string(),
     }
 }
@@ -641,15 +899,111 @@ pub (crate) fn fx_is_fill(fx : &(String, HashMap<String, Vec<crate::FxData>>)) -
         "stroke" => false,
         "colorfill" => true,
         "gradfill" => true,
+        "patternfill" => true,
         "dropshadow" => false,
+        "outerglow" => false,
+        "innerglow" => true,
+        "bevel" => fx.1["_region"][0].s() != "outside",
+        "satin" => true,
         _ => false
     }
 }
+// effects that go underneath the layer's own content
+pub (crate) fn fx_is_underlay(fx : &(String, HashMap<String, Vec<crate::FxData>>)) -> bool
+{
+    match fx.0.as_str()
+    {
+        "dropshadow" | "outerglow" => true,
+        "bevel" => fx.1["_region"][0].s() == "outside",
+        _ => false,
+    }
+}
+// default parameters for effects that can be added to a layer from scratch
+pub (crate) fn fx_default(kind : &str) -> Option<HashMap<String, Vec<crate::FxData>>>
+{
+    let mut hm : HashMap<String, Vec<crate::FxData>> = HashMap::new();
+    hm.insert("enabled".to_string(), vec!(true.into()));
+    match kind
+    {
+        "outerglow" | "innerglow" =>
+        {
+            hm.insert("mode".to_string(), vec!("Screen".to_string().into()));
+            hm.insert("opacity".to_string(), vec!(75.0.into()));
+            hm.insert("color".to_string(), vec!(1.0.into(), 1.0.into(), 0.75.into(), 1.0.into()));
+            hm.insert("size".to_string(), vec!(5.0.into()));
+            hm.insert("choke".to_string(), vec!(0.0.into()));
+            hm.insert("technique".to_string(), vec!("softer".to_string().into()));
+            hm.insert("source".to_string(), vec!("edge".to_string().into()));
+        }
+        "bevel" =>
+        {
+            hm.insert("style".to_string(), vec!("inner".to_string().into()));
+            hm.insert("technique".to_string(), vec!("smooth".to_string().into()));
+            hm.insert("depth".to_string(), vec!(100.0.into()));
+            hm.insert("direction".to_string(), vec!("up".to_string().into()));
+            hm.insert("size".to_string(), vec!(5.0.into()));
+            hm.insert("angle".to_string(), vec!(120.0.into()));
+            hm.insert("altitude".to_string(), vec!(30.0.into()));
+            hm.insert("highlight mode".to_string(), vec!("Screen".to_string().into()));
+            hm.insert("highlight color".to_string(), vec!(1.0.into(), 1.0.into(), 1.0.into(), 1.0.into()));
+            hm.insert("highlight opacity".to_string(), vec!(75.0.into()));
+            hm.insert("shadow mode".to_string(), vec!("Multiply".to_string().into()));
+            hm.insert("shadow color".to_string(), vec!(0.0.into(), 0.0.into(), 0.0.into(), 1.0.into()));
+            hm.insert("shadow opacity".to_string(), vec!(75.0.into()));
+        }
+        "satin" =>
+        {
+            hm.insert("mode".to_string(), vec!("Multiply".to_string().into()));
+            hm.insert("opacity".to_string(), vec!(50.0.into()));
+            hm.insert("color".to_string(), vec!(0.0.into(), 0.0.into(), 0.0.into(), 1.0.into()));
+            hm.insert("angle".to_string(), vec!(19.0.into()));
+            hm.insert("distance".to_string(), vec!(11.0.into()));
+            hm.insert("size".to_string(), vec!(14.0.into()));
+            hm.insert("invert".to_string(), vec!(true.into()));
+        }
+        _ => return None,
+    }
+    Some(hm)
+}
+// split effects that draw several passes (e.g. bevel highlights and shadows) into one entry per pass
+pub (crate) fn fx_expand(fx : (String, HashMap<String, Vec<crate::FxData>>)) -> Vec<(String, HashMap<String, Vec<crate::FxData>>)>
+{
+    if fx.0 != "bevel"
+    {
+        return vec!(fx);
+    }
+    let style = fx.1["style"][0].s();
+    let mut regions = vec!();
+    if style != "outer"
+    {
+        regions.push("inside");
+    }
+    if style == "outer" || style == "emboss" || style == "pillow"
+    {
+        regions.push("outside");
+    }
+    
+    let mut ret = vec!();
+    for region in regions
+    {
+        for pass in ["highlight", "shadow"]
+        {
+            let mut hm = fx.1.clone();
+            hm.insert("_pass".to_string(), vec!(pass.to_string().into()));
+            hm.insert("_region".to_string(), vec!(region.to_string().into()));
+            hm.insert("mode".to_string(), fx.1[&format!("{} mode", pass)].clone());
+            hm.insert("color".to_string(), fx.1[&format!("{} color", pass)].clone());
+            hm.insert("opacity".to_string(), fx.1[&format!("{} opacity", pass)].clone());
+            ret.push((fx.0.clone(), hm));
+        }
+    }
+    ret
+}
 pub (crate) fn fx_update_metadata(fx : &mut (String, HashMap<String, Vec<crate::FxData>>), layer : &crate::Layer, img : &Image<4>)
 {
     match fx.0.as_str()
     {
-        "gradfill" =>
+        "gradfill" | "patternfill" =>
         {
             fx.1.insert("_x0".to_string(), vec!((layer.offset[0] as f64).into()));
             fx.1.insert("_y0".to_string(), vec!((layer.offset[1] as f64).into()));
@@ -684,7 +1038,12 @@ pub (crate) fn fx_get_weld_func(fx : &(String, HashMap<String, Vec<crate::FxData
         }
         "colorfill" => "Copy".to_string(),
         "gradfill" => "Copy".to_string(),
+        "patternfill" => "Copy".to_string(),
         "dropshadow" => "Interpolate".to_string(),
+        "outerglow" => "Interpolate".to_string(),
+        "innerglow" => "Copy".to_string(),
+        "bevel" => if fx.1["_region"][0].s() == "outside" { "Interpolate".to_string() } else { "Copy".to_string() },
+        "satin" => "Copy".to_string(),
         //"dropshadow" => fx.1["mode"][0].s(),
         _ => "Weld".to_string()
     }
@@ -707,6 +1066,21 @@ impl Image<4>
         }
         ret
     }
+    pub (crate) fn from_rgbafimage(input : &image::Rgba32FImage) -> Self
+    {
+        let (w, h) = input.dimensions();
+        let data = ImageData::<4>::new_float(w as usize, h as usize);
+        let mut ret = Self { width : w as usize, height : h as usize, data };
+        for y in 0..ret.height
+        {
+            for x in 0..ret.width
+            {
+                let px = input.get_pixel(x as u32, y as u32).0;
+                ret.set_pixel_float(x as isize, y as isize, px);
+            }
+        }
+        ret
+    }
     pub (crate) fn blank_white_transparent(w : usize, h : usize) -> Self
     {
         let mut data = ImageData::new_int(w, h);
@@ -729,7 +1103,108 @@ impl Image<4>
         }
         Self { width : w, height : h, data }
     }
-    pub (crate) fn apply_fx_dummy_outline(&mut self, rect : [[f32; 2]; 2], source : Option<&Self>, mask : Option<&Image<1>>, mask_info : Option<&MaskInfo>, top_opacity : f32, top_alpha_modifier : f32, top_funny_flag : bool, top_offset : [isize; 2], blend_mode : &str)
+    // approximate distance from each pixel to the nearest mostly-opaque pixel (or mostly-transparent one, if inside),
+    // over the image grown by pad pixels on each side. returns the field and its size
+    pub (crate) fn distance_field(&self, pad : usize, inside : bool) -> (Vec<f32>, usize, usize)
+    {
+        let w = self.width + pad * 2;
+        let h = self.height + pad * 2;
+        let mut field = vec![f32::INFINITY; w * h];
+        for y in 0..h
+        {
+            for x in 0..w
+            {
+                let a = self.get_pixel_float(x as isize - pad as isize, y as isize - pad as isize)[3];
+                if (a >= 0.5) != inside
+                {
+                    field[y * w + x] = 0.0;
+                }
+            }
+        }
+        
+        let diag = std::f32::consts::SQRT_2;
+        for y in 0..h
+        {
+            for x in 0..w
+            {
+                let mut d = field[y * w + x];
+                if x > 0 { d = d.min(field[y * w + x - 1] + 1.0); }
+                if y > 0
+                {
+                    d = d.min(field[(y - 1) * w + x] + 1.0);
+                    if x > 0 { d = d.min(field[(y - 1) * w + x - 1] + diag); }
+                    if x + 1 < w { d = d.min(field[(y - 1) * w + x + 1] + diag); }
+                }
+                field[y * w + x] = d;
+            }
+        }
+        for y in (0..h).rev()
+        {
+            for x in (0..w).rev()
+            {
+                let mut d = field[y * w + x];
+                if x + 1 < w { d = d.min(field[y * w + x + 1] + 1.0); }
+                if y + 1 < h
+                {
+                    d = d.min(field[(y + 1) * w + x] + 1.0);
+                    if x + 1 < w { d = d.min(field[(y + 1) * w + x + 1] + diag); }
+                    if x > 0 { d = d.min(field[(y + 1) * w + x - 1] + diag); }
+                }
+                field[y * w + x] = d;
+            }
+        }
+        (field, w, h)
+    }
+    // alpha channel blurred by roughly the given radius (three box blur passes),
+    // over the image grown by pad pixels on each side. returns the field and its size
+    pub (crate) fn blurred_alpha(&self, pad : usize, radius : f32) -> (Vec<f32>, usize, usize)
+    {
+        let w = self.width + pad * 2;
+        let h = self.height + pad * 2;
+        let mut field = vec![0.0; w * h];
+        for y in 0..h
+        {
+            for x in 0..w
+            {
+                field[y * w + x] = self.get_pixel_float(x as isize - pad as isize, y as isize - pad as isize)[3];
+            }
+        }
+        
+        let r = (radius / 2.0).ceil().max(0.0) as isize;
+        if r == 0
+        {
+            return (field, w, h);
+        }
+        let n = (r * 2 + 1) as f32;
+        let mut temp = vec![0.0; w.max(h)];
+        for _ in 0..3
+        {
+            for (stride, len, lines, line_stride) in [(1, w, h, w), (w, h, w, 1)]
+            {
+                for line in 0..lines
+                {
+                    let base = line * line_stride;
+                    let get = |field : &Vec<f32>, i : isize| field[base + i.clamp(0, len as isize - 1) as usize * stride];
+                    let mut sum = 0.0;
+                    for i in -r..=r
+                    {
+                        sum += get(&field, i);
+                    }
+                    for i in 0..len as isize
+                    {
+                        temp[i as usize] = sum / n;
+                        sum += get(&field, i + r + 1) - get(&field, i - r);
+                    }
+                    for i in 0..len
+                    {
+                        field[base + i * stride] = temp[i];
+                    }
+                }
+            }
+        }
+        (field, w, h)
+    }
+    pub (crate) fn apply_fx_dummy_outline(&mut self, rect : [[f32; 2]; 2], source : Option<&Self>, mask : Option<&Image<1>>, mask_info : Option<&MaskInfo>, vector_mask : Option<(&Image<1>, &MaskInfo)>, top_opacity : f32, top_alpha_modifier : f32, top_funny_flag : bool, top_offset : [isize; 2], blend_mode : &str)
     {
         if blend_mode == "None" { return; }
         //println!("----evil");
@@ -776,10 +1251,10 @@ impl Image<4>
                 [0.0, 0.0, 0.0, 0.0]
             }
         });
-        self.apply_modifier(rect, adj, source, false, mask, mask_info, top_opacity, top_alpha_modifier, top_funny_flag, top_offset, blend_mode);
+        self.apply_modifier(rect, adj, source, false, mask, mask_info, vector_mask, top_opacity, top_alpha_modifier, top_funny_flag, top_offset, blend_mode);
     }
     
-    pub (crate) fn apply_fx(&mut self, rect : [[f32; 2]; 2], fx : &(String, HashMap<String, Vec<crate::FxData>>), source : Option<&Self>, mask : Option<&Image<1>>, mask_info : Option<&MaskInfo>, top_opacity : f32, top_alpha_modifier : f32, top_funny_flag : bool, top_offset : [isize; 2], blend_mode : &str)
+    pub (crate) fn apply_fx(&mut self, rect : [[f32; 2]; 2], fx : &(String, HashMap<String, Vec<crate::FxData>>), source : Option<&Self>, mask : Option<&Image<1>>, mask_info : Option<&MaskInfo>, vector_mask : Option<(&Image<1>, &MaskInfo)>, top_opacity : f32, top_alpha_modifier : f32, top_funny_flag : bool, top_offset : [isize; 2], blend_mode : &str)
     {
         if blend_mode == "None" { return; }
         let adj : Box<dyn Fn([f32; 4], usize, usize, Option<&Self>) -> [f32; 4] + Send + Sync> = match fx.0.as_str()
@@ -829,66 +1304,154 @@ impl Image<4>
                     [_r, _g, _b, c[3]]
                 })
             }
-            "gradfill" =>
+            "outerglow" | "innerglow" =>
             {
-                // FIXME: not pixel perfect. doesn't handle transparency sizing properly. alpha cutoff is 50%. doesn't support "smooth" gradients (cubic hermite spline...?)
+                let r = fx.1["color"][0].f() as f32;
+                let g = fx.1["color"][1].f() as f32;
+                let b = fx.1["color"][2].f() as f32;
+                let size = (fx.1["size"][0].f() as f32).max(0.0);
+                let spread = size * (fx.1["choke"][0].f() as f32 / 100.0).clamp(0.0, 1.0);
+                let precise = fx.1["technique"][0].s() == "precise";
+                let inner = fx.0 == "innerglow";
+                let from_center = inner && fx.1["source"][0].s() == "center";
+                // inner glows go through the fill path, which doesn't apply the effect's opacity itself
+                let opacity = if inner { fx.1["opacity"][0].f() as f32 / 100.0 } else { 1.0 };
                 
-                //println!("{:?}", fx.1);
-                let colors = fx.1["gradient"][0].vvf().iter().map(|x| x.clone()).collect::<Vec<_>>();
-                let alphas = fx.1["gradient"][1].vvf().iter().map(|x| x.clone()).collect::<Vec<_>>();
+                let pad = size.ceil() as usize + 2;
+                let (field, field_w, field_h) = source.unwrap().distance_field(pad, inner);
                 
-                let read_gradient = |colors : &Vec<Vec<f64>>, alphas : &Vec<Vec<f64>>, t|
+                Box::new(move |_c : [f32; 4], x : usize, y : usize, _img : Option<&Self>| -> [f32; 4]
                 {
-                    let mut nc = 0;
-                    while nc + 1 < colors.len() && (colors[nc+1][3] as f32) < t
-                    {
-                        nc += 1;
-                    }
-                    let nc2 = (nc+1).min(colors.len()-1);
-                    let mut tc = unlerp(colors[nc][3] as f32, colors[nc2][3] as f32, t);
-                    let biascraw = (colors[nc2][3+1] as f32).clamp(0.0001, 0.9999);
-                    let biasc = biascraw * 2.0 - 1.0;
-                    if tc > biascraw
+                    let x = (x as isize + pad as isize).clamp(0, field_w as isize - 1) as usize;
+                    let y = (y as isize + pad as isize).clamp(0, field_h as isize - 1) as usize;
+                    let d = (field[y * field_w + x] - 0.5).max(0.0);
+                    
+                    let t = if size > spread { ((d - spread) / (size - spread)).clamp(0.0, 1.0) } else if d <= size { 0.0 } else { 1.0 };
+                    let mut a = 1.0 - t;
+                    if !precise
                     {
-                        tc = (tc - 1.0) / (1.0 - biasc) + 1.0;
+                        a = a * a * (3.0 - 2.0 * a);
                     }
-                    else
+                    if from_center
                     {
-                        tc /= 1.0 + biasc;
+                        a = 1.0 - a;
                     }
-                    
-                    let mut c = [colors[nc][0] as f32, colors[nc][1] as f32, colors[nc][2] as f32];
-                    for i in 0..3
+                    [r, g, b, a * opacity]
+                })
+            }
+            "bevel" =>
+            {
+                let r = fx.1["color"][0].f() as f32;
+                let g = fx.1["color"][1].f() as f32;
+                let b = fx.1["color"][2].f() as f32;
+                let size = (fx.1["size"][0].f() as f32).max(1.0);
+                let depth = fx.1["depth"][0].f() as f32 / 100.0;
+                let style = fx.1["style"][0].s();
+                let smooth = fx.1["technique"][0].s() == "smooth";
+                let down = fx.1["direction"][0].s() == "down";
+                let highlight = fx.1["_pass"][0].s() == "highlight";
+                let outside = fx.1["_region"][0].s() == "outside";
+                // the inside passes go through the fill path, which doesn't apply the effect's opacity itself
+                let opacity = if outside { 1.0 } else { fx.1["opacity"][0].f() as f32 / 100.0 };
+                
+                let angle = fx.1["angle"][0].f() as f32 * (std::f32::consts::PI / 180.0);
+                let altitude = fx.1["altitude"][0].f() as f32 * (std::f32::consts::PI / 180.0);
+                let light = [altitude.cos() * angle.cos(), -altitude.cos() * angle.sin(), altitude.sin()];
+                
+                let pad = size.ceil() as usize + 2;
+                let src = source.unwrap();
+                let (d_in, field_w, field_h) = src.distance_field(pad, true);
+                let (d_out, _, _) = src.distance_field(pad, false);
+                
+                // height map: 0 is the surrounding surface, 1 is the fully raised part of the bevel
+                let mut height = vec![0.0; field_w * field_h];
+                let mut is_inside = vec![false; field_w * field_h];
+                for i in 0..field_w * field_h
+                {
+                    is_inside[i] = d_out[i] == 0.0;
+                    // signed distance from the edge, positive inside
+                    let sd = if is_inside[i] { d_in[i] - 0.5 } else { 0.5 - d_out[i] };
+                    let mut t = match style.as_str()
                     {
-                        c[i] = lerp(c[i], colors[nc2][i] as f32, tc);
-                    }
-                    
-                    let mut na = 0;
-                    while na + 1 < alphas.len() && (alphas[na+1][1] as f32) < t
+                        "outer" => 1.0 + sd / size,
+                        "emboss" => 0.5 + sd / size * 0.5,
+                        "pillow" => (sd / size).abs(),
+                        _ => sd / size,
+                    }.clamp(0.0, 1.0);
+                    if smooth
                     {
-                        na += 1;
+                        t = t * t * (3.0 - 2.0 * t);
                     }
-                    let na2 = (na+1).min(alphas.len()-1);
-                    let mut ta = unlerp(alphas[na][1] as f32, alphas[na2][1] as f32, t);
-                    let biasaraw = (alphas[na2][1+1] as f32).clamp(0.0001, 0.9999);
-                    let biasa = biasaraw * 2.0 - 1.0;
-                    if ta > biasaraw
+                    if down
                     {
-                        ta = (ta - 1.0) / (1.0 - biasa) + 1.0;
+                        t = 1.0 - t;
                     }
-                    else
+                    height[i] = t * size * depth;
+                }
+                
+                Box::new(move |_c : [f32; 4], x : usize, y : usize, _img : Option<&Self>| -> [f32; 4]
+                {
+                    let x = (x as isize + pad as isize).clamp(1, field_w as isize - 2) as usize;
+                    let y = (y as isize + pad as isize).clamp(1, field_h as isize - 2) as usize;
+                    let i = y * field_w + x;
+                    if is_inside[i] == outside
                     {
-                        ta /= 1.0 + biasa;
+                        return [r, g, b, 0.0];
                     }
+                    let dx = (height[i + 1] - height[i - 1]) * 0.5;
+                    let dy = (height[i + field_w] - height[i - field_w]) * 0.5;
+                    let len = (dx * dx + dy * dy + 1.0).sqrt();
+                    let n = [-dx / len, -dy / len, 1.0 / len];
                     
-                    let mut a = [alphas[na][0] as f32];
-                    for i in 0..1
+                    // compare against how lit a flat surface would be
+                    let diff = n[0] * light[0] + n[1] * light[1] + n[2] * light[2] - light[2];
+                    let a = if highlight { diff / (1.0 - light[2]).max(0.001) } else { -diff / light[2].max(0.001) };
+                    [r, g, b, a.clamp(0.0, 1.0) * opacity]
+                })
+            }
+            "satin" =>
+            {
+                let r = fx.1["color"][0].f() as f32;
+                let g = fx.1["color"][1].f() as f32;
+                let b = fx.1["color"][2].f() as f32;
+                let size = (fx.1["size"][0].f() as f32).max(0.0);
+                let distance = fx.1["distance"][0].f() as f32;
+                let invert = fx.1["invert"][0].f() != 0.0;
+                let opacity = fx.1["opacity"][0].f() as f32 / 100.0;
+                
+                let angle = fx.1["angle"][0].f() as f32 * (std::f32::consts::PI / 180.0);
+                let (sin, cos) = angle.sin_cos();
+                let ox = (cos * distance).round() as isize;
+                let oy = (-sin * distance).round() as isize;
+                
+                let pad = (size + distance.abs()).ceil() as usize + 2;
+                let (field, field_w, field_h) = source.unwrap().blurred_alpha(pad, size);
+                
+                Box::new(move |_c : [f32; 4], x : usize, y : usize, _img : Option<&Self>| -> [f32; 4]
+                {
+                    let sample = |x : isize, y : isize|
                     {
-                        a[i] = lerp(a[i], alphas[na2][i] as f32, ta);
+                        let x = (x + pad as isize).clamp(0, field_w as isize - 1) as usize;
+                        let y = (y + pad as isize).clamp(0, field_h as isize - 1) as usize;
+                        field[y * field_w + x]
+                    };
+                    let x = x as isize;
+                    let y = y as isize;
+                    let mut a = (sample(x + ox, y + oy) - sample(x - ox, y - oy)).abs();
+                    if invert
+                    {
+                        a = 1.0 - a;
                     }
-                    
-                    [c[0], c[1], c[2], a[0]]
-                };
+                    [r, g, b, a * opacity]
+                })
+            }
+            "gradfill" =>
+            {
+                // FIXME: not pixel perfect. doesn't handle transparency sizing properly. alpha cutoff is 50%. doesn't support "smooth" gradients (cubic hermite spline...?)
+                
+                //println!("{:?}", fx.1);
+                let colors = fx.1["gradient"][0].vvf().iter().map(|x| [x[0] as f32, x[1] as f32, x[2] as f32, x[3] as f32, x[4] as f32]).collect::<Vec<_>>();
+                let alphas = fx.1["gradient"][1].vvf().iter().map(|x| [x[0] as f32, x[1] as f32, x[2] as f32]).collect::<Vec<_>>();
                 
                 let x0 = fx.1["_x0"][0].f();
                 let x1 = fx.1["_x1"][0].f();
@@ -923,6 +1486,17 @@ impl Image<4>
                 //let wdp = wd/wd.max(hd);
                 let _wdp = 1.0;
                 
+                let style = fx.1.get("type").map(|x| x[0].s()).unwrap_or("linear".to_string());
+                let reverse = fx.1.get("reverse").is_some_and(|x| x[0].f() != 0.0);
+                let dither = fx.1.get("dither").is_some_and(|x| x[0].f() != 0.0);
+                // the fill path doesn't apply the effect's opacity itself
+                let opacity = fx.1["opacity"][0].f() as f32 / 100.0;
+                let (cx, cy) = match fx.1.get("offset")
+                {
+                    Some(o) => (wh + o[0].f() / 100.0 * w, hh + o[1].f() / 100.0 * h),
+                    None => (wh, hh),
+                };
+                
                 Box::new(move |_c : [f32; 4], x : usize, y : usize, _img : Option<&Self>| -> [f32; 4]
                 {
                     //let img = img.unwrap();
@@ -930,22 +1504,79 @@ impl Image<4>
                     let y = y as isize;
                     //let c = img.get_pixel_float(x, y);
                     
-                    let xd = x as f64 - wh;
-                    let yd = y as f64 - hh;
+                    // sample at pixel centers
+                    let xd = x as f64 + 0.5 - cx;
+                    let yd = y as f64 + 0.5 - cy;
                     
-                    let mut xd2 = xd * b - yd * a;
-                    //xd2 *= asdf;
-                    xd2 *= s;
+                    // position along the gradient's direction (-0.5 to 0.5 over its length) and across it
+                    let along = (xd * b - yd * a) * s;
+                    let across = (xd * a + yd * b) * s;
                     
-                    let xd2 = xd2 as f32 + 0.5;
-                    let xd2 = xd2.clamp(0.0, 1.0);
-                    let c2 = read_gradient(&colors, &alphas, xd2);
-                    //c2[3] *= c[3];
+                    let t = match style.as_str()
+                    {
+                        "radial" => (along * along + across * across).sqrt() * 2.0,
+                        "angle" => across.atan2(along).rem_euclid(std::f64::consts::TAU) / std::f64::consts::TAU,
+                        "reflected" => along.abs() * 2.0,
+                        "diamond" => (along.abs() + across.abs()) * 2.0,
+                        _ => along + 0.5,
+                    } as f32;
+                    let mut t = t.clamp(0.0, 1.0);
+                    if reverse
+                    {
+                        t = 1.0 - t;
+                    }
+                    let mut c2 = sample_gradient(&colors, &alphas, t);
+                    if dither
+                    {
+                        let d = dither_noise(x as usize, y as usize);
+                        for c in c2.iter_mut().take(3)
+                        {
+                            *c += d;
+                        }
+                    }
+                    c2[3] *= opacity;
                     c2
                     
                     //[xd2 as f32 + 0.5, xd2 as f32 + 0.5, xd2 as f32 + 0.5, c[3]]
                 })
             }
+            "patternfill" =>
+            {
+                let opacity = fx.1["opacity"][0].f() as f32 / 100.0;
+                let (pw, ph, pattern) = match fx.1.get("pattern")
+                {
+                    Some(p) => (p[0].vf()[0] as isize, p[0].vf()[1] as isize, p[1].vf().iter().map(|x| *x as f32).collect::<Vec<_>>()),
+                    None => (0, 0, vec!()),
+                };
+                let scale = (fx.1["scale"][0].f() / 100.0).max(0.01);
+                let phase = [fx.1["phase"][0].f(), fx.1["phase"][1].f()];
+                // patterns that aren't linked to the layer stay put relative to the canvas
+                let origin = if fx.1["link"][0].f() != 0.0 { [0.0, 0.0] } else { [fx.1["_x0"][0].f(), fx.1["_y0"][0].f()] };
+                
+                Box::new(move |_c : [f32; 4], x : usize, y : usize, _img : Option<&Self>| -> [f32; 4]
+                {
+                    if pw <= 0 || ph <= 0 || pattern.len() < (pw * ph * 4) as usize
+                    {
+                        return [0.0, 0.0, 0.0, 0.0];
+                    }
+                    let get = |x : isize, y : isize|
+                    {
+                        let i = (y.rem_euclid(ph) * pw + x.rem_euclid(pw)) as usize * 4;
+                        [pattern[i], pattern[i + 1], pattern[i + 2], pattern[i + 3]]
+                    };
+                    // bilinear, which lands exactly on pattern pixels at 100% scale
+                    let px = (x as isize as f64 + origin[0] - phase[0] + 0.5) / scale - 0.5;
+                    let py = (y as isize as f64 + origin[1] - phase[1] + 0.5) / scale - 0.5;
+                    let (fx, fy) = (px.floor(), py.floor());
+                    let (tx, ty) = ((px - fx) as f32, (py - fy) as f32);
+                    let (ix, iy) = (fx as isize, fy as isize);
+                    let top = px_lerp_float(get(ix, iy), get(ix + 1, iy), tx);
+                    let bottom = px_lerp_float(get(ix, iy + 1), get(ix + 1, iy + 1), tx);
+                    let mut c = px_lerp_float(top, bottom, ty);
+                    c[3] *= opacity;
+                    c
+                })
+            }
             "stroke" =>
             {
                 let r = fx.1["color"][0].f() as f32;
@@ -1107,18 +1738,18 @@ impl Image<4>
             _ => panic!()
         };
         //println!("{:?}", rect_translate(rect, vec_neg(&rect[0])));
-        self.apply_modifier(rect, adj, source, false, mask, mask_info, top_opacity, top_alpha_modifier, top_funny_flag, top_offset, blend_mode);
+        self.apply_modifier(rect, adj, source, false, mask, mask_info, vector_mask, top_opacity, top_alpha_modifier, top_funny_flag, top_offset, blend_mode);
     }
-    pub (crate) fn apply_adjustment(&mut self, rect : [[f32; 2]; 2], adjustment : &Adjustment, mask : Option<&Image<1>>, mask_info : Option<&MaskInfo>, top_opacity : f32, top_alpha_modifier : f32, top_funny_flag : bool, top_offset : [isize; 2], blend_mode : &str)
+    pub (crate) fn apply_adjustment(&mut self, rect : [[f32; 2]; 2], adjustment : &Adjustment, mask : Option<&Image<1>>, mask_info : Option<&MaskInfo>, vector_mask : Option<(&Image<1>, &MaskInfo)>, top_opacity : f32, top_alpha_modifier : f32, top_funny_flag : bool, top_offset : [isize; 2], blend_mode : &str)
     {
         if blend_mode == "None" { return; }
         let adj = Self::find_adjustment(adjustment);
-        self.apply_modifier(rect, adj, None, true, mask, mask_info, top_opacity, top_alpha_modifier, top_funny_flag, top_offset, blend_mode);
+        self.apply_modifier(rect, adj, None, true, mask, mask_info, vector_mask, top_opacity, top_alpha_modifier, top_funny_flag, top_offset, blend_mode);
     }
     #[inline(never)]
     pub (crate) fn apply_modifier(&mut self, rect : [[f32; 2]; 2], modifier : Box<dyn Fn([f32; 4], usize, usize, Option<&Self>) -> [f32; 4] + Send + Sync>,
         source : Option<&Self>, flush_opacity : bool,
-        mask : Option<&Image<1>>, mask_info : Option<&MaskInfo>,
+        mask : Option<&Image<1>>, mask_info : Option<&MaskInfo>, vector_mask : Option<(&Image<1>, &MaskInfo)>,
         top_opacity : f32, top_alpha_modifier : f32, top_funny_flag : bool, top_offset : [isize; 2], blend_mode : &str)
     {
         if blend_mode == "None" { return; }
@@ -1151,6 +1782,7 @@ impl Image<4>
         {
             Box::new(|_x : usize, _y : usize| top_opacity)
         };
+        let get_opacity = with_vector_mask(get_opacity, vector_mask, top_offset);
         
         macro_rules! do_loop
         {
@@ -1394,26 +2026,6 @@ impl Image<4>
             }),
             Adjustment::BlackWhite((v, _colorized, _color)) => Box::new(move |mut c : [f32; 4], _x : usize, _y : usize, _img : Option<&Self>| -> [f32; 4]
             {
-                fn rgb_to_rygcbml(rgb: &[f32]) -> [f32; 7]
-                {
-                    let [r, g, b] = [rgb[0], rgb[1], rgb[2]];
-
-                    let l = r.min(g).min(b);
-
-                    let r = r - l;
-                    let g = g - l;
-                    let b = b - l;
-
-                    let y = r.min(g);
-                    let c = g.min(b);
-                    let m = r.min(b);
-                    let r2 = r - y - m;
-                    let g2 = g - y - c;
-                    let b2 = b - c - m;
-
-                    [r2, y, g2, c, b2, m, l]
-                }
-                
                 let sept = rgb_to_rygcbml(&c);
                 let mut l = sept[6];
                 for i in 0..6
@@ -1427,6 +2039,143 @@ impl Image<4>
                 c[2] = l;
                 c
             }),
+            Adjustment::GradientMap((colors, alphas, reversed, dithered)) => Box::new(move |mut c : [f32; 4], x : usize, y : usize, _img : Option<&Self>| -> [f32; 4]
+            {
+                let mut t = calc_y([c[0], c[1], c[2]]).clamp(0.0, 1.0);
+                if reversed
+                {
+                    t = 1.0 - t;
+                }
+                let g = sample_gradient(&colors, &alphas, t);
+                // transparent parts of the gradient let the original color through
+                for i in 0..3
+                {
+                    c[i] = lerp(c[i], g[i], g[3]);
+                }
+                if dithered
+                {
+                    let d = dither_noise(x, y);
+                    for v in c.iter_mut().take(3)
+                    {
+                        *v += d;
+                    }
+                }
+                c
+            }),
+            Adjustment::PhotoFilter((color, density, preserve_luminosity)) => Box::new(move |mut c : [f32; 4], _x : usize, _y : usize, _img : Option<&Self>| -> [f32; 4]
+            {
+                let d = (density / 100.0).clamp(0.0, 1.0);
+                let old = [c[0], c[1], c[2]];
+                let mut rgb = [0.0; 3];
+                for i in 0..3
+                {
+                    rgb[i] = lerp(old[i], old[i] * color[i], d);
+                }
+                if preserve_luminosity
+                {
+                    rgb = apply_y(rgb, calc_y(old));
+                }
+                c[..3].copy_from_slice(&rgb);
+                c
+            }),
+            Adjustment::Exposure(n) => Box::new(move |mut c : [f32; 4], _x : usize, _y : usize, _img : Option<&Self>| -> [f32; 4]
+            {
+                // works in linear light, like photoshop does
+                let scale = 2.0f32.powf(n[0]);
+                let gamma = n[2].max(0.01);
+                for v in c.iter_mut().take(3)
+                {
+                    let lin = srgb_to_linear(*v) * scale + n[1];
+                    *v = linear_to_srgb(lin.max(0.0).powf(1.0 / gamma));
+                }
+                c
+            }),
+            Adjustment::Vibrance(n) => Box::new(move |mut c : [f32; 4], _x : usize, _y : usize, _img : Option<&Self>| -> [f32; 4]
+            {
+                let mut rgb = [c[0], c[1], c[2]];
+                let y = calc_y(rgb);
+                let scale_sat = |rgb : [f32; 3], amount : f32| -> [f32; 3]
+                {
+                    let f = if amount <= 0.0 { amount + 1.0 } else { 1.0 / (1.0 - amount * 0.99) };
+                    color_clipped([lerp(y, rgb[0], f), lerp(y, rgb[1], f), lerp(y, rgb[2], f)])
+                };
+                // vibrance boosts muted colors more than already-saturated ones
+                let vibrance = n[0] / 100.0;
+                let vibrance = if vibrance > 0.0 { vibrance * (1.0 - calc_sat(rgb).clamp(0.0, 1.0)) } else { vibrance };
+                rgb = scale_sat(rgb, vibrance);
+                rgb = scale_sat(rgb, n[1] / 100.0);
+                c[..3].copy_from_slice(&rgb);
+                c
+            }),
+            Adjustment::ChannelMixer((rows, monochrome)) => Box::new(move |mut c : [f32; 4], _x : usize, _y : usize, _img : Option<&Self>| -> [f32; 4]
+            {
+                let old = [c[0], c[1], c[2]];
+                let mix = |row : &[f32; 4]| (old[0] * row[0] + old[1] * row[1] + old[2] * row[2] + row[3]) * 0.01;
+                for i in 0..3
+                {
+                    c[i] = mix(if monochrome { &rows[0] } else { &rows[i] }).clamp(0.0, 1.0);
+                }
+                c
+            }),
+            Adjustment::ColorBalance((ranges, preserve_luminosity)) => Box::new(move |mut c : [f32; 4], _x : usize, _y : usize, _img : Option<&Self>| -> [f32; 4]
+            {
+                // same tonal range masks as gimp's color balance, which mimics photoshop's
+                let l = c[0].max(c[1]).max(c[2]) * 0.5 + c[0].min(c[1]).min(c[2]) * 0.5;
+                let (a, b, scale) = (0.25, 0.333, 0.7);
+                let weights = [
+                    ((l - b) / -a + 0.5).clamp(0.0, 1.0) * scale,
+                    ((l - b) / a + 0.5).clamp(0.0, 1.0) * ((l + b - 1.0) / -a + 0.5).clamp(0.0, 1.0) * scale,
+                    ((l + b - 1.0) / a + 0.5).clamp(0.0, 1.0) * scale,
+                ];
+                for i in 0..3
+                {
+                    for (range, w) in ranges.iter().zip(weights)
+                    {
+                        c[i] += range[i] * 0.01 * w;
+                    }
+                    c[i] = c[i].clamp(0.0, 1.0);
+                }
+                if preserve_luminosity
+                {
+                    let mut hsl = rgb_to_hsl(c);
+                    hsl[2] = l;
+                    c = hsl_to_rgb(hsl);
+                }
+                c
+            }),
+            Adjustment::SelectiveColor((v, absolute)) => Box::new(move |mut c : [f32; 4], _x : usize, _y : usize, _img : Option<&Self>| -> [f32; 4]
+            {
+                let sept = rgb_to_rygcbml(&c);
+                let max = c[0].max(c[1]).max(c[2]);
+                let min = c[0].min(c[1]).min(c[2]);
+                let whites = (min * 2.0 - 1.0).max(0.0);
+                let blacks = (1.0 - max * 2.0).max(0.0);
+                let neutrals = (1.0 - whites - blacks).max(0.0) * (1.0 - (max - min));
+                let weights = [sept[0], sept[1], sept[2], sept[3], sept[4], sept[5], whites, neutrals, blacks];
+                
+                let old = [c[0], c[1], c[2]];
+                for (adj, w) in v.iter().zip(weights)
+                {
+                    if w <= 0.0
+                    {
+                        continue;
+                    }
+                    let k = adj[3] * 0.01;
+                    for i in 0..3
+                    {
+                        // adding cyan/magenta/yellow ink takes away red/green/blue; relative mode scales by how much ink is already there
+                        let ink = adj[i] * 0.01;
+                        let scale = if absolute { 1.0 } else { 1.0 - old[i] };
+                        let delta = (((-1.0 - ink) * k - ink) * scale).clamp(-old[i], 1.0 - old[i]);
+                        c[i] += delta * w;
+                    }
+                }
+                for v in c.iter_mut().take(3)
+                {
+                    *v = v.clamp(0.0, 1.0);
+                }
+                c
+            }),
             _ => Box::new(|c : [f32; 4], _x : usize, _y : usize, _img : Option<&Self>| -> [f32; 4] { c }),
         }
     }
@@ -1453,7 +2202,7 @@ impl Image<4>
         }
     }
     #[inline(never)]
-    pub (crate) fn blend_rect_from(&mut self, rect : [[f32; 2]; 2], top : &Image<4>, mask : Option<&Image<1>>, mask_info : Option<&MaskInfo>, top_opacity : f32, top_alpha_modifier : f32, top_funny_flag : bool, top_offset : [isize; 2], blend_mode : &str)
+    pub (crate) fn blend_rect_from(&mut self, rect : [[f32; 2]; 2], top : &Image<4>, mask : Option<&Image<1>>, mask_info : Option<&MaskInfo>, vector_mask : Option<(&Image<1>, &MaskInfo)>, top_opacity : f32, top_alpha_modifier : f32, top_funny_flag : bool, top_offset : [isize; 2], blend_mode : &str)
     {
         if blend_mode == "None" { return; }
         //rect[0][0] += top_offset[0] as f32;
@@ -1468,6 +2217,8 @@ impl Image<4>
         
         let self_width = self.width;
         
+        // custom blend modes only run on the gpu, and only the editor sets that up
+        #[cfg(feature = "gui")]
         if blend_mode.starts_with("Custom") || blend_mode.starts_with("TriCustom") || blend_mode.starts_with("QuadCustom")
         {
             let mono = blend_mode.starts_with("Custom");
@@ -1681,6 +2432,7 @@ impl Image<4>
         {
             Box::new(|_x : usize, _y : usize| top_opacity)
         };
+        let get_opacity = with_vector_mask(get_opacity, vector_mask, top_offset);
         
         // separate from loop_rect_threaded because this is used by layer stack flattening, and needs to be as fast as possible
         // so we do everything purely with a macro to ensure that as much inlining can be done as the compiler is capable of
@@ -1814,7 +2566,7 @@ impl Image<4>
     pub (crate) fn blend_from(&mut self, top : &Image<4>, mask : Option<&Image<1>>, mask_info : Option<&MaskInfo>, top_opacity : f32, top_offset : [isize; 2], blend_mode : &str)
     {
         if blend_mode == "None" { return; }
-        self.blend_rect_from([[0.0, 0.0], [self.width as f32, self.height as f32]], top, mask, mask_info, top_opacity, 1.0, false, top_offset, blend_mode)
+        self.blend_rect_from([[0.0, 0.0], [self.width as f32, self.height as f32]], top, mask, mask_info, None, top_opacity, 1.0, false, top_offset, blend_mode)
     }
     
     #[inline(never)]
@@ -1937,6 +2689,7 @@ impl<const N : usize> Image<N>
         Self::blank(self.width, self.height)
     }
     // for icons etc. too slow to use for anything else.
+    #[cfg(feature = "gui")]
     pub (crate) fn to_egui(&self) -> egui::ColorImage
     {
         egui::ColorImage::from_rgba_unmultiplied([self.width, self.height], &self.data.clone().to_int())
@@ -2441,3 +3194,78 @@ impl<const N : usize> Image<N>
     
 }
 
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    
+    fn adjust(adjustment : Adjustment, c : [f32; 3]) -> [f32; 3]
+    {
+        let c = Image::<4>::find_adjustment(&adjustment)([c[0], c[1], c[2], 1.0], 0, 0, None);
+        [c[0], c[1], c[2]]
+    }
+    fn assert_near(a : [f32; 3], b : [f32; 3])
+    {
+        assert!(a.iter().zip(b.iter()).all(|(a, b)| (a - b).abs() < 0.002), "{:?} != {:?}", a, b);
+    }
+    
+    #[test]
+    pub fn test_adjustments()
+    {
+        let black_to_white = (vec!([0.0, 0.0, 0.0, 0.0, 0.5], [1.0, 1.0, 1.0, 1.0, 0.5]), vec!([1.0, 0.0, 0.5], [1.0, 1.0, 0.5]));
+        assert_near(adjust(Adjustment::GradientMap((black_to_white.0.clone(), black_to_white.1.clone(), false, false)), [1.0, 0.0, 0.0]), [0.3, 0.3, 0.3]);
+        assert_near(adjust(Adjustment::GradientMap((black_to_white.0, black_to_white.1, true, false)), [1.0, 0.0, 0.0]), [0.7, 0.7, 0.7]);
+        let red_to_blue = vec!([1.0, 0.0, 0.0, 0.0, 0.5], [0.0, 0.0, 1.0, 1.0, 0.5]);
+        assert_near(adjust(Adjustment::GradientMap((red_to_blue, vec!([1.0, 0.0, 0.5]), false, false)), [0.5, 0.5, 0.5]), [0.5, 0.0, 0.5]);
+        
+        assert_near(adjust(Adjustment::PhotoFilter(([1.0, 0.5, 0.0], 50.0, false)), [1.0, 1.0, 1.0]), [1.0, 0.75, 0.5]);
+        assert_near(adjust(Adjustment::PhotoFilter(([1.0, 0.5, 0.0], 50.0, true)), [0.5, 0.5, 0.5]), [0.60125, 0.47625, 0.35125]);
+        
+        assert_near(adjust(Adjustment::Exposure([1.0, 0.0, 1.0]), [0.5, 0.5, 0.5]), [0.6859, 0.6859, 0.6859]);
+        assert_near(adjust(Adjustment::Exposure([0.0, 0.0, 2.0]), [0.5, 0.5, 0.5]), [0.7102, 0.7102, 0.7102]);
+        assert_near(adjust(Adjustment::Exposure([0.0, 0.1, 1.0]), [0.0, 0.0, 0.0]), [0.3492, 0.3492, 0.3492]);
+        
+        assert_near(adjust(Adjustment::Vibrance([100.0, 0.0]), [0.5, 0.5, 0.5]), [0.5, 0.5, 0.5]);
+        assert_near(adjust(Adjustment::Vibrance([100.0, 0.0]), [1.0, 0.0, 0.0]), [1.0, 0.0, 0.0]);
+        assert_near(adjust(Adjustment::Vibrance([100.0, 0.0]), [0.6, 0.4, 0.4]), [1.0, 0.2285, 0.2285]);
+        assert_near(adjust(Adjustment::Vibrance([0.0, -100.0]), [0.6, 0.4, 0.4]), [0.46, 0.46, 0.46]);
+        
+        let swap = [[0.0, 100.0, 0.0, 0.0], [100.0, 0.0, 0.0, 0.0], [0.0, 0.0, 100.0, 0.0]];
+        assert_near(adjust(Adjustment::ChannelMixer((swap, false)), [0.2, 0.6, 0.9]), [0.6, 0.2, 0.9]);
+        let mono = [[40.0, 40.0, 20.0, 0.0], [0.0; 4], [0.0; 4]];
+        assert_near(adjust(Adjustment::ChannelMixer((mono, true)), [1.0, 0.5, 0.0]), [0.6, 0.6, 0.6]);
+        let constant = [[100.0, 0.0, 0.0, 10.0], [0.0, 100.0, 0.0, 10.0], [0.0, 0.0, 100.0, 10.0]];
+        assert_near(adjust(Adjustment::ChannelMixer((constant, false)), [0.5, 0.5, 0.5]), [0.6, 0.6, 0.6]);
+        
+        let warm_mids = [[0.0; 3], [50.0, 0.0, 0.0], [0.0; 3]];
+        assert_near(adjust(Adjustment::ColorBalance((warm_mids, false)), [0.5, 0.5, 0.5]), [0.85, 0.5, 0.5]);
+        assert_near(adjust(Adjustment::ColorBalance((warm_mids, true)), [0.5, 0.5, 0.5]), [0.7692, 0.2308, 0.2308]);
+        // midtone changes don't reach pure black
+        assert_near(adjust(Adjustment::ColorBalance((warm_mids, false)), [0.0, 0.0, 0.0]), [0.0, 0.0, 0.0]);
+        
+        let mut cyan_reds = vec!([0.0; 4]; 9);
+        cyan_reds[0] = [50.0, 0.0, 0.0, 0.0];
+        assert_near(adjust(Adjustment::SelectiveColor((cyan_reds.clone(), true)), [1.0, 0.0, 0.0]), [0.5, 0.0, 0.0]);
+        assert_near(adjust(Adjustment::SelectiveColor((cyan_reds.clone(), true)), [0.8, 0.2, 0.2]), [0.5, 0.2, 0.2]);
+        assert_near(adjust(Adjustment::SelectiveColor((cyan_reds.clone(), false)), [0.8, 0.2, 0.2]), [0.74, 0.2, 0.2]);
+        assert_near(adjust(Adjustment::SelectiveColor((cyan_reds, true)), [0.0, 0.0, 1.0]), [0.0, 0.0, 1.0]);
+        let mut darker_neutrals = vec!([0.0; 4]; 9);
+        darker_neutrals[7] = [0.0, 0.0, 0.0, 20.0];
+        assert_near(adjust(Adjustment::SelectiveColor((darker_neutrals, true)), [0.5, 0.5, 0.5]), [0.3, 0.3, 0.3]);
+    }
+    
+    #[test]
+    pub fn test_color_blend_modes()
+    {
+        // whole colors get picked, unlike darken/lighten which mix channels
+        let top = [1.0, 0.0, 0.0, 1.0];
+        let bottom = [0.0, 0.6, 0.0, 1.0];
+        assert_eq!(find_blend_func_float("Darker Color")(top, bottom, 1.0, 1.0, false), top);
+        assert_eq!(find_blend_func_float("Lighter Color")(top, bottom, 1.0, 1.0, false), bottom);
+        assert_eq!(find_blend_func_float("Darken")(top, bottom, 1.0, 1.0, false), [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(find_blend_func("Lighter Color")([255, 0, 0, 255], [0, 153, 0, 255], 1.0, 1.0, false), [0, 153, 0, 255]);
+        
+        let half = find_blend_func_float("Darker Color")(top, bottom, 0.5, 1.0, false);
+        assert_near([half[0], half[1], half[2]], [0.5, 0.3, 0.0]);
+    }
+}
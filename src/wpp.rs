@@ -0,0 +1,770 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::Arc;
+use rayon::prelude::*;
+use crate::*;
+
+// bump this whenever the saved layout changes, and add a migration from the old one
+pub (crate) const WPP_VERSION : u32 = 2;
+
+#[derive(Clone, Debug, PartialEq)]
+pub (crate) enum WppError
+{
+    Io(String),
+    Decode(String),
+    TooNew(u32),
+    CorruptChunk(String),
+}
+
+impl std::fmt::Display for WppError
+{
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self
+        {
+            WppError::Io(x) => write!(f, "{}", x),
+            WppError::Decode(x) => write!(f, "not a readable project file ({})", x),
+            WppError::TooNew(x) => write!(f, "project file version {} is newer than this version of warpainter supports ({})", x, WPP_VERSION),
+            WppError::CorruptChunk(x) => write!(f, "the pixel data of layer {:?} is corrupt", x),
+        }
+    }
+}
+
+impl std::error::Error for WppError { }
+
+// the image itself: everything a project file has to keep
+#[derive(Default, Serialize, Deserialize)]
+pub (crate) struct WppDocument
+{
+    pub (crate) layers : Layer, // tree, layers contain other layers
+    pub (crate) current_layer : u128, // uuid
+    
+    pub (crate) canvas_width : usize,
+    pub (crate) canvas_height : usize,
+    
+    pub (crate) selection_mask : Option<Image<1>>,
+    pub (crate) selection_poly : Vec<Vec<[f32; 2]>>,
+    
+    #[serde(default)]
+    pub (crate) metadata : DocumentMetadata,
+}
+
+impl WppDocument
+{
+    pub (crate) fn flatten(&mut self) -> &Image<4>
+    {
+        self.layers.flatten_as_root(self.canvas_width, self.canvas_height, None, None)
+    }
+}
+
+// editor state that's nice to get back when reopening a project, but isn't part of the image
+#[derive(Clone, Serialize, Deserialize)]
+pub (crate) struct ViewState
+{
+    pub (crate) eraser_mode : bool,
+    pub (crate) main_color_rgb : [f32; 4],
+    pub (crate) main_color_hsv : [f32; 4],
+    pub (crate) sub_color_rgb : [f32; 4],
+    pub (crate) sub_color_hsv : [f32; 4],
+    pub (crate) current_tool : usize,
+    pub (crate) xform : Transform,
+}
+
+// what a fresh editor starts with
+impl Default for ViewState
+{
+    fn default() -> Self
+    {
+        Self {
+            eraser_mode : false,
+            main_color_rgb : [0.0, 0.0, 0.0, 1.0],
+            main_color_hsv : [0.0, 0.0, 0.0, 1.0],
+            sub_color_rgb : [1.0, 1.0, 1.0, 1.0],
+            sub_color_hsv : [1.0, 1.0, 1.0, 1.0],
+            current_tool : 0,
+            xform : Transform::ident(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub (crate) enum WppChunkKind
+{
+    Pixels,
+    Mask,
+}
+
+const CODEC_RAW : u8 = 0;
+const CODEC_LZ4 : u8 = 1;
+const CODEC_RLE16_LZ4 : u8 = 2;
+
+// one layer image, compressed on its own so it can be decoded in parallel or not at all
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub (crate) struct WppChunk
+{
+    layer : u128, // uuid
+    kind : WppChunkKind,
+    width : usize,
+    height : usize,
+    float : bool,
+    codec : u8,
+    len : usize, // decoded
+    crc : u32, // of the stored bytes
+    #[serde(with = "serde_bytes")]
+    data : Vec<u8>,
+}
+
+// the layer tree is the manifest; pixels and masks are in the chunks, keyed by layer uuid
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize)]
+struct WppFile
+{
+    // for detection of warpainter project files as different from other CBOR files. has to stay the first field
+    WarpainterDocumentCBOR : (),
+    version : u32,
+    document : WppDocument,
+    view : ViewState,
+    chunks : Vec<WppChunk>,
+}
+
+// version 0: the whole editor state, serialized directly
+#[derive(Deserialize)]
+struct WppV0
+{
+    layers : Layer,
+    current_layer : u128,
+    canvas_width : usize,
+    canvas_height : usize,
+    eraser_mode : bool,
+    main_color_rgb : [f32; 4],
+    main_color_hsv : [f32; 4],
+    sub_color_rgb : [f32; 4],
+    sub_color_hsv : [f32; 4],
+    #[serde(default)]
+    current_tool : usize,
+    xform : Transform,
+    selection_mask : Option<Image<1>>,
+    selection_poly : Vec<Vec<[f32; 2]>>,
+    #[serde(default)]
+    metadata : DocumentMetadata,
+}
+
+// version 1: document and view state, with pixels inline in the layer tree
+#[derive(Deserialize)]
+struct WppV1
+{
+    document : WppDocument,
+    view : ViewState,
+}
+
+fn migrate_v0(old : WppV0) -> WppV1
+{
+    WppV1 {
+        document : WppDocument {
+            layers : old.layers,
+            current_layer : old.current_layer,
+            canvas_width : old.canvas_width,
+            canvas_height : old.canvas_height,
+            selection_mask : old.selection_mask,
+            selection_poly : old.selection_poly,
+            metadata : old.metadata,
+        },
+        view : ViewState {
+            eraser_mode : old.eraser_mode,
+            main_color_rgb : old.main_color_rgb,
+            main_color_hsv : old.main_color_hsv,
+            sub_color_rgb : old.sub_color_rgb,
+            sub_color_hsv : old.sub_color_hsv,
+            current_tool : old.current_tool,
+            xform : old.xform,
+        },
+    }
+}
+
+fn migrate_v1(old : WppV1) -> WppFile
+{
+    // layers that still have inline pixels just don't get any chunks
+    WppFile { WarpainterDocumentCBOR : (), version : 2, document : old.document, view : old.view, chunks : vec!() }
+}
+
+fn decode<T : serde::de::DeserializeOwned>(bytes : &[u8]) -> Result<T, WppError>
+{
+    cbor4ii::serde::from_reader(std::io::Cursor::new(bytes)).map_err(|x| WppError::Decode(x.to_string()))
+}
+
+fn image_bytes<const N : usize>(img : &Image<N>) -> (bool, Vec<u8>)
+{
+    match &img.data
+    {
+        ImageData::Int(data) => (false, data.iter().flatten().copied().collect()),
+        ImageData::Float(data) => (true, data.iter().flatten().flat_map(|x| x.to_le_bytes()).collect()),
+    }
+}
+
+fn image_from_bytes<const N : usize>(chunk : &WppChunk, bytes : &[u8]) -> Image<N>
+{
+    let data = if chunk.float
+    {
+        ImageData::Float(bytes.chunks_exact(N * 4).map(|px| std::array::from_fn(|c| f32::from_le_bytes(px[c*4..c*4 + 4].try_into().unwrap()))).collect())
+    }
+    else
+    {
+        ImageData::Int(bytes.chunks_exact(N).map(|px| px.try_into().unwrap()).collect())
+    };
+    Image { width : chunk.width, height : chunk.height, data }
+}
+
+// flate2's crc32, which is the same one zip uses
+fn chunk_crc(data : &[u8]) -> u32
+{
+    let mut crc = flate2::Crc::new();
+    crc.update(data);
+    crc.sum()
+}
+
+fn encode_chunk<const N : usize>(layer : u128, kind : WppChunkKind, img : &Image<N>) -> WppChunk
+{
+    let (float, raw) = image_bytes(img);
+    // rle16 first, so that big empty areas don't cost lz4 anything
+    let mut rle = vec!();
+    rle16::Compressor::new(&mut rle).write_all(&raw).unwrap();
+    let data = lz4_flex::block::compress_prepend_size(&rle);
+    WppChunk { layer, kind, width : img.width, height : img.height, float, codec : CODEC_RLE16_LZ4, len : raw.len(), crc : chunk_crc(&data), data }
+}
+
+fn decode_chunk(chunk : &WppChunk) -> Result<Vec<u8>, String>
+{
+    let raw = match chunk.codec
+    {
+        CODEC_RAW => chunk.data.clone(),
+        CODEC_LZ4 => lz4_flex::block::decompress_size_prepended(&chunk.data).map_err(|x| x.to_string())?,
+        CODEC_RLE16_LZ4 =>
+        {
+            let rle = lz4_flex::block::decompress_size_prepended(&chunk.data).map_err(|x| x.to_string())?;
+            let mut raw = Vec::with_capacity(chunk.len);
+            rle16::Decompressor::new(&rle[..]).read_to_end(&mut raw).map_err(|x| x.to_string())?;
+            raw
+        }
+        x => return Err(format!("unknown codec {}", x)),
+    };
+    if raw.len() != chunk.len
+    {
+        return Err(format!("decoded to {} bytes instead of {}", raw.len(), chunk.len));
+    }
+    Ok(raw)
+}
+
+fn chunk_is_valid(chunk : &WppChunk) -> bool
+{
+    let channels = match chunk.kind { WppChunkKind::Pixels => 4, WppChunkKind::Mask => 1 };
+    let size = if chunk.float { 4 } else { 1 };
+    chunk.width.checked_mul(chunk.height).and_then(|x| x.checked_mul(channels * size)) == Some(chunk.len)
+        && chunk_crc(&chunk.data) == chunk.crc
+}
+
+// checks every chunk up front, so that corruption is an error when opening and not a blank layer later
+fn attach_chunks(layers : &mut Layer, chunks : Vec<WppChunk>) -> Result<(), WppError>
+{
+    let valid : Vec<bool> = get_pool().install(|| chunks.par_iter().map(chunk_is_valid).collect());
+    let mut by_layer : HashMap<u128, (bool, Vec<WppChunk>)> = HashMap::new();
+    for (chunk, valid) in chunks.into_iter().zip(valid)
+    {
+        let entry = by_layer.entry(chunk.layer).or_insert((true, vec!()));
+        entry.0 &= valid;
+        entry.1.push(chunk);
+    }
+    
+    let mut error = None;
+    layers.visit_layers_mut(0, &mut |layer, _|
+    {
+        if let Some((valid, chunks)) = by_layer.remove(&layer.uuid)
+        {
+            if !valid
+            {
+                error = Some(WppError::CorruptChunk(layer.name.clone()));
+                return None;
+            }
+            layer.pending = Some(Arc::new(chunks));
+        }
+        Some(())
+    });
+    error.map_or(Ok(()), Err)
+}
+
+fn take_pending(layer : &mut Layer, shown : bool, all : bool, current_layer : u128, out : &mut Vec<Arc<Vec<WppChunk>>>)
+{
+    let shown = shown && layer.visible;
+    if all || shown || layer.uuid == current_layer
+    {
+        out.extend(layer.pending.take());
+    }
+    for child in layer.children.iter_mut()
+    {
+        take_pending(child, shown, all, current_layer, out);
+    }
+}
+
+fn decode_pending(layers : &mut Layer, current_layer : u128, all : bool) -> bool
+{
+    let mut pending = vec!();
+    take_pending(layers, true, all, current_layer, &mut pending);
+    if pending.is_empty()
+    {
+        return false;
+    }
+    
+    let chunks : Vec<&WppChunk> = pending.iter().flat_map(|x| x.iter()).collect();
+    let decoded : Vec<_> = get_pool().install(|| chunks.par_iter().map(|x| decode_chunk(x)).collect());
+    let mut by_layer : HashMap<u128, Vec<_>> = HashMap::new();
+    for (chunk, raw) in chunks.into_iter().zip(decoded)
+    {
+        by_layer.entry(chunk.layer).or_default().push((chunk, raw));
+    }
+    
+    layers.visit_layers_mut(0, &mut |layer, _|
+    {
+        if let Some(list) = by_layer.remove(&layer.uuid)
+        {
+            for (chunk, raw) in list
+            {
+                // the checksum matched, so this shouldn't happen; keep the layer around, blank
+                let raw = raw.unwrap_or_else(|e|
+                {
+                    println!("failed to decode pixels of layer {:?}: {}", layer.name, e);
+                    vec!(0; chunk.len)
+                });
+                match chunk.kind
+                {
+                    WppChunkKind::Pixels => layer.data = Some(image_from_bytes(chunk, &raw)),
+                    WppChunkKind::Mask => layer.mask = Some(image_from_bytes(chunk, &raw)),
+                }
+            }
+            layer.dirtify_all();
+        }
+        Some(())
+    });
+    true
+}
+
+#[cfg(feature = "gui")]
+fn load_pending(app : &mut Warpainter, all : bool) -> bool
+{
+    if !decode_pending(&mut app.layers, app.current_layer, all)
+    {
+        return false;
+    }
+    app.cache_rect_full();
+    app.edit_progress += 1;
+    true
+}
+
+// decodes what's needed to draw the canvas and edit the current layer. hidden layers, and
+// everything inside hidden groups, stay compressed until they're shown or selected.
+// returns whether anything was decoded
+#[cfg(feature = "gui")]
+pub (crate) fn wpp_load_needed(app : &mut Warpainter) -> bool
+{
+    load_pending(app, false)
+}
+
+// for exports that need every layer's pixels
+#[cfg(feature = "gui")]
+pub (crate) fn wpp_load_all(app : &mut Warpainter)
+{
+    load_pending(app, true);
+}
+
+pub (crate) fn wpp_decode_all(document : &mut WppDocument)
+{
+    decode_pending(&mut document.layers, document.current_layer, true);
+}
+
+// just enough to pick a loader, without decoding the whole file an extra time. the marker is always
+// the first key and the version the second; files from before versioning don't have a version
+fn read_version(bytes : &[u8]) -> u32
+{
+    // a CBOR item head: the major type, and the small value or length that comes with it
+    fn head(bytes : &[u8], pos : &mut usize) -> Option<(u8, u64)>
+    {
+        let b = *bytes.get(*pos)?;
+        *pos += 1;
+        let n = match b & 31
+        {
+            x @ 0..=23 => x as u64,
+            x @ 24..=27 =>
+            {
+                let len = 1 << (x - 24);
+                let n = bytes.get(*pos..*pos + len)?.iter().fold(0, |n, x| n << 8 | *x as u64);
+                *pos += len;
+                n
+            }
+            // indefinite length maps
+            31 if b >> 5 == 5 => 0,
+            _ => return None,
+        };
+        Some((b >> 5, n))
+    }
+    fn key<'a>(bytes : &'a [u8], pos : &mut usize) -> Option<&'a [u8]>
+    {
+        let (3, len) = head(bytes, pos)? else { return None };
+        let ret = bytes.get(*pos..*pos + len as usize)?;
+        *pos += len as usize;
+        Some(ret)
+    }
+    let mut pos = 0;
+    let mut read = ||
+    {
+        let (5, _) = head(bytes, &mut pos)? else { return None };
+        if key(bytes, &mut pos)? != b"WarpainterDocumentCBOR" || !matches!(head(bytes, &mut pos)?, (4, 0) | (7, 22))
+        {
+            return None;
+        }
+        if key(bytes, &mut pos)? != b"version"
+        {
+            return None;
+        }
+        let (0, version) = head(bytes, &mut pos)? else { return None };
+        version.try_into().ok()
+    };
+    read().unwrap_or(0)
+}
+
+pub (crate) fn wpp_read(bytes : &[u8]) -> Result<(WppDocument, ViewState), WppError>
+{
+    let mut file : WppFile = match read_version(bytes)
+    {
+        0 => migrate_v1(migrate_v0(decode(bytes)?)),
+        1 => migrate_v1(decode(bytes)?),
+        WPP_VERSION => decode(bytes)?,
+        x => return Err(WppError::TooNew(x)),
+    };
+    attach_chunks(&mut file.document.layers, std::mem::take(&mut file.chunks))?;
+    Ok((file.document, file.view))
+}
+
+#[cfg(feature = "gui")]
+pub (crate) fn wpp_open(app : &mut Warpainter, bytes : &[u8]) -> Result<(), WppError>
+{
+    let (document, view) = wpp_read(bytes)?;
+    app.set_document(document);
+    
+    app.eraser_mode = view.eraser_mode;
+    app.main_color_rgb = view.main_color_rgb;
+    app.main_color_hsv = view.main_color_hsv;
+    app.sub_color_rgb = view.sub_color_rgb;
+    app.sub_color_hsv = view.sub_color_hsv;
+    app.current_tool = view.current_tool;
+    app.xform = view.xform;
+    
+    app.layers.visit_layers_mut(0, &mut |layer, _| { layer.commit_info(); Some(()) });
+    wpp_load_needed(app);
+    
+    app.queue_fit = true;
+    Ok(())
+}
+
+#[cfg(feature = "gui")]
+fn view_state(app : &Warpainter) -> ViewState
+{
+    ViewState {
+        eraser_mode : app.eraser_mode,
+        main_color_rgb : app.main_color_rgb,
+        main_color_hsv : app.main_color_hsv,
+        sub_color_rgb : app.sub_color_rgb,
+        sub_color_hsv : app.sub_color_hsv,
+        current_tool : app.current_tool,
+        xform : app.xform.clone(),
+    }
+}
+
+// compresses the pixels into chunks and serializes everything; gives the document back with its pixels in place
+fn encode_file(mut document : WppDocument, view : ViewState) -> (Vec<u8>, WppDocument)
+{
+    // flattened caches get rebuilt after loading, so leave them out
+    let swap_caches = |layers : &mut Layer| layers.visit_layers_mut(0, &mut |l, _depth|
+    {
+        std::mem::swap(&mut l.flattened_data, &mut l._dummy_flattened_data);
+        std::mem::swap(&mut l.flattened_dirty_rect, &mut l._dummy_flattened_dirty_rect);
+        Some(())
+    });
+    swap_caches(&mut document.layers);
+    
+    // move pixels out of the tree and into chunks. ones that were never decoded are written back as-is
+    let mut images = vec!();
+    let mut chunks = vec!();
+    document.layers.visit_layers_mut(0, &mut |l, _|
+    {
+        if l.data.is_some() || l.mask.is_some()
+        {
+            images.push((l.uuid, l.data.take(), l.mask.take()));
+        }
+        if let Some(pending) = &l.pending
+        {
+            chunks.extend(pending.iter().cloned());
+        }
+        Some(())
+    });
+    chunks.extend(get_pool().install(|| images.par_iter().flat_map_iter(|(uuid, data, mask)|
+    {
+        data.iter().map(|x| encode_chunk(*uuid, WppChunkKind::Pixels, x))
+            .chain(mask.iter().map(|x| encode_chunk(*uuid, WppChunkKind::Mask, x)))
+    }).collect::<Vec<_>>()));
+    
+    let file = WppFile {
+        WarpainterDocumentCBOR : (),
+        version : WPP_VERSION,
+        document,
+        view,
+        chunks,
+    };
+    
+    let mut data = vec!();
+    cbor4ii::serde::to_writer(&mut data, &file).unwrap();
+    
+    let mut document = file.document;
+    let mut images : HashMap<_, _> = images.into_iter().map(|(uuid, data, mask)| (uuid, (data, mask))).collect();
+    document.layers.visit_layers_mut(0, &mut |l, _|
+    {
+        if let Some((data, mask)) = images.remove(&l.uuid)
+        {
+            l.data = data;
+            l.mask = mask;
+        }
+        Some(())
+    });
+    swap_caches(&mut document.layers);
+    (data, document)
+}
+
+#[cfg(feature = "gui")]
+pub (crate) fn wpp_save(app : &mut Warpainter) -> Vec<u8>
+{
+    app.cancel_edit();
+    let mut document = app.take_document();
+    let data = wpp_save_document(&mut document, view_state(app));
+    app.set_document(document);
+    data
+}
+
+pub (crate) fn wpp_save_document(document : &mut WppDocument, view : ViewState) -> Vec<u8>
+{
+    let (data, ret) = encode_file(std::mem::take(document), view);
+    *document = ret;
+    data
+}
+
+// a copy of everything wpp_save writes, so the slow part can happen away from the editor
+#[cfg(feature = "gui")]
+pub (crate) struct WppSnapshot
+{
+    document : WppDocument,
+    view : ViewState,
+}
+
+#[cfg(feature = "gui")]
+pub (crate) fn wpp_snapshot(app : &mut Warpainter) -> WppSnapshot
+{
+    app.cancel_edit();
+    
+    // caches aren't saved, so don't copy them
+    let mut caches = vec!();
+    app.layers.visit_layers_mut(0, &mut |l, _|
+    {
+        caches.push((l.flattened_data.take(), l.thumbnail.take(), l.mask_thumbnail.take()));
+        Some(())
+    });
+    let layers = app.layers.clone();
+    let mut caches = caches.into_iter();
+    app.layers.visit_layers_mut(0, &mut |l, _|
+    {
+        (l.flattened_data, l.thumbnail, l.mask_thumbnail) = caches.next()?;
+        Some(())
+    });
+    
+    let document = WppDocument {
+        layers,
+        current_layer : app.current_layer,
+        canvas_width : app.canvas_width,
+        canvas_height : app.canvas_height,
+        selection_mask : app.selection_mask.clone(),
+        selection_poly : app.selection_poly.clone(),
+        metadata : app.metadata.clone(),
+    };
+    WppSnapshot { document, view : view_state(app) }
+}
+
+// the same bytes wpp_save would have made when the snapshot was taken
+#[cfg(feature = "gui")]
+pub (crate) fn wpp_encode(snapshot : WppSnapshot) -> Vec<u8>
+{
+    encode_file(snapshot.document, snapshot.view).0
+}
+
+// these all go through the editor
+#[cfg(all(test, feature = "gui"))]
+mod tests
+{
+    use super::*;
+    
+    // one file per format version, saved by the warpainter of that time. never regenerate these
+    const FIXTURES : [(&str, &[u8]); 3] = [
+        ("v0", include_bytes!("../testdata/wpp/v0.wpp")),
+        ("v1", include_bytes!("../testdata/wpp/v1.wpp")),
+        ("v2", include_bytes!("../testdata/wpp/v2.wpp")),
+    ];
+    
+    #[test]
+    pub fn test_wpp_fixtures()
+    {
+        for (name, bytes) in FIXTURES
+        {
+            let mut app = Warpainter::default();
+            wpp_open(&mut app, bytes).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert_eq!((app.canvas_width, app.canvas_height), (6, 4), "{}", name);
+            assert_eq!(app.main_color_rgb, [0.25, 0.5, 0.75, 1.0], "{}", name);
+            assert_eq!(app.current_tool, 2, "{}", name);
+            
+            let names : Vec<_> = app.layers.children.iter().map(|x| x.name.as_str()).collect();
+            assert_eq!(names, vec!("Group", "Background"), "{}", name);
+            let masked = &app.layers.children[0].children[0];
+            assert_eq!(masked.name, "Masked", "{}", name);
+            assert_eq!(app.current_layer, masked.uuid, "{}", name);
+            assert_eq!(masked.blend_mode, "Multiply", "{}", name);
+            assert_eq!(masked.offset, [2.0, 1.0], "{}", name);
+            assert_eq!(masked.mask.as_ref().unwrap().get_pixel(0, 0), [255], "{}", name);
+            
+            let flat = app.flatten();
+            assert_eq!(flat.get_pixel(1, 1), [255, 0, 0, 255], "{}", name);
+            assert_eq!(flat.get_pixel(5, 3), [0, 0, 255, 128], "{}", name);
+            // the green pixel is masked in, at half opacity
+            assert_eq!(flat.get_pixel(2, 1), [0, 255, 0, 128], "{}", name);
+            assert_eq!(flat.get_pixel(3, 1)[3], 0, "{}", name);
+        }
+    }
+    
+    #[test]
+    pub fn test_wpp_roundtrip_and_errors()
+    {
+        let mut app = Warpainter::default();
+        wpp_open(&mut app, FIXTURES[0].1).unwrap();
+        app.flatten();
+        app.metadata.icc_profile = Some(vec!(1, 2, 3));
+        let data = wpp_save(&mut app);
+        // the app keeps its document
+        assert_eq!(app.layers.children.len(), 2);
+        assert!(app.layers.flattened_data.is_some());
+        assert_eq!(app.metadata.icc_profile, Some(vec!(1, 2, 3)));
+        
+        let mut app2 = Warpainter::default();
+        wpp_open(&mut app2, &data).unwrap();
+        assert_eq!(app2.metadata, app.metadata);
+        assert_eq!(app2.current_layer, app.current_layer);
+        assert!(app2.layers.flattened_data.is_none());
+        assert_eq!(app2.flatten().get_pixel(1, 1), [255, 0, 0, 255]);
+        
+        assert_eq!(FIXTURES.map(|(_, bytes)| read_version(bytes)), [0, 1, 2]);
+        assert_eq!(read_version(&data), WPP_VERSION);
+        assert!(matches!(wpp_read(b"not cbor at all"), Err(WppError::Decode(_))));
+        assert!(matches!(wpp_read(&data[..data.len() / 2]), Err(WppError::Decode(_))));
+        
+        #[allow(non_snake_case)]
+        #[derive(Serialize)]
+        struct Future { WarpainterDocumentCBOR : (), version : u32 }
+        let mut data = vec!();
+        cbor4ii::serde::to_writer(&mut data, &Future { WarpainterDocumentCBOR : (), version : 99 }).unwrap();
+        assert_eq!(wpp_read(&data).err(), Some(WppError::TooNew(99)));
+    }
+    
+    #[test]
+    pub fn test_wpp_snapshot()
+    {
+        let mut app = Warpainter::default();
+        wpp_open(&mut app, FIXTURES[0].1).unwrap();
+        app.flatten();
+        let snapshot = wpp_snapshot(&mut app);
+        // the app keeps its caches
+        assert!(app.layers.flattened_data.is_some());
+        
+        // edits made after the snapshot don't end up in it
+        app.layers.children[1].data.as_mut().unwrap().set_pixel(1, 1, [0, 0, 0, 255]);
+        let data = std::thread::spawn(move || wpp_encode(snapshot)).join().unwrap();
+        
+        let mut app2 = Warpainter::default();
+        wpp_open(&mut app2, &data).unwrap();
+        assert_eq!(app2.current_layer, app.current_layer);
+        assert!(app2.layers.flattened_data.is_none() && app2.layers.flattened_dirty_rect.is_none());
+        assert_eq!(app2.flatten().get_pixel(1, 1), [255, 0, 0, 255]);
+        assert_eq!(app.layers.children[1].data.as_ref().unwrap().get_pixel(1, 1), [0, 0, 0, 255]);
+    }
+    
+    #[test]
+    pub fn test_wpp_lazy_layers()
+    {
+        let mut app = Warpainter::default();
+        let mut img = Image::<4>::blank(8, 8);
+        img.set_pixel(0, 0, [10, 20, 30, 255]);
+        app.load_from_img(img);
+        
+        let mut img = Image::<4>::blank_float(4, 4);
+        img.set_pixel_float(3, 3, [1.0, 0.0, 0.0, 1.0]);
+        let mut hidden = Layer::new_layer_from_image("Hidden", img);
+        hidden.visible = false;
+        let hidden_uuid = hidden.uuid;
+        
+        let mut inner = Layer::new_layer("Inner", 8, 8);
+        inner.mask = Some(Image::<1>::blank(8, 8));
+        inner.mask_info = Some(crate::wpsd_raw::MaskInfo { x : 0, y : 0, w : 8, h : 8, default_color : 0, relative : false, disabled : false, invert : false });
+        let inner_uuid = inner.uuid;
+        let mut group = Layer::new_group("Closed");
+        group.closed = true;
+        group.visible = false;
+        group.children.push(inner);
+        
+        app.layers.children.insert(0, hidden);
+        app.layers.children.insert(0, group);
+        let data = wpp_save(&mut app);
+        
+        // only what's drawn, and the current layer, gets decoded
+        let mut app2 = Warpainter::default();
+        wpp_open(&mut app2, &data).unwrap();
+        let layer = app2.layers.find_layer(hidden_uuid).unwrap();
+        assert!(layer.data.is_none() && layer.pending.is_some());
+        let layer = app2.layers.find_layer(inner_uuid).unwrap();
+        assert!(layer.data.is_none() && layer.mask.is_none() && layer.pending.is_some());
+        assert!(app2.layers.children[2].data.is_some());
+        assert!(!wpp_load_needed(&mut app2));
+        
+        // undecoded layers aren't groups, and hidden ones don't get a buffer when flattening
+        app2.flatten();
+        let layer = app2.layers.find_layer(hidden_uuid).unwrap();
+        assert!(!layer.is_group() && layer.flattened_data.is_none());
+        let layer = app2.layers.find_layer(inner_uuid).unwrap();
+        assert!(!layer.is_group() && layer.flattened_data.is_none());
+        
+        // undecoded layers are saved as they are
+        let data = wpp_save(&mut app2);
+        assert!(app2.layers.find_layer(hidden_uuid).unwrap().pending.is_some());
+        let mut app3 = Warpainter::default();
+        wpp_open(&mut app3, &data).unwrap();
+        
+        app3.layers.find_layer_mut(hidden_uuid).unwrap().visible = true;
+        assert_eq!(app3.flatten().get_pixel(3, 3), [255, 0, 0, 255]);
+        assert!(app3.layers.find_layer(hidden_uuid).unwrap().has_float_data());
+        
+        app3.current_layer = inner_uuid;
+        assert!(wpp_load_needed(&mut app3));
+        let layer = app3.layers.find_layer(inner_uuid).unwrap();
+        assert!(layer.data.is_some() && layer.mask.is_some() && layer.pending.is_none());
+        
+        wpp_load_all(&mut app2);
+        assert!(app2.layers.find_layer(hidden_uuid).unwrap().data.is_some());
+        
+        // a damaged chunk is reported when opening, not when the layer is shown
+        let mut file : WppFile = decode(&data).unwrap();
+        let chunk = file.chunks.iter_mut().find(|x| x.layer == hidden_uuid).unwrap();
+        chunk.data[4] ^= 1;
+        let mut data = vec!();
+        cbor4ii::serde::to_writer(&mut data, &file).unwrap();
+        assert_eq!(wpp_read(&data).err(), Some(WppError::CorruptChunk("Hidden".to_string())));
+    }
+}
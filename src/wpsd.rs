@@ -5,20 +5,20 @@ pub (crate) fn get_blend_mode(mode : &str) -> String
 {
     match mode
     {
-        "pass" => "Normal",
+        "pass" => "Pass Through",
         "norm" => "Normal",
         "diss" => "Dither",
         "dark" => "Darken",
         "mul " => "Multiply",
         "idiv" => "Color Burn",
         "lbrn" => "Linear Burn",
-        "dkCl" => "Darken",
+        "dkCl" => "Darker Color",
         "lite" => "Lighten",
         "scrn" => "Screen",
         "div " => "Color Dodge",
         "lddg" => "Add",
         "lddg_glow" => "Glow Add",
-        "lgCl" => "Lighten",
+        "lgCl" => "Lighter Color",
         "over" => "Overlay",
         "sLit" => "Soft Light",
         "hLit" => "Hard Light",
@@ -48,12 +48,12 @@ pub (crate) fn get_blend_mode_2(mode : &str) -> String
         "Mltp" => "Multiply",
         "CBrn" => "Color Burn",
         "linearBurn" => "Linear Burn",
-        "darkerColor" => "Darken",
+        "darkerColor" => "Darker Color",
         "Lghn" => "Lighten",
         "Scrn" => "Screen",
         "CDdg" => "Color Dodge",
         "linearDodge" => "Add",
-        "lighterColor" => "Lighten",
+        "lighterColor" => "Lighter Color",
         "Ovrl" => "Overlay",
         "SftL" => "Soft Light",
         "HrdL" => "Hard Light",
@@ -73,39 +73,385 @@ pub (crate) fn get_blend_mode_2(mode : &str) -> String
     }.to_string()
 }
 
+// photoshop's noise gradient generator isn't documented; this builds random stops from the same settings
+#[derive(Clone, Debug, Default)]
+struct NoiseGradient
+{
+    seed : u32,
+    roughness : f64,
+    space : String,
+    transparency : bool,
+    min : Vec<f64>,
+    max : Vec<f64>,
+}
+
+impl NoiseGradient
+{
+    fn to_stops(&self) -> (Vec<Vec<f64>>, Vec<Vec<f64>>)
+    {
+        let mut state = self.seed.max(1);
+        let mut rand = move ||
+        {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f64 / u32::MAX as f64
+        };
+        
+        let count = 2 + (self.roughness.clamp(0.0, 1.0) * 30.0) as usize;
+        let mut colors = vec!();
+        let mut trans = vec!();
+        for i in 0..count
+        {
+            let loc = i as f64 / (count - 1) as f64;
+            let mut v = [0.0; 4];
+            for (c, v) in v.iter_mut().enumerate()
+            {
+                let lo = self.min.get(c).copied().unwrap_or(0.0) / 100.0;
+                let hi = self.max.get(c).copied().unwrap_or(100.0) / 100.0;
+                *v = lo + (hi - lo) * rand();
+            }
+            let rgb = match self.space.as_str()
+            {
+                "HSBl" =>
+                {
+                    let c = hsv_to_rgb([v[0] as f32 * 360.0, v[1] as f32, v[2] as f32, 1.0]);
+                    [c[0] as f64, c[1] as f64, c[2] as f64]
+                }
+                "LbCl" => lab_to_rgb(v[0] * 100.0, v[1] * 255.0 - 128.0, v[2] * 255.0 - 128.0),
+                _ => [v[0], v[1], v[2]],
+            };
+            colors.push(vec!(rgb[0], rgb[1], rgb[2], loc, 0.5));
+            trans.push(vec!(if self.transparency { v[3] } else { 1.0 }, loc, 0.5));
+        }
+        (colors, trans)
+    }
+}
+
+// other color spaces come out black
+fn read_rgb(data : &DescItem) -> Option<[f64; 3]>
+{
+    let mut color = [0.0f64, 0.0f64, 0.0f64];
+    let data = data.Objc()?;
+    if data.0 == "RGBC"
+    {
+        for (c, item) in color.iter_mut().zip(data.1.iter())
+        {
+            *c = item.1.doub()? / 255.0;
+        }
+    }
+    Some(color)
+}
+
+fn read_fx_color(data : &DescItem) -> Option<Vec<FxData>>
+{
+    let [r, g, b] = read_rgb(data)?;
+    Some(vec!(r.into(), g.into(), b.into(), 1.0.into()))
+}
+
+// a value of the wrong type is skipped, the same as a missing one
+fn insert_fx<T : Into<FxData>>(hm : &mut HashMap<String, Vec<FxData>>, key : &str, value : Option<T>)
+{
+    if let Some(value) = value
+    {
+        hm.insert(key.to_string(), vec!(value.into()));
+    }
+}
+
+// reverse of get_blend_mode, for modes that photoshop has
+pub (crate) fn get_blend_mode_key(mode : &str) -> &'static str
+{
+    match mode
+    {
+        "Pass Through" => "pass",
+        "Normal" => "norm",
+        "Dither" => "diss",
+        "Darken" => "dark",
+        "Darker Color" => "dkCl",
+        "Multiply" => "mul ",
+        "Color Burn" => "idiv",
+        "Linear Burn" => "lbrn",
+        "Lighten" => "lite",
+        "Lighter Color" => "lgCl",
+        "Screen" => "scrn",
+        "Color Dodge" => "div ",
+        "Add" => "lddg",
+        "Glow Add" => "lddg",
+        "Overlay" => "over",
+        "Soft Light" => "sLit",
+        "Hard Light" => "hLit",
+        "Vivid Light" => "vLit",
+        "Linear Light" => "lLit",
+        "Pin Light" => "pLit",
+        "Hard Mix" => "hMix",
+        "Difference" => "diff",
+        "Exclusion" => "smud",
+        "Subtract" => "fsub",
+        "Divide" => "fdiv",
+        "Hue" => "hue ",
+        "Saturation" => "sat ",
+        "Color" => "colr",
+        "Luminosity" => "lum ",
+        _ => "norm",
+    }
+}
+
 use crate::wpsd_raw::*;
 
-pub (crate) fn wpsd_open(app : &mut Warpainter, bytes : &[u8])
+// only covers the paths' bounds; everything outside of them is the mask's default color
+fn rasterize_vector_mask(vector_mask : &PsdVectorMask, canvas_width : usize, canvas_height : usize) -> (Image<1>, MaskInfo)
+{
+    let scale = [canvas_width as f64, canvas_height as f64];
+    let mut min = [canvas_width as f64, canvas_height as f64];
+    let mut max = [0.0f64, 0.0];
+    for point in vector_mask.subpaths.iter().flat_map(|x| x.1.iter()).flatten()
+    {
+        for c in 0..2
+        {
+            min[c] = min[c].min(point[c] * scale[c]);
+            max[c] = max[c].max(point[c] * scale[c]);
+        }
+    }
+    let x = min[0].floor().max(0.0) as usize;
+    let y = min[1].floor().max(0.0) as usize;
+    let w = (max[0].ceil().min(scale[0]) as usize).saturating_sub(x).max(1);
+    let h = (max[1].ceil().min(scale[1]) as usize).saturating_sub(y).max(1);
+    
+    let mut loops : Vec<_> = vector_mask.subpaths.iter().map(|(op, knots)|
+        (*op, vec!(knots.iter().map(|knot| knot.map(|p| [(p[0] * scale[0] - x as f64) as f32, (p[1] * scale[1] - y as f64) as f32])).collect()))
+    ).collect();
+    if vector_mask.invert
+    {
+        // inverting is the same as xoring everything
+        let corners = [[0.0, 0.0], [w as f32, 0.0], [w as f32, h as f32], [0.0, h as f32]];
+        loops.push((0, vec!(corners.iter().map(|&p| [p, p, p]).collect())));
+    }
+    let mask = Image::<1>::from_bezier_loops(w, h, &loops, vector_mask.initial_fill);
+    
+    let default_color = if vector_mask.initial_fill != vector_mask.invert { 255 } else { 0 };
+    let info = MaskInfo { x : x as i32, y : y as i32, w : w as u32, h : h as u32, default_color, relative : false, disabled : vector_mask.disabled, invert : false };
+    (mask, info)
+}
+
+fn psd_image(depth : u16, w : u32, h : u32, rgba : Vec<u8>, mut float : Vec<f32>) -> Option<Image<4>>
+{
+    if depth == 32
+    {
+        // 32-bit documents are stored in linear light
+        for px in float.chunks_exact_mut(4)
+        {
+            for c in px.iter_mut().take(3)
+            {
+                *c = linear_to_srgb(*c);
+            }
+        }
+    }
+    if depth != 8
+    {
+        image::Rgba32FImage::from_raw(w, h, float).map(|img| Image::<4>::from_rgbafimage(&img))
+    }
+    else
+    {
+        image::RgbaImage::from_raw(w, h, rgba).map(|img| Image::<4>::from_rgbaimage(&img))
+    }
+}
+
+// N values starting at `at`, if the block was long enough to have them
+fn adjustment_values<const N : usize>(n : &[f32], at : usize, offset : u64) -> Result<[f32; N], PsdError>
+{
+    n.get(at..at + N).and_then(|x| x.try_into().ok()).ok_or(PsdError::Malformed(offset, "adjustment layer data"))
+}
+
+fn read_adjustment(kind : &str, n : &[f32], desc : Option<Descriptor>, offset : u64) -> Result<Option<Adjustment>, PsdError>
+{
+    let get = |at| adjustment_values::<1>(n, at, offset).map(|x| x[0]);
+    Ok(match kind
+    {
+        "" => None,
+        "nvrt" => Some(Adjustment::Invert),
+        "post" => Some(Adjustment::Posterize(get(0)?)),
+        "thrs" => Some(Adjustment::Threshold(get(0)?)),
+        "brit" => Some(Adjustment::BrightContrast(adjustment_values(n, 0, offset)?)),
+        "hue2" => Some(Adjustment::HueSatLum(adjustment_values(n, 4, offset)?)),
+        "levl" =>
+        {
+            let mut data = vec!();
+            for i in 0..6
+            {
+                data.push(adjustment_values(n, i * 5, offset)?);
+            }
+            Some(Adjustment::Levels(data))
+        }
+        "curv" =>
+        {
+            let mut data = vec!();
+            let mut i = 0;
+            for _ in 0..6
+            {
+                let count = get(i)?;
+                i += 1;
+                let mut nodes = vec!();
+                for _j in 0..count as usize
+                {
+                    nodes.push(adjustment_values(n, i, offset)?);
+                    i += 2;
+                }
+                data.push(nodes);
+            }
+            Some(Adjustment::Curves(data))
+        }
+        "blwh" =>
+        {
+            let mut data = [0.0; 6];
+            #[allow(non_snake_case)]
+            let tintColor = false; // TODO
+            let data2 = [0.0; 3]; // TODO
+            
+            let mut n = HashMap::new();
+            for t in &desc.unwrap_or_default().1
+            {
+                n.insert(t.0.clone(), t.1.clone());
+            }
+            
+            // missing values fall back to photoshop's defaults
+            let get = |key : &str, default : i32| match n.get(key) { Some(DescItem::long(x)) => *x as f32, _ => default as f32 };
+            data[0] = get("Rd  ", 40);
+            data[1] = get("Yllw", 60);
+            data[2] = get("Grn ", 40);
+            data[3] = get("Cyn ", 60);
+            data[4] = get("Bl  ", 20);
+            data[5] = get("Mgnt", 80);
+            
+            Some(Adjustment::BlackWhite((data, tintColor, data2)))
+        }
+        "grdm" =>
+        {
+            let mut i = 2;
+            let mut colors = vec!();
+            for _ in 0..get(i)? as usize
+            {
+                colors.push(adjustment_values(n, i + 1, offset)?);
+                i += 5;
+            }
+            i += 1;
+            let mut alphas = vec!();
+            for _ in 0..get(i)? as usize
+            {
+                alphas.push(adjustment_values(n, i + 1, offset)?);
+                i += 3;
+            }
+            i += 1;
+            if get(i)? != 0.0
+            {
+                let m : [f32; 13] = adjustment_values(n, i + 1, offset)?;
+                let noise = NoiseGradient {
+                    seed : ((m[0] as u32) << 16) | m[1] as u32,
+                    transparency : m[2] != 0.0,
+                    roughness : m[3] as f64,
+                    space : match m[4] as u32 { 1 => "HSBl", 2 => "LbCl", _ => "RGBC" }.to_string(),
+                    min : m[5..9].iter().map(|x| *x as f64).collect(),
+                    max : m[9..13].iter().map(|x| *x as f64).collect(),
+                };
+                let (c, a) = noise.to_stops();
+                colors = c.iter().map(|x| [x[0] as f32, x[1] as f32, x[2] as f32, x[3] as f32, x[4] as f32]).collect();
+                alphas = a.iter().map(|x| [x[0] as f32, x[1] as f32, x[2] as f32]).collect();
+            }
+            Some(Adjustment::GradientMap((colors, alphas, n[0] != 0.0, n[1] != 0.0)))
+        }
+        "phfl" =>
+        {
+            let n : [f32; 5] = adjustment_values(n, 0, offset)?;
+            Some(Adjustment::PhotoFilter(([n[0], n[1], n[2]], n[3], n[4] != 0.0)))
+        }
+        "mixr" =>
+        {
+            let mut rows = [[0.0; 4]; 3];
+            for (i, row) in rows.iter_mut().enumerate()
+            {
+                *row = adjustment_values(n, 1 + i * 4, offset)?;
+            }
+            Some(Adjustment::ChannelMixer((rows, get(0)? != 0.0)))
+        }
+        "blnc" =>
+        {
+            let mut ranges = [[0.0; 3]; 3];
+            for (i, range) in ranges.iter_mut().enumerate()
+            {
+                *range = adjustment_values(n, i * 3, offset)?;
+            }
+            Some(Adjustment::ColorBalance((ranges, get(9)? != 0.0)))
+        }
+        "selc" =>
+        {
+            let absolute = get(0)? != 0.0;
+            let data = n[1..].chunks_exact(4).map(|x| [x[0], x[1], x[2], x[3]]).collect();
+            Some(Adjustment::SelectiveColor((data, absolute)))
+        }
+        "expA" => Some(Adjustment::Exposure(adjustment_values(n, 0, offset)?)),
+        "vibA" =>
+        {
+            let desc = desc.unwrap_or_default();
+            let get = |key : &str| desc.1.iter().find(|x| x.0 == key).map(|x| match x.1 { DescItem::long(n) => n as f32, _ => 0.0 }).unwrap_or(0.0);
+            Some(Adjustment::Vibrance([get("vibrance"), get("Strt")]))
+        }
+        //_ => panic!(),
+        _ => None,
+    })
+}
+
+#[cfg(feature = "gui")]
+pub (crate) fn wpsd_open(app : &mut Warpainter, bytes : &[u8]) -> Result<(), PsdError>
 {
-    let psd_data = parse_psd_metadata(&bytes);
-    let psd_layers = parse_layer_records(&bytes);
+    let document = wpsd_read(bytes)?;
+    app.set_document(document);
     
-    app.layers = Layer::new_group("___root___");
-    app.layers.uuid = 0;
-    app.canvas_width = psd_data.width as usize;
-    app.canvas_height = psd_data.height as usize;
+    app.current_tool = 4;
+    app.queue_fit = true;
+    Ok(())
+}
+
+pub (crate) fn wpsd_read(bytes : &[u8]) -> Result<WppDocument, PsdError>
+{
+    let psd_data = parse_psd_metadata(bytes)?;
+    let resources = parse_image_resources(bytes)?;
+    let patterns = parse_patterns(bytes)?;
+    let linked_files = parse_linked_files(bytes)?;
+    let mut psd_layers = parse_layer_records(bytes)?;
+    if psd_layers.is_empty()
+    {
+        // e.g. indexed files, which can't have layers
+        psd_layers.push(parse_merged_image(bytes)?);
+    }
     
-    let root = Layer::new_group("PSD File");
+    let mut root = Layer::new_group("PSD File");
+    root.psd_file_root = true;
     let mut stack = vec!(root);
+    let mut closer_offsets = vec!();
+    let mut id_to_uuid = HashMap::new();
     
     for (i, mut layerdata) in psd_layers.into_iter().enumerate()
     {
-        let w = layerdata.w as u32;
-        let h = layerdata.h as u32;
+        let mut w = layerdata.w as u32;
+        let mut h = layerdata.h as u32;
+        if w == 0 || h == 0
+        {
+            // don't spin over a huge number of empty rows
+            w = 0;
+            h = 0;
+        }
         let mut mask_img = None;
         if layerdata.mask_channel_count != 0
         {
             mask_img = image::GrayImage::from_raw(layerdata.mask_info.w, layerdata.mask_info.h, layerdata.image_data_mask);
         }
         //println!("{:?}", mask_img);
-        if let Some(img) = image::RgbaImage::from_raw(w, h, layerdata.image_data_rgba)
+        if let Some(img) = psd_image(layerdata.depth, w, h, layerdata.image_data_rgba, layerdata.image_data_float)
         {
             let mask = mask_img.map(|x| Image::<1>::from_yimage(&x, layerdata.mask_info.invert));
             if layerdata.mask_info.invert { layerdata.mask_info.default_color = 255 - layerdata.mask_info.default_color; }
             layerdata.mask_info.invert = false;
-            layerdata.mask_info.x -= layerdata.x as i32;
-            layerdata.mask_info.y -= layerdata.y as i32;
-            let img = Image::<4>::from_rgbaimage(&img);
+            layerdata.mask_info.x -= layerdata.x;
+            layerdata.mask_info.y -= layerdata.y;
             let mut layer = if layerdata.group_opener { Layer::new_group("New Layer") } else { Layer::new_layer_from_image("New Layer", img) };
             layer.mask_info = if mask.is_some() { Some(layerdata.mask_info) } else { None };
             layer.mask = mask;
@@ -121,6 +467,31 @@ pub (crate) fn wpsd_open(app : &mut Warpainter, bytes : &[u8])
             layer.fill_opacity = layerdata.fill_opacity;
             //println!("!!!!{:?}", layer.offset);
             layer.blend_mode = get_blend_mode(&layerdata.blend_mode);
+            // keep the embedded original, so the layer can be re-rendered at other sizes
+            if let Some((id, transform)) = &layerdata.smart_object
+            {
+                if let Some(file) = linked_files.get(id)
+                {
+                    let mut transform = transform.map(|x| x as f32);
+                    for i in 0..4
+                    {
+                        transform[i * 2] -= layer.offset[0];
+                        transform[i * 2 + 1] -= layer.offset[1];
+                    }
+                    layer.smart_object = Some(SmartObject { name : file.name.clone(), data : file.data.clone(), transform });
+                }
+            }
+            if let Some(vector_mask) = &layerdata.vector_mask
+            {
+                let (mask, mut info) = rasterize_vector_mask(vector_mask, psd_data.width as usize, psd_data.height as usize);
+                info.x -= layerdata.x;
+                info.y -= layerdata.y;
+                layer.vector_mask = Some((mask, info));
+            }
+            if let Some(text) = &layerdata.text
+            {
+                layer.text = Some(crate::wpsd_text::text_layer_from_psd(text, layer.offset));
+            }
             if layerdata.group_opener
             {
                 layer.closed = !layerdata.group_expanded;
@@ -141,13 +512,15 @@ pub (crate) fn wpsd_open(app : &mut Warpainter, bytes : &[u8])
                         }
                         "masterFXSwitch" =>
                         {
+                            let Some(enabled) = fx.bool() else { continue };
                             let mut hm = HashMap::new();
-                            hm.insert("bool".to_string(), vec!(fx.bool().into()));
+                            hm.insert("bool".to_string(), vec!(enabled.into()));
                             layer.effects.insert("_enabled".to_string(), hm);
                         }
                         "SoFi" =>
                         {
-                            let (_, fx) = *fx.Objc();
+                            let Some(desc) = fx.Objc() else { continue };
+                            let (_, fx) = *desc;
                             
                             let mut hm = HashMap::new();
                             hm.insert("color".to_string(), vec!(0.0.into(), 0.0.into(), 0.0.into(), 1.0.into()));
@@ -156,25 +529,10 @@ pub (crate) fn wpsd_open(app : &mut Warpainter, bytes : &[u8])
                             {
                                 match name.as_str()
                                 {
-                                    "enab" => { hm.insert("enabled".to_string(), vec!(data.bool().into())); }
-                                    "Md  " => { hm.insert("mode".to_string(), vec!(get_blend_mode_2(&data.r#enum().1).into())); }
-                                    "Opct" => { hm.insert("opacity".to_string(), vec!(data.UntF().1.into())); }
-                                    "Clr " =>
-                                    {
-                                        let mut color = [0.0f64, 0.0f64, 0.0f64, 1.0f64];
-                                        let data = data.Objc();
-                                        match data.0.as_str()
-                                        {
-                                            "RGBC" =>
-                                            {
-                                                color[0] = data.1[0].1.doub() / 255.0;
-                                                color[1] = data.1[1].1.doub() / 255.0;
-                                                color[2] = data.1[2].1.doub() / 255.0;
-                                            }
-                                            _ => { }
-                                        }
-                                        hm.insert("color".to_string(), color.map(|x| x.into()).to_vec());
-                                    }
+                                    "enab" => insert_fx(&mut hm, "enabled", data.bool()),
+                                    "Md  " => insert_fx(&mut hm, "mode", data.r#enum().map(|x| get_blend_mode_2(&x.1))),
+                                    "Opct" => insert_fx(&mut hm, "opacity", data.UntF().map(|x| x.1)),
+                                    "Clr " => if let Some(color) = read_fx_color(&data) { hm.insert("color".to_string(), color); },
                                     _ => {}
                                 }
                             }
@@ -199,7 +557,8 @@ pub (crate) fn wpsd_open(app : &mut Warpainter, bytes : &[u8])
                         
                         "DrSh" =>
                         {
-                            let (_, fx) = *fx.Objc();
+                            let Some(desc) = fx.Objc() else { continue };
+                            let (_, fx) = *desc;
                             
                             println!("{:#?}", fx);
                             
@@ -210,105 +569,235 @@ pub (crate) fn wpsd_open(app : &mut Warpainter, bytes : &[u8])
                             {
                                 match name.as_str()
                                 {
-                                    "enab" => { hm.insert("enabled".to_string(), vec!(data.bool().into())); }
-                                    "Md  " => { hm.insert("mode".to_string(), vec!(get_blend_mode_2(&data.r#enum().1).into())); }
-                                    "Opct" => { hm.insert("opacity".to_string(), vec!(data.UntF().1.into())); }
-                                    //"Angl" => { hm.insert("angle".to_string(), vec!(data.UntF().1.into())); }
+                                    "enab" => insert_fx(&mut hm, "enabled", data.bool()),
+                                    "Md  " => insert_fx(&mut hm, "mode", data.r#enum().map(|x| get_blend_mode_2(&x.1))),
+                                    "Opct" => insert_fx(&mut hm, "opacity", data.UntF().map(|x| x.1)),
+                                    //"Angl" => insert_fx(&mut hm, "angle", data.UntF().map(|x| x.1)),
                                     
-                                    "uglg" => { hm.insert("use global angle".to_string(), vec!(data.bool().into())); }
-                                    "lagl" => { hm.insert("angle".to_string(), vec!(data.UntF().1.into())); }
-                                    "Dstn" => { hm.insert("distance".to_string(), vec!(data.UntF().1.into())); }
-                                    "Ckmt" => { hm.insert("spread".to_string(), vec!(data.UntF().1.into())); }
-                                    "Nose" => { hm.insert("noise".to_string(), vec!(data.UntF().1.into())); }
-                                    "blur" => { hm.insert("blur".to_string(), vec!(data.UntF().1.into())); }
+                                    "uglg" => insert_fx(&mut hm, "use global angle", data.bool()),
+                                    "lagl" => insert_fx(&mut hm, "angle", data.UntF().map(|x| x.1)),
+                                    "Dstn" => insert_fx(&mut hm, "distance", data.UntF().map(|x| x.1)),
+                                    "Ckmt" => insert_fx(&mut hm, "spread", data.UntF().map(|x| x.1)),
+                                    "Nose" => insert_fx(&mut hm, "noise", data.UntF().map(|x| x.1)),
+                                    "blur" => insert_fx(&mut hm, "blur", data.UntF().map(|x| x.1)),
                                     
-                                    "AntA" => { hm.insert("antialias".to_string(), vec!(data.bool().into())); }
-                                    "layerConceals" => { hm.insert("knockout".to_string(), vec!(data.bool().into())); }
+                                    "AntA" => insert_fx(&mut hm, "antialias", data.bool()),
+                                    "layerConceals" => insert_fx(&mut hm, "knockout", data.bool()),
                                     
-                                    "Clr " =>
+                                    "Clr " => if let Some(color) = read_fx_color(&data) { hm.insert("color".to_string(), color); },
+                                    _ => { }
+                                }
+                            }
+                            
+                            layer.effects.insert("dropshadow".to_string(), hm);
+                        }
+                        
+                        "OrGl" | "IrGl" =>
+                        {
+                            let Some(desc) = fx.Objc() else { continue };
+                            let (_, fx) = *desc;
+                            
+                            let kind = if name == "OrGl" { "outerglow" } else { "innerglow" };
+                            let mut hm = fx_default(kind).unwrap_or_default();
+                            
+                            for (name, data) in fx
+                            {
+                                match name.as_str()
+                                {
+                                    "enab" => insert_fx(&mut hm, "enabled", data.bool()),
+                                    "Md  " => insert_fx(&mut hm, "mode", data.r#enum().map(|x| get_blend_mode_2(&x.1))),
+                                    "Opct" => insert_fx(&mut hm, "opacity", data.UntF().map(|x| x.1)),
+                                    "Ckmt" => insert_fx(&mut hm, "choke", data.UntF().map(|x| x.1)),
+                                    "blur" => insert_fx(&mut hm, "size", data.UntF().map(|x| x.1)),
+                                    "Nose" => insert_fx(&mut hm, "noise", data.UntF().map(|x| x.1)),
+                                    "Inpr" => insert_fx(&mut hm, "range", data.UntF().map(|x| x.1)),
+                                    "AntA" => insert_fx(&mut hm, "antialias", data.bool()),
+                                    "GlwT" =>
+                                    {
+                                        let n = data.r#enum().map(|x| if x.1 == "PrBL" { "precise" } else { "softer" }.to_string());
+                                        insert_fx(&mut hm, "technique", n);
+                                    }
+                                    "glwS" =>
+                                    {
+                                        let n = data.r#enum().map(|x| if x.1 == "SrcC" { "center" } else { "edge" }.to_string());
+                                        insert_fx(&mut hm, "source", n);
+                                    }
+                                    "Clr " => if let Some(color) = read_fx_color(&data) { hm.insert("color".to_string(), color); },
+                                    _ => { }
+                                }
+                            }
+                            
+                            layer.effects.insert(kind.to_string(), hm);
+                        }
+                        
+                        "ebbl" =>
+                        {
+                            let Some(desc) = fx.Objc() else { continue };
+                            let (_, fx) = *desc;
+                            
+                            let mut hm = fx_default("bevel").unwrap_or_default();
+                            
+                            for (name, data) in fx
+                            {
+                                match name.as_str()
+                                {
+                                    "enab" => insert_fx(&mut hm, "enabled", data.bool()),
+                                    "hglM" => insert_fx(&mut hm, "highlight mode", data.r#enum().map(|x| get_blend_mode_2(&x.1))),
+                                    "hglC" => if let Some(color) = read_fx_color(&data) { hm.insert("highlight color".to_string(), color); },
+                                    "hglO" => insert_fx(&mut hm, "highlight opacity", data.UntF().map(|x| x.1)),
+                                    "sdwM" => insert_fx(&mut hm, "shadow mode", data.r#enum().map(|x| get_blend_mode_2(&x.1))),
+                                    "sdwC" => if let Some(color) = read_fx_color(&data) { hm.insert("shadow color".to_string(), color); },
+                                    "sdwO" => insert_fx(&mut hm, "shadow opacity", data.UntF().map(|x| x.1)),
+                                    "uglg" => insert_fx(&mut hm, "use global angle", data.bool()),
+                                    "lagl" => insert_fx(&mut hm, "angle", data.UntF().map(|x| x.1)),
+                                    "Lald" => insert_fx(&mut hm, "altitude", data.UntF().map(|x| x.1)),
+                                    "srgR" => insert_fx(&mut hm, "depth", data.UntF().map(|x| x.1)),
+                                    "blur" => insert_fx(&mut hm, "size", data.UntF().map(|x| x.1)),
+                                    "Sftn" => insert_fx(&mut hm, "soften", data.UntF().map(|x| x.1)),
+                                    "bvlT" =>
                                     {
-                                        let mut color = [0.0f64, 0.0f64, 0.0f64, 1.0f64];
-                                        let data = data.Objc();
-                                        match data.0.as_str()
+                                        let n = data.r#enum().map(|x| match x.1.as_str()
                                         {
-                                            "RGBC" =>
-                                            {
-                                                color[0] = data.1[0].1.doub() / 255.0;
-                                                color[1] = data.1[1].1.doub() / 255.0;
-                                                color[2] = data.1[2].1.doub() / 255.0;
-                                            }
-                                            _ => { }
-                                        }
-                                        hm.insert("color".to_string(), color.map(|x| x.into()).to_vec());
+                                            "PrBL" => "chisel hard",
+                                            "Slmt" => "chisel soft",
+                                            _ => "smooth",
+                                        }.to_string());
+                                        insert_fx(&mut hm, "technique", n);
+                                    }
+                                    "bvlS" =>
+                                    {
+                                        let n = data.r#enum().map(|x| match x.1.as_str()
+                                        {
+                                            "OtrB" => "outer",
+                                            "Embs" => "emboss",
+                                            "PlEb" => "pillow",
+                                            "strokeEmboss" => "stroke",
+                                            _ => "inner",
+                                        }.to_string());
+                                        insert_fx(&mut hm, "style", n);
+                                    }
+                                    "bvlD" =>
+                                    {
+                                        let n = data.r#enum().map(|x| if x.1 == "Out " { "down" } else { "up" }.to_string());
+                                        insert_fx(&mut hm, "direction", n);
                                     }
                                     _ => { }
                                 }
                             }
                             
-                            layer.effects.insert("dropshadow".to_string(), hm);
+                            layer.effects.insert("bevel".to_string(), hm);
+                        }
+                        
+                        "ChFX" =>
+                        {
+                            let Some(desc) = fx.Objc() else { continue };
+                            let (_, fx) = *desc;
+                            
+                            let mut hm = fx_default("satin").unwrap_or_default();
+                            
+                            for (name, data) in fx
+                            {
+                                match name.as_str()
+                                {
+                                    "enab" => insert_fx(&mut hm, "enabled", data.bool()),
+                                    "Md  " => insert_fx(&mut hm, "mode", data.r#enum().map(|x| get_blend_mode_2(&x.1))),
+                                    "Opct" => insert_fx(&mut hm, "opacity", data.UntF().map(|x| x.1)),
+                                    "Clr " => if let Some(color) = read_fx_color(&data) { hm.insert("color".to_string(), color); },
+                                    "lagl" => insert_fx(&mut hm, "angle", data.UntF().map(|x| x.1)),
+                                    "Dstn" => insert_fx(&mut hm, "distance", data.UntF().map(|x| x.1)),
+                                    "blur" => insert_fx(&mut hm, "size", data.UntF().map(|x| x.1)),
+                                    "Invr" => insert_fx(&mut hm, "invert", data.bool()),
+                                    "AntA" => insert_fx(&mut hm, "antialias", data.bool()),
+                                    _ => { }
+                                }
+                            }
+                            
+                            layer.effects.insert("satin".to_string(), hm);
                         }
                         
                         "GrFl" =>
                         {
-                            let (_, fx) = *fx.Objc();
+                            let Some(desc) = fx.Objc() else { continue };
+                            let (_, fx) = *desc;
                             
                             let mut hm = HashMap::new();
                             
                             let mut colors = vec!();
                             let mut trans = vec!();
+                            let mut noise = None;
                             
                             for (name, data) in fx
                             {
                                 match name.as_str()
                                 {
-                                    "enab" => { hm.insert("enabled".to_string(), vec!(data.bool().into())); }
-                                    "Md  " => { hm.insert("mode".to_string(), vec!(get_blend_mode_2(&data.r#enum().1).into())); }
-                                    "Opct" => { hm.insert("opacity".to_string(), vec!(data.UntF().1.into())); }
-                                    "Angl" => { hm.insert("angle".to_string(), vec!(data.UntF().1.into())); }
-                                    "Type" => { hm.insert("type".to_string(), vec!( match data.r#enum().1.as_str()
+                                    "enab" => insert_fx(&mut hm, "enabled", data.bool()),
+                                    "Md  " => insert_fx(&mut hm, "mode", data.r#enum().map(|x| get_blend_mode_2(&x.1))),
+                                    "Opct" => insert_fx(&mut hm, "opacity", data.UntF().map(|x| x.1)),
+                                    "Angl" => insert_fx(&mut hm, "angle", data.UntF().map(|x| x.1)),
+                                    "Type" => insert_fx(&mut hm, "type", data.r#enum().map(|x| match x.1.as_str()
                                     {
                                         "Lnr " => "linear".to_string(),
+                                        "Rdl " => "radial".to_string(),
+                                        "Angl" => "angle".to_string(),
+                                        "Rflc" => "reflected".to_string(),
+                                        "Dmnd" => "diamond".to_string(),
                                         s => s.to_string(),
-                                    }.into())); }
-                                    "Rvrs" => { hm.insert("reverse".to_string(), vec!(data.bool().into())); }
-                                    "Dthr" => { hm.insert("dither".to_string(), vec!(data.bool().into())); }
-                                    "Algn" => { hm.insert("align".to_string(), vec!(data.bool().into())); }
-                                    "Scl " => { hm.insert("scale".to_string(), vec!(data.UntF().1.into())); }
+                                    })),
+                                    "Ofst" =>
+                                    {
+                                        let Some(desc) = data.Objc() else { continue };
+                                        let mut offset = [0.0f64, 0.0f64];
+                                        for (name, data) in desc.1
+                                        {
+                                            match (name.as_str(), data.UntF())
+                                            {
+                                                ("Hrzn", Some(x)) => offset[0] = x.1,
+                                                ("Vrtc", Some(x)) => offset[1] = x.1,
+                                                _ => { }
+                                            }
+                                        }
+                                        hm.insert("offset".to_string(), offset.map(|x| x.into()).to_vec());
+                                    }
+                                    "Rvrs" => insert_fx(&mut hm, "reverse", data.bool()),
+                                    "Dthr" => insert_fx(&mut hm, "dither", data.bool()),
+                                    "Algn" => insert_fx(&mut hm, "align", data.bool()),
+                                    "Scl " => insert_fx(&mut hm, "scale", data.UntF().map(|x| x.1)),
                                     "Grad" =>
                                     {
                                         let n = 4096.0f64;
-                                        let data = data.Objc();
-                                        for data in data.1
+                                        let Some(data) = data.Objc() else { continue };
+                                        let number = |item : &DescItem| match item { DescItem::long(x) => *x as f64, DescItem::doub(x) => *x, _ => 0.0 };
+                                        for data in &data.1
                                         {
                                             match data.0.as_str()
                                             {
                                                 //"Intr" => n = data.1.doub(),
+                                                "GrdF" =>
+                                                {
+                                                    if data.1.r#enum().is_some_and(|x| x.1 == "ClNs")
+                                                    {
+                                                        noise = Some(NoiseGradient::default());
+                                                    }
+                                                }
                                                 "Clrs" =>
                                                 {
-                                                    for data in data.1.VlLs()
+                                                    for data in data.1.VlLs().unwrap_or_default()
                                                     {
                                                         let mut color = vec![0.0f64, 0.0f64, 0.0f64, 0.0f64, 0.5f64];
-                                                        let data = data.Objc();
+                                                        let Some(data) = data.Objc() else { continue };
                                                         for data in data.1
                                                         {
                                                             match data.0.as_str()
                                                             {
                                                                 "Clr " =>
                                                                 {
-                                                                    let data = data.1.Objc();
-                                                                    match data.0.as_str()
+                                                                    if let Some(rgb) = read_rgb(&data.1)
                                                                     {
-                                                                        "RGBC" =>
-                                                                        {
-                                                                            color[0] = data.1[0].1.doub() / 255.0;
-                                                                            color[1] = data.1[1].1.doub() / 255.0;
-                                                                            color[2] = data.1[2].1.doub() / 255.0;
-                                                                        }
-                                                                        _ => { }
+                                                                        color[..3].copy_from_slice(&rgb);
                                                                     }
                                                                 }
-                                                                "Lctn" => color[3] = data.1.long() as f64 / n,
-                                                                "Mdpn" => color[4] = data.1.long() as f64 / 100.0,
+                                                                "Lctn" => color[3] = number(&data.1) / n,
+                                                                "Mdpn" => color[4] = number(&data.1) / 100.0,
                                                                 _ => { }
                                                             }
                                                         }
@@ -317,17 +806,17 @@ pub (crate) fn wpsd_open(app : &mut Warpainter, bytes : &[u8])
                                                 }
                                                 "Trns" =>
                                                 {
-                                                    for data in data.1.VlLs()
+                                                    for data in data.1.VlLs().unwrap_or_default()
                                                     {
-                                                        let data = data.Objc();
+                                                        let Some(data) = data.Objc() else { continue };
                                                         let mut tx = vec![0.0f64, 0.0f64, 0.5f64];
                                                         for data in data.1
                                                         {
                                                             match data.0.as_str()
                                                             {
-                                                                "Opct" => tx[0] = data.1.UntF().1 / 100.0,
-                                                                "Lctn" => tx[1] = data.1.long() as f64 / n,
-                                                                "Mdpn" => tx[2] = data.1.long() as f64 / 100.0,
+                                                                "Opct" => if let Some(x) = data.1.UntF() { tx[0] = x.1 / 100.0 },
+                                                                "Lctn" => tx[1] = number(&data.1) / n,
+                                                                "Mdpn" => tx[2] = number(&data.1) / 100.0,
                                                                 _ => { }
                                                             }
                                                         }
@@ -337,18 +826,109 @@ pub (crate) fn wpsd_open(app : &mut Warpainter, bytes : &[u8])
                                                 _ => { }
                                             }
                                         }
+                                        if let Some(noise) = &mut noise
+                                        {
+                                            for data in data.1
+                                            {
+                                                match data.0.as_str()
+                                                {
+                                                    "RndS" => noise.seed = number(&data.1) as u32,
+                                                    "Smth" => noise.roughness = number(&data.1) / 4096.0,
+                                                    "ClrS" => noise.space = data.1.r#enum().map(|x| x.1).unwrap_or_default(),
+                                                    "ShTr" => noise.transparency = data.1.bool().unwrap_or_default(),
+                                                    "Mnm " => noise.min = data.1.VlLs().unwrap_or_default().iter().map(number).collect(),
+                                                    "Mxm " => noise.max = data.1.VlLs().unwrap_or_default().iter().map(number).collect(),
+                                                    _ => { }
+                                                }
+                                            }
+                                        }
                                     }
                                     _ => {}
                                 }
                             }
+                            if let Some(noise) = noise
+                            {
+                                (colors, trans) = noise.to_stops();
+                            }
                             hm.insert("gradient".to_string(), vec!(colors.into(), trans.into()));
                             
                             layer.effects.insert("gradfill".to_string(), hm);
                         }
                         
+                        "patternFill" =>
+                        {
+                            let Some(desc) = fx.Objc() else { continue };
+                            let (_, fx) = *desc;
+                            
+                            let mut hm = HashMap::new();
+                            hm.insert("enabled".to_string(), vec!(true.into()));
+                            hm.insert("mode".to_string(), vec!("Normal".to_string().into()));
+                            hm.insert("opacity".to_string(), vec!(100.0.into()));
+                            hm.insert("scale".to_string(), vec!(100.0.into()));
+                            hm.insert("link".to_string(), vec!(true.into()));
+                            hm.insert("phase".to_string(), vec!(0.0.into(), 0.0.into()));
+                            
+                            let mut id = String::new();
+                            for (name, data) in fx
+                            {
+                                match name.as_str()
+                                {
+                                    "enab" => insert_fx(&mut hm, "enabled", data.bool()),
+                                    "Md  " => insert_fx(&mut hm, "mode", data.r#enum().map(|x| get_blend_mode_2(&x.1))),
+                                    "Opct" => insert_fx(&mut hm, "opacity", data.UntF().map(|x| x.1)),
+                                    "Scl " => insert_fx(&mut hm, "scale", data.UntF().map(|x| x.1)),
+                                    "Algn" => insert_fx(&mut hm, "link", data.bool()),
+                                    "phase" =>
+                                    {
+                                        let Some(desc) = data.Objc() else { continue };
+                                        let mut phase = [0.0f64, 0.0f64];
+                                        for (name, data) in desc.1
+                                        {
+                                            match (name.as_str(), data.doub())
+                                            {
+                                                ("Hrzn", Some(x)) => phase[0] = x,
+                                                ("Vrtc", Some(x)) => phase[1] = x,
+                                                _ => { }
+                                            }
+                                        }
+                                        hm.insert("phase".to_string(), phase.map(|x| x.into()).to_vec());
+                                    }
+                                    "Ptrn" =>
+                                    {
+                                        let Some(desc) = data.Objc() else { continue };
+                                        for (name, data) in desc.1
+                                        {
+                                            match (name.as_str(), data.TEXT())
+                                            {
+                                                ("Nm  ", text) => insert_fx(&mut hm, "pattern name", text),
+                                                ("Idnt", Some(text)) => id = text,
+                                                _ => { }
+                                            }
+                                        }
+                                        hm.insert("pattern id".to_string(), vec!(id.clone().into()));
+                                    }
+                                    _ => { }
+                                }
+                            }
+                            
+                            // patterns that only exist in photoshop's preset library won't be embedded in the file
+                            if let Some(pattern) = patterns.iter().find(|p| p.id == id)
+                            {
+                                let pixels = pattern.image_data_rgba.iter().map(|x| *x as f64 / 255.0).collect::<Vec<_>>();
+                                hm.insert("pattern".to_string(), vec!(vec!(pattern.w as f64, pattern.h as f64).into(), pixels.into()));
+                            }
+                            else
+                            {
+                                println!("pattern {:?} not found in file", id);
+                            }
+                            
+                            layer.effects.insert("patternfill".to_string(), hm);
+                        }
+                        
                         "FrFX" =>
                         {
-                            let (_, fx) = *fx.Objc();
+                            let Some(desc) = fx.Objc() else { continue };
+                            let (_, fx) = *desc;
                             
                             let mut hm = HashMap::new();
                             hm.insert("color".to_string(), vec!(0.0.into(), 0.0.into(), 0.0.into(), 1.0.into()));
@@ -357,37 +937,22 @@ pub (crate) fn wpsd_open(app : &mut Warpainter, bytes : &[u8])
                             {
                                 match name.as_str()
                                 {
-                                    "enab" => { hm.insert("enabled".to_string(), vec!(data.bool().into())); }
-                                    "Md  " => { hm.insert("mode".to_string(), vec!(get_blend_mode_2(&data.r#enum().1).into())); }
-                                    "Opct" => { hm.insert("opacity".to_string(), vec!(data.UntF().1.into())); }
-                                    "Sz  " => { hm.insert("size".to_string(), vec!(data.UntF().1.into())); }
+                                    "enab" => insert_fx(&mut hm, "enabled", data.bool()),
+                                    "Md  " => insert_fx(&mut hm, "mode", data.r#enum().map(|x| get_blend_mode_2(&x.1))),
+                                    "Opct" => insert_fx(&mut hm, "opacity", data.UntF().map(|x| x.1)),
+                                    "Sz  " => insert_fx(&mut hm, "size", data.UntF().map(|x| x.1)),
                                     "Styl" =>
                                     {
-                                        let n = &data.r#enum().1;
-                                        hm.insert("style".to_string(), vec!(match n.as_str()
+                                        let n = data.r#enum().map(|x| match x.1.as_str()
                                         {
                                             "OutF" => "outside",
                                             "InsF" => "inside",
                                             "CtrF" => "center",
                                             _ => "outside",
-                                        }.to_string().into()));
-                                    }
-                                    "Clr " =>
-                                    {
-                                        let mut color = [0.0f64, 0.0f64, 0.0f64, 1.0f64];
-                                        let data = data.Objc();
-                                        match data.0.as_str()
-                                        {
-                                            "RGBC" =>
-                                            {
-                                                color[0] = data.1[0].1.doub() / 255.0;
-                                                color[1] = data.1[1].1.doub() / 255.0;
-                                                color[2] = data.1[2].1.doub() / 255.0;
-                                            }
-                                            _ => { }
-                                        }
-                                        hm.insert("color".to_string(), color.map(|x| x.into()).to_vec());
+                                        }.to_string());
+                                        insert_fx(&mut hm, "style", n);
                                     }
+                                    "Clr " => if let Some(color) = read_fx_color(&data) { hm.insert("color".to_string(), color); },
                                     _ => {}
                                 }
                             }
@@ -399,81 +964,27 @@ pub (crate) fn wpsd_open(app : &mut Warpainter, bytes : &[u8])
                 }
             }
             
-            layer.adjustment = match layerdata.adjustment_type.as_str()
-            {
-                "" => None,
-                "nvrt" => Some(Adjustment::Invert),
-                "post" => Some(Adjustment::Posterize(layerdata.adjustment_info[0])),
-                "thrs" => Some(Adjustment::Threshold(layerdata.adjustment_info[0])),
-                "brit" => Some(Adjustment::BrightContrast(<[f32; 5]>::try_from(&layerdata.adjustment_info[0..5]).unwrap())),
-                "hue2" => Some(Adjustment::HueSatLum(<[f32; 3]>::try_from(&layerdata.adjustment_info[4..7]).unwrap())),
-                "levl" => 
+            layer.adjustment = read_adjustment(&layerdata.adjustment_type, &layerdata.adjustment_info, layerdata.adjustment_desc, layerdata.offset)?;
+            
+            //println!("layer {}: {} (of {:?})", i, layer.name, layer.parent_id());
+            println!("layer {}: {}", i, layer.name);
+            
+            layer.commit_info();
+            id_to_uuid.insert(layerdata.layer_id, layer.uuid);
+            
+            if layerdata.group_closer
+            {
+                stack.push(layer);
+                closer_offsets.push(layerdata.offset);
+            }
+            else if layerdata.group_opener
+            {
+                if stack.len() < 2
                 {
-                    let mut data = vec!();
-                    let mut i = 0;
-                    for _ in 0..6
-                    {
-                        data.push(<[f32; 5]>::try_from(&layerdata.adjustment_info[i..i+5]).unwrap());
-                        i += 5;
-                    }
-                    Some(Adjustment::Levels(data))
-                }
-                "curv" =>
-                {
-                    let mut data = vec!();
-                    let mut i = 0;
-                    for _ in 0..6
-                    {
-                        let n = layerdata.adjustment_info[i];
-                        i += 1;
-                        let mut nodes = vec!();
-                        for _j in 0..n as usize
-                        {
-                            nodes.push([layerdata.adjustment_info[i], layerdata.adjustment_info[i+1]]);
-                            i += 2;
-                        }
-                        data.push(nodes);
-                    }
-                    Some(Adjustment::Curves(data))
+                    return Err(PsdError::Malformed(layerdata.offset, "group structure (unmatched group opener)"));
                 }
-                "blwh" =>
-                {
-                    let mut data = [0.0; 6];
-                    #[allow(non_snake_case)]
-                    let tintColor = false; // TODO
-                    let data2 = [0.0; 3]; // TODO
-                    
-                    let mut n = HashMap::new();
-                    for t in &layerdata.adjustment_desc.unwrap().1
-                    {
-                        n.insert(t.0.clone(), t.1.clone());
-                    }
-                    
-                    data[0] = n.get("Rd  ").unwrap().long() as f32;
-                    data[1] = n.get("Yllw").unwrap().long() as f32;
-                    data[2] = n.get("Grn ").unwrap().long() as f32;
-                    data[3] = n.get("Cyn ").unwrap().long() as f32;
-                    data[4] = n.get("Bl  ").unwrap().long() as f32;
-                    data[5] = n.get("Mgnt").unwrap().long() as f32;
-                    
-                    Some(Adjustment::BlackWhite((data, tintColor, data2)))
-                }
-                //_ => panic!(),
-                _ => None,
-            };
-            
-            //println!("layer {}: {} (of {:?})", i, layer.name, layer.parent_id());
-            println!("layer {}: {}", i, layer.name);
-            
-            layer.commit_info();
-            
-            if layerdata.group_closer
-            {
-                stack.push(layer);
-            }
-            else if layerdata.group_opener
-            {
                 let mut temp = stack.pop().unwrap();
+                closer_offsets.pop();
                 std::mem::swap(&mut temp.children, &mut layer.children);
                 stack.last_mut().unwrap().children.insert(0, layer);
             }
@@ -483,15 +994,1743 @@ pub (crate) fn wpsd_open(app : &mut Warpainter, bytes : &[u8])
             }
         }
     }
-    assert!(stack.len() == 1);
-    app.layers.children = vec!(stack.pop().unwrap());
-    app.current_layer = app.layers.children[0].uuid;
-    app.current_tool = 4;
-    app.queue_fit = true;
+    if let Some(offset) = closer_offsets.pop()
+    {
+        return Err(PsdError::Malformed(offset, "group structure (unmatched group closer)"));
+    }
+    
+    let mut layers = Layer::new_group("___root___");
+    layers.uuid = 0;
+    layers.children = vec!(stack.pop().unwrap());
+    let mut current_layer = layers.children[0].uuid;
+    
+    let mut metadata = resources.metadata;
+    metadata.selected_layers = resources.selected_layer_ids.iter().filter_map(|id| id_to_uuid.get(id).copied()).collect();
+    if let Some(uuid) = metadata.selected_layers.first()
+    {
+        current_layer = *uuid;
+    }
+    
     //for (i, group) in psd.groups() {
     //    let name = group.name();
     //    println!("group {}: {}", i, name);
     //    for (j, n) in 
     //}
     println!("asdf");
-}
\ No newline at end of file
+    Ok(WppDocument {
+        layers,
+        current_layer,
+        canvas_width : psd_data.width as usize,
+        canvas_height : psd_data.height as usize,
+        selection_mask : None,
+        selection_poly : vec!(),
+        metadata,
+    })
+}
+
+// how far our render of an imported file is from the composite photoshop saved alongside its layers
+pub (crate) struct CompositeDiff
+{
+    pub (crate) max_error : f32,
+    pub (crate) mean_error : f32,
+    // pixels off by more than one 8-bit step
+    pub (crate) bad_pixels : usize,
+    // white where the error is a quarter of the range or more
+    pub (crate) diff : Image<4>,
+}
+
+pub (crate) fn wpsd_compare_composite(bytes : &[u8]) -> Result<CompositeDiff, PsdError>
+{
+    let merged = parse_merged_image(bytes)?;
+    let expected = psd_image(merged.depth, merged.w, merged.h, merged.image_data_rgba, merged.image_data_float)
+        .ok_or(PsdError::Malformed(0, "merged image size"))?;
+    
+    let mut document = wpsd_read(bytes)?;
+    let (w, h) = (document.canvas_width, document.canvas_height);
+    let actual = document.flatten();
+    
+    let mut diff = Image::<4>::blank(w, h);
+    let mut max_error = 0.0f32;
+    let mut total_error = 0.0;
+    let mut bad_pixels = 0;
+    for y in 0..h as isize
+    {
+        for x in 0..w as isize
+        {
+            let a = expected.get_pixel_float(x, y);
+            let b = actual.get_pixel_float(x, y);
+            // compare premultiplied, so invisible colors don't count
+            let mut error = (a[3] - b[3]).abs();
+            for c in 0..3
+            {
+                error = error.max((a[c] * a[3] - b[c] * b[3]).abs());
+            }
+            max_error = max_error.max(error);
+            total_error += error as f64;
+            if error > 1.0 / 255.0
+            {
+                bad_pixels += 1;
+            }
+            let v = (error * 4.0).min(1.0);
+            diff.set_pixel_float(x, y, [v, v, v, 1.0]);
+        }
+    }
+    let mean_error = (total_error / (w * h).max(1) as f64) as f32;
+    
+    Ok(CompositeDiff { max_error, mean_error, bad_pixels, diff })
+}
+
+struct PsdRecord
+{
+    rect : [i32; 4], // top, left, bottom, right
+    channels : Vec<(i16, Vec<u8>)>,
+    blend_mode : &'static str,
+    opacity : f32,
+    clipped : bool,
+    flags : u8,
+    mask : Option<([i32; 4], u8, u8)>, // rect, default color, flags
+    name : String,
+    blocks : Vec<(&'static str, Vec<u8>)>,
+}
+
+impl PsdRecord
+{
+    fn new(name : &str) -> Self
+    {
+        Self {
+            rect : [0; 4],
+            channels : vec!(),
+            blend_mode : "norm",
+            opacity : 1.0,
+            clipped : false,
+            flags : 0,
+            mask : None,
+            name : name.to_string(),
+            blocks : vec!(),
+        }
+    }
+    fn from_layer(layer : &Layer) -> Self
+    {
+        let mut ret = Self::new(&layer.name);
+        ret.blend_mode = get_blend_mode_key(&layer.blend_mode);
+        ret.opacity = layer.opacity;
+        ret.clipped = layer.clipped;
+        ret.flags = (layer.alpha_locked as u8) | ((!layer.visible as u8) << 1);
+        
+        let mut luni = vec!();
+        write_unicode_string(&mut luni, &layer.name);
+        ret.blocks.push(("luni", luni));
+        ret.blocks.push(("iOpa", vec!((layer.fill_opacity * 255.0).round() as u8)));
+        ret.blocks.push(("tsly", vec!(!layer.funny_flag as u8)));
+        
+        if let (Some(mask), Some(info)) = (&layer.mask, &layer.mask_info)
+        {
+            // our mask offsets are relative to the layer, photoshop's aren't
+            let x = layer.offset[0].round() as i32 + info.x;
+            let y = layer.offset[1].round() as i32 + info.y;
+            let rect = [y, x, y + mask.height as i32, x + mask.width as i32];
+            let flags = (info.relative as u8) | ((info.disabled as u8) << 1);
+            ret.mask = Some((rect, info.default_color, flags));
+            
+            let mut data = vec!();
+            write_channel_data(&mut data, &mask.to_yimage().into_raw(), mask.width, mask.height);
+            ret.channels.push((-2, data));
+        }
+        ret
+    }
+    // color channels go before the mask channel, if any
+    fn set_color_channels(&mut self, img : &[u8], w : usize, h : usize)
+    {
+        let mut channels = vec!();
+        for (id, c) in [(-1, 3), (0, 0), (1, 1), (2, 2)]
+        {
+            let plane : Vec<u8> = img.iter().skip(c).step_by(4).copied().collect();
+            let mut data = vec!();
+            write_channel_data(&mut data, &plane, w, h);
+            channels.push((id, data));
+        }
+        channels.append(&mut self.channels);
+        self.channels = channels;
+    }
+    fn write(&self, out : &mut Vec<u8>)
+    {
+        for n in self.rect
+        {
+            write_i32(out, n);
+        }
+        write_u16(out, self.channels.len() as u16);
+        for (id, data) in &self.channels
+        {
+            write_u16(out, *id as u16);
+            write_u32(out, data.len() as u32);
+        }
+        out.extend_from_slice(b"8BIM");
+        out.extend_from_slice(self.blend_mode.as_bytes());
+        write_u8(out, (self.opacity * 255.0).round() as u8);
+        write_u8(out, self.clipped as u8);
+        write_u8(out, self.flags);
+        write_u8(out, 0);
+        
+        let extra_at = out.len();
+        write_u32(out, 0);
+        
+        if let Some((rect, default_color, flags)) = self.mask
+        {
+            write_u32(out, 20);
+            for n in rect
+            {
+                write_i32(out, n);
+            }
+            write_u8(out, default_color);
+            write_u8(out, flags);
+            write_u16(out, 0);
+        }
+        else
+        {
+            write_u32(out, 0);
+        }
+        
+        // blending ranges: composite gray plus one per channel, all passing everything
+        write_u32(out, 40);
+        for _ in 0..10
+        {
+            out.extend_from_slice(&[0, 0, 255, 255]);
+        }
+        
+        write_pascal_name(out, &self.name);
+        
+        for (key, data) in &self.blocks
+        {
+            out.extend_from_slice(b"8BIM");
+            out.extend_from_slice(key.as_bytes());
+            let len = data.len().div_ceil(4) * 4;
+            write_u32(out, len as u32);
+            out.extend_from_slice(data);
+            out.resize(out.len() + len - data.len(), 0);
+        }
+        
+        patch_u32_len(out, extra_at);
+    }
+}
+
+fn adjustment_blocks(adjustment : &Adjustment) -> Result<Vec<(&'static str, Vec<u8>)>, String>
+{
+    let mut ret = vec!();
+    let mut data = vec!();
+    match adjustment
+    {
+        Adjustment::Invert => ret.push(("nvrt", data)),
+        Adjustment::Posterize(n) =>
+        {
+            write_u16(&mut data, *n as u16);
+            ret.push(("post", data));
+        }
+        Adjustment::Threshold(n) =>
+        {
+            write_u16(&mut data, *n as u16);
+            ret.push(("thrs", data));
+        }
+        Adjustment::BrightContrast(n) =>
+        {
+            write_u16(&mut data, n[0] as i16 as u16);
+            write_u16(&mut data, n[1] as i16 as u16);
+            write_u16(&mut data, n[2] as u16);
+            write_u8(&mut data, n[3] as u8);
+            ret.push(("brit", data));
+            
+            // the legacy block can't hold negative values or the legacy flag, so store the real values here too
+            let mut data = vec!();
+            write_u32(&mut data, 16);
+            write_descriptor(&mut data, &("null".to_string(), vec!(
+                ("Vrsn".to_string(), DescItem::long(1)),
+                ("Brgh".to_string(), DescItem::long(n[0] as i32)),
+                ("Cntr".to_string(), DescItem::long(n[1] as i32)),
+                ("means".to_string(), DescItem::long(n[2] as i32)),
+                ("Lab ".to_string(), DescItem::bool(n[3] != 0.0)),
+                ("useLegacy".to_string(), DescItem::bool(n[4] != 0.0)),
+                ("Auto".to_string(), DescItem::bool(false)),
+            )))?;
+            ret.push(("CgEd", data));
+        }
+        Adjustment::HueSatLum(n) =>
+        {
+            write_u16(&mut data, 2); // version
+            write_u8(&mut data, 0); // not colorized
+            write_u8(&mut data, 0);
+            for _ in 0..3
+            {
+                write_u16(&mut data, 0);
+            }
+            for x in n
+            {
+                write_u16(&mut data, *x as i16 as u16);
+            }
+            // default ranges for each hextant, with no adjustment
+            for i in 0..6
+            {
+                let base = 315 + i * 60;
+                for r in [base, base + 30, base + 60, base + 90]
+                {
+                    write_u16(&mut data, (r % 360) as u16);
+                }
+                for _ in 0..3
+                {
+                    write_u16(&mut data, 0);
+                }
+            }
+            ret.push(("hue2", data));
+        }
+        Adjustment::Levels(v) =>
+        {
+            write_u16(&mut data, 2); // version
+            for i in 0..29
+            {
+                let l = v.get(i).copied().unwrap_or([0.0, 1.0, 0.0, 1.0, 1.0]);
+                for x in &l[0..4]
+                {
+                    write_u16(&mut data, (x * 255.0).round() as u16);
+                }
+                write_u16(&mut data, (l[4] * 100.0).round() as u16);
+            }
+            ret.push(("levl", data));
+        }
+        Adjustment::Curves(v) =>
+        {
+            write_u8(&mut data, 0);
+            write_u16(&mut data, 1); // version
+            let mut enabled = 0u32;
+            for (i, points) in v.iter().enumerate().take(32)
+            {
+                if !points.is_empty()
+                {
+                    enabled |= 1 << i;
+                }
+            }
+            write_u32(&mut data, enabled);
+            for points in v.iter().take(32).filter(|x| !x.is_empty())
+            {
+                write_u16(&mut data, points.len() as u16);
+                for p in points
+                {
+                    // output first, then input
+                    write_u16(&mut data, (p[1] * 255.0).round() as u16);
+                    write_u16(&mut data, (p[0] * 255.0).round() as u16);
+                }
+            }
+            ret.push(("curv", data));
+        }
+        Adjustment::BlackWhite((v, tint, color)) =>
+        {
+            write_u32(&mut data, 16);
+            let rgb = |n : f32| DescItem::doub(n as f64 * 255.0);
+            write_descriptor(&mut data, &("null".to_string(), vec!(
+                ("Rd  ".to_string(), DescItem::long(v[0] as i32)),
+                ("Yllw".to_string(), DescItem::long(v[1] as i32)),
+                ("Grn ".to_string(), DescItem::long(v[2] as i32)),
+                ("Cyn ".to_string(), DescItem::long(v[3] as i32)),
+                ("Bl  ".to_string(), DescItem::long(v[4] as i32)),
+                ("Mgnt".to_string(), DescItem::long(v[5] as i32)),
+                ("useTint".to_string(), DescItem::bool(*tint)),
+                ("tintColor".to_string(), DescItem::Objc(Box::new(("RGBC".to_string(), vec!(
+                    ("Rd  ".to_string(), rgb(color[0])),
+                    ("Grn ".to_string(), rgb(color[1])),
+                    ("Bl  ".to_string(), rgb(color[2])),
+                ))))),
+                ("bwPresetKind".to_string(), DescItem::long(1)),
+            )))?;
+            ret.push(("blwh", data));
+        }
+        Adjustment::GradientMap((colors, alphas, reversed, dithered)) =>
+        {
+            write_u16(&mut data, 1); // version
+            write_u8(&mut data, *reversed as u8);
+            write_u8(&mut data, *dithered as u8);
+            write_unicode_string(&mut data, "Custom");
+            write_u16(&mut data, colors.len() as u16);
+            for c in colors
+            {
+                write_u32(&mut data, (c[3] * 4096.0).round() as u32);
+                write_u32(&mut data, (c[4] * 100.0).round() as u32);
+                write_u16(&mut data, 0); // RGB
+                for x in &c[0..3]
+                {
+                    write_u16(&mut data, (x.clamp(0.0, 1.0) * 65535.0).round() as u16);
+                }
+                write_u16(&mut data, 0);
+                write_u16(&mut data, 0);
+            }
+            write_u16(&mut data, alphas.len() as u16);
+            for a in alphas
+            {
+                write_u32(&mut data, (a[1] * 4096.0).round() as u32);
+                write_u32(&mut data, (a[2] * 100.0).round() as u32);
+                write_u16(&mut data, (a[0].clamp(0.0, 1.0) * 255.0).round() as u16);
+            }
+            write_u16(&mut data, 2); // expansion count
+            write_u16(&mut data, 4096); // smoothness
+            write_u16(&mut data, 32);
+            write_u16(&mut data, 0); // not a noise gradient
+            write_u32(&mut data, 0);
+            write_u16(&mut data, 0);
+            write_u16(&mut data, 0);
+            write_u32(&mut data, 2048);
+            write_u16(&mut data, 0);
+            for x in [0, 0, 0, 0, 0x8000, 0x8000, 0x8000, 0x8000]
+            {
+                write_u16(&mut data, x);
+            }
+            write_u16(&mut data, 0);
+            ret.push(("grdm", data));
+        }
+        Adjustment::PhotoFilter((color, density, preserve_luminosity)) =>
+        {
+            write_u16(&mut data, 2); // version
+            write_u16(&mut data, 0); // RGB
+            for x in color
+            {
+                write_u16(&mut data, (x.clamp(0.0, 1.0) * 65535.0).round() as u16);
+            }
+            write_u16(&mut data, 0);
+            write_u32(&mut data, density.round() as u32);
+            write_u8(&mut data, *preserve_luminosity as u8);
+            ret.push(("phfl", data));
+        }
+        Adjustment::Exposure(n) =>
+        {
+            write_u16(&mut data, 1); // version
+            for x in n
+            {
+                write_f32(&mut data, *x);
+            }
+            ret.push(("expA", data));
+        }
+        Adjustment::Vibrance(n) =>
+        {
+            write_u32(&mut data, 16);
+            write_descriptor(&mut data, &("null".to_string(), vec!(
+                ("vibrance".to_string(), DescItem::long(n[0] as i32)),
+                ("Strt".to_string(), DescItem::long(n[1] as i32)),
+            )))?;
+            ret.push(("vibA", data));
+        }
+        Adjustment::ChannelMixer((rows, monochrome)) =>
+        {
+            write_u16(&mut data, 1); // version
+            write_u16(&mut data, *monochrome as u16);
+            for row in rows
+            {
+                for x in [row[0], row[1], row[2], 0.0, row[3]]
+                {
+                    write_u16(&mut data, x.round() as i16 as u16);
+                }
+            }
+            // unused CMYK black output
+            for x in [0, 0, 0, 100, 0]
+            {
+                write_u16(&mut data, x);
+            }
+            ret.push(("mixr", data));
+        }
+        Adjustment::ColorBalance((ranges, preserve_luminosity)) =>
+        {
+            for x in ranges.iter().flatten()
+            {
+                write_u16(&mut data, x.round() as i16 as u16);
+            }
+            write_u8(&mut data, *preserve_luminosity as u8);
+            write_u8(&mut data, 0);
+            ret.push(("blnc", data));
+        }
+        Adjustment::SelectiveColor((v, absolute)) =>
+        {
+            write_u16(&mut data, 1); // version
+            write_u16(&mut data, *absolute as u16);
+            for _ in 0..4
+            {
+                write_u16(&mut data, 0);
+            }
+            for i in 0..9
+            {
+                for x in v.get(i).copied().unwrap_or_default()
+                {
+                    write_u16(&mut data, x.round() as i16 as u16);
+                }
+            }
+            ret.push(("selc", data));
+        }
+        Adjustment::Xxx => { }
+    }
+    Ok(ret)
+}
+
+fn push_psd_records(layer : &Layer, records : &mut Vec<PsdRecord>) -> Result<(), String>
+{
+    if let Some(adjustment) = &layer.adjustment
+    {
+        let mut record = PsdRecord::from_layer(layer);
+        record.set_color_channels(&[], 0, 0);
+        record.blocks.extend(adjustment_blocks(adjustment).map_err(|e| format!("layer {:?}: {}", layer.name, e))?);
+        records.push(record);
+    }
+    else if layer.is_group()
+    {
+        let mut closer = PsdRecord::new("</Layer group>");
+        closer.set_color_channels(&[], 0, 0);
+        closer.blocks.push(("lsct", 3u32.to_be_bytes().to_vec()));
+        records.push(closer);
+        
+        for child in layer.children.iter().rev()
+        {
+            push_psd_records(child, records)?;
+        }
+        
+        let mut opener = PsdRecord::from_layer(layer);
+        opener.set_color_channels(&[], 0, 0);
+        let mut lsct = vec!();
+        write_u32(&mut lsct, if layer.closed { 2 } else { 1 });
+        lsct.extend_from_slice(b"8BIM");
+        lsct.extend_from_slice(opener.blend_mode.as_bytes());
+        opener.blocks.push(("lsct", lsct));
+        records.push(opener);
+    }
+    else if let Some(data) = &layer.data
+    {
+        let mut record = PsdRecord::from_layer(layer);
+        let x = layer.offset[0].round() as i32;
+        let y = layer.offset[1].round() as i32;
+        record.rect = [y, x, y + data.height as i32, x + data.width as i32];
+        
+        record.set_color_channels(data.to_imagebuffer().as_raw(), data.width, data.height);
+        if let Some(text) = &layer.text
+        {
+            if let Some(block) = crate::wpsd_text::type_tool_block(text, layer.offset).map_err(|e| format!("layer {:?}: {}", layer.name, e))?
+            {
+                record.blocks.push(("TySh", block));
+            }
+        }
+        records.push(record);
+    }
+    Ok(())
+}
+
+fn write_image_resources(out : &mut Vec<u8>, metadata : &DocumentMetadata)
+{
+    let mut resources = vec!();
+    if let Some(res) = &metadata.resolution
+    {
+        let mut data = vec!();
+        write_u32(&mut data, (res.h_res * 65536.0).round() as u32);
+        write_u16(&mut data, res.h_unit);
+        write_u16(&mut data, res.width_unit);
+        write_u32(&mut data, (res.v_res * 65536.0).round() as u32);
+        write_u16(&mut data, res.v_unit);
+        write_u16(&mut data, res.height_unit);
+        resources.push((1005, data));
+    }
+    if !metadata.guides.is_empty()
+    {
+        let mut data = vec!();
+        write_u32(&mut data, 1);
+        write_u32(&mut data, 576);
+        write_u32(&mut data, 576);
+        write_u32(&mut data, metadata.guides.len() as u32);
+        for guide in &metadata.guides
+        {
+            write_i32(&mut data, (guide.position * 32.0).round() as i32);
+            write_u8(&mut data, guide.horizontal as u8);
+        }
+        resources.push((1032, data));
+    }
+    if let Some(icc) = &metadata.icc_profile
+    {
+        resources.push((1039, icc.clone()));
+    }
+    
+    let start = out.len();
+    write_u32(out, 0);
+    for (id, data) in resources
+    {
+        out.extend_from_slice(b"8BIM");
+        write_u16(out, id);
+        write_u16(out, 0); // empty name, padded
+        write_u32(out, data.len() as u32);
+        out.extend_from_slice(&data);
+        if data.len() % 2 != 0
+        {
+            out.push(0);
+        }
+    }
+    patch_u32_len(out, start);
+}
+
+#[cfg(feature = "gui")]
+pub (crate) fn wpsd_save(app : &mut Warpainter) -> Result<Vec<u8>, String>
+{
+    app.cancel_edit();
+    wpp_load_all(app);
+    
+    let mut document = app.take_document();
+    let data = wpsd_write(&mut document);
+    app.set_document(document);
+    data
+}
+
+pub (crate) fn wpsd_write(document : &mut WppDocument) -> Result<Vec<u8>, String>
+{
+    let merged = document.flatten().to_imagebuffer();
+    
+    // files we opened ourselves are wrapped in a group by wpsd_read; don't nest it again
+    let mut top = &document.layers.children;
+    if top.len() == 1 && top[0].psd_file_root
+    {
+        top = &top[0].children;
+    }
+    
+    let mut records = vec!();
+    for layer in top.iter().rev()
+    {
+        push_psd_records(layer, &mut records)?;
+    }
+    // the count is an i16, and group dividers count too
+    if records.len() > i16::MAX as usize
+    {
+        return Err(format!("too many layers for a PSD file ({} records, at most {})", records.len(), i16::MAX));
+    }
+    
+    Ok(write_psd(&records, merged, &document.metadata))
+}
+
+fn write_psd(records : &[PsdRecord], merged : image::RgbaImage, metadata : &DocumentMetadata) -> Vec<u8>
+{
+    let (w, h) = (merged.width() as usize, merged.height() as usize);
+    
+    let mut out = vec!();
+    out.extend_from_slice(b"8BPS");
+    write_u16(&mut out, 1);
+    out.extend_from_slice(&[0; 6]);
+    write_u16(&mut out, 4); // RGB + merged transparency
+    write_u32(&mut out, h as u32);
+    write_u32(&mut out, w as u32);
+    write_u16(&mut out, 8);
+    write_u16(&mut out, 3); // RGB
+    
+    write_u32(&mut out, 0); // color mode data
+    write_image_resources(&mut out, metadata);
+    
+    let layer_mask_info_at = out.len();
+    write_u32(&mut out, 0);
+    let layer_info_at = out.len();
+    write_u32(&mut out, 0);
+    
+    // negative: the first alpha channel is the merged transparency
+    write_u16(&mut out, (-(records.len() as i16)) as u16);
+    for record in records
+    {
+        record.write(&mut out);
+    }
+    for record in records
+    {
+        for (_, data) in &record.channels
+        {
+            out.extend_from_slice(data);
+        }
+    }
+    if (out.len() - layer_info_at) % 2 != 0
+    {
+        out.push(0);
+    }
+    patch_u32_len(&mut out, layer_info_at);
+    
+    write_u32(&mut out, 0); // global layer mask info
+    patch_u32_len(&mut out, layer_mask_info_at);
+    
+    // merged image: all row lengths for every channel, then all rows
+    // like photoshop, the colors are matted against white
+    let mut merged = merged.into_raw();
+    for px in merged.chunks_exact_mut(4)
+    {
+        let a = px[3] as u32;
+        for c in px[..3].iter_mut()
+        {
+            *c = ((*c as u32 * a + 255 * (255 - a) + 127) / 255) as u8;
+        }
+    }
+    write_u16(&mut out, 1);
+    let mut rows = vec!();
+    for c in 0..4
+    {
+        let plane : Vec<u8> = merged.iter().skip(c).step_by(4).copied().collect();
+        for y in 0..h
+        {
+            let mut row = vec!();
+            packbits_encode(&plane[y*w..y*w + w], &mut row);
+            write_u16(&mut out, row.len() as u16);
+            rows.push(row);
+        }
+    }
+    for row in rows
+    {
+        out.extend_from_slice(&row);
+    }
+    
+    out
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    
+    #[test]
+    #[cfg(feature = "gui")]
+    pub fn test_save_roundtrip()
+    {
+        let mut app = Warpainter::default();
+        let mut img = Image::<4>::blank(5, 3);
+        img.set_pixel(1, 2, [10, 20, 30, 255]);
+        img.set_pixel(4, 0, [200, 100, 50, 128]);
+        app.load_from_img(img);
+        
+        let mut bottom = app.layers.children.pop().unwrap();
+        bottom.name = "Bottom ünicode".to_string();
+        bottom.offset = [2.0, -1.0];
+        bottom.blend_mode = "Multiply".to_string();
+        bottom.fill_opacity = 0.4;
+        
+        let mut clipped = Layer::new_layer("Clipped", 4, 4);
+        clipped.clipped = true;
+        clipped.visible = false;
+        clipped.opacity = 0.6;
+        clipped.blend_mode = "Darker Color".to_string();
+        let mut mask = Image::<1>::blank(2, 2);
+        mask.set_pixel(1, 1, [255]);
+        clipped.mask = Some(mask);
+        clipped.mask_info = Some(MaskInfo { x : 1, y : 1, w : 2, h : 2, default_color : 255, relative : false, disabled : false, invert : false });
+        
+        let mut group = Layer::new_group("Group");
+        group.blend_mode = "Screen".to_string();
+        group.closed = true;
+        group.children.push(clipped);
+        group.children.push(bottom);
+        
+        let mut adjustment = Layer::new_layer("Levels", 0, 0);
+        adjustment.adjustment = Some(Adjustment::Levels(vec!([0.0, 1.0, 0.0, 1.0, 1.0]; 6)));
+        let mut curves = Layer::new_layer("Curves", 0, 0);
+        curves.adjustment = Some(Adjustment::Curves(vec!(vec!([0.0, 0.0], [0.4, 0.6], [1.0, 1.0]), vec!(), vec!(), vec!(), vec!(), vec!())));
+        let mut brightness = Layer::new_layer("Brightness", 0, 0);
+        brightness.adjustment = Some(Adjustment::BrightContrast([-20.0, 30.0, 127.0, 0.0, 0.0]));
+        
+        let mut gradient_map = Layer::new_layer("Gradient Map", 0, 0);
+        gradient_map.adjustment = Some(Adjustment::GradientMap((vec!([0.0, 0.0, 1.0, 0.0, 0.5], [1.0, 1.0, 0.0, 1.0, 0.25]), vec!([1.0, 0.0, 0.5], [1.0, 1.0, 0.5]), true, false)));
+        let mut photo_filter = Layer::new_layer("Photo Filter", 0, 0);
+        photo_filter.adjustment = Some(Adjustment::PhotoFilter(([1.0, 0.0, 1.0], 25.0, true)));
+        let mut exposure = Layer::new_layer("Exposure", 0, 0);
+        exposure.adjustment = Some(Adjustment::Exposure([0.75, -0.125, 1.5]));
+        let mut vibrance = Layer::new_layer("Vibrance", 0, 0);
+        vibrance.adjustment = Some(Adjustment::Vibrance([40.0, -15.0]));
+        let mut channel_mixer = Layer::new_layer("Channel Mixer", 0, 0);
+        channel_mixer.adjustment = Some(Adjustment::ChannelMixer(([[80.0, 20.0, 0.0, 0.0], [0.0, 100.0, -10.0, 5.0], [0.0, 0.0, 150.0, -20.0]], false)));
+        let mut color_balance = Layer::new_layer("Color Balance", 0, 0);
+        color_balance.adjustment = Some(Adjustment::ColorBalance(([[10.0, 0.0, -5.0], [0.0, 20.0, 0.0], [-30.0, 0.0, 40.0]], true)));
+        let mut selective_color = Layer::new_layer("Selective Color", 0, 0);
+        let mut colors = vec!([0.0; 4]; 9);
+        colors[0] = [-20.0, 10.0, 0.0, 5.0];
+        colors[8] = [0.0, 0.0, 0.0, 30.0];
+        selective_color.adjustment = Some(Adjustment::SelectiveColor((colors, true)));
+        
+        app.layers.children = vec!(selective_color, color_balance, channel_mixer, vibrance, exposure, photo_filter, gradient_map, brightness, curves, adjustment, group);
+        
+        let data = wpsd_save(&mut app).unwrap();
+        
+        let mut app2 = Warpainter::default();
+        wpsd_open(&mut app2, &data).unwrap();
+        
+        assert_eq!(app2.canvas_width, app.canvas_width);
+        assert_eq!(app2.canvas_height, app.canvas_height);
+        
+        fn compare(a : &Layer, b : &Layer)
+        {
+            assert_eq!(a.name, b.name);
+            assert_eq!(a.is_group() || a.adjustment.is_some(), b.is_group() || b.adjustment.is_some());
+            assert_eq!(a.blend_mode, b.blend_mode);
+            assert_eq!(a.clipped, b.clipped);
+            assert_eq!(a.visible, b.visible);
+            assert!((a.opacity - b.opacity).abs() < 0.01);
+            assert!((a.fill_opacity - b.fill_opacity).abs() < 0.01);
+            assert_eq!(format!("{:?}", a.adjustment), format!("{:?}", b.adjustment));
+            assert_eq!(a.mask.as_ref().map(|x| x.bytes().to_vec()), b.mask.as_ref().map(|x| x.bytes().to_vec()));
+            assert_eq!(a.mask_info.as_ref().map(|x| (x.x, x.y, x.default_color)), b.mask_info.as_ref().map(|x| (x.x, x.y, x.default_color)));
+            if let (Some(a_data), Some(b_data)) = (&a.data, &b.data)
+            {
+                assert_eq!(a.offset, b.offset);
+                assert_eq!(a_data.bytes(), b_data.bytes());
+            }
+            if a.is_group() && a.adjustment.is_none()
+            {
+                assert_eq!(a.closed, b.closed);
+            }
+            assert_eq!(a.children.len(), b.children.len());
+            for (a, b) in a.children.iter().zip(b.children.iter())
+            {
+                compare(a, b);
+            }
+        }
+        
+        let root = &app2.layers.children[0];
+        assert_eq!(root.children.len(), app.layers.children.len());
+        for (a, b) in app.layers.children.iter().zip(root.children.iter())
+        {
+            compare(a, b);
+        }
+    }
+    
+    #[test]
+    #[cfg(feature = "gui")]
+    pub fn test_save_psd_file_group()
+    {
+        // a user's own group that happens to be called "PSD File" is a real group
+        let mut app = Warpainter::default();
+        app.load_from_img(Image::<4>::blank(4, 4));
+        let layer = app.layers.children.pop().unwrap();
+        let mut group = Layer::new_group("PSD File");
+        group.children.push(layer);
+        app.layers.children.push(group);
+        
+        let mut app2 = Warpainter::default();
+        wpsd_open(&mut app2, &wpsd_save(&mut app).unwrap()).unwrap();
+        let root = &app2.layers.children[0];
+        assert!(root.psd_file_root);
+        assert_eq!(root.children.len(), 1);
+        assert!(!root.children[0].psd_file_root);
+        assert_eq!(root.children[0].name, "PSD File");
+        assert_eq!(root.children[0].children.len(), 1);
+        
+        // saving the re-opened file unwraps only the group wpsd_open made
+        let mut app3 = Warpainter::default();
+        wpsd_open(&mut app3, &wpsd_save(&mut app2).unwrap()).unwrap();
+        assert_eq!(app3.layers.children[0].children[0].name, "PSD File");
+        assert_eq!(app3.layers.children[0].children[0].children.len(), 1);
+        
+        // the layer count is a signed 16-bit number, and each group takes two records
+        let mut app = Warpainter::default();
+        app.layers.children = (0..i16::MAX / 2 + 1).map(|_| Layer::new_group("Group")).collect();
+        assert!(wpsd_save(&mut app).is_err());
+    }
+    
+    // a single full-canvas layer with raw (uncompressed) channel data
+    fn build_test_psd(w : u32, h : u32, depth : u16, color_mode : u16, color_mode_data : &[u8], channels : &[(i16, Vec<u8>)], merged : &[Vec<u8>]) -> Vec<u8>
+    {
+        build_test_psd_version(false, w, h, depth, color_mode, color_mode_data, channels, merged)
+    }
+    
+    #[allow(clippy::too_many_arguments)]
+    fn build_test_psd_version(psb : bool, w : u32, h : u32, depth : u16, color_mode : u16, color_mode_data : &[u8], channels : &[(i16, Vec<u8>)], merged : &[Vec<u8>]) -> Vec<u8>
+    {
+        // PSB files use 8-byte lengths for the layer sections
+        let write_len = |data : &mut Vec<u8>, n : u64| if psb { data.extend_from_slice(&n.to_be_bytes()) } else { write_u32(data, n as u32) };
+        let patch_len = |data : &mut Vec<u8>, at : usize|
+        {
+            if psb
+            {
+                let len = (data.len() - at - 8) as u64;
+                data[at..at + 8].copy_from_slice(&len.to_be_bytes());
+            }
+            else
+            {
+                patch_u32_len(data, at);
+            }
+        };
+        
+        let mut data = vec!();
+        data.extend_from_slice(b"8BPS");
+        write_u16(&mut data, if psb { 2 } else { 1 });
+        data.extend_from_slice(&[0; 6]);
+        write_u16(&mut data, merged.len() as u16);
+        write_u32(&mut data, h);
+        write_u32(&mut data, w);
+        write_u16(&mut data, depth);
+        write_u16(&mut data, color_mode);
+        write_u32(&mut data, color_mode_data.len() as u32);
+        data.extend_from_slice(color_mode_data);
+        write_u32(&mut data, 0); // image resources
+        
+        let layer_mask_start = data.len();
+        write_len(&mut data, 0);
+        if !channels.is_empty()
+        {
+            let layer_info_start = if depth == 8
+            {
+                data.len()
+            }
+            else
+            {
+                write_len(&mut data, 0); // empty layer info, layers are in Lr16 instead
+                write_u32(&mut data, 0); // global layer mask info
+                data.extend_from_slice(b"8BIMLr16");
+                data.len()
+            };
+            write_len(&mut data, 0);
+            
+            write_u16(&mut data, 1);
+            for n in [0, 0, h, w]
+            {
+                write_u32(&mut data, n);
+            }
+            write_u16(&mut data, channels.len() as u16);
+            for (id, channel) in channels
+            {
+                write_u16(&mut data, *id as u16);
+                write_len(&mut data, 2 + channel.len() as u64);
+            }
+            data.extend_from_slice(b"8BIMnorm");
+            data.extend_from_slice(&[255, 0, 0, 0]);
+            let extra_start = data.len();
+            write_u32(&mut data, 0);
+            write_u32(&mut data, 0); // mask
+            write_u32(&mut data, 0); // blending ranges
+            write_pascal_name(&mut data, "Deep");
+            patch_u32_len(&mut data, extra_start);
+            
+            for (_, channel) in channels
+            {
+                write_u16(&mut data, 0);
+                data.extend_from_slice(channel);
+            }
+            patch_len(&mut data, layer_info_start);
+        }
+        patch_len(&mut data, layer_mask_start);
+        
+        write_u16(&mut data, 0);
+        for channel in merged
+        {
+            data.extend_from_slice(channel);
+        }
+        data
+    }
+    
+    #[test]
+    #[cfg(feature = "gui")]
+    pub fn test_open_16bit()
+    {
+        let (w, h) = (3u32, 2u32);
+        let mut channels = vec!();
+        for (id, value) in [(-1i16, 0xFFFFu16), (0, 0x1234), (1, 0x8000), (2, 0x0001)]
+        {
+            channels.push((id, value.to_be_bytes().repeat((w * h) as usize)));
+        }
+        let data = build_test_psd(w, h, 16, 3, &[], &channels, &[vec!(0; 12), vec!(0; 12), vec!(0; 12)]);
+        
+        let mut app = Warpainter::default();
+        wpsd_open(&mut app, &data).unwrap();
+        
+        let layer = &app.layers.children[0].children[0];
+        assert_eq!(layer.name, "Deep");
+        let img = layer.data.as_ref().unwrap();
+        assert!(img.is_float());
+        let px = img.get_pixel_float(2, 1);
+        assert!((px[0] - 0x1234 as f32 / 65535.0).abs() < 0.00001);
+        assert!((px[1] - 0x8000 as f32 / 65535.0).abs() < 0.00001);
+        assert!((px[2] - 0x0001 as f32 / 65535.0).abs() < 0.00001);
+        assert_eq!(px[3], 1.0);
+        
+        let flat = app.layers.flatten_as_root(w as usize, h as usize, None, None);
+        assert!(flat.is_float());
+        assert!((flat.get_pixel_float(0, 0)[0] - 0x1234 as f32 / 65535.0).abs() < 0.00001);
+    }
+    
+    #[test]
+    #[cfg(feature = "gui")]
+    pub fn test_open_broken_files()
+    {
+        let mut app = Warpainter::default();
+        let mut img = Image::<4>::blank(5, 3);
+        img.set_pixel(1, 2, [10, 20, 30, 255]);
+        app.load_from_img(img);
+        let mut layer = app.layers.children.pop().unwrap();
+        let mut mask = Image::<1>::blank(2, 2);
+        mask.set_pixel(1, 1, [255]);
+        layer.mask = Some(mask);
+        layer.mask_info = Some(MaskInfo { x : 1, y : 1, w : 2, h : 2, default_color : 255, relative : false, disabled : false, invert : false });
+        let mut group = Layer::new_group("Group");
+        group.children.push(layer);
+        let mut brightness = Layer::new_layer("Brightness", 0, 0);
+        brightness.adjustment = Some(Adjustment::BrightContrast([-20.0, 30.0, 127.0, 0.0, 0.0]));
+        let mut levels = Layer::new_layer("Levels", 0, 0);
+        levels.adjustment = Some(Adjustment::Levels(vec!([0.0, 1.0, 0.0, 1.0, 1.0]; 6)));
+        app.layers.children = vec!(brightness, levels, group);
+        let data = wpsd_save(&mut app).unwrap();
+        
+        // everything up to the end of the layer info is needed
+        let read_len = |at : usize| u32::from_be_bytes(data[at..at + 4].try_into().unwrap()) as usize;
+        let mut layers_end = 26;
+        layers_end += 4 + read_len(layers_end); // color mode data
+        layers_end += 4 + read_len(layers_end); // image resources
+        layers_end += 4; // layer and mask info
+        layers_end += 4 + read_len(layers_end); // layer info
+        
+        // truncated files
+        for len in 0..data.len()
+        {
+            let ret = wpsd_open(&mut app, &data[..len]);
+            if len < layers_end
+            {
+                assert!(ret.is_err(), "truncated to {} of {} bytes but loaded anyway", len, layers_end);
+            }
+        }
+        
+        // corrupted files; these just need to not crash
+        for i in 0..data.len()
+        {
+            for n in [0x00, 0x01, 0x7F, 0x80, 0xFF]
+            {
+                let mut data = data.clone();
+                data[i] = n;
+                let _ = wpsd_open(&mut app, &data);
+            }
+        }
+        
+        let mut bad = data.clone();
+        bad[0] = b'9';
+        assert_eq!(wpsd_open(&mut app, &bad), Err(PsdError::BadSignature(0)));
+        let mut bad = data.clone();
+        bad[5] = 3;
+        assert_eq!(wpsd_open(&mut app, &bad), Err(PsdError::UnsupportedVersion(4, 3)));
+        let mut bad = data.clone();
+        bad[23] = 1;
+        assert_eq!(wpsd_open(&mut app, &bad), Err(PsdError::UnsupportedDepth(22, 1)));
+        let mut bad = data.clone();
+        bad[25] = 9;
+        assert_eq!(wpsd_open(&mut app, &bad), Err(PsdError::UnsupportedColorMode(24, 9)));
+        let mut bad = data.clone();
+        bad.truncate(40);
+        assert!(matches!(wpsd_open(&mut app, &bad), Err(PsdError::UnexpectedEof(_))));
+        assert!(wpsd_open(&mut app, &data).is_ok());
+        
+        // a single small layer, with extra blocks that can be broken in ways the byte-level corruption above won't reach
+        let layer = Layer::new_layer("Pixels", 2, 2);
+        let file = |blocks : Vec<(&'static str, Vec<u8>)>|
+        {
+            let mut record = PsdRecord::from_layer(&layer);
+            record.rect = [0, 0, 2, 2];
+            record.set_color_channels(&[0; 16], 2, 2);
+            record.blocks.extend(blocks);
+            write_psd(&[record], image::RgbaImage::new(2, 2), &DocumentMetadata::default())
+        };
+        
+        // effects with every value swapped out for one of each other type
+        let key = |k : &str, item : DescItem| (k.to_string(), item);
+        let obj = |name : &str, items : Vec<(String, DescItem)>| DescItem::Objc(Box::new((name.to_string(), items)));
+        let color = obj("RGBC", vec!(key("Rd  ", DescItem::doub(255.0)), key("Grn ", DescItem::doub(128.0)), key("Bl  ", DescItem::doub(0.0))));
+        let px = |x| DescItem::UntF("#Pxl".to_string(), x);
+        let en = |x : &str| DescItem::r#enum("x".to_string(), x.to_string());
+        let common = |mut items : Vec<(String, DescItem)>|
+        {
+            items.extend([key("enab", DescItem::bool(true)), key("Md  ", en("Mltp")), key("Opct", px(50.0)), key("Clr ", color.clone())]);
+            items
+        };
+        let stops = DescItem::VlLs(vec!(obj("Clrt", vec!(key("Clr ", color.clone()), key("Lctn", DescItem::long(2048)), key("Mdpn", DescItem::long(50))))));
+        let alphas = DescItem::VlLs(vec!(obj("TrnS", vec!(key("Opct", px(100.0)), key("Lctn", DescItem::long(0)), key("Mdpn", DescItem::long(50))))));
+        let noise = DescItem::VlLs(vec!(DescItem::long(0), DescItem::doub(100.0)));
+        let effects = vec!(
+            key("Scl ", px(100.0)),
+            key("masterFXSwitch", DescItem::bool(true)),
+            key("SoFi", obj("SoFi", common(vec!()))),
+            key("DrSh", obj("DrSh", common(vec!(key("uglg", DescItem::bool(true)), key("lagl", px(90.0)), key("Dstn", px(5.0)), key("Ckmt", px(0.0)), key("Nose", px(0.0)), key("blur", px(3.0)), key("AntA", DescItem::bool(false)), key("layerConceals", DescItem::bool(true)))))),
+            key("OrGl", obj("OrGl", common(vec!(key("Ckmt", px(0.0)), key("blur", px(5.0)), key("Nose", px(0.0)), key("Inpr", px(50.0)), key("AntA", DescItem::bool(false)), key("GlwT", en("PrBL")))))),
+            key("IrGl", obj("IrGl", common(vec!(key("glwS", en("SrcC")), key("GlwT", en("SfBL")))))),
+            key("ebbl", obj("ebbl", vec!(key("enab", DescItem::bool(true)), key("hglM", en("Scrn")), key("hglC", color.clone()), key("hglO", px(75.0)), key("sdwM", en("Mltp")), key("sdwC", color.clone()), key("sdwO", px(75.0)), key("uglg", DescItem::bool(true)), key("lagl", px(120.0)), key("Lald", px(30.0)), key("srgR", px(100.0)), key("blur", px(5.0)), key("Sftn", px(0.0)), key("bvlT", en("PrBL")), key("bvlS", en("Embs")), key("bvlD", en("Out "))))),
+            key("ChFX", obj("ChFX", common(vec!(key("lagl", px(45.0)), key("Dstn", px(10.0)), key("blur", px(7.0)), key("Invr", DescItem::bool(true)), key("AntA", DescItem::bool(false)))))),
+            key("GrFl", obj("GrFl", common(vec!(key("Angl", px(90.0)), key("Type", en("Rdl ")), key("Ofst", obj("Pnt ", vec!(key("Hrzn", px(0.0)), key("Vrtc", px(0.0))))), key("Rvrs", DescItem::bool(false)), key("Dthr", DescItem::bool(false)), key("Algn", DescItem::bool(true)), key("Scl ", px(100.0)),
+                key("Grad", obj("Grdn", vec!(key("GrdF", en("CstS")), key("Clrs", stops.clone()), key("Trns", alphas.clone())))))))),
+            key("GrFl", obj("GrFl", common(vec!(key("Grad", obj("Grdn", vec!(key("GrdF", en("ClNs")), key("RndS", DescItem::long(5)), key("Smth", DescItem::long(2048)), key("ClrS", en("HSBl")), key("ShTr", DescItem::bool(true)), key("Mnm ", noise.clone()), key("Mxm ", noise.clone())))))))),
+            key("patternFill", obj("patternFill", common(vec!(key("Scl ", px(100.0)), key("Algn", DescItem::bool(true)), key("phase", obj("Pnt ", vec!(key("Hrzn", DescItem::doub(0.0)), key("Vrtc", DescItem::doub(0.0))))), key("Ptrn", obj("Ptrn", vec!(key("Nm  ", DescItem::TEXT("Dots".to_string())), key("Idnt", DescItem::TEXT("1234".to_string()))))))))),
+            key("FrFX", obj("FrFX", common(vec!(key("Sz  ", px(3.0)), key("Styl", en("InsF")))))),
+        );
+        let others = [DescItem::long(7), DescItem::doub(0.5), px(3.0), DescItem::bool(true), DescItem::TEXT("x".to_string()), en("x"), obj("null", vec!()), DescItem::VlLs(vec!())];
+        // every version of the item with exactly one value in it replaced
+        fn swapped(item : &DescItem, others : &[DescItem], out : &mut Vec<DescItem>)
+        {
+            out.extend(others.iter().cloned());
+            let children = match item
+            {
+                DescItem::Objc(desc) => desc.1.iter().map(|x| x.1.clone()).collect(),
+                DescItem::VlLs(list) => list.clone(),
+                _ => vec!(),
+            };
+            for (i, child) in children.iter().enumerate()
+            {
+                let mut variants = vec!();
+                swapped(child, others, &mut variants);
+                for variant in variants
+                {
+                    let mut item = item.clone();
+                    match &mut item
+                    {
+                        DescItem::Objc(desc) => desc.1[i].1 = variant,
+                        DescItem::VlLs(list) => list[i] = variant,
+                        _ => { }
+                    }
+                    out.push(item);
+                }
+            }
+        }
+        let lfx2 = |desc : &DescItem|
+        {
+            let mut data = vec!();
+            write_u32(&mut data, 0);
+            write_u32(&mut data, 16);
+            if let DescItem::Objc(desc) = desc
+            {
+                write_descriptor(&mut data, desc).unwrap();
+            }
+            data
+        };
+        let desc = obj("null", effects);
+        assert!(wpsd_open(&mut app, &file(vec!(("lfx2", lfx2(&desc))))).is_ok());
+        assert_eq!(app.layers.children[0].children[0].effects.len(), 11);
+        let mut variants = vec!();
+        swapped(&desc, &others, &mut variants);
+        for variant in variants.iter().filter(|x| matches!(x, DescItem::Objc(_)))
+        {
+            assert!(wpsd_open(&mut app, &file(vec!(("lfx2", lfx2(variant))))).is_ok());
+        }
+        let sorted = |hm : &HashMap<String, Vec<FxData>>| format!("{:?}", hm.iter().collect::<std::collections::BTreeMap<_, _>>());
+        
+        // glows, bevels and satins start out with the defaults, which values of the wrong type don't replace
+        let glow_keys = ["enab", "Md  ", "Opct", "Clr ", "Ckmt", "blur", "Nose", "Inpr", "AntA", "GlwT", "glwS"].as_slice();
+        let bevel_keys = ["enab", "hglM", "hglC", "hglO", "sdwM", "sdwC", "sdwO", "uglg", "lagl", "Lald", "srgR", "blur", "Sftn", "bvlT", "bvlS", "bvlD"].as_slice();
+        let satin_keys = ["enab", "Md  ", "Opct", "Clr ", "lagl", "Dstn", "blur", "Invr", "AntA"].as_slice();
+        for (name, kind, keys) in [("OrGl", "outerglow", glow_keys), ("IrGl", "innerglow", glow_keys), ("ebbl", "bevel", bevel_keys), ("ChFX", "satin", satin_keys)]
+        {
+            let items = keys.iter().map(|k| key(k, DescItem::TEXT("x".to_string()))).collect();
+            let desc = obj("null", vec!(key(name, obj(name, items))));
+            wpsd_open(&mut app, &file(vec!(("lfx2", lfx2(&desc))))).unwrap();
+            let effects = &app.layers.children[0].children[0].effects;
+            assert_eq!(sorted(&effects[kind]), sorted(&fx_default(kind).unwrap()));
+        }
+        
+        // adjustment blocks that are cut short, or that hold another kind's data
+        let kinds = ["post", "thrs", "brit", "hue2", "levl", "curv", "grdm", "phfl", "mixr", "blnc", "selc", "expA", "vibA", "blwh", "nvrt", "CgEd"];
+        let adjustments = [
+            Adjustment::Posterize(4.0),
+            Adjustment::BrightContrast([-20.0, 30.0, 127.0, 0.0, 0.0]),
+            Adjustment::HueSatLum([10.0, -20.0, 5.0]),
+            Adjustment::Levels(vec!([0.0, 1.0, 0.0, 1.0, 1.0]; 6)),
+            Adjustment::Curves(vec!(vec!([0.0, 0.0], [0.4, 0.6], [1.0, 1.0]), vec!(), vec!(), vec!(), vec!(), vec!())),
+            Adjustment::GradientMap((vec!([0.0, 0.0, 1.0, 0.0, 0.5], [1.0, 1.0, 0.0, 1.0, 0.5]), vec!([1.0, 0.0, 0.5], [1.0, 1.0, 0.5]), true, false)),
+            Adjustment::PhotoFilter(([1.0, 0.0, 1.0], 25.0, true)),
+            Adjustment::ChannelMixer(([[80.0, 20.0, 0.0, 0.0], [0.0, 100.0, -10.0, 5.0], [0.0, 0.0, 150.0, -20.0]], false)),
+            Adjustment::ColorBalance(([[10.0, 0.0, -5.0], [0.0, 20.0, 0.0], [-30.0, 0.0, 40.0]], true)),
+            Adjustment::SelectiveColor((vec!([0.0; 4]; 9), true)),
+            Adjustment::Exposure([0.75, -0.125, 1.5]),
+            Adjustment::Vibrance([40.0, -15.0]),
+        ];
+        let blocks : Vec<_> = adjustments.iter().flat_map(|x| adjustment_blocks(x).unwrap()).collect();
+        for (_, data) in &blocks
+        {
+            for kind in kinds
+            {
+                for len in 0..data.len()
+                {
+                    let _ = wpsd_open(&mut app, &file(vec!((kind, data[..len].to_vec()))));
+                }
+                let _ = wpsd_open(&mut app, &file(vec!((kind, data.clone()))));
+            }
+        }
+        // the brightness/contrast descriptor after some other adjustment
+        let cged = blocks.iter().find(|x| x.0 == "CgEd").unwrap().clone();
+        let hue = blocks.iter().find(|x| x.0 == "hue2").unwrap().clone();
+        assert!(wpsd_open(&mut app, &file(vec!(hue, cged))).is_ok());
+        assert!(matches!(app.layers.children[0].children[0].adjustment, Some(Adjustment::BrightContrast(_))));
+    }
+    
+    #[test]
+    #[cfg(feature = "gui")]
+    pub fn test_open_color_modes()
+    {
+        // grayscale, with a layer
+        let data = build_test_psd(2, 1, 8, 1, &[], &[(-1, vec!(255, 128)), (0, vec!(10, 200))], &[vec!(0, 0)]);
+        let mut app = Warpainter::default();
+        wpsd_open(&mut app, &data).unwrap();
+        let img = app.layers.children[0].children[0].data.as_ref().unwrap();
+        assert_eq!(img.get_pixel(0, 0), [10, 10, 10, 255]);
+        assert_eq!(img.get_pixel(1, 0), [200, 200, 200, 128]);
+        
+        // indexed, which only has the merged image
+        let mut palette = vec![0; 768];
+        palette[1] = 255;
+        palette[256 + 1] = 100;
+        palette[512 + 1] = 50;
+        let data = build_test_psd(2, 1, 8, 2, &palette, &[], &[vec!(1, 0)]);
+        let mut app = Warpainter::default();
+        wpsd_open(&mut app, &data).unwrap();
+        let layer = &app.layers.children[0].children[0];
+        assert_eq!(layer.name, "Background");
+        let img = layer.data.as_ref().unwrap();
+        assert_eq!(img.get_pixel(0, 0), [255, 100, 50, 255]);
+        assert_eq!(img.get_pixel(1, 0), [0, 0, 0, 255]);
+        
+        // CMYK, stored inverted
+        let channels = [(-1, vec!(255)), (0, vec!(255)), (1, vec!(0)), (2, vec!(255)), (3, vec!(128))];
+        let data = build_test_psd(1, 1, 8, 4, &[], &channels, &[vec!(0), vec!(0), vec!(0), vec!(0)]);
+        let mut app = Warpainter::default();
+        wpsd_open(&mut app, &data).unwrap();
+        let img = app.layers.children[0].children[0].data.as_ref().unwrap();
+        assert_eq!(img.get_pixel(0, 0), [128, 0, 128, 255]);
+    }
+    
+    #[test]
+    #[cfg(feature = "gui")]
+    pub fn test_image_resources()
+    {
+        let mut app = Warpainter::default();
+        app.metadata.resolution = Some(Resolution { h_res : 300.0, v_res : 72.5, h_unit : 1, v_unit : 1, width_unit : 2, height_unit : 2 });
+        app.metadata.guides = vec!(Guide { position : 16.5, horizontal : false }, Guide { position : 8.0, horizontal : true });
+        app.metadata.icc_profile = Some(vec!(1, 2, 3));
+        let data = wpsd_save(&mut app).unwrap();
+        
+        let mut app2 = Warpainter::default();
+        wpsd_open(&mut app2, &data).unwrap();
+        assert_eq!(app2.metadata, app.metadata);
+        
+        // survives a WPP save
+        let wpp = wpp_save(&mut app2);
+        let mut app3 = Warpainter::default();
+        wpp_open(&mut app3, &wpp).unwrap();
+        assert_eq!(app3.metadata, app.metadata);
+        
+        // slices and layer selection, which we don't export
+        let mut section = vec!();
+        let bounds = |l, t, r, b| DescItem::Objc(Box::new(("Rct1".to_string(), vec!(
+            ("Top ".to_string(), DescItem::long(t)),
+            ("Left".to_string(), DescItem::long(l)),
+            ("Btom".to_string(), DescItem::long(b)),
+            ("Rght".to_string(), DescItem::long(r)),
+        ))));
+        let mut slices = vec!();
+        write_u32(&mut slices, 7);
+        write_u32(&mut slices, 16);
+        write_descriptor(&mut slices, &("null".to_string(), vec!(
+            ("bounds".to_string(), bounds(0, 0, 4, 4)),
+            ("slices".to_string(), DescItem::VlLs(vec!(
+                DescItem::Objc(Box::new(("slice".to_string(), vec!(
+                    ("sliceID".to_string(), DescItem::long(3)),
+                    ("Nm  ".to_string(), DescItem::TEXT("Button".to_string())),
+                    ("bounds".to_string(), bounds(1, 2, 3, 4)),
+                )))),
+            ))),
+        ))).unwrap();
+        let mut selection = vec!();
+        write_u16(&mut selection, 2);
+        write_u32(&mut selection, 5);
+        write_u32(&mut selection, 9);
+        for (id, name, resource) in [(1050u16, "", slices), (1069, "ab", selection)]
+        {
+            section.extend_from_slice(b"8BIM");
+            write_u16(&mut section, id);
+            write_u8(&mut section, name.len() as u8);
+            section.extend_from_slice(name.as_bytes());
+            if name.len() % 2 == 0
+            {
+                section.push(0);
+            }
+            write_u32(&mut section, resource.len() as u32);
+            section.extend_from_slice(&resource);
+            if resource.len() % 2 != 0
+            {
+                section.push(0);
+            }
+        }
+        
+        let mut data = build_test_psd(1, 1, 8, 3, &[], &[], &[vec!(0), vec!(0), vec!(0)]);
+        let mut len = vec!();
+        write_u32(&mut len, section.len() as u32);
+        data.splice(30..34, len.into_iter().chain(section));
+        
+        let resources = parse_image_resources(&data).unwrap();
+        assert_eq!(resources.metadata.slices, vec!(Slice { id : 3, name : "Button".to_string(), left : 1, top : 2, right : 3, bottom : 4 }));
+        assert_eq!(resources.selected_layer_ids, vec!(5, 9));
+        wpsd_open(&mut app, &data).unwrap();
+        assert_eq!(app.metadata.slices.len(), 1);
+    }
+    
+    #[test]
+    #[cfg(feature = "gui")]
+    pub fn test_open_psb()
+    {
+        for depth in [8, 16]
+        {
+            let size = depth as usize / 8 * 6;
+            let channels = [(-1i16, vec!(255; size)), (0, vec!(255; size)), (1, vec!(0; size)), (2, vec!(128; size))];
+            let data = build_test_psd_version(true, 3, 2, depth, 3, &[], &channels, &[vec!(0; size), vec!(0; size), vec!(0; size)]);
+            let mut app = Warpainter::default();
+            wpsd_open(&mut app, &data).unwrap();
+            let layer = &app.layers.children[0].children[0];
+            assert_eq!(layer.name, "Deep");
+            let img = layer.data.as_ref().unwrap();
+            assert_eq!((img.width, img.height), (3, 2));
+            assert_eq!(img.get_pixel(2, 1), [255, 0, 128, 255]);
+        }
+        
+        // RLE-compressed merged image, which has 4-byte row lengths
+        let mut data = build_test_psd_version(true, 4, 2, 8, 3, &[], &[], &[]);
+        data[13] = 3;
+        data.truncate(data.len() - 2);
+        write_u16(&mut data, 1);
+        let mut rows = vec!();
+        for c in [10u8, 20, 30]
+        {
+            for y in 0..2
+            {
+                let mut row = vec!();
+                packbits_encode(&[c, c + y, c, c], &mut row);
+                rows.push(row);
+            }
+        }
+        for row in &rows
+        {
+            write_u32(&mut data, row.len() as u32);
+        }
+        for row in &rows
+        {
+            data.extend_from_slice(row);
+        }
+        let mut app = Warpainter::default();
+        wpsd_open(&mut app, &data).unwrap();
+        let img = app.layers.children[0].children[0].data.as_ref().unwrap();
+        assert_eq!(img.get_pixel(0, 0), [10, 20, 30, 255]);
+        assert_eq!(img.get_pixel(1, 1), [11, 21, 31, 255]);
+    }
+    
+    #[test]
+    #[cfg(feature = "gui")]
+    pub fn test_patterns()
+    {
+        let mut pattern = vec!();
+        write_u32(&mut pattern, 1);
+        write_u32(&mut pattern, 3); // RGB
+        write_u16(&mut pattern, 2);
+        write_u16(&mut pattern, 2);
+        write_unicode_string(&mut pattern, "Dots");
+        write_u8(&mut pattern, 3);
+        pattern.extend_from_slice(b"abc");
+        write_u32(&mut pattern, 3);
+        write_u32(&mut pattern, 0);
+        for n in [0, 0, 2, 2]
+        {
+            write_u32(&mut pattern, n);
+        }
+        write_u32(&mut pattern, 24);
+        for c in 0..26
+        {
+            if c >= 4
+            {
+                write_u32(&mut pattern, 0);
+                continue;
+            }
+            write_u32(&mut pattern, 1);
+            // the red channel is RLE-compressed
+            write_u32(&mut pattern, if c == 0 { 23 + 4 + 6 } else { 23 + 4 });
+            write_u32(&mut pattern, 8);
+            for n in [0, 0, 2, 2]
+            {
+                write_u32(&mut pattern, n);
+            }
+            write_u16(&mut pattern, 8);
+            write_u8(&mut pattern, (c == 0) as u8);
+            match c
+            {
+                0 => pattern.extend_from_slice(&[0, 3, 0, 3, 1, 10, 20, 1, 30, 40]),
+                3 => pattern.extend_from_slice(&[255, 0, 255, 128]),
+                _ => pattern.extend_from_slice(&[c * 50; 4]),
+            }
+        }
+        
+        let mut block = b"8BIMPatt".to_vec();
+        write_u32(&mut block, 0);
+        write_u32(&mut block, pattern.len() as u32);
+        block.extend_from_slice(&pattern);
+        while block.len() % 4 != 0
+        {
+            block.push(0);
+        }
+        patch_u32_len(&mut block, 8);
+        
+        let mut data = build_test_psd(2, 1, 8, 1, &[], &[(-1, vec!(255, 128)), (0, vec!(10, 200))], &[vec!(0, 0)]);
+        let len = u32::from_be_bytes(data[34..38].try_into().unwrap()) as usize;
+        let mut global = vec!();
+        write_u32(&mut global, 0); // global layer mask info
+        global.extend_from_slice(&block);
+        data.splice(38 + len..38 + len, global.iter().copied());
+        let new_len = (len + global.len()) as u32;
+        data[34..38].copy_from_slice(&new_len.to_be_bytes());
+        
+        let patterns = parse_patterns(&data).unwrap();
+        assert_eq!(patterns.len(), 1);
+        assert_eq!(patterns[0].id, "abc");
+        assert_eq!(patterns[0].name, "Dots");
+        assert_eq!((patterns[0].w, patterns[0].h), (2, 2));
+        assert_eq!(patterns[0].image_data_rgba[0..4], [10, 50, 100, 255]);
+        assert_eq!(patterns[0].image_data_rgba[12..16], [40, 50, 100, 128]);
+        
+        let mut app = Warpainter::default();
+        wpsd_open(&mut app, &data).unwrap();
+    }
+    
+    fn test_png() -> Vec<u8>
+    {
+        let mut img = image::RgbaImage::new(2, 2);
+        img.put_pixel(0, 0, image::Rgba([255, 0, 0, 255]));
+        img.put_pixel(1, 0, image::Rgba([0, 255, 0, 255]));
+        img.put_pixel(0, 1, image::Rgba([0, 0, 255, 255]));
+        img.put_pixel(1, 1, image::Rgba([255, 255, 255, 128]));
+        let mut png = vec!();
+        img.write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png).unwrap();
+        png
+    }
+    
+    // adds a tagged block to the end of the only layer record, after its name
+    fn splice_layer_block(data : &mut Vec<u8>, block : &[u8])
+    {
+        let extra_at = data.windows(8).position(|x| x == b"8BIMnorm").unwrap() + 12;
+        let block_at = extra_at + 4 + 4 + 4 + 8;
+        data.splice(block_at..block_at, block.iter().copied());
+        for at in [34, 38, extra_at]
+        {
+            let len = u32::from_be_bytes(data[at..at + 4].try_into().unwrap()) + block.len() as u32;
+            data[at..at + 4].copy_from_slice(&len.to_be_bytes());
+        }
+    }
+    #[test]
+    #[cfg(feature = "gui")]
+    pub fn test_smart_objects()
+    {
+        let png = test_png();
+        
+        let mut placed = b"plcL".to_vec();
+        write_u32(&mut placed, 3);
+        write_u8(&mut placed, 3);
+        placed.extend_from_slice(b"xyz");
+        for n in [1, 1, 16, 2]
+        {
+            write_u32(&mut placed, n);
+        }
+        for n in [1.0, 0.0, 5.0, 0.0, 5.0, 4.0, 1.0, 4.0]
+        {
+            write_f64(&mut placed, n);
+        }
+        let mut block = b"8BIMPlLd".to_vec();
+        write_u32(&mut block, placed.len() as u32);
+        block.extend_from_slice(&placed);
+        
+        let mut file = b"liFD".to_vec();
+        write_u32(&mut file, 7);
+        write_u8(&mut file, 3);
+        file.extend_from_slice(b"xyz");
+        write_unicode_string(&mut file, "dots.png");
+        file.extend_from_slice(b"png ");
+        write_u32(&mut file, 0);
+        file.extend_from_slice(&(png.len() as u64).to_be_bytes());
+        write_u8(&mut file, 0);
+        file.extend_from_slice(&png);
+        let mut linked = b"8BIMlnk2".to_vec();
+        write_u32(&mut linked, 0);
+        linked.extend_from_slice(&(file.len() as u64).to_be_bytes());
+        linked.extend_from_slice(&file);
+        while linked.len() % 4 != 0
+        {
+            linked.push(0);
+        }
+        patch_u32_len(&mut linked, 8);
+        
+        let mut data = build_test_psd(6, 4, 8, 1, &[], &[(-1, vec!(255; 24)), (0, vec!(10; 24))], &[vec!(0; 24)]);
+        splice_layer_block(&mut data, &block);
+        // and the embedded file to the global blocks
+        let layer_info_len = u32::from_be_bytes(data[38..42].try_into().unwrap()) as usize;
+        let mut global = vec!();
+        write_u32(&mut global, 0); // global layer mask info
+        global.extend_from_slice(&linked);
+        data.splice(42 + layer_info_len..42 + layer_info_len, global.iter().copied());
+        let len = u32::from_be_bytes(data[34..38].try_into().unwrap()) + global.len() as u32;
+        data[34..38].copy_from_slice(&len.to_be_bytes());
+        
+        let files = parse_linked_files(&data).unwrap();
+        assert_eq!(files["xyz"].name, "dots.png");
+        assert_eq!(files["xyz"].data, png);
+        
+        let mut app = Warpainter::default();
+        wpsd_open(&mut app, &data).unwrap();
+        let layer = &mut app.layers.children[0].children[0];
+        let smart = layer.smart_object.as_ref().unwrap();
+        assert_eq!(smart.data, png);
+        assert_eq!(smart.transform, [1.0, 0.0, 5.0, 0.0, 5.0, 4.0, 1.0, 4.0]);
+        
+        // the cached pixels get replaced with a rendering of the original
+        assert!(layer.rasterize_smart_object());
+        assert_eq!(layer.offset, [1.0, 0.0]);
+        let img = layer.data.as_ref().unwrap();
+        assert_eq!((img.width, img.height), (4, 4));
+        assert_eq!(img.get_pixel(0, 0), [255, 0, 0, 255]);
+        assert_eq!(img.get_pixel(3, 3)[3], 128);
+    }
+    #[test]
+    #[cfg(feature = "gui")]
+    pub fn test_text_layers()
+    {
+        use crate::wpsd_text::*;
+        let text = |x : &str| EngineValue::Text(x.to_string());
+        let dict = |items : Vec<(&str, EngineValue)>| EngineValue::Dict(items.into_iter().map(|(k, v)| (k.to_string(), v)).collect());
+        let style = dict(vec!(("StyleSheet", dict(vec!(("StyleSheetData", dict(vec!(
+            ("Font", EngineValue::Int(0)),
+            ("FontSize", EngineValue::Float(20.0)),
+            ("FillColor", dict(vec!(("Type", EngineValue::Int(1)), ("Values", EngineValue::Array(vec!(EngineValue::Float(1.0), EngineValue::Float(1.0), EngineValue::Float(0.0), EngineValue::Float(0.0))))))),
+        ))))))));
+        let engine = dict(vec!(
+            ("EngineDict", dict(vec!(
+                ("Editor", dict(vec!(("Text", text("Hi\r"))))),
+                ("StyleRun", dict(vec!(("RunArray", EngineValue::Array(vec!(style))), ("RunLengthArray", EngineValue::Array(vec!(EngineValue::Int(3))))))),
+                ("ParagraphRun", dict(vec!(("RunArray", EngineValue::Array(vec!(dict(vec!())))), ("RunLengthArray", EngineValue::Array(vec!(EngineValue::Int(3))))))),
+            ))),
+            ("ResourceDict", dict(vec!(("FontSet", EngineValue::Array(vec!(dict(vec!(("Name", text("ArialMT")))))))))),
+        ));
+        
+        let mut tysh = vec!();
+        write_u16(&mut tysh, 1);
+        for n in [1.0, 0.0, 0.0, 1.0, 1.0, 3.0]
+        {
+            write_f64(&mut tysh, n);
+        }
+        write_u16(&mut tysh, 50);
+        write_u32(&mut tysh, 16);
+        write_descriptor(&mut tysh, &("TxLr".to_string(), vec!(
+            ("Txt ".to_string(), DescItem::TEXT("Hi\r".to_string())),
+            ("EngineData".to_string(), DescItem::tdta(write_engine_data(&engine))),
+        ))).unwrap();
+        write_u16(&mut tysh, 1);
+        write_u32(&mut tysh, 16);
+        write_descriptor(&mut tysh, &("warp".to_string(), vec!(("warpStyle".to_string(), DescItem::r#enum("warpStyle".to_string(), "warpNone".to_string()))))).unwrap();
+        for n in [0, 0, 4, 6]
+        {
+            write_i32(&mut tysh, n);
+        }
+        tysh.resize(tysh.len().div_ceil(4) * 4, 0);
+        let mut block = b"8BIMTySh".to_vec();
+        write_u32(&mut block, tysh.len() as u32);
+        block.extend_from_slice(&tysh);
+        
+        let mut data = build_test_psd(6, 4, 8, 1, &[], &[(-1, vec!(255; 24)), (0, vec!(10; 24))], &[vec!(0; 24)]);
+        splice_layer_block(&mut data, &block);
+        
+        let mut app = Warpainter::default();
+        wpsd_open(&mut app, &data).unwrap();
+        let layer = &mut app.layers.children[0].children[0];
+        let info = layer.text.as_ref().unwrap();
+        assert_eq!(info.text, "Hi");
+        assert_eq!(info.runs.iter().map(|x| (x.length, x.font.as_str(), x.size, x.color)).collect::<Vec<_>>(), vec!((2, "ArialMT", 20.0, [1.0, 0.0, 0.0, 1.0])));
+        assert_eq!(info.transform, [1.0, 0.0, 0.0, 1.0, 1.0, 3.0]);
+        assert_eq!(info.psd_block, tysh);
+        // the original rendering stays until an edit
+        assert!(!info.edited);
+        let img = layer.data.as_ref().unwrap();
+        assert_eq!((img.width, img.height, img.get_pixel(0, 0)), (6, 4, [10, 10, 10, 255]));
+        
+        // untouched text goes back out as it came in
+        let saved = wpsd_save(&mut app).unwrap();
+        let mut app2 = Warpainter::default();
+        wpsd_open(&mut app2, &saved).unwrap();
+        assert_eq!(app2.layers.children[0].children[0].text.as_ref().unwrap().psd_block, tysh);
+        
+        let layer = &mut app.layers.children[0].children[0];
+        let unedited = layer.get_info();
+        assert!(layer.edit_text("Hi\nyo"));
+        let info = layer.text.as_ref().unwrap();
+        assert_eq!(info.runs[0].length, 5);
+        let img = layer.data.as_ref().unwrap();
+        assert!(img.height > 20);
+        let mut reds = 0;
+        for y in 0..img.height
+        {
+            for x in 0..img.width
+            {
+                let px = img.get_pixel(x as isize, y as isize);
+                if px[3] == 255
+                {
+                    assert_eq!(&px[..3], &[255, 0, 0]);
+                    reds += 1;
+                }
+            }
+        }
+        assert!(reds > 20);
+        // rendering moves the layer to fit, but the baseline stays put
+        let info = layer.text.as_ref().unwrap();
+        assert_eq!([info.transform[4] + layer.offset[0], info.transform[5] + layer.offset[1]], [1.0, 3.0]);
+        
+        let saved = wpsd_save(&mut app).unwrap();
+        let mut app2 = Warpainter::default();
+        wpsd_open(&mut app2, &saved).unwrap();
+        let layer = &app2.layers.children[0].children[0];
+        let info = layer.text.as_ref().unwrap();
+        assert_eq!(info.text, "Hi\nyo");
+        assert_eq!(info.runs[0].length, 5);
+        assert_eq!([info.transform[4] + layer.offset[0], info.transform[5] + layer.offset[1]], [1.0, 3.0]);
+        let psd = read_type_tool(&mut std::io::Cursor::new(&info.psd_block[..]), info.psd_block.len() as u64).unwrap();
+        let Some(DescItem::tdta(engine)) = desc_get(&psd.desc, "EngineData") else { panic!() };
+        let engine = parse_engine_data(engine).unwrap();
+        assert_eq!(engine.path(&["EngineDict", "Editor", "Text"]), Some(&text("Hi\ryo\r")));
+        assert_eq!(engine.path(&["EngineDict", "StyleRun", "RunLengthArray"]).unwrap().array(), &[EngineValue::Int(6)]);
+        
+        // undoing the edit brings back the original rendering instead of re-rendering the old text
+        let layer = &mut app.layers.children[0].children[0];
+        let edited = layer.get_info();
+        assert!(edited.unedited_text.is_none());
+        layer.set_info(&unedited);
+        let info = layer.text.as_ref().unwrap();
+        assert!(!info.edited);
+        assert_eq!((info.text.as_str(), info.transform, &info.psd_block), ("Hi", [1.0, 0.0, 0.0, 1.0, 1.0, 3.0], &tysh));
+        let img = layer.data.as_ref().unwrap();
+        assert_eq!((img.width, img.height, img.get_pixel(0, 0)), (6, 4, [10, 10, 10, 255]));
+        assert_eq!(layer.offset, unedited.offset);
+        // and redoing it renders again
+        layer.set_info(&edited);
+        assert!(layer.text.as_ref().unwrap().edited);
+        assert!(layer.data.as_ref().unwrap().height > 20);
+        
+        // items that can't be written are an error, not a panic
+        let inner = DescItem::Objc(Box::new(("Innr".to_string(), vec!(("Bad ".to_string(), DescItem::Xxx)))));
+        let error = write_descriptor(&mut vec!(), &("null".to_string(), vec!(("Objc".to_string(), inner)))).unwrap_err();
+        assert_eq!(error, "Objc: Bad: can't write empty descriptor item");
+    }
+    #[test]
+    #[cfg(feature = "gui")]
+    pub fn test_vector_masks()
+    {
+        let record = |selector : u16, body : &[u8]|
+        {
+            let mut out = selector.to_be_bytes().to_vec();
+            out.extend_from_slice(body);
+            out.resize(26, 0);
+            out
+        };
+        let mut paths = vec!();
+        write_u32(&mut paths, 3);
+        write_u32(&mut paths, 0);
+        paths.extend(record(6, &[]));
+        paths.extend(record(8, &[0, 0]));
+        paths.extend(record(0, &[0, 4, 0, 1]));
+        // a rectangle from x = 1 to x = 4.5, over the full height of a 6x4 canvas
+        for (x, y) in [(1.0, 0.0), (4.5, 0.0), (4.5, 4.0), (1.0, 4.0)]
+        {
+            let mut knot = vec!();
+            for _ in 0..3
+            {
+                knot.extend_from_slice(&((y / 4.0 * 16777216.0_f64).round() as i32).to_be_bytes());
+                knot.extend_from_slice(&((x / 6.0 * 16777216.0_f64).round() as i32).to_be_bytes());
+            }
+            paths.extend(record(1, &knot));
+        }
+        let mut block = b"8BIMvmsk".to_vec();
+        write_u32(&mut block, paths.len() as u32);
+        block.extend_from_slice(&paths);
+        
+        let mut data = build_test_psd(6, 4, 8, 1, &[], &[(-1, vec!(255; 24)), (0, vec!(10; 24))], &[vec!(0; 24)]);
+        splice_layer_block(&mut data, &block);
+        
+        let mut app = Warpainter::default();
+        wpsd_open(&mut app, &data).unwrap();
+        let layer = &app.layers.children[0].children[0];
+        let (mask, info) = layer.vector_mask.as_ref().unwrap();
+        assert_eq!((info.x, info.y, mask.width, mask.height, info.default_color), (1, 0, 4, 4, 0));
+        
+        // antialiased on the right edge, and the raster-less layer gets clipped by it alone
+        let merged = app.flatten();
+        let alphas : Vec<_> = (0..6).map(|x| merged.get_pixel(x, 2)[3]).collect();
+        assert_eq!(alphas, vec!(0, 255, 255, 255, 128, 0));
+    }
+    
+    #[test]
+    #[cfg(feature = "gui")]
+    pub fn test_merged_composite()
+    {
+        // our own files carry a composite that matches what we render
+        let mut app = Warpainter::default();
+        let mut img = Image::<4>::blank(4, 2);
+        img.set_pixel(1, 0, [200, 100, 50, 128]);
+        img.set_pixel(2, 1, [0, 0, 255, 255]);
+        app.load_from_img(img);
+        app.layers.children[0].blend_mode = "Multiply".to_string();
+        let data = wpsd_save(&mut app).unwrap();
+        
+        let merged = parse_merged_image(&data).unwrap();
+        let px = &merged.image_data_rgba[4..8];
+        assert_eq!(px[3], 128);
+        assert!(px[0].abs_diff(200) <= 2 && px[1].abs_diff(100) <= 2 && px[2].abs_diff(50) <= 2, "{:?}", px);
+        assert_eq!(merged.image_data_rgba[..4], [0, 0, 0, 0]);
+        
+        let result = wpsd_compare_composite(&data).unwrap();
+        assert!(result.max_error <= 2.0 / 255.0, "{}", result.max_error);
+        assert_eq!(result.bad_pixels, 0);
+        
+        // a composite we don't match gets reported, per pixel
+        let data = build_test_psd(3, 2, 8, 1, &[], &[(-1, vec!(255; 6)), (0, vec!(10, 10, 10, 10, 10, 10))], &[vec!(10, 10, 10, 10, 10, 50)]);
+        let result = wpsd_compare_composite(&data).unwrap();
+        assert_eq!(result.bad_pixels, 1);
+        assert!((result.max_error - 40.0 / 255.0).abs() < 0.001, "{}", result.max_error);
+        assert!(result.mean_error > 0.0 && result.mean_error < result.max_error);
+        assert_eq!(result.diff.get_pixel(0, 0), [0, 0, 0, 255]);
+        assert!(result.diff.get_pixel(2, 1)[0] > 128);
+    }
+    
+    // regression harness: every .psd/.psb in the fixture directory is imported, flattened, and
+    // checked against the composite photoshop saved with it. diff images go to target/psd_diffs
+    #[test]
+    pub fn test_psd_fixtures()
+    {
+        let dir = std::env::var("WARPAINTER_PSD_FIXTURES").unwrap_or(concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/psd").to_string());
+        let limit = |var : &str, default : f32| std::env::var(var).ok().and_then(|x| x.parse().ok()).unwrap_or(default);
+        let tolerance = limit("WARPAINTER_PSD_TOLERANCE", 2.0 / 255.0);
+        // a mistake in one layer or effect barely moves the mean over a whole canvas, so check the worst pixels too
+        let max_tolerance = limit("WARPAINTER_PSD_MAX_ERROR", 8.0 / 255.0);
+        let bad_fraction = limit("WARPAINTER_PSD_BAD_PIXELS", 0.001);
+        let entries = std::fs::read_dir(&dir).unwrap_or_else(|e| panic!("can't read PSD fixtures from {}: {}", dir, e));
+        let mut paths : Vec<_> = entries.flatten().map(|x| x.path())
+            .filter(|x| x.extension().is_some_and(|x| x.eq_ignore_ascii_case("psd") || x.eq_ignore_ascii_case("psb")))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty(), "no PSD fixtures in {}", dir);
+        
+        let diff_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("target/psd_diffs");
+        let mut failures = vec!();
+        for path in paths
+        {
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            let bytes = std::fs::read(&path).unwrap();
+            match wpsd_compare_composite(&bytes)
+            {
+                Ok(result) =>
+                {
+                    println!("{}: mean error {:.5}, max error {:.5}, {} bad pixels", name, result.mean_error, result.max_error, result.bad_pixels);
+                    if result.bad_pixels > 0
+                    {
+                        std::fs::create_dir_all(&diff_dir).unwrap();
+                        result.diff.to_imagebuffer().save(diff_dir.join(format!("{}.png", name))).unwrap();
+                    }
+                    if result.mean_error > tolerance
+                    {
+                        failures.push(format!("{}: mean error {} over {}", name, result.mean_error, tolerance));
+                    }
+                    if result.max_error > max_tolerance
+                    {
+                        failures.push(format!("{}: max error {} over {}", name, result.max_error, max_tolerance));
+                    }
+                    let fraction = result.bad_pixels as f32 / (result.diff.width * result.diff.height).max(1) as f32;
+                    if fraction > bad_fraction
+                    {
+                        failures.push(format!("{}: {} bad pixels, {} of the canvas, over {}", name, result.bad_pixels, fraction, bad_fraction));
+                    }
+                }
+                Err(e) => failures.push(format!("{}: {}", name, e)),
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
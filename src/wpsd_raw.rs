@@ -18,28 +18,32 @@ pub enum DescItem
     #[allow(non_camel_case_types)]
     r#enum(String, String),
     VlLs(Vec<DescItem>),
+    #[allow(non_camel_case_types)]
+    tdta(Vec<u8>),
     #[default] Xxx
 }
 
 impl DescItem
 {
-    pub fn long(&self) -> i32 { match self { DescItem::long(x) => return *x, _ => panic!(), } }
-    pub fn doub(&self) -> f64 { match self { DescItem::doub(x) => return *x, _ => panic!(), } }
-    pub fn bool(&self) -> bool { match self { DescItem::bool(x) => return *x, _ => panic!(), } }
-    pub fn r#enum(&self) -> (String, String) { match self { DescItem::r#enum(y, x) => return (y.clone(), x.clone()), _ => panic!(), } }
+    // None if the item is some other type, which corrupted or unusual files can have
+    pub fn long(&self) -> Option<i32> { match self { DescItem::long(x) => Some(*x), _ => None, } }
+    pub fn doub(&self) -> Option<f64> { match self { DescItem::doub(x) => Some(*x), _ => None, } }
+    pub fn bool(&self) -> Option<bool> { match self { DescItem::bool(x) => Some(*x), _ => None, } }
+    pub fn r#enum(&self) -> Option<(String, String)> { match self { DescItem::r#enum(y, x) => Some((y.clone(), x.clone())), _ => None, } }
     #[allow(non_snake_case)]
-    pub fn UntF(&self) -> (String, f64) { match self { DescItem::UntF(y, x) => return (y.clone(), *x), _ => panic!(), } }
+    pub fn UntF(&self) -> Option<(String, f64)> { match self { DescItem::UntF(y, x) => Some((y.clone(), *x)), _ => None, } }
     #[allow(non_snake_case)]
-    pub fn Objc(&self) -> Box<Descriptor> { match self { DescItem::Objc(x) => return x.clone(), _ => panic!(), } }
+    pub fn Objc(&self) -> Option<Box<Descriptor>> { match self { DescItem::Objc(x) => Some(x.clone()), _ => None, } }
     #[allow(non_snake_case)]
-    pub fn TEXT(&self) -> String { match self { DescItem::TEXT(x) => return x.clone(), _ => panic!(), } }
+    pub fn TEXT(&self) -> Option<String> { match self { DescItem::TEXT(x) => Some(x.clone()), _ => None, } }
     #[allow(non_snake_case)]
-    pub fn VlLs(&self) -> Vec<DescItem> { match self { DescItem::VlLs(x) => return x.clone(), _ => panic!(), } }
+    pub fn VlLs(&self) -> Option<Vec<DescItem>> { match self { DescItem::VlLs(x) => Some(x.clone()), _ => None, } }
 }
 
-type Descriptor = (String, Vec<(String, DescItem)>);
+pub type Descriptor = (String, Vec<(String, DescItem)>);
 
 use serde::{Serialize, Deserialize};
+use crate::docmeta::*;
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct MaskInfo {
     pub x : i32,
@@ -52,8 +56,28 @@ pub struct MaskInfo {
     pub invert : bool,
 }
 
+#[derive(Clone, Debug, Default)]
+pub struct PsdVectorMask {
+    pub invert : bool,
+    pub disabled : bool,
+    pub initial_fill : bool,
+    // combining operation (0 xor, 1 union, 2 subtract, 3 intersect), then knots as (preceding control, anchor, leaving control)
+    // points are (x, y), as fractions of the canvas size
+    pub subpaths : Vec<(i16, Vec<[[f64; 2]; 3]>)>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PsdText {
+    pub transform : [f64; 6], // xx, xy, yx, yy, tx, ty
+    pub desc : Descriptor,
+    pub desc_range : (usize, usize), // where the text descriptor is inside of raw, so an edited one can be spliced back in
+    pub raw : Vec<u8>, // the whole TySh block
+}
+
 #[derive(Clone, Debug, Default)]
 pub struct LayerInfo {
+    pub offset : u64, // of the layer record, for error reporting
+    pub layer_id : u32,
     pub name : String,
     pub opacity : f32,
     pub fill_opacity : f32,
@@ -63,7 +87,9 @@ pub struct LayerInfo {
     pub w : u32,
     pub h : u32,
     pub image_channel_count : u16,
+    pub depth : u16,
     pub image_data_rgba : Vec<u8>,
+    pub image_data_float : Vec<f32>, // used instead of image_data_rgba for 16-bit and 32-bit files
     pub image_data_k : Vec<u8>,
     pub image_data_has_g : bool,
     pub image_data_has_b : bool,
@@ -84,112 +110,472 @@ pub struct LayerInfo {
     pub adjustment_info : Vec<f32>,
     pub adjustment_desc : Option<Descriptor>,
     pub effects_desc : Option<Descriptor>,
+    pub smart_object : Option<(String, [f64; 8])>, // linked file id, canvas positions of its corners (top left, top right, bottom right, bottom left)
+    pub vector_mask : Option<PsdVectorMask>,
+    pub text : Option<PsdText>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PsdError
+{
+    UnexpectedEof(u64),
+    BadSignature(u64),
+    UnsupportedVersion(u64, u16),
+    UnsupportedDepth(u64, u16),
+    UnsupportedColorMode(u64, u16),
+    UnsupportedCompression(u64, u16),
+    UnknownDescriptorType(u64, String),
+    Malformed(u64, &'static str),
+}
+
+impl PsdError
+{
+    pub fn offset(&self) -> u64
+    {
+        match self
+        {
+            PsdError::UnexpectedEof(x) => *x,
+            PsdError::BadSignature(x) => *x,
+            PsdError::UnsupportedVersion(x, _) => *x,
+            PsdError::UnsupportedDepth(x, _) => *x,
+            PsdError::UnsupportedColorMode(x, _) => *x,
+            PsdError::UnsupportedCompression(x, _) => *x,
+            PsdError::UnknownDescriptorType(x, _) => *x,
+            PsdError::Malformed(x, _) => *x,
+        }
+    }
+}
+
+impl std::fmt::Display for PsdError
+{
+    fn fmt(&self, f : &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+    {
+        match self
+        {
+            PsdError::UnexpectedEof(_) => write!(f, "unexpected end of file")?,
+            PsdError::BadSignature(_) => write!(f, "bad signature")?,
+            PsdError::UnsupportedVersion(_, x) => write!(f, "unsupported PSD version {}", x)?,
+            PsdError::UnsupportedDepth(_, x) => write!(f, "unsupported bit depth {}", x)?,
+            PsdError::UnsupportedColorMode(_, x) => write!(f, "unsupported color mode {}", x)?,
+            PsdError::UnsupportedCompression(_, x) => write!(f, "unsupported compression format {}", x)?,
+            PsdError::UnknownDescriptorType(_, x) => write!(f, "unknown descriptor type {:?}", x)?,
+            PsdError::Malformed(_, x) => write!(f, "malformed {}", x)?,
+        }
+        write!(f, " at byte offset 0x{:X}", self.offset())
+    }
+}
+
+impl std::error::Error for PsdError { }
+
+fn read_exact(cursor: &mut Cursor<&[u8]>, buf : &mut [u8]) -> Result<(), PsdError>
+{
+    let pos = cursor.position();
+    cursor.read_exact(buf).map_err(|_| PsdError::UnexpectedEof(pos))
+}
+
+// checks the length against the remaining data first, so that garbage lengths don't try to allocate gigabytes
+fn read_vec(cursor: &mut Cursor<&[u8]>, len : u64) -> Result<Vec<u8>, PsdError>
+{
+    let pos = cursor.position();
+    if pos.saturating_add(len) > cursor.get_ref().len() as u64
+    {
+        return Err(PsdError::UnexpectedEof(pos));
+    }
+    let mut buf = vec![0; len as usize];
+    read_exact(cursor, &mut buf)?;
+    Ok(buf)
 }
 
-fn read_u8(cursor: &mut Cursor<&[u8]>) -> u8
+fn read_u8(cursor: &mut Cursor<&[u8]>) -> Result<u8, PsdError>
 {
     let mut buf = [0; 1];
-    cursor.read_exact(&mut buf).expect("Failed to read u8");
-    buf[0]
+    read_exact(cursor, &mut buf)?;
+    Ok(buf[0])
 }
 
-fn read_u16(cursor: &mut Cursor<&[u8]>) -> u16
+fn read_u16(cursor: &mut Cursor<&[u8]>) -> Result<u16, PsdError>
 {
     let mut buf = [0; 2];
-    cursor.read_exact(&mut buf).expect("Failed to read u16");
-    u16::from_be_bytes(buf)
+    read_exact(cursor, &mut buf)?;
+    Ok(u16::from_be_bytes(buf))
 }
 
-fn read_u32(cursor: &mut Cursor<&[u8]>) -> u32
+fn read_u32(cursor: &mut Cursor<&[u8]>) -> Result<u32, PsdError>
 {
     let mut buf = [0; 4];
-    cursor.read_exact(&mut buf).expect("Failed to read u32");
-    u32::from_be_bytes(buf)
+    read_exact(cursor, &mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u64(cursor: &mut Cursor<&[u8]>) -> Result<u64, PsdError>
+{
+    let mut buf = [0; 8];
+    read_exact(cursor, &mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+// section and channel lengths are 8 bytes long in PSB files
+fn read_len(cursor: &mut Cursor<&[u8]>, psb : bool) -> Result<u64, PsdError>
+{
+    if psb { read_u64(cursor) } else { Ok(read_u32(cursor)? as u64) }
 }
 
-fn read_i32(cursor: &mut Cursor<&[u8]>) -> i32
+fn read_i32(cursor: &mut Cursor<&[u8]>) -> Result<i32, PsdError>
 {
     let mut buf = [0; 4];
-    cursor.read_exact(&mut buf).expect("Failed to read i32");
-    i32::from_be_bytes(buf)
+    read_exact(cursor, &mut buf)?;
+    Ok(i32::from_be_bytes(buf))
 }
 
-fn read_f32(cursor: &mut Cursor<&[u8]>) -> f32
+fn read_f32(cursor: &mut Cursor<&[u8]>) -> Result<f32, PsdError>
 {
     let mut buf = [0; 4];
-    cursor.read_exact(&mut buf).expect("Failed to read f32");
-    f32::from_be_bytes(buf)
+    read_exact(cursor, &mut buf)?;
+    Ok(f32::from_be_bytes(buf))
 }
 
-fn read_f64(cursor: &mut Cursor<&[u8]>) -> f64
+fn read_f64(cursor: &mut Cursor<&[u8]>) -> Result<f64, PsdError>
 {
     let mut buf = [0; 8];
-    cursor.read_exact(&mut buf).expect("Failed to read f64");
-    f64::from_be_bytes(buf)
+    read_exact(cursor, &mut buf)?;
+    Ok(f64::from_be_bytes(buf))
 }
 
-pub fn parse_psd_metadata(data : &[u8]) -> PsdMetadata
+pub fn parse_psd_metadata(data : &[u8]) -> Result<PsdMetadata, PsdError>
 {
     let mut cursor = Cursor::new(&data[..]);
 
     let mut signature = [0; 4];
-    cursor.read_exact(&mut signature).expect("Failed to read PSD signature");
+    read_exact(&mut cursor, &mut signature)?;
     if signature != [0x38, 0x42, 0x50, 0x53]
     {
-        panic!("Invalid PSD signature");
+        return Err(PsdError::BadSignature(0));
     }
-
-    let version = read_u16(&mut cursor);
-    if version != 1
+    
+    // version 2 is PSB, the large document format
+    let version = read_u16(&mut cursor)?;
+    if version != 1 && version != 2
     {
-        panic!("Unsupported PSD version");
+        return Err(PsdError::UnsupportedVersion(4, version));
     }
 
     cursor.set_position(cursor.position() + 6);
-
-    let channel_count = read_u16(&mut cursor);
-    let height = read_u32(&mut cursor);
-    let width = read_u32(&mut cursor);
-    let depth = read_u16(&mut cursor);
-    let color_mode = read_u16(&mut cursor);
-
-    PsdMetadata
+    
+    let channel_count = read_u16(&mut cursor)?;
+    let height = read_u32(&mut cursor)?;
+    let width = read_u32(&mut cursor)?;
+    let depth = read_u16(&mut cursor)?;
+    let color_mode = read_u16(&mut cursor)?;
+    if depth != 8 && depth != 16 && depth != 32
+    {
+        return Err(PsdError::UnsupportedDepth(22, depth));
+    }
+    if !matches!(color_mode, 1 | 2 | 3 | 4 | 8)
+    {
+        return Err(PsdError::UnsupportedColorMode(24, color_mode));
+    }
+    
+    // indexed files store their palette as 256 red values, then 256 green values, then 256 blue values
+    let mut palette = vec!();
+    let color_mode_length = read_u32(&mut cursor)? as usize;
+    if color_mode == 2 && color_mode_length >= 768
+    {
+        let mut data = vec![0; 768];
+        read_exact(&mut cursor, &mut data)?;
+        for i in 0..256
+        {
+            palette.push([data[i], data[i + 256], data[i + 512]]);
+        }
+    }
+    
+    Ok(PsdMetadata
     {
+        version,
         width,
         height,
         channel_count,
         depth,
         color_mode,
+        palette,
+    })
+}
+#[derive(Clone, Debug, Default)]
+pub struct PsdImageResources
+{
+    pub metadata : DocumentMetadata,
+    pub selected_layer_ids : Vec<u32>, // lyid values, mapped to uuids by the caller
+}
+
+fn read_unicode_string(cursor : &mut Cursor<&[u8]>) -> Result<String, PsdError>
+{
+    let len = read_u32(cursor)? as u64;
+    let text : Vec<u16> = read_vec(cursor, len * 2)?.chunks_exact(2).map(|x| u16::from_be_bytes([x[0], x[1]])).collect();
+    Ok(String::from_utf16_lossy(&text).trim_end_matches('\0').to_string())
+}
+
+pub fn desc_get<'a>(desc : &'a Descriptor, key : &str) -> Option<&'a DescItem>
+{
+    desc.1.iter().find(|x| x.0 == key).map(|x| &x.1)
+}
+
+fn parse_slice_descriptor(desc : &Descriptor) -> Vec<Slice>
+{
+    let mut ret = vec!();
+    if let Some(DescItem::VlLs(list)) = desc_get(desc, "slices")
+    {
+        for item in list
+        {
+            if let DescItem::Objc(slice) = item
+            {
+                let long = |d : &Descriptor, key : &str| if let Some(DescItem::long(x)) = desc_get(d, key) { *x } else { 0 };
+                let name = if let Some(DescItem::TEXT(name)) = desc_get(slice, "Nm  ") { name.clone() } else { String::new() };
+                let empty = (String::new(), vec!());
+                let bounds = if let Some(DescItem::Objc(bounds)) = desc_get(slice, "bounds") { bounds } else { &empty };
+                ret.push(Slice
+                {
+                    id : long(slice, "sliceID"),
+                    name,
+                    left : long(bounds, "Left"),
+                    top : long(bounds, "Top "),
+                    right : long(bounds, "Rght"),
+                    bottom : long(bounds, "Btom"),
+                });
+            }
+        }
+    }
+    ret
+}
+
+fn parse_image_resource(cursor : &mut Cursor<&[u8]>, id : u16, size : u64, resources : &mut PsdImageResources) -> Result<(), PsdError>
+{
+    let pos = cursor.position();
+    let metadata = &mut resources.metadata;
+    match id
+    {
+        // ResolutionInfo. resolutions are 16.16 fixed point
+        1005 =>
+        {
+            let h_res = read_u32(cursor)? as f64 / 65536.0;
+            let h_unit = read_u16(cursor)?;
+            let width_unit = read_u16(cursor)?;
+            let v_res = read_u32(cursor)? as f64 / 65536.0;
+            let v_unit = read_u16(cursor)?;
+            let height_unit = read_u16(cursor)?;
+            metadata.resolution = Some(Resolution { h_res, v_res, h_unit, v_unit, width_unit, height_unit });
+        }
+        // grid and guides. guide locations are in 1/32nds of a pixel
+        1032 =>
+        {
+            expect_u32(cursor, 1, "guide resource version")?;
+            cursor.set_position(cursor.position() + 8); // grid cycle, not used by photoshop anymore
+            let count = read_u32(cursor)? as u64;
+            if count * 5 > size
+            {
+                return Err(PsdError::Malformed(pos, "guide count"));
+            }
+            for _ in 0..count
+            {
+                let position = read_i32(cursor)? as f32 / 32.0;
+                let horizontal = read_u8(cursor)? == 1;
+                metadata.guides.push(Guide { position, horizontal });
+            }
+        }
+        1039 =>
+        {
+            metadata.icc_profile = Some(read_vec(cursor, size)?);
+        }
+        1050 =>
+        {
+            let version = read_u32(cursor)?;
+            if version == 6
+            {
+                cursor.set_position(cursor.position() + 16); // bounds of all slices
+                read_unicode_string(cursor)?; // slice group name
+                let count = read_u32(cursor)? as u64;
+                if count > size
+                {
+                    return Err(PsdError::Malformed(pos, "slice count"));
+                }
+                for _ in 0..count
+                {
+                    let id = read_i32(cursor)?;
+                    read_u32(cursor)?; // group id
+                    if read_u32(cursor)? == 1 // origin. layer-based slices also have the id of their layer
+                    {
+                        read_u32(cursor)?;
+                    }
+                    let name = read_unicode_string(cursor)?;
+                    read_u32(cursor)?; // type
+                    let left = read_i32(cursor)?;
+                    let top = read_i32(cursor)?;
+                    let right = read_i32(cursor)?;
+                    let bottom = read_i32(cursor)?;
+                    for _ in 0..4 // url, target, message, alt tag
+                    {
+                        read_unicode_string(cursor)?;
+                    }
+                    read_u8(cursor)?; // cell text is html
+                    read_unicode_string(cursor)?; // cell text
+                    cursor.set_position(cursor.position() + 12); // alignments, argb background color
+                    metadata.slices.push(Slice { id, name, left, top, right, bottom });
+                }
+            }
+            else if version == 7 || version == 8
+            {
+                expect_u32(cursor, 16, "slice descriptor version")?;
+                let desc = read_descriptor(cursor)?;
+                metadata.slices = parse_slice_descriptor(&desc);
+            }
+            else
+            {
+                return Err(PsdError::UnsupportedVersion(pos, version as u16));
+            }
+        }
+        // layer selection ids
+        1069 =>
+        {
+            let count = read_u16(cursor)?;
+            for _ in 0..count
+            {
+                resources.selected_layer_ids.push(read_u32(cursor)?);
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+pub fn parse_image_resources(data : &[u8]) -> Result<PsdImageResources, PsdError>
+{
+    let mut cursor = Cursor::new(data);
+    cursor.set_position(26);
+    
+    let color_mode_length = read_u32(&mut cursor)? as u64;
+    cursor.set_position(cursor.position() + color_mode_length);
+    
+    let image_resources_length = read_u32(&mut cursor)? as u64;
+    let image_resources_end = cursor.position() + image_resources_length;
+    if image_resources_end > data.len() as u64
+    {
+        return Err(PsdError::UnexpectedEof(data.len() as u64));
+    }
+    
+    let mut ret = PsdImageResources::default();
+    while cursor.position() + 12 <= image_resources_end
+    {
+        let start = cursor.position();
+        let mut sig = [0; 4];
+        read_exact(&mut cursor, &mut sig)?;
+        let id = read_u16(&mut cursor)?;
+        // pascal name, padded to an even size
+        let name_len = read_u8(&mut cursor)? as u64;
+        cursor.set_position(cursor.position() + name_len + (1 - name_len % 2));
+        let size = read_u32(&mut cursor)? as u64;
+        let data_start = cursor.position();
+        if data_start + size > image_resources_end
+        {
+            return Err(PsdError::Malformed(start, "image resource length"));
+        }
+        
+        if &sig == b"8BIM"
+        {
+            if let Err(e) = parse_image_resource(&mut cursor, id, size, &mut ret)
+            {
+                println!("skipping image resource {}: {}", id, e);
+            }
+        }
+        
+        cursor.set_position(data_start + size + size % 2);
+    }
+    
+    Ok(ret)
+}
+
+// converts grayscale, indexed, and CMYK channel data (already in the rgba/float buffers) into RGB
+pub fn convert_to_rgb(metadata : &PsdMetadata, layer : &mut LayerInfo)
+{
+    let k = &layer.image_data_k;
+    match metadata.color_mode
+    {
+        1 | 8 =>
+        {
+            for px in layer.image_data_rgba.chunks_exact_mut(4)
+            {
+                px[1] = px[0];
+                px[2] = px[0];
+            }
+            for px in layer.image_data_float.chunks_exact_mut(4)
+            {
+                px[1] = px[0];
+                px[2] = px[0];
+            }
+        }
+        2 =>
+        {
+            for px in layer.image_data_rgba.chunks_exact_mut(4)
+            {
+                let c = metadata.palette.get(px[0] as usize).copied().unwrap_or([0, 0, 0]);
+                px[..3].copy_from_slice(&c);
+            }
+        }
+        4 =>
+        {
+            // CMYK is stored inverted, so 255 means no ink
+            for (i, px) in layer.image_data_rgba.chunks_exact_mut(4).enumerate()
+            {
+                let k = k.get(i).copied().unwrap_or(255) as u32;
+                for c in px.iter_mut().take(3)
+                {
+                    *c = (*c as u32 * k / 255) as u8;
+                }
+            }
+            for (i, px) in layer.image_data_float.chunks_exact_mut(4).enumerate()
+            {
+                let k = k.get(i).copied().unwrap_or(255) as f32 / 255.0;
+                for c in px.iter_mut().take(3)
+                {
+                    *c *= k;
+                }
+            }
+        }
+        _ => {}
     }
 }
-pub fn append_img_data(cursor : &mut Cursor<&[u8]>, output : &mut Vec<u8>, size : u64, h : u64)
+// RLE row byte counts are 4 bytes long in PSB files instead of 2
+fn read_row_len(cursor : &mut Cursor<&[u8]>, psb : bool) -> Result<u64, PsdError>
+{
+    if psb { Ok(read_u32(cursor)? as u64) } else { Ok(read_u16(cursor)? as u64) }
+}
+
+pub fn append_img_data(cursor : &mut Cursor<&[u8]>, output : &mut Vec<u8>, size : u64, h : u64, psb : bool) -> Result<(), PsdError>
 {
     //println!("starting at: {:X}\t", cursor.position());
-    let mode = read_u16(cursor);
+    let pos = cursor.position();
+    let mode = read_u16(cursor)?;
     if mode == 0
     {
-        cursor.take(size).read_to_end(output).unwrap();
+        output.extend(read_vec(cursor, size - 2)?);
     }
     else if mode == 1
     {
         let mut c2 = cursor.clone();
-        c2.set_position(c2.position() + h * 2);
+        c2.set_position(c2.position() + h * if psb { 4 } else { 2 });
         for _ in 0..h
         {
             //println!("at: {:X} - {:X}\t", cursor.position(), c2.position());
-            let len = read_u16(cursor);
+            let len = read_row_len(cursor, psb)?;
             let start = c2.position();
             // FIXME: ignore overflow and pad out underflow?
-            while c2.position() < start as u64 + len as u64
+            while c2.position() < start + len
             {
-                let n = read_u8(&mut c2) as i8;
+                let n = read_u8(&mut c2)? as i8;
                 if n >= 0
                 {
-                    (&mut c2).take(n as u64 + 1).read_to_end(output).unwrap();
+                    output.extend(read_vec(&mut c2, n as u64 + 1)?);
                 }
                 else if n != -128
                 {
-                    output.extend(std::iter::repeat(read_u8(&mut c2)).take((1 - n as i64) as usize));
+                    output.extend(std::iter::repeat(read_u8(&mut c2)?).take((1 - n as i64) as usize));
                 }
             }
         }
@@ -197,45 +583,89 @@ pub fn append_img_data(cursor : &mut Cursor<&[u8]>, output : &mut Vec<u8>, size
     }
     else
     {
-        panic!("unsupported compression format");
+        return Err(PsdError::UnsupportedCompression(pos, mode));
+    }
+    Ok(())
+}
+pub fn packbits_decode(input : &[u8], output : &mut Vec<u8>)
+{
+    let mut i = 0;
+    while i < input.len()
+    {
+        let n = input[i] as i8;
+        i += 1;
+        if n >= 0
+        {
+            let end = (i + n as usize + 1).min(input.len());
+            output.extend_from_slice(&input[i..end]);
+            i = end;
+        }
+        else if n != -128 && i < input.len()
+        {
+            output.extend(std::iter::repeat_n(input[i], (1 - n as i64) as usize));
+            i += 1;
+        }
+    }
+}
+// big-endian samples of the given bit depth, normalized to 0.0~1.0 (except for 32-bit, which is already float)
+pub fn decode_samples(raw : &[u8], depth : u16) -> Vec<f32>
+{
+    match depth
+    {
+        16 => raw.chunks_exact(2).map(|x| u16::from_be_bytes([x[0], x[1]]) as f32 / 65535.0).collect(),
+        32 => raw.chunks_exact(4).map(|x| f32::from_be_bytes([x[0], x[1], x[2], x[3]])).collect(),
+        _ => raw.iter().map(|x| *x as f32 / 255.0).collect(),
     }
 }
-pub fn copy_img_data(cursor : &mut Cursor<&[u8]>, output : &mut [u8], stride : usize, size : u64, h : u64)
+pub fn decode_samples_to_u8(raw : &[u8], depth : u16) -> Vec<u8>
+{
+    match depth
+    {
+        8 => raw.to_vec(),
+        _ => decode_samples(raw, depth).into_iter().map(|x| (x.clamp(0.0, 1.0) * 255.0 + 0.5) as u8).collect(),
+    }
+}
+pub fn copy_img_data(cursor : &mut Cursor<&[u8]>, output : &mut [u8], stride : usize, size : u64, h : u64, psb : bool) -> Result<(), PsdError>
 {
     //println!("pos... 0x{:X}", cursor.position());
     let pos = cursor.position();
-    let mode = read_u16(cursor);
+    let mode = read_u16(cursor)?;
     //println!("size... 0x{:X}", size as usize - 2);
     if mode == 0
     {
         for i in 0..size as usize - 2
         {
-            output[i*stride] = read_u8(cursor);
+            let c = read_u8(cursor)?;
+            if i*stride < output.len()
+            {
+                output[i*stride] = c;
+            }
         }
     }
     else if mode == 1
     {
+        let count_size = if psb { 4 } else { 2 };
         let mut c2 = cursor.clone();
-        c2.set_position(c2.position() + h * 2);
+        c2.set_position(c2.position() + h * count_size);
         let mut i = 0;
         let mut j = 2;
         for _ in 0..h
         {
             let _i2 = i;
             //print!("at: {:X} - {:X}\t", cursor.position(), c2.position());
-            let len = read_u16(cursor);
-            j += 2;
+            let len = read_row_len(cursor, psb)?;
+            j += count_size;
             let start = c2.position();
             // FIXME: ignore overflow and pad out underflow?
-            while c2.position() - start < len as u64
+            while c2.position() - start < len
             {
-                let n = read_u8(&mut c2) as i8;
+                let n = read_u8(&mut c2)? as i8;
                 j += 1;
                 if n >= 0
                 {
                     for _ in 0..n as u64 + 1
                     {
-                        let c = read_u8(&mut c2);
+                        let c = read_u8(&mut c2)?;
                         if i*stride < output.len()
                         {
                             output[i*stride] = c;
@@ -246,7 +676,7 @@ pub fn copy_img_data(cursor : &mut Cursor<&[u8]>, output : &mut [u8], stride : u
                 }
                 else if n != -128
                 {
-                    let c = read_u8(&mut c2);
+                    let c = read_u8(&mut c2)?;
                     for _ in 0..1 - n as i64
                     {
                         if i*stride < output.len()
@@ -259,541 +689,1584 @@ pub fn copy_img_data(cursor : &mut Cursor<&[u8]>, output : &mut [u8], stride : u
                 }
             }
             //println!("effective w: {}", i - _i2);
-            c2.set_position(start + len as u64);
+            c2.set_position(start + len);
+        }
+        if j != size
+        {
+            return Err(PsdError::Malformed(pos, "channel data length"));
         }
-        assert!(j == size, "{} {}", j, size);
     }
     else
     {
-        panic!("unsupported compression format {} at 0x{:X}", mode, pos);
+        return Err(PsdError::UnsupportedCompression(pos, mode));
     }
     cursor.set_position(pos + size);
+    Ok(())
 }
-pub fn parse_layer_records(data : &[u8]) -> Vec<LayerInfo>
+fn read_desc_key(c : &mut Cursor<&[u8]>) -> Result<String, PsdError>
 {
-    let metadata = parse_psd_metadata(data);
-    assert!(metadata.depth == 8);
-    assert!(metadata.color_mode == 3);
-    
-    let mut cursor = Cursor::new(&data[..]);
-    cursor.set_position(26);
-
-    let color_mode_length = read_u32(&mut cursor) as u64;
-    cursor.set_position(cursor.position() + color_mode_length);
-
-    let image_resources_length = read_u32(&mut cursor) as u64;
-    cursor.set_position(cursor.position() + image_resources_length);
+    let mut len = read_u32(c)? as u64;
+    if len == 0 { len = 4; }
+    let key = read_vec(c, len)?;
+    Ok(String::from_utf8_lossy(&key).to_string())
+}
 
-    let layer_mask_info_length = read_u32(&mut cursor) as u64;
-    let _layer_mask_info_end = cursor.position() + layer_mask_info_length;
+fn read_desc_item(c : &mut Cursor<&[u8]>, depth : usize) -> Result<DescItem, PsdError>
+{
+    let pos = c.position();
+    if depth > 64
+    {
+        return Err(PsdError::Malformed(pos, "descriptor nesting"));
+    }
+    let mut id = [0; 4];
+    read_exact(c, &mut id)?;
+    let id = String::from_utf8_lossy(&id).to_string();
+    
+    match id.as_str()
+    {
+        "long" => Ok(DescItem::long(read_i32(c)?)),
+        "doub" => Ok(DescItem::doub(read_f64(c)?)),
+        "Objc" => Ok(DescItem::Objc(Box::new(read_descriptor_nested(c, depth + 1)?))),
+        "bool" => Ok(DescItem::bool(read_u8(c)? != 0)),
+        "TEXT" =>
+        {
+            let len = read_u32(c)? as u64;
+            let text : Vec<u16> = read_vec(c, len * 2)?.chunks_exact(2).map(|x| u16::from_be_bytes([x[0], x[1]])).collect();
+            let text = String::from_utf16_lossy(&text).trim_end_matches('\0').to_string();
+            Ok(DescItem::TEXT(text))
+        }
+        "UntF" =>
+        {
+            let mut typ = [0; 4];
+            read_exact(c, &mut typ)?;
+            let typ = String::from_utf8_lossy(&typ).to_string();
+            
+            Ok(DescItem::UntF(typ, read_f64(c)?))
+        }
+        "enum" =>
+        {
+            let name1 = read_desc_key(c)?;
+            let name2 = read_desc_key(c)?;
+            Ok(DescItem::r#enum(name1, name2))
+        }
+        "tdta" =>
+        {
+            let len = read_u32(c)? as u64;
+            Ok(DescItem::tdta(read_vec(c, len)?))
+        }
+        "VlLs" =>
+        {
+            let len = read_u32(c)?;
+            let mut ret = vec!();
+            for _ in 0..len
+            {
+                ret.push(read_desc_item(c, depth + 1)?);
+            }
+            Ok(DescItem::VlLs(ret))
+        }
+        _ => Err(PsdError::UnknownDescriptorType(pos, id)),
+    }
+}
 
-    let layer_info_length = read_u32(&mut cursor) as u64;
-    let _layer_info_end = cursor.position() + layer_info_length;
+fn read_descriptor_nested(c : &mut Cursor<&[u8]>, depth : usize) -> Result<Descriptor, PsdError>
+{
+    // skip name. usually/often blank
+    let n = read_u32(c)? as u64;
+    c.set_position(c.position() + n * 2);
     
-    let layer_count = read_u16(&mut cursor) as i16;
-    let layer_count = layer_count.abs(); // If negative, transparency info exists
+    let id = read_desc_key(c)?;
     
-    println!("starting at {:X}", cursor.position());
+    let mut data = vec!();
     
-    let mut idata_c = Cursor::new(&data[..]);
-    idata_c.set_position(cursor.position());
+    let itemcount = read_u32(c)?;
     
-    for _i in 0..layer_count
+    for _ in 0..itemcount
     {
-        //println!("{}", _i);
-        read_i32(&mut idata_c);
-        read_i32(&mut idata_c);
-        read_i32(&mut idata_c);
-        read_i32(&mut idata_c);
-        let image_channel_count = read_u16(&mut idata_c) as u64;
-        idata_c.set_position(idata_c.position() + 6*image_channel_count + 4 + 4 + 4);
-        let idat_len = read_u32(&mut idata_c) as u64;
-        idata_c.set_position(idata_c.position() + idat_len);
+        let name = read_desc_key(c)?;
+        data.push((name, read_desc_item(c, depth)?));
     }
+    
+    Ok((id, data))
+}
 
-    let mut layers = Vec::new();
+pub fn read_descriptor(c : &mut Cursor<&[u8]>) -> Result<Descriptor, PsdError>
+{
+    read_descriptor_nested(c, 0)
+}
 
-    for _ in 0..layer_count
+fn expect_u16(cursor : &mut Cursor<&[u8]>, value : u16, what : &'static str) -> Result<(), PsdError>
+{
+    let pos = cursor.position();
+    if read_u16(cursor)? != value
     {
-        let top = read_i32(&mut cursor);
-        let left = read_i32(&mut cursor);
-        let bottom = read_i32(&mut cursor);
-        let right = read_i32(&mut cursor);
+        return Err(PsdError::Malformed(pos, what));
+    }
+    Ok(())
+}
 
-        let x = left;
-        let y = top;
-        let w = (right - left) as u32;
-        let h = (bottom - top) as u32;
-        
-        let image_channel_count = read_u16(&mut cursor);
-        //println!("chan count {}", image_channel_count);
-        
-        let channel_info_start = cursor.position();
-        
-        cursor.set_position(channel_info_start);
-        let mut image_data_rgba : Vec<u8> = vec![255u8; (w * h * 4) as usize];
-        let mut image_data_k : Vec<u8> = vec!();
-        let mut image_data_mask : Vec<u8> = vec!();
-        
-        let mut _rgba_count = 0;
-        let mut has_g = false;
-        let mut has_b = false;
-        let mut has_a = false;
-        let mut aux_count = 0;
-        
-        let mut cdat_cursor = cursor.clone();
-        
-        let mut has_neg2 = false;
-        let mut has_neg3 = false;
-        for _ in 0..image_channel_count
-        {
-            let channel_id = read_u16(&mut cursor) as i16;
-            let _channel_length = read_u32(&mut cursor) as usize;
-            has_neg2 = has_neg2 || channel_id == -2;
-            has_neg3 = has_neg3 || channel_id == -3;
-        }
-        
-        let mut blend_mode_signature = [0; 4];
-        cursor.read_exact(&mut blend_mode_signature).expect("Failed to read blend mode signature");
-        assert!(blend_mode_signature == [0x38, 0x42, 0x49, 0x4D]);
+fn expect_u32(cursor : &mut Cursor<&[u8]>, value : u32, what : &'static str) -> Result<(), PsdError>
+{
+    let pos = cursor.position();
+    if read_u32(cursor)? != value
+    {
+        return Err(PsdError::Malformed(pos, what));
+    }
+    Ok(())
+}
 
-        let mut blend_mode_key = [0; 4];
-        cursor.read_exact(&mut blend_mode_key).expect("Failed to read blend mode key");
-        let blend_mode = String::from_utf8_lossy(&blend_mode_key).to_string();
+// a color space id followed by four 16-bit components, converted to RGB
+fn read_psd_color(cursor : &mut Cursor<&[u8]>) -> Result<[f32; 3], PsdError>
+{
+    let space = read_u16(cursor)?;
+    let mut n = [0u16; 4];
+    for x in n.iter_mut()
+    {
+        *x = read_u16(cursor)?;
+    }
+    let f = n.map(|x| x as f32 / 65535.0);
+    Ok(match space
+    {
+        1 =>
+        {
+            let c = crate::pixelmath::hsv_to_rgb([f[0] * 360.0, f[1], f[2], 1.0]);
+            [c[0], c[1], c[2]]
+        }
+        // CMYK, stored inverted so 65535 means no ink
+        2 => [f[0] * f[3], f[1] * f[3], f[2] * f[3]],
+        7 =>
+        {
+            let c = crate::pixelmath::lab_to_rgb(n[0] as f64 / 100.0, n[1] as i16 as f64 / 100.0, n[2] as i16 as f64 / 100.0);
+            c.map(|x| x as f32)
+        }
+        8 =>
+        {
+            let v = 1.0 - n[0] as f32 / 10000.0;
+            [v, v, v]
+        }
+        _ => [f[0], f[1], f[2]],
+    })
+}
 
-        let opacity = read_u8(&mut cursor) as f32 / 255.0;
-        println!("opacity: {}", opacity * 100.0);
-        let clipping = read_u8(&mut cursor);
-        let flags = read_u8(&mut cursor);
-        let _filler = read_u8(&mut cursor);
+// in PSB files, some tagged blocks have 8-byte lengths
+fn read_block_len(cursor : &mut Cursor<&[u8]>, name : &[u8; 4], psb : bool) -> Result<u64, PsdError>
+{
+    let wide = matches!(name, b"LMsk" | b"Lr16" | b"Lr32" | b"Layr" | b"Mt16" | b"Mt32" | b"Mtrn" | b"Alph" | b"FMsk" | b"lnk2" | b"FEid" | b"FXid" | b"PxSD");
+    read_len(cursor, psb && wide)
+}
 
-        let exdat_len = read_u32(&mut cursor) as u64;
-        let exdat_start = cursor.position();
-        
-        let maskdat_len = read_u32(&mut cursor) as u64;
-        let maskdat_start = cursor.position();
-        
-        // FIXME: support maskdat_len == 0 case
-        let mtop = read_i32(&mut cursor);
-        let mleft = read_i32(&mut cursor);
-        let mbottom = read_i32(&mut cursor);
-        let mright = read_i32(&mut cursor);
-        let mut mask_info = MaskInfo::default();
-        mask_info.x = mleft;
-        mask_info.y = mtop;
-        mask_info.w = (mright - mleft) as u32;
-        mask_info.h = (mbottom - mtop) as u32;
-        mask_info.default_color = read_u8(&mut cursor);
-        let mflags = read_u8(&mut cursor);
-        mask_info.relative = (mflags & 1) != 0;
-        mask_info.disabled = (mflags & 2) != 0;
-        mask_info.invert = (mflags & 4) != 0;
-        
-        cursor.set_position(maskdat_start + maskdat_len);
-        
-        for _ in 0..image_channel_count
+// the contents of a TySh block, which ends at end
+pub fn read_type_tool(cursor : &mut Cursor<&[u8]>, end : u64) -> Result<PsdText, PsdError>
+{
+    let start = cursor.position();
+    expect_u16(cursor, 1, "type tool version")?;
+    let mut transform = [0.0; 6];
+    for x in transform.iter_mut()
+    {
+        *x = read_f64(cursor)?;
+    }
+    expect_u16(cursor, 50, "text version")?;
+    expect_u32(cursor, 16, "text descriptor version")?;
+    let desc_start = cursor.position();
+    let desc = read_descriptor(cursor)?;
+    let desc_end = cursor.position();
+    // the warp descriptor and the bounds after it only get carried along in raw
+    if end > cursor.get_ref().len() as u64
+    {
+        return Err(PsdError::UnexpectedEof(end));
+    }
+    let raw = cursor.get_ref()[start as usize..end as usize].to_vec();
+    let desc_range = ((desc_start - start) as usize, (desc_end - start) as usize);
+    Ok(PsdText { transform, desc, desc_range, raw })
+}
+
+// a single tagged block from a layer record's extra data
+fn parse_layer_block(cursor : &mut Cursor<&[u8]>, name : &str, end : u64, layer : &mut LayerInfo) -> Result<(), PsdError>
+{
+    let pos = cursor.position();
+    match name
+    {
+        "lsct" =>
         {
-            let channel_id = read_u16(&mut cdat_cursor) as i16;
-            has_g |= channel_id == 1;
-            has_b |= channel_id == 2;
-            has_a |= channel_id == -1;
-            let channel_length = read_u32(&mut cdat_cursor) as usize;
-            println!("channel... {} {} at 0x{:X}", channel_id, channel_length, idata_c.position());
-            if channel_id >= -1 && channel_id <= 2
+            let kind = read_u32(cursor)? as u64;
+            layer.group_expanded = kind == 1;
+            layer.group_opener = kind == 1 || kind == 2;
+            layer.group_closer = kind == 3;
+            if kind == 1 || kind == 2
             {
-                _rgba_count += 1;
-                let pos = if channel_id >= 0 { channel_id } else { 3 } as usize;
-                println!("{} {} {} {}", w, h, pos, channel_length);
-                if channel_length > 2
-                {
-                    copy_img_data(&mut idata_c, &mut image_data_rgba[pos..], 4, channel_length as u64, h as u64);
-                }
-                else
-                {
-                    idata_c.set_position(idata_c.position() + 2);
-                }
+                println!("group opener!");
             }
-            else if channel_id == 3 // CMYK's K
+            if kind == 3
             {
-                if channel_length > 2
-                {
-                    append_img_data(&mut idata_c, &mut image_data_k, channel_length as u64, h as u64);
-                }
-                else
-                {
-                    idata_c.set_position(idata_c.position() + 2);
-                }
+                println!("group closer!");
             }
-            else
+        }
+        "PlLd" =>
+        {
+            expect_u32(cursor, u32::from_be_bytes(*b"plcL"), "placed layer key")?;
+            read_u32(cursor)?; // version
+            let id = read_pascal_string(cursor)?;
+            for _ in 0..4 // page, page count, antialiasing, placed layer type
             {
-                println!("mask... {} {} {}", mask_info.w, mask_info.h, channel_length);
-                aux_count += 1;
-                if aux_count > 1
-                {
-                    idata_c.set_position(idata_c.position() + channel_length as u64);
-                }
-                else if channel_length > 2
-                {
-                    println!("adding mask data...");
-                    append_img_data(&mut idata_c, &mut image_data_mask, channel_length as u64, mask_info.h as u64);
-                }
-                else
+                read_u32(cursor)?;
+            }
+            let mut transform = [0.0; 8];
+            for x in transform.iter_mut()
+            {
+                *x = read_f64(cursor)?;
+            }
+            // SoLd has the same information and comes later, so don't override it
+            if layer.smart_object.is_none()
+            {
+                layer.smart_object = Some((id, transform));
+            }
+        }
+        "SoLd" | "SoLE" =>
+        {
+            expect_u32(cursor, u32::from_be_bytes(*b"soLD"), "smart object key")?;
+            read_u32(cursor)?; // version
+            expect_u32(cursor, 16, "smart object descriptor version")?;
+            let desc = read_descriptor(cursor)?;
+            if let (Some(DescItem::TEXT(id)), Some(DescItem::VlLs(list))) = (desc_get(&desc, "Idnt"), desc_get(&desc, "Trnf"))
+            {
+                let mut transform = [0.0; 8];
+                for (x, item) in transform.iter_mut().zip(list.iter())
                 {
-                    idata_c.set_position(idata_c.position() + 2);
+                    if let DescItem::doub(n) = item
+                    {
+                        *x = *n;
+                    }
                 }
+                layer.smart_object = Some((id.clone(), transform));
             }
         }
-        
-        let blendat_len = read_u32(&mut cursor) as u64;
-        cursor.set_position(cursor.position() + blendat_len);
-        
-        let mut name_len = read_u8(&mut cursor);
-        let orig_namelen = name_len;
-        while (name_len + 1) % 4 != 0
+        "TySh" =>
         {
-            name_len += 1;
+            layer.text = Some(read_type_tool(cursor, end)?);
         }
-        let mut name = vec![0; name_len as usize];
-        cursor.read_exact(&mut name[..]).expect("Failed to read ASCII name");
-        let name = String::from_utf8_lossy(&name[..orig_namelen as usize]).to_string();
-
-        let mut layer = LayerInfo {
-            name,
-            opacity,
-            fill_opacity : 1.0,
-            blend_mode,
-            x,
-            y,
-            w,
-            h,
-            image_channel_count,
-            image_data_rgba,
-            image_data_k,
-            image_data_has_g : has_g,
-            image_data_has_b : has_b,
-            image_data_has_a : has_a,
-            mask_channel_count : aux_count,
-            mask_info,
-            image_data_mask,
-            group_expanded : false,
-            group_opener : false,
-            group_closer : false,
-            funny_flag : false,
-            is_clipped : clipping != 0,
-            is_alpha_locked : (flags & 1) != 0,
-            is_visible : (flags & 2) == 0,
-            adjustment_type : "".to_string(),
-            adjustment_info : vec!(),
-            adjustment_desc : None,
-            effects_desc : None,
-        };
-        
-        //println!("--- {:X}", cursor.position());
-        
-        while cursor.position() < exdat_start + exdat_len
+        "vmsk" | "vsms" =>
         {
-            let mut sig = [0; 4];
-            cursor.read_exact(&mut sig).expect("Failed to read metadata signature");
-            assert!(sig == [0x38, 0x42, 0x49, 0x4D]);
-            
-            let mut name = [0; 4];
-            cursor.read_exact(&mut name).expect("Failed to read metadata name");
-            let name = String::from_utf8_lossy(&name).to_string();
-            
-            let len = read_u32(&mut cursor) as u64;
-            //println!("?? {}", len);
-            let start = cursor.position();
-            
-            println!("reading metadata.... {}", name.as_str());
-            
-            fn read_descriptor(c : &mut Cursor<&[u8]>) -> Descriptor
-            {
-                // skip name. usually/often blank
-                let n = read_u32(c) as u64;
-                c.set_position(c.position() + n * 2);
-                
-                let mut idlen = read_u32(c);
-                if idlen == 0 { idlen = 4; }
-                let mut id = vec![0; idlen as usize];
-                c.read_exact(&mut id).unwrap();
-                let id = String::from_utf8_lossy(&id).to_string();
-                
-                let mut data = vec!();
-                
-                let itemcount = read_u32(c);
-                
-                for _ in 0..itemcount
+            read_u32(cursor)?; // version
+            let flags = read_u32(cursor)?;
+            let mut mask = PsdVectorMask { invert : (flags & 1) != 0, disabled : (flags & 4) != 0, ..Default::default() };
+            // fixed-size path records, each starting with a selector
+            while cursor.position() + 26 <= end
+            {
+                let record_start = cursor.position();
+                match read_u16(cursor)?
                 {
-                    let mut namelen = read_u32(c);
-                    if namelen == 0 { namelen = 4; }
-                    let mut name = vec![0; namelen as usize];
-                    c.read_exact(&mut name).unwrap();
-                    let name = String::from_utf8_lossy(&name).to_string();
-                    
-                    fn read_key(c : &mut Cursor<&[u8]>) -> DescItem
+                    // subpath length records, open or closed; the knot count is implied by the knot records after it
+                    0 | 3 =>
+                    {
+                        read_u16(cursor)?;
+                        let op = read_u16(cursor)? as i16;
+                        mask.subpaths.push((op, vec!()));
+                    }
+                    // bezier knots, linked or not, for closed or open subpaths
+                    1 | 2 | 4 | 5 =>
                     {
-                        let mut id = vec![0; 4];
-                        c.read_exact(&mut id).unwrap();
-                        let id = String::from_utf8_lossy(&id).to_string();
-                        
-                        match id.as_str()
+                        let mut knot = [[0.0; 2]; 3];
+                        for point in knot.iter_mut()
                         {
-                            "long" => return DescItem::long(read_i32(c)),
-                            "doub" => return DescItem::doub(read_f64(c)),
-                            "Objc" => return DescItem::Objc(Box::new(read_descriptor(c))),
-                            "bool" => return DescItem::bool(read_u8(c) != 0),
-                            "TEXT" =>
-                            {
-                                let len = read_u32(c) as u64;
-                                let mut text = vec![0; len as usize];
-                                for i in 0..len
-                                {
-                                    text[i as usize] = read_u16(c);
-                                }
-                                let text = String::from_utf16_lossy(&text).trim_end_matches('\0').to_string();
-                                return DescItem::TEXT(text);
-                            }
-                            "UntF" =>
-                            {
-                                let mut typ = vec![0; 4];
-                                c.read_exact(&mut typ).unwrap();
-                                let typ = String::from_utf8_lossy(&typ).to_string();
-                                
-                                return DescItem::UntF(typ, read_f64(c));
-                            }
-                            "enum" =>
-                            {
-                                let mut len = read_u32(c);
-                                if len == 0 { len = 4; }
-                                let mut name1 = vec![0; len as usize];
-                                c.read_exact(&mut name1).unwrap();
-                                let name1 = String::from_utf8_lossy(&name1).to_string();
-                                
-                                let mut len = read_u32(c);
-                                if len == 0 { len = 4; }
-                                let mut name2 = vec![0; len as usize];
-                                c.read_exact(&mut name2).unwrap();
-                                let name2 = String::from_utf8_lossy(&name2).to_string();
-                                
-                                return DescItem::r#enum(name1, name2);
-                            }
-                            "VlLs" =>
-                            {
-                                let len = read_u32(c);
-                                let mut ret = vec!();
-                                for _ in 0..len
-                                {
-                                    ret.push(read_key(c));
-                                }
-                                return DescItem::VlLs(ret);
-                            }
-                            _ =>
-                            {
-                                println!("!!! errant descriptor subobject type... {}", id);
-                                return DescItem::Err(id);
-                            }
+                            // 8.24 fixed point, vertical first
+                            let y = read_i32(cursor)? as f64 / 16777216.0;
+                            let x = read_i32(cursor)? as f64 / 16777216.0;
+                            *point = [x, y];
+                        }
+                        if let Some(subpath) = mask.subpaths.last_mut()
+                        {
+                            subpath.1.push(knot);
                         }
                     }
-                    
-                    data.push((name, read_key(c)));
+                    8 => mask.initial_fill = read_u16(cursor)? == 1,
+                    _ => {}
                 }
-                
-                //
-                
-                (id, data)
+                cursor.set_position(record_start + 26);
             }
+            layer.vector_mask = Some(mask);
+        }
+        "lyid" =>
+        {
+            layer.layer_id = read_u32(cursor)?;
+        }
+        "luni" =>
+        {
+            let len = read_u32(cursor)? as u64;
+            let name : Vec<u16> = read_vec(cursor, len * 2)?.chunks_exact(2).map(|x| u16::from_be_bytes([x[0], x[1]])).collect();
+            layer.name = String::from_utf16_lossy(&name).to_string();
+        }
+        "tsly" =>
+        {
+            let thing = read_u8(cursor)?;
+            layer.funny_flag = thing == 0;
+            println!("{}", layer.funny_flag);
+        }
+        "iOpa" =>
+        {
+            layer.fill_opacity = read_u8(cursor)? as f32 / 255.0;
+        }
+        "lfx2" =>
+        {
+            expect_u32(cursor, 0, "effects version")?;
+            expect_u32(cursor, 16, "effects descriptor version")?;
+            layer.effects_desc = Some(read_descriptor(cursor)?);
+        }
+        // adjustment layers
+        "post" =>
+        {
+            let mut data = vec!();
+            data.push(read_u16(cursor)? as f32); // number of levels
+            layer.adjustment_type = name.to_string();
+            layer.adjustment_info = data;
+        }
+        "nvrt" =>
+        {
+            layer.adjustment_type = name.to_string();
+            layer.adjustment_info = vec!();
+        }
+        "brit" =>
+        {
+            let mut data = vec!();
+            data.push(read_u16(cursor)? as f32); // brightness
+            data.push(read_u16(cursor)? as f32); // contrast
+            data.push(read_u16(cursor)? as f32); // "Mean value for brightness and contrast"
+            data.push(read_u8(cursor)? as f32); // "Lab color only"
+            data.push(1.0); // legacy mode
+            layer.adjustment_type = name.to_string();
+            layer.adjustment_info = data;
+        }
+        "thrs" =>
+        {
+            let mut data = vec!();
+            data.push(read_u16(cursor)? as f32);
+            layer.adjustment_type = name.to_string();
+            layer.adjustment_info = data;
+        }
+        "hue2" =>
+        {
+            let mut data = vec!();
+            
+            //assert!(read_u16(cursor)? == 2);
+            read_u16(cursor)?; // version
+            data.push(read_u8(cursor)? as f32); // if 1, is absolute/colorization (rather than relative)
+            read_u8(cursor)?;
+            
+            // "colorization"
+            data.push(read_u16(cursor)? as i16 as f32); // hue
+            data.push(read_u16(cursor)? as i16 as f32); // sat
+            data.push(read_u16(cursor)? as i16 as f32); // lightness (-1 to +1)
             
-            match name.as_str()
+            // "master"
+            data.push(read_u16(cursor)? as i16 as f32); // hue
+            data.push(read_u16(cursor)? as i16 as f32); // sat
+            data.push(read_u16(cursor)? as i16 as f32); // lightness (-1 to +1)
+            
+            // todo: read hextant values?
+            
+            layer.adjustment_type = name.to_string();
+            layer.adjustment_info = data;
+        }
+        "levl" =>
+        {
+            let mut data = vec!();
+            
+            expect_u16(cursor, 2, "levels version")?;
+            for _ in 0..28
             {
-                "lsct" =>
-                {
-                    let kind = read_u32(&mut cursor) as u64;
-                    layer.group_expanded = kind == 1;
-                    layer.group_opener = kind == 1 || kind == 2;
-                    layer.group_closer = kind == 3;
-                    if kind == 1 || kind == 2
-                    {
-                        println!("group opener!");
-                    }
-                    if kind == 3
-                    {
-                        println!("group closer!");
-                    }
-                }
-                "luni" =>
-                {
-                    let len = read_u32(&mut cursor) as u64;
-                    let mut name = vec![0; len as usize];
-                    for i in 0..len
-                    {
-                        name[i as usize] = read_u16(&mut cursor);
-                    }
-                    layer.name = String::from_utf16_lossy(&name).to_string();
-                }
-                "tsly" =>
-                {
-                    let thing = read_u8(&mut cursor);
-                    layer.funny_flag = thing == 0;
-                    println!("{}", layer.funny_flag);
-                }
-                "iOpa" =>
-                {
-                    layer.fill_opacity = read_u8(&mut cursor) as f32 / 255.0;
-                }
-                "lfx2" =>
-                {
-                    assert!(read_u32(&mut cursor) == 0);
-                    assert!(read_u32(&mut cursor) == 16);
-                    layer.effects_desc = Some(read_descriptor(&mut cursor));
-                }
-                // adjustment layers
-                "post" =>
-                {
-                    let mut data = vec!();
-                    data.push(read_u16(&mut cursor) as f32); // number of levels
-                    layer.adjustment_type = name.clone();
-                    layer.adjustment_info = data;
-                }
-                "nvrt" =>
-                {
-                    layer.adjustment_type = name.clone();
-                    layer.adjustment_info = vec!();
-                }
-                "brit" =>
-                {
-                    let mut data = vec!();
-                    data.push(read_u16(&mut cursor) as f32); // brightness
-                    data.push(read_u16(&mut cursor) as f32); // contrast
-                    data.push(read_u16(&mut cursor) as f32); // "Mean value for brightness and contrast"
-                    data.push(read_u8(&mut cursor) as f32); // "Lab color only"
-                    data.push(1.0); // legacy mode
-                    layer.adjustment_type = name.clone();
-                    layer.adjustment_info = data;
-                }
-                "thrs" =>
-                {
-                    let mut data = vec!();
-                    data.push(read_u16(&mut cursor) as f32);
-                    layer.adjustment_type = name.clone();
-                    layer.adjustment_info = data;
-                }
-                "hue2" =>
-                {
-                    let mut data = vec!();
-                    
-                    //assert!(read_u16(&mut cursor) == 2);
-                    read_u16(&mut cursor); // version
-                    data.push(read_u8(&mut cursor) as f32); // if 1, is absolute/colorization (rather than relative)
-                    read_u8(&mut cursor);
-                    
-                    // "colorization"
-                    data.push(read_u16(&mut cursor) as i16 as f32); // hue
-                    data.push(read_u16(&mut cursor) as i16 as f32); // sat
-                    data.push(read_u16(&mut cursor) as i16 as f32); // lightness (-1 to +1)
-                    
-                    // "master"
-                    data.push(read_u16(&mut cursor) as i16 as f32); // hue
-                    data.push(read_u16(&mut cursor) as i16 as f32); // sat
-                    data.push(read_u16(&mut cursor) as i16 as f32); // lightness (-1 to +1)
-                    
-                    // todo: read hextant values?
-                    
-                    layer.adjustment_type = name.clone();
-                    layer.adjustment_info = data;
-                }
-                "levl" =>
+                data.push(read_u16(cursor)? as f32 / 255.0); // in floor
+                data.push(read_u16(cursor)? as f32 / 255.0); // in ceil
+                data.push(read_u16(cursor)? as f32 / 255.0); // out floor
+                data.push(read_u16(cursor)? as f32 / 255.0); // out ceil
+                data.push(read_u16(cursor)? as f32 / 100.0); // gamma
+            }
+            layer.adjustment_type = name.to_string();
+            layer.adjustment_info = data;
+        }
+        "curv" =>
+        {
+            let mut data = vec!();
+            
+            read_u8(cursor)?;
+            expect_u16(cursor, 1, "curves version")?;
+            let enabled = read_u32(cursor)?;
+            
+            for i in 0..32
+            {
+                if (enabled & (1u32 << i)) != 0
                 {
-                    let mut data = vec!();
-                    
-                    assert!(read_u16(&mut cursor) == 2);
-                    for _ in 0..28
+                    let n = read_u16(cursor)?;
+                    data.push(n as f32); // number of points
+                    for _ in 0..n
                     {
-                        data.push(read_u16(&mut cursor) as f32 / 255.0); // in floor
-                        data.push(read_u16(&mut cursor) as f32 / 255.0); // in ceil
-                        data.push(read_u16(&mut cursor) as f32 / 255.0); // out floor
-                        data.push(read_u16(&mut cursor) as f32 / 255.0); // out ceil
-                        data.push(read_u16(&mut cursor) as f32 / 100.0); // gamma
+                        let y = read_u16(cursor)? as f32 / 255.0;
+                        data.push(read_u16(cursor)? as f32 / 255.0); // x
+                        data.push(y); // y
                     }
-                    layer.adjustment_type = name.clone();
-                    layer.adjustment_info = data;
                 }
-                "curv" =>
+                else
                 {
-                    let mut data = vec!();
-                    
-                    read_u8(&mut cursor);
-                    assert!(read_u16(&mut cursor) == 1);
-                    let enabled = read_u32(&mut cursor);
-                    
-                    for i in 0..32
-                    {
-                        if (enabled & (1u32 << i)) != 0
-                        {
-                            let n = read_u16(&mut cursor);
-                            data.push(n as f32); // number of points
-                            for _ in 0..n
-                            {
-                                let y = read_u16(&mut cursor) as f32 / 255.0;
-                                data.push(read_u16(&mut cursor) as f32 / 255.0); // x
-                                data.push(y); // y
-                            }
-                        }
-                        else
-                        {
-                            data.push(0.0); // number of points
-                        }
-                    }
-                    layer.adjustment_type = name.clone();
-                    layer.adjustment_info = data;
+                    data.push(0.0); // number of points
                 }
-                "blwh" =>
+            }
+            layer.adjustment_type = name.to_string();
+            layer.adjustment_info = data;
+        }
+        "grdm" =>
+        {
+            // reversed, dithered, color stops, alpha stops, then the noise settings
+            let mut data = vec!();
+            
+            expect_u16(cursor, 1, "gradient map version")?;
+            data.push(read_u8(cursor)? as f32); // reversed
+            data.push(read_u8(cursor)? as f32); // dithered
+            read_unicode_string(cursor)?; // gradient name
+            
+            let color_count = read_u16(cursor)?;
+            data.push(color_count as f32);
+            for _ in 0..color_count
+            {
+                let location = read_u32(cursor)? as f32 / 4096.0;
+                let midpoint = read_u32(cursor)? as f32 / 100.0;
+                data.extend(read_psd_color(cursor)?);
+                data.push(location);
+                data.push(midpoint);
+                read_u16(cursor)?;
+            }
+            let alpha_count = read_u16(cursor)?;
+            data.push(alpha_count as f32);
+            for _ in 0..alpha_count
+            {
+                let location = read_u32(cursor)? as f32 / 4096.0;
+                let midpoint = read_u32(cursor)? as f32 / 100.0;
+                data.push(read_u16(cursor)? as f32 / 255.0); // opacity
+                data.push(location);
+                data.push(midpoint);
+            }
+            
+            expect_u16(cursor, 2, "gradient map expansion count")?;
+            read_u16(cursor)?; // smoothness
+            expect_u16(cursor, 32, "gradient map noise settings length")?;
+            data.push(read_u16(cursor)? as f32); // 1 if noise
+            let seed = read_u32(cursor)?;
+            data.push((seed >> 16) as f32);
+            data.push((seed & 0xFFFF) as f32);
+            data.push(read_u16(cursor)? as f32); // show transparency
+            read_u16(cursor)?; // restrict colors
+            data.push(read_u32(cursor)? as f32 / 4096.0); // roughness
+            data.push(read_u16(cursor)? as f32); // color model
+            for _ in 0..8 // minimum and maximum channel values, in percent
+            {
+                data.push(read_u16(cursor)? as f32 / 32768.0 * 100.0);
+            }
+            
+            layer.adjustment_type = name.to_string();
+            layer.adjustment_info = data;
+        }
+        "phfl" =>
+        {
+            let mut data = vec!();
+            
+            let version = read_u16(cursor)?;
+            match version
+            {
+                3 =>
                 {
-                    assert!(read_u32(&mut cursor) == 16);
-                    layer.adjustment_type = name.clone();
-                    layer.adjustment_desc = Some(read_descriptor(&mut cursor));
+                    let l = read_i32(cursor)? as f64 / 100.0;
+                    let a = read_i32(cursor)? as f64 / 100.0;
+                    let b = read_i32(cursor)? as f64 / 100.0;
+                    data.extend(crate::pixelmath::lab_to_rgb(l, a, b).map(|x| x as f32));
                 }
-                "CgEd" =>
+                2 => data.extend(read_psd_color(cursor)?),
+                _ => return Err(PsdError::Malformed(pos, "photo filter version")),
+            }
+            data.push(read_u32(cursor)? as f32); // density
+            data.push(read_u8(cursor)? as f32); // preserve luminosity
+            
+            layer.adjustment_type = name.to_string();
+            layer.adjustment_info = data;
+        }
+        "expA" =>
+        {
+            let mut data = vec!();
+            
+            expect_u16(cursor, 1, "exposure version")?;
+            data.push(read_f32(cursor)?); // exposure
+            data.push(read_f32(cursor)?); // offset
+            data.push(read_f32(cursor)?); // gamma
+            
+            layer.adjustment_type = name.to_string();
+            layer.adjustment_info = data;
+        }
+        "mixr" =>
+        {
+            let mut data = vec!();
+            
+            expect_u16(cursor, 1, "channel mixer version")?;
+            data.push(read_u16(cursor)? as f32); // monochrome
+            // red, green and blue outputs: weights for each input channel (plus an unused CMYK one) and a constant, in percent.
+            // monochrome output uses the first set
+            for _ in 0..3
+            {
+                for i in 0..5
                 {
-                    assert!(read_u32(&mut cursor) == 16);
-                    //layer.adjustment_type = name.clone();
-                    //layer.adjustment_type = "brit".to_string();
-                    let temp = read_descriptor(&mut cursor).1;
-                    println!("{:?}", temp);
-                    let mut n = HashMap::new();
-                    for t in temp
+                    let n = read_u16(cursor)? as i16 as f32;
+                    if i != 3
                     {
-                        n.insert(t.0, t.1);
+                        data.push(n);
                     }
-                    println!("{:?}", n);
-                    //("null", [("Vrsn", long(1)), ("Brgh", long(9)), ("Cntr", long(30)), ("means", long(127)), ("Lab ", bool(false)), ("useLegacy", bool(true)), ("Auto", bool(true))])
-                    let mut data = vec!();
-                    data.push(n.get("Brgh").unwrap().long() as f32);
-                    data.push(n.get("Cntr").unwrap().long() as f32);
-                    data.push(n.get("means").unwrap().long() as f32);
-                    data.push(n.get("Lab ").unwrap().bool() as u8 as f32);
-                    data.push(n.get("useLegacy").unwrap().bool() as u8 as f32);
-                    println!("??????????? {:?}", data);
-                    layer.adjustment_info = data;
                 }
-                _ => {}
             }
-            cursor.set_position(start + len);
+            
+            layer.adjustment_type = name.to_string();
+            layer.adjustment_info = data;
         }
-        //println!("{:X} {:X}", cursor.position(), exdat_start + exdat_len);
-        assert!(cursor.position() == exdat_start + exdat_len);
-        
-        println!("added layer with name {}", layer.name);
-        layers.push(layer);
-    }
-    
-    layers
-}
-
-#[derive(Debug, PartialEq)]
-pub struct PsdMetadata {
-    pub width: u32,
-    pub height: u32,
-    pub color_mode: u16,
-    pub depth: u16,
-    pub channel_count: u16,
+        "blnc" =>
+        {
+            let mut data = vec!();
+            
+            // shadows, midtones, highlights: cyan-red, magenta-green, yellow-blue
+            for _ in 0..9
+            {
+                data.push(read_u16(cursor)? as i16 as f32);
+            }
+            data.push(read_u8(cursor)? as f32); // preserve luminosity
+            
+            layer.adjustment_type = name.to_string();
+            layer.adjustment_info = data;
+        }
+        "selc" =>
+        {
+            let mut data = vec!();
+            
+            expect_u16(cursor, 1, "selective color version")?;
+            data.push(read_u16(cursor)? as f32); // 1 if absolute
+            // reserved, then reds, yellows, greens, cyans, blues, magentas, whites, neutrals, blacks: cyan, magenta, yellow, black in percent
+            for _ in 0..4
+            {
+                read_u16(cursor)?;
+            }
+            for _ in 0..9 * 4
+            {
+                data.push(read_u16(cursor)? as i16 as f32);
+            }
+            
+            layer.adjustment_type = name.to_string();
+            layer.adjustment_info = data;
+        }
+        "vibA" =>
+        {
+            expect_u32(cursor, 16, "vibrance descriptor version")?;
+            layer.adjustment_desc = Some(read_descriptor(cursor)?);
+            layer.adjustment_type = name.to_string();
+        }
+        "blwh" =>
+        {
+            expect_u32(cursor, 16, "black and white descriptor version")?;
+            layer.adjustment_desc = Some(read_descriptor(cursor)?);
+            layer.adjustment_type = name.to_string();
+        }
+        "CgEd" =>
+        {
+            expect_u32(cursor, 16, "brightness/contrast descriptor version")?;
+            // always set together with the data, so they can't disagree if another adjustment block came first
+            layer.adjustment_type = "brit".to_string();
+            let temp = read_descriptor(cursor)?.1;
+            println!("{:?}", temp);
+            let mut n = HashMap::new();
+            for t in temp
+            {
+                n.insert(t.0, t.1);
+            }
+            println!("{:?}", n);
+            //("null", [("Vrsn", long(1)), ("Brgh", long(9)), ("Cntr", long(30)), ("means", long(127)), ("Lab ", bool(false)), ("useLegacy", bool(true)), ("Auto", bool(true))])
+            let get = |key : &str| match n.get(key)
+            {
+                Some(DescItem::long(x)) => Ok(*x as f32),
+                Some(DescItem::bool(x)) => Ok(*x as u8 as f32),
+                _ => Err(PsdError::Malformed(pos, "brightness/contrast descriptor")),
+            };
+            let data = vec!(get("Brgh")?, get("Cntr")?, get("means")?, get("Lab ")?, get("useLegacy")?);
+            println!("??????????? {:?}", data);
+            layer.adjustment_info = data;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+pub fn parse_layer_records(data : &[u8]) -> Result<Vec<LayerInfo>, PsdError>
+{
+    let metadata = parse_psd_metadata(data)?;
+    let depth = metadata.depth;
+    let psb = metadata.version == 2;
+    
+    let mut cursor = Cursor::new(&data[..]);
+    cursor.set_position(26);
+    
+    let color_mode_length = read_u32(&mut cursor)? as u64;
+    cursor.set_position(cursor.position() + color_mode_length);
+    
+    let image_resources_length = read_u32(&mut cursor)? as u64;
+    cursor.set_position(cursor.position() + image_resources_length);
+    
+    let layer_mask_info_length = read_len(&mut cursor, psb)?;
+    let layer_mask_info_end = cursor.position() + layer_mask_info_length;
+    if layer_mask_info_length == 0
+    {
+        return Ok(vec!());
+    }
+    
+    let layer_info_length = read_len(&mut cursor, psb)?;
+    let layer_info_end = cursor.position() + layer_info_length;
+    
+    if layer_info_length == 0
+    {
+        // 16-bit and 32-bit files keep their layer info in a tagged block after the global layer mask info instead
+        cursor.set_position(layer_info_end);
+        let global_mask_length = read_u32(&mut cursor)? as u64;
+        cursor.set_position(cursor.position() + global_mask_length);
+        
+        let mut found = false;
+        while cursor.position() + 12 <= layer_mask_info_end
+        {
+            let mut sig = [0; 4];
+            read_exact(&mut cursor, &mut sig)?;
+            let mut name = [0; 4];
+            read_exact(&mut cursor, &mut name)?;
+            let len = read_block_len(&mut cursor, &name, psb)?;
+            if &name == b"Lr16" || &name == b"Lr32" || &name == b"Layr"
+            {
+                found = true;
+                break;
+            }
+            cursor.set_position(cursor.position() + len);
+        }
+        if !found
+        {
+            return Ok(vec!());
+        }
+    }
+    
+    let layer_count = read_u16(&mut cursor)? as i16;
+    let layer_count = layer_count.abs(); // If negative, transparency info exists
+    
+    println!("starting at {:X}", cursor.position());
+    
+    let mut idata_c = Cursor::new(&data[..]);
+    idata_c.set_position(cursor.position());
+    
+    for _i in 0..layer_count
+    {
+        //println!("{}", _i);
+        read_i32(&mut idata_c)?;
+        read_i32(&mut idata_c)?;
+        read_i32(&mut idata_c)?;
+        read_i32(&mut idata_c)?;
+        let image_channel_count = read_u16(&mut idata_c)? as u64;
+        let channel_info_size = if psb { 10 } else { 6 };
+        idata_c.set_position(idata_c.position() + channel_info_size*image_channel_count + 4 + 4 + 4);
+        let idat_len = read_u32(&mut idata_c)? as u64;
+        idata_c.set_position(idata_c.position() + idat_len);
+    }
+
+    let mut layers = Vec::new();
+    let mut claimed_channel_length = 0;
+    
+    for _ in 0..layer_count
+    {
+        let record_start = cursor.position();
+        let top = read_i32(&mut cursor)?;
+        let left = read_i32(&mut cursor)?;
+        let bottom = read_i32(&mut cursor)?;
+        let right = read_i32(&mut cursor)?;
+        if right < left || bottom < top
+        {
+            return Err(PsdError::Malformed(record_start, "layer bounds"));
+        }
+        
+        let x = left;
+        let y = top;
+        let w = (right as i64 - left as i64) as u32;
+        let h = (bottom as i64 - top as i64) as u32;
+        
+        let image_channel_count = read_u16(&mut cursor)?;
+        //println!("chan count {}", image_channel_count);
+        
+        let channel_info_start = cursor.position();
+        
+        cursor.set_position(channel_info_start);
+        let mut image_data_k : Vec<u8> = vec!();
+        let mut image_data_mask : Vec<u8> = vec!();
+        
+        let mut _rgba_count = 0;
+        let mut has_g = false;
+        let mut has_b = false;
+        let mut has_a = false;
+        let mut aux_count = 0;
+        
+        let mut cdat_cursor = cursor.clone();
+        
+        let mut has_neg2 = false;
+        let mut has_neg3 = false;
+        let mut total_channel_length = 0;
+        for _ in 0..image_channel_count
+        {
+            let channel_id = read_u16(&mut cursor)? as i16;
+            let channel_length = read_len(&mut cursor, psb)?;
+            has_neg2 = has_neg2 || channel_id == -2;
+            has_neg3 = has_neg3 || channel_id == -3;
+            total_channel_length += channel_length;
+        }
+        
+        claimed_channel_length += total_channel_length;
+        if claimed_channel_length > data.len() as u64
+        {
+            return Err(PsdError::Malformed(record_start, "channel lengths"));
+        }
+        // packbits can't compress better than 64:1, so anything bigger than this can't be real
+        if w as u64 * h as u64 > total_channel_length * 64 + 0x10000
+        {
+            return Err(PsdError::Malformed(record_start, "layer size"));
+        }
+        let mut image_data_rgba : Vec<u8> = if depth == 8 { vec![255u8; w as usize * h as usize * 4] } else { vec!() };
+        let mut image_data_float : Vec<f32> = if depth != 8 { vec![1.0; w as usize * h as usize * 4] } else { vec!() };
+        
+        let blend_mode_signature_pos = cursor.position();
+        let mut blend_mode_signature = [0; 4];
+        read_exact(&mut cursor, &mut blend_mode_signature)?;
+        if blend_mode_signature != [0x38, 0x42, 0x49, 0x4D]
+        {
+            return Err(PsdError::BadSignature(blend_mode_signature_pos));
+        }
+        
+        let mut blend_mode_key = [0; 4];
+        read_exact(&mut cursor, &mut blend_mode_key)?;
+        let blend_mode = String::from_utf8_lossy(&blend_mode_key).to_string();
+        
+        let opacity = read_u8(&mut cursor)? as f32 / 255.0;
+        println!("opacity: {}", opacity * 100.0);
+        let clipping = read_u8(&mut cursor)?;
+        let flags = read_u8(&mut cursor)?;
+        let _filler = read_u8(&mut cursor)?;
+        
+        let exdat_len = read_u32(&mut cursor)? as u64;
+        let exdat_start = cursor.position();
+        
+        let maskdat_len = read_u32(&mut cursor)? as u64;
+        let maskdat_start = cursor.position();
+        
+        let mut mask_info = MaskInfo::default();
+        if maskdat_len >= 18
+        {
+            let mtop = read_i32(&mut cursor)?;
+            let mleft = read_i32(&mut cursor)?;
+            let mbottom = read_i32(&mut cursor)?;
+            let mright = read_i32(&mut cursor)?;
+            mask_info.x = mleft;
+            mask_info.y = mtop;
+            mask_info.w = mright.wrapping_sub(mleft) as u32;
+            mask_info.h = mbottom.wrapping_sub(mtop) as u32;
+            mask_info.default_color = read_u8(&mut cursor)?;
+            let mflags = read_u8(&mut cursor)?;
+            mask_info.relative = (mflags & 1) != 0;
+            mask_info.disabled = (mflags & 2) != 0;
+            mask_info.invert = (mflags & 4) != 0;
+        }
+        
+        cursor.set_position(maskdat_start + maskdat_len);
+        
+        for _ in 0..image_channel_count
+        {
+            let channel_id = read_u16(&mut cdat_cursor)? as i16;
+            has_g |= channel_id == 1;
+            has_b |= channel_id == 2;
+            has_a |= channel_id == -1;
+            let channel_length = read_len(&mut cdat_cursor, psb)? as usize;
+            println!("channel... {} {} at 0x{:X}", channel_id, channel_length, idata_c.position());
+            if channel_id >= -1 && channel_id <= 2
+            {
+                _rgba_count += 1;
+                let pos = if channel_id >= 0 { channel_id } else { 3 } as usize;
+                println!("{} {} {} {}", w, h, pos, channel_length);
+                if channel_length > 2 && depth != 8
+                {
+                    let start = idata_c.position();
+                    let mut raw = vec!();
+                    append_img_data(&mut idata_c, &mut raw, channel_length as u64, h as u64, psb)?;
+                    idata_c.set_position(start + channel_length as u64);
+                    for (i, sample) in decode_samples(&raw, depth).into_iter().take(w as usize * h as usize).enumerate()
+                    {
+                        image_data_float[i*4 + pos] = sample;
+                    }
+                }
+                else if channel_length > 2
+                {
+                    let pos = pos.min(image_data_rgba.len());
+                    copy_img_data(&mut idata_c, &mut image_data_rgba[pos..], 4, channel_length as u64, h as u64, psb)?;
+                }
+                else
+                {
+                    idata_c.set_position(idata_c.position() + 2);
+                }
+            }
+            else if channel_id == 3 // CMYK's K
+            {
+                if channel_length > 2
+                {
+                    let start = idata_c.position();
+                    let mut raw = vec!();
+                    append_img_data(&mut idata_c, &mut raw, channel_length as u64, h as u64, psb)?;
+                    idata_c.set_position(start + channel_length as u64);
+                    image_data_k = decode_samples_to_u8(&raw, depth);
+                }
+                else
+                {
+                    idata_c.set_position(idata_c.position() + 2);
+                }
+            }
+            else
+            {
+                println!("mask... {} {} {}", mask_info.w, mask_info.h, channel_length);
+                aux_count += 1;
+                if aux_count > 1
+                {
+                    idata_c.set_position(idata_c.position() + channel_length as u64);
+                }
+                else if channel_length > 2
+                {
+                    println!("adding mask data...");
+                    let start = idata_c.position();
+                    let mut raw = vec!();
+                    append_img_data(&mut idata_c, &mut raw, channel_length as u64, mask_info.h as u64, psb)?;
+                    idata_c.set_position(start + channel_length as u64);
+                    image_data_mask = decode_samples_to_u8(&raw, depth);
+                }
+                else
+                {
+                    idata_c.set_position(idata_c.position() + 2);
+                }
+            }
+        }
+        
+        let blendat_len = read_u32(&mut cursor)? as u64;
+        cursor.set_position(cursor.position() + blendat_len);
+        
+        let mut name_len = read_u8(&mut cursor)? as usize;
+        let orig_namelen = name_len;
+        while (name_len + 1) % 4 != 0
+        {
+            name_len += 1;
+        }
+        let mut name = vec![0; name_len];
+        read_exact(&mut cursor, &mut name[..])?;
+        let name = String::from_utf8_lossy(&name[..orig_namelen]).to_string();
+        
+        let mut layer = LayerInfo {
+            offset : record_start,
+            layer_id : 0,
+            name,
+            opacity,
+            fill_opacity : 1.0,
+            blend_mode,
+            x,
+            y,
+            w,
+            h,
+            image_channel_count,
+            depth,
+            image_data_rgba,
+            image_data_float,
+            image_data_k,
+            image_data_has_g : has_g,
+            image_data_has_b : has_b,
+            image_data_has_a : has_a,
+            mask_channel_count : aux_count,
+            mask_info,
+            image_data_mask,
+            group_expanded : false,
+            group_opener : false,
+            group_closer : false,
+            funny_flag : false,
+            is_clipped : clipping != 0,
+            is_alpha_locked : (flags & 1) != 0,
+            is_visible : (flags & 2) == 0,
+            adjustment_type : "".to_string(),
+            adjustment_info : vec!(),
+            adjustment_desc : None,
+            effects_desc : None,
+            smart_object : None,
+            vector_mask : None,
+            text : None,
+        };
+        
+        //println!("--- {:X}", cursor.position());
+        
+        while cursor.position() < exdat_start + exdat_len
+        {
+            let mut sig = [0; 4];
+            read_exact(&mut cursor, &mut sig)?;
+            if sig != [0x38, 0x42, 0x49, 0x4D] && sig != [0x38, 0x42, 0x36, 0x34]
+            {
+                return Err(PsdError::BadSignature(cursor.position() - 4));
+            }
+            
+            let mut name = [0; 4];
+            read_exact(&mut cursor, &mut name)?;
+            let len = read_block_len(&mut cursor, &name, psb)?;
+            let name = String::from_utf8_lossy(&name).to_string();
+            //println!("?? {}", len);
+            let start = cursor.position();
+            
+            println!("reading metadata.... {}", name.as_str());
+            
+            if let Err(e) = parse_layer_block(&mut cursor, &name, start + len, &mut layer)
+            {
+                // a broken block in an otherwise-intact file only loses that block
+                if start + len > data.len() as u64
+                {
+                    return Err(e);
+                }
+                println!("skipping unreadable {} block: {}", name, e);
+            }
+            cursor.set_position(start + len);
+        }
+        //println!("{:X} {:X}", cursor.position(), exdat_start + exdat_len);
+        if cursor.position() != exdat_start + exdat_len
+        {
+            return Err(PsdError::Malformed(exdat_start, "layer extra data length"));
+        }
+        
+        convert_to_rgb(&metadata, &mut layer);
+        
+        println!("added layer with name {}", layer.name);
+        layers.push(layer);
+    }
+    
+    // empty channels get skipped over without being read, so they need to be checked for truncation separately
+    if idata_c.position() > data.len() as u64
+    {
+        return Err(PsdError::UnexpectedEof(data.len() as u64));
+    }
+    
+    Ok(layers)
+}
+
+// the flattened image stored at the end of the file, as a single opaque layer
+pub fn parse_merged_image(data : &[u8]) -> Result<LayerInfo, PsdError>
+{
+    let metadata = parse_psd_metadata(data)?;
+    let depth = metadata.depth;
+    let psb = metadata.version == 2;
+    
+    let mut cursor = Cursor::new(data);
+    cursor.set_position(26);
+    // a negative layer count means the first alpha channel holds the merged transparency
+    let mut has_transparency = false;
+    for i in 0..3 // color mode data, image resources, layer and mask info
+    {
+        let len = if i == 2 { read_len(&mut cursor, psb)? } else { read_u32(&mut cursor)? as u64 };
+        let start = cursor.position();
+        if i == 2 && len >= if psb { 10 } else { 6 }
+        {
+            read_len(&mut cursor, psb)?;
+            has_transparency = (read_u16(&mut cursor)? as i16) < 0;
+        }
+        cursor.set_position(start + len);
+    }
+    
+    let w = metadata.width as usize;
+    let h = metadata.height as usize;
+    let color_channels = match metadata.color_mode { 3 => 3, 4 => 4, _ => 1 };
+    let channel_count = (metadata.channel_count as usize).min(color_channels + has_transparency as usize);
+    let row_len = w * depth as usize / 8;
+    if w as u64 * h as u64 > data.len() as u64 * 64 + 0x10000
+    {
+        return Err(PsdError::Malformed(14, "image size"));
+    }
+    
+    let mut channels = vec!();
+    let compression = read_u16(&mut cursor)?;
+    if compression == 0
+    {
+        for _ in 0..channel_count
+        {
+            channels.push(read_vec(&mut cursor, (row_len * h) as u64)?);
+        }
+    }
+    else if compression == 1
+    {
+        let mut counts = vec!();
+        for _ in 0..metadata.channel_count as usize * h
+        {
+            counts.push(read_row_len(&mut cursor, psb)? as usize);
+        }
+        for c in 0..channel_count
+        {
+            let mut raw = vec!();
+            for y in 0..h
+            {
+                let row = read_vec(&mut cursor, counts[c * h + y] as u64)?;
+                packbits_decode(&row, &mut raw);
+                raw.resize(row_len * (y + 1), 0);
+            }
+            channels.push(raw);
+        }
+    }
+    else
+    {
+        return Err(PsdError::UnsupportedCompression(cursor.position() - 2, compression));
+    }
+    
+    let mut layer = LayerInfo {
+        name : "Background".to_string(),
+        opacity : 1.0,
+        fill_opacity : 1.0,
+        blend_mode : "norm".to_string(),
+        w : w as u32,
+        h : h as u32,
+        image_channel_count : channel_count as u16,
+        depth,
+        is_visible : true,
+        ..Default::default()
+    };
+    if depth == 8
+    {
+        layer.image_data_rgba = vec![255; w * h * 4];
+    }
+    else
+    {
+        layer.image_data_float = vec![1.0; w * h * 4];
+    }
+    for (c, raw) in channels.into_iter().enumerate()
+    {
+        let c = if c == color_channels { 3 } else { c };
+        if c == 3 && color_channels == 4
+        {
+            layer.image_data_k = decode_samples_to_u8(&raw, depth);
+        }
+        else if depth == 8
+        {
+            for (i, sample) in raw.into_iter().take(w * h).enumerate()
+            {
+                layer.image_data_rgba[i*4 + c] = sample;
+            }
+        }
+        else
+        {
+            for (i, sample) in decode_samples(&raw, depth).into_iter().take(w * h).enumerate()
+            {
+                layer.image_data_float[i*4 + c] = sample;
+            }
+        }
+    }
+    convert_to_rgb(&metadata, &mut layer);
+    
+    // the merged colors are matted against white wherever they're transparent
+    if has_transparency
+    {
+        for px in layer.image_data_rgba.chunks_exact_mut(4)
+        {
+            let a = px[3] as f32 / 255.0;
+            for c in px[..3].iter_mut()
+            {
+                *c = if a > 0.0 { ((*c as f32 / 255.0 - (1.0 - a)) / a * 255.0).round().clamp(0.0, 255.0) as u8 } else { 0 };
+            }
+        }
+        for px in layer.image_data_float.chunks_exact_mut(4)
+        {
+            let a = px[3];
+            for c in px[..3].iter_mut()
+            {
+                *c = if a > 0.0 { ((*c - (1.0 - a)) / a).clamp(0.0, 1.0) } else { 0.0 };
+            }
+        }
+    }
+    
+    Ok(layer)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PsdPattern
+{
+    pub id : String,
+    pub name : String,
+    pub w : u32,
+    pub h : u32,
+    pub image_data_rgba : Vec<u8>,
+}
+
+// one pattern from a Patt/Pat2/Pat3 block. cursor starts after the pattern's length
+fn parse_pattern(cursor : &mut Cursor<&[u8]>) -> Result<PsdPattern, PsdError>
+{
+    expect_u32(cursor, 1, "pattern version")?;
+    let color_mode = read_u32(cursor)? as u16;
+    let h = read_u16(cursor)? as u32;
+    let w = read_u16(cursor)? as u32;
+    let name = read_unicode_string(cursor)?;
+    let id_len = read_u8(cursor)? as u64;
+    let id = String::from_utf8_lossy(&read_vec(cursor, id_len)?).to_string();
+    let mut palette = vec!();
+    if color_mode == 2
+    {
+        for c in read_vec(cursor, 768)?.chunks_exact(3)
+        {
+            palette.push([c[0], c[1], c[2]]);
+        }
+    }
+    
+    // virtual memory array list
+    expect_u32(cursor, 3, "pattern data version")?;
+    let _len = read_u32(cursor)?;
+    for _ in 0..4
+    {
+        read_u32(cursor)?;
+    }
+    let channel_count = read_u32(cursor)?;
+    if w as u64 * h as u64 > cursor.get_ref().len() as u64 * 64 + 0x10000
+    {
+        return Err(PsdError::Malformed(cursor.position(), "pattern size"));
+    }
+    
+    // written channels are the color channels followed by transparency, if any
+    let mut channels = vec!();
+    for _ in 0..channel_count + 2
+    {
+        if read_u32(cursor)? == 0
+        {
+            continue;
+        }
+        let len = read_u32(cursor)? as u64;
+        if len == 0
+        {
+            continue;
+        }
+        let end = cursor.position() + len;
+        let depth = read_u32(cursor)? as u16;
+        let top = read_u32(cursor)?;
+        let left = read_u32(cursor)?;
+        let bottom = read_u32(cursor)?;
+        let right = read_u32(cursor)?;
+        let _depth = read_u16(cursor)?;
+        let compression = read_u8(cursor)?;
+        
+        let cw = right.saturating_sub(left) as usize;
+        let ch = bottom.saturating_sub(top) as usize;
+        let row_len = cw * depth.max(8) as usize / 8;
+        let mut raw = vec!();
+        if compression == 0
+        {
+            raw = read_vec(cursor, (row_len * ch) as u64)?;
+        }
+        else if compression == 1
+        {
+            let mut counts = vec!();
+            for _ in 0..ch
+            {
+                counts.push(read_u16(cursor)? as u64);
+            }
+            for (y, count) in counts.into_iter().enumerate()
+            {
+                packbits_decode(&read_vec(cursor, count)?, &mut raw);
+                raw.resize(row_len * (y + 1), 0);
+            }
+        }
+        else
+        {
+            return Err(PsdError::UnsupportedCompression(cursor.position() - 1, compression as u16));
+        }
+        cursor.set_position(end);
+        
+        let mut samples = decode_samples_to_u8(&raw, depth);
+        samples.resize(cw * ch, 0);
+        // channel rects can be smaller than the pattern
+        let mut channel = vec![0; (w * h) as usize];
+        for y in 0..ch.min((h as usize).saturating_sub(top as usize))
+        {
+            for x in 0..cw.min((w as usize).saturating_sub(left as usize))
+            {
+                channel[(y + top as usize) * w as usize + x + left as usize] = samples[y * cw + x];
+            }
+        }
+        channels.push(channel);
+    }
+    
+    let color_channels = match color_mode { 3 => 3, 4 => 4, _ => 1 };
+    let mut layer = LayerInfo {
+        w,
+        h,
+        depth : 8,
+        image_data_rgba : vec![255; (w * h * 4) as usize],
+        ..Default::default()
+    };
+    for (c, channel) in channels.into_iter().enumerate()
+    {
+        if c == 3 && color_channels == 4
+        {
+            layer.image_data_k = channel;
+            continue;
+        }
+        let c = if c >= color_channels { 3 } else { c };
+        for (i, sample) in channel.into_iter().enumerate()
+        {
+            layer.image_data_rgba[i*4 + c] = sample;
+        }
+    }
+    let metadata = PsdMetadata { version : 1, width : w, height : h, color_mode, depth : 8, channel_count : color_channels as u16, palette };
+    convert_to_rgb(&metadata, &mut layer);
+    
+    Ok(PsdPattern { id, name, w, h, image_data_rgba : layer.image_data_rgba })
+}
+
+// calls f with the name, a cursor positioned at the start of the data, and the data's end, for each global tagged block
+fn walk_global_blocks<F : FnMut(&[u8; 4], &mut Cursor<&[u8]>, u64) -> Result<(), PsdError>>(data : &[u8], mut f : F) -> Result<(), PsdError>
+{
+    let metadata = parse_psd_metadata(data)?;
+    let psb = metadata.version == 2;
+    
+    let mut cursor = Cursor::new(data);
+    cursor.set_position(26);
+    for _ in 0..2 // color mode data, image resources
+    {
+        let len = read_u32(&mut cursor)? as u64;
+        cursor.set_position(cursor.position() + len);
+    }
+    
+    let layer_mask_info_length = read_len(&mut cursor, psb)?;
+    let layer_mask_info_end = cursor.position() + layer_mask_info_length;
+    if layer_mask_info_length == 0
+    {
+        return Ok(());
+    }
+    let layer_info_length = read_len(&mut cursor, psb)?;
+    cursor.set_position(cursor.position() + layer_info_length);
+    if cursor.position() + 4 > layer_mask_info_end
+    {
+        return Ok(());
+    }
+    let global_mask_length = read_u32(&mut cursor)? as u64;
+    cursor.set_position(cursor.position() + global_mask_length);
+    
+    while cursor.position() + 12 <= layer_mask_info_end
+    {
+        let mut sig = [0; 4];
+        read_exact(&mut cursor, &mut sig)?;
+        if &sig != b"8BIM" && &sig != b"8B64"
+        {
+            return Err(PsdError::BadSignature(cursor.position() - 4));
+        }
+        let mut name = [0; 4];
+        read_exact(&mut cursor, &mut name)?;
+        let len = read_block_len(&mut cursor, &name, psb)?;
+        let end = cursor.position() + len;
+        if end > layer_mask_info_end
+        {
+            return Err(PsdError::Malformed(cursor.position(), "tagged block length"));
+        }
+        f(&name, &mut cursor, end)?;
+        cursor.set_position(end);
+    }
+    
+    Ok(())
+}
+
+// patterns embedded in the global tagged blocks, used by pattern overlay effects
+pub fn parse_patterns(data : &[u8]) -> Result<Vec<PsdPattern>, PsdError>
+{
+    let mut patterns = vec!();
+    walk_global_blocks(data, |name, cursor, end|
+    {
+        if name == b"Patt" || name == b"Pat2" || name == b"Pat3"
+        {
+            while cursor.position() + 4 <= end
+            {
+                let pattern_len = read_u32(cursor)? as u64;
+                // patterns are padded to multiples of 4 bytes
+                let pattern_end = cursor.position() + ((pattern_len + 3) & !3);
+                if pattern_len == 0 || pattern_end > end + 3
+                {
+                    break;
+                }
+                match parse_pattern(cursor)
+                {
+                    Ok(pattern) => patterns.push(pattern),
+                    Err(e) => println!("skipping broken pattern: {}", e),
+                }
+                cursor.set_position(pattern_end);
+            }
+        }
+        Ok(())
+    })?;
+    
+    Ok(patterns)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PsdLinkedFile
+{
+    pub id : String,
+    pub name : String,
+    pub file_type : String,
+    pub data : Vec<u8>,
+}
+
+fn read_pascal_string(cursor : &mut Cursor<&[u8]>) -> Result<String, PsdError>
+{
+    let len = read_u8(cursor)? as u64;
+    Ok(String::from_utf8_lossy(&read_vec(cursor, len)?).to_string())
+}
+
+// one entry of a lnk2/lnkD/lnk3 block; external and alias links have no data
+fn parse_linked_file(cursor : &mut Cursor<&[u8]>) -> Result<Option<PsdLinkedFile>, PsdError>
+{
+    let mut kind = [0; 4];
+    read_exact(cursor, &mut kind)?;
+    let _version = read_u32(cursor)?;
+    let id = read_pascal_string(cursor)?;
+    let name = read_unicode_string(cursor)?;
+    let mut file_type = [0; 4];
+    read_exact(cursor, &mut file_type)?;
+    let _creator = read_u32(cursor)?;
+    let data_len = read_u64(cursor)?;
+    if read_u8(cursor)? != 0
+    {
+        expect_u32(cursor, 16, "linked file descriptor version")?;
+        read_descriptor(cursor)?;
+    }
+    if &kind != b"liFD"
+    {
+        return Ok(None);
+    }
+    let data = read_vec(cursor, data_len)?;
+    Ok(Some(PsdLinkedFile { id, name, file_type : String::from_utf8_lossy(&file_type).to_string(), data }))
+}
+
+// files embedded for smart object layers, looked up by the layers' unique ids
+pub fn parse_linked_files(data : &[u8]) -> Result<HashMap<String, PsdLinkedFile>, PsdError>
+{
+    let mut files = HashMap::new();
+    walk_global_blocks(data, |name, cursor, end|
+    {
+        if name == b"lnk2" || name == b"lnkD" || name == b"lnk3"
+        {
+            while cursor.position() + 8 <= end
+            {
+                let file_len = read_u64(cursor)?;
+                // also padded to multiples of 4 bytes
+                let file_end = cursor.position() + ((file_len + 3) & !3);
+                if file_len == 0 || file_end > end + 3
+                {
+                    break;
+                }
+                match parse_linked_file(cursor)
+                {
+                    Ok(Some(file)) => { files.insert(file.id.clone(), file); }
+                    Ok(None) => { }
+                    Err(e) => println!("skipping broken linked file: {}", e),
+                }
+                cursor.set_position(file_end);
+            }
+        }
+        Ok(())
+    })?;
+    
+    Ok(files)
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PsdMetadata {
+    pub version: u16,
+    pub width: u32,
+    pub height: u32,
+    pub color_mode: u16,
+    pub depth: u16,
+    pub channel_count: u16,
+    pub palette: Vec<[u8; 3]>,
+}
+
+pub fn write_u8(out : &mut Vec<u8>, n : u8)
+{
+    out.push(n);
+}
+
+pub fn write_u16(out : &mut Vec<u8>, n : u16)
+{
+    out.extend_from_slice(&n.to_be_bytes());
+}
+
+pub fn write_u32(out : &mut Vec<u8>, n : u32)
+{
+    out.extend_from_slice(&n.to_be_bytes());
+}
+
+pub fn write_i32(out : &mut Vec<u8>, n : i32)
+{
+    out.extend_from_slice(&n.to_be_bytes());
+}
+
+pub fn write_f32(out : &mut Vec<u8>, n : f32)
+{
+    out.extend_from_slice(&n.to_be_bytes());
+}
+
+pub fn write_f64(out : &mut Vec<u8>, n : f64)
+{
+    out.extend_from_slice(&n.to_be_bytes());
+}
+
+// overwrite a previously-reserved length field with the number of bytes written since it
+pub fn patch_u32_len(out : &mut [u8], at : usize)
+{
+    let len = (out.len() - at - 4) as u32;
+    out[at..at + 4].copy_from_slice(&len.to_be_bytes());
+}
+
+// padded so that the length byte and name together are a multiple of 4 bytes long
+pub fn write_pascal_name(out : &mut Vec<u8>, name : &str)
+{
+    let mut bytes : Vec<u8> = name.chars().map(|c| if c.is_ascii() && !c.is_ascii_control() { c as u8 } else { b'?' }).take(255).collect();
+    let len = bytes.len() as u8;
+    while (bytes.len() + 1) % 4 != 0
+    {
+        bytes.push(0);
+    }
+    out.push(len);
+    out.extend_from_slice(&bytes);
+}
+
+pub fn write_unicode_string(out : &mut Vec<u8>, text : &str)
+{
+    let text : Vec<u16> = text.encode_utf16().collect();
+    write_u32(out, text.len() as u32);
+    for c in text
+    {
+        write_u16(out, c);
+    }
+}
+
+pub fn packbits_encode(row : &[u8], out : &mut Vec<u8>)
+{
+    let mut i = 0;
+    while i < row.len()
+    {
+        let mut run = 1;
+        while i + run < row.len() && run < 128 && row[i + run] == row[i]
+        {
+            run += 1;
+        }
+        if run >= 3
+        {
+            out.push((1 - run as i32) as i8 as u8);
+            out.push(row[i]);
+            i += run;
+        }
+        else
+        {
+            let start = i;
+            while i < row.len() && i - start < 128
+            {
+                if i + 2 < row.len() && row[i] == row[i + 1] && row[i] == row[i + 2]
+                {
+                    break;
+                }
+                i += 1;
+            }
+            out.push((i - start - 1) as u8);
+            out.extend_from_slice(&row[start..i]);
+        }
+    }
+}
+
+// RLE-compressed planar channel data, including the compression mode
+pub fn write_channel_data(out : &mut Vec<u8>, data : &[u8], w : usize, h : usize)
+{
+    if w == 0 || h == 0
+    {
+        write_u16(out, 0);
+        return;
+    }
+    write_u16(out, 1);
+    let mut rows = vec!();
+    for y in 0..h
+    {
+        let mut row = vec!();
+        packbits_encode(&data[y*w..y*w + w], &mut row);
+        write_u16(out, row.len() as u16);
+        rows.push(row);
+    }
+    for row in rows
+    {
+        out.extend_from_slice(&row);
+    }
+}
+
+fn write_desc_key(out : &mut Vec<u8>, key : &str)
+{
+    if key.len() == 4
+    {
+        write_u32(out, 0);
+    }
+    else
+    {
+        write_u32(out, key.len() as u32);
+    }
+    out.extend_from_slice(key.as_bytes());
+}
+
+fn write_desc_item(out : &mut Vec<u8>, item : &DescItem) -> Result<(), String>
+{
+    match item
+    {
+        DescItem::long(x) => { out.extend_from_slice(b"long"); write_i32(out, *x); }
+        DescItem::doub(x) => { out.extend_from_slice(b"doub"); write_f64(out, *x); }
+        DescItem::UntF(unit, x) =>
+        {
+            out.extend_from_slice(b"UntF");
+            out.extend_from_slice(unit.as_bytes());
+            write_f64(out, *x);
+        }
+        DescItem::bool(x) => { out.extend_from_slice(b"bool"); write_u8(out, *x as u8); }
+        DescItem::TEXT(text) =>
+        {
+            out.extend_from_slice(b"TEXT");
+            write_unicode_string(out, &(text.clone() + "\0"));
+        }
+        DescItem::Objc(desc) => { out.extend_from_slice(b"Objc"); write_descriptor(out, desc)?; }
+        DescItem::r#enum(typ, val) =>
+        {
+            out.extend_from_slice(b"enum");
+            write_desc_key(out, typ);
+            write_desc_key(out, val);
+        }
+        DescItem::VlLs(list) =>
+        {
+            out.extend_from_slice(b"VlLs");
+            write_u32(out, list.len() as u32);
+            for item in list
+            {
+                write_desc_item(out, item)?;
+            }
+        }
+        DescItem::tdta(data) =>
+        {
+            out.extend_from_slice(b"tdta");
+            write_u32(out, data.len() as u32);
+            out.extend_from_slice(data);
+        }
+        DescItem::Err(x) => return Err(format!("can't write descriptor item that failed to parse ({})", x)),
+        DescItem::Xxx => return Err("can't write empty descriptor item".to_string()),
+    }
+    Ok(())
+}
+
+pub fn write_descriptor(out : &mut Vec<u8>, desc : &Descriptor) -> Result<(), String>
+{
+    // blank name
+    write_unicode_string(out, "\0");
+    write_desc_key(out, &desc.0);
+    write_u32(out, desc.1.len() as u32);
+    for (key, item) in &desc.1
+    {
+        write_desc_key(out, key);
+        write_desc_item(out, item).map_err(|e| format!("{}: {}", key.trim(), e))?;
+    }
+    Ok(())
 }
@@ -0,0 +1,411 @@
+use std::io::Cursor;
+use crate::wpsd_raw::*;
+use crate::layers::{TextLayer, TextRun};
+
+// photoshop's text engine data, a postscript-like tree of dicts, arrays, names, numbers and strings
+#[derive(Clone, Debug, PartialEq)]
+pub (crate) enum EngineValue
+{
+    Dict(Vec<(String, EngineValue)>),
+    Array(Vec<EngineValue>),
+    Name(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Text(String),
+}
+
+impl EngineValue
+{
+    pub (crate) fn get(&self, key : &str) -> Option<&EngineValue>
+    {
+        match self
+        {
+            EngineValue::Dict(items) => items.iter().find(|x| x.0 == key).map(|x| &x.1),
+            _ => None,
+        }
+    }
+    pub (crate) fn get_mut(&mut self, key : &str) -> Option<&mut EngineValue>
+    {
+        match self
+        {
+            EngineValue::Dict(items) => items.iter_mut().find(|x| x.0 == key).map(|x| &mut x.1),
+            _ => None,
+        }
+    }
+    pub (crate) fn path(&self, keys : &[&str]) -> Option<&EngineValue>
+    {
+        keys.iter().try_fold(self, |value, key| value.get(key))
+    }
+    pub (crate) fn path_mut(&mut self, keys : &[&str]) -> Option<&mut EngineValue>
+    {
+        keys.iter().try_fold(self, |value, key| value.get_mut(key))
+    }
+    pub (crate) fn array(&self) -> &[EngineValue]
+    {
+        match self
+        {
+            EngineValue::Array(items) => items,
+            _ => &[],
+        }
+    }
+    pub (crate) fn number(&self) -> Option<f64>
+    {
+        match self
+        {
+            EngineValue::Int(x) => Some(*x as f64),
+            EngineValue::Float(x) => Some(*x),
+            _ => None,
+        }
+    }
+}
+
+struct EngineParser<'a>
+{
+    data : &'a [u8],
+    pos : usize,
+}
+
+impl EngineParser<'_>
+{
+    fn skip_space(&mut self)
+    {
+        while self.pos < self.data.len() && self.data[self.pos].is_ascii_whitespace()
+        {
+            self.pos += 1;
+        }
+    }
+    fn rest(&self) -> &[u8]
+    {
+        &self.data[self.pos.min(self.data.len())..]
+    }
+    fn token(&mut self) -> String
+    {
+        let start = self.pos;
+        while self.pos < self.data.len() && !self.data[self.pos].is_ascii_whitespace() && !b"/[]<>()".contains(&self.data[self.pos])
+        {
+            self.pos += 1;
+        }
+        String::from_utf8_lossy(&self.data[start..self.pos]).to_string()
+    }
+    fn value(&mut self) -> Option<EngineValue>
+    {
+        self.skip_space();
+        if self.rest().starts_with(b"<<")
+        {
+            self.pos += 2;
+            let mut items = vec!();
+            loop
+            {
+                self.skip_space();
+                if self.rest().starts_with(b">>")
+                {
+                    self.pos += 2;
+                    return Some(EngineValue::Dict(items));
+                }
+                if !self.rest().starts_with(b"/")
+                {
+                    return None;
+                }
+                self.pos += 1;
+                let key = self.token();
+                items.push((key, self.value()?));
+            }
+        }
+        match *self.rest().first()?
+        {
+            b'[' =>
+            {
+                self.pos += 1;
+                let mut items = vec!();
+                loop
+                {
+                    self.skip_space();
+                    if self.rest().starts_with(b"]")
+                    {
+                        self.pos += 1;
+                        return Some(EngineValue::Array(items));
+                    }
+                    items.push(self.value()?);
+                }
+            }
+            b'/' =>
+            {
+                self.pos += 1;
+                Some(EngineValue::Name(self.token()))
+            }
+            b'(' =>
+            {
+                self.pos += 1;
+                let mut bytes = vec!();
+                loop
+                {
+                    let c = *self.rest().first()?;
+                    self.pos += 1;
+                    match c
+                    {
+                        b')' => break,
+                        b'\\' =>
+                        {
+                            bytes.push(*self.rest().first()?);
+                            self.pos += 1;
+                        }
+                        _ => bytes.push(c),
+                    }
+                }
+                Some(EngineValue::Text(decode_engine_text(&bytes)))
+            }
+            _ =>
+            {
+                let token = self.token();
+                match token.as_str()
+                {
+                    "true" => Some(EngineValue::Bool(true)),
+                    "false" => Some(EngineValue::Bool(false)),
+                    _ if token.contains('.') => token.parse().ok().map(EngineValue::Float),
+                    _ => token.parse().ok().map(EngineValue::Int),
+                }
+            }
+        }
+    }
+}
+
+// strings are utf-16 with a byte order mark, or single-byte otherwise
+fn decode_engine_text(bytes : &[u8]) -> String
+{
+    if let Some(rest) = bytes.strip_prefix(&[0xFE, 0xFF])
+    {
+        let text : Vec<u16> = rest.chunks_exact(2).map(|x| u16::from_be_bytes([x[0], x[1]])).collect();
+        return String::from_utf16_lossy(&text);
+    }
+    bytes.iter().map(|&c| c as char).collect()
+}
+
+pub (crate) fn parse_engine_data(data : &[u8]) -> Option<EngineValue>
+{
+    EngineParser { data, pos : 0 }.value()
+}
+
+fn write_engine_value(out : &mut Vec<u8>, value : &EngineValue, depth : usize)
+{
+    match value
+    {
+        EngineValue::Dict(items) =>
+        {
+            out.extend_from_slice(b"<<\n");
+            for (key, value) in items
+            {
+                out.extend(std::iter::repeat_n(b'\t', depth + 1));
+                out.push(b'/');
+                out.extend_from_slice(key.as_bytes());
+                if matches!(value, EngineValue::Dict(_))
+                {
+                    out.push(b'\n');
+                    out.extend(std::iter::repeat_n(b'\t', depth + 1));
+                }
+                else
+                {
+                    out.push(b' ');
+                }
+                write_engine_value(out, value, depth + 1);
+                out.push(b'\n');
+            }
+            out.extend(std::iter::repeat_n(b'\t', depth));
+            out.extend_from_slice(b">>");
+        }
+        EngineValue::Array(items) =>
+        {
+            out.push(b'[');
+            for item in items
+            {
+                out.push(b' ');
+                write_engine_value(out, item, depth);
+            }
+            out.extend_from_slice(b" ]");
+        }
+        EngineValue::Name(name) =>
+        {
+            out.push(b'/');
+            out.extend_from_slice(name.as_bytes());
+        }
+        EngineValue::Int(x) => out.extend_from_slice(x.to_string().as_bytes()),
+        // keep the decimal point, so that it still reads back as a float
+        EngineValue::Float(x) if x.fract() == 0.0 => out.extend_from_slice(format!("{:.1}", x).as_bytes()),
+        EngineValue::Float(x) => out.extend_from_slice(x.to_string().as_bytes()),
+        EngineValue::Bool(x) => out.extend_from_slice(x.to_string().as_bytes()),
+        EngineValue::Text(text) =>
+        {
+            out.push(b'(');
+            let mut bytes = vec!(0xFE, 0xFF);
+            for c in text.encode_utf16()
+            {
+                bytes.extend_from_slice(&c.to_be_bytes());
+            }
+            for c in bytes
+            {
+                if matches!(c, b'(' | b')' | b'\\')
+                {
+                    out.push(b'\\');
+                }
+                out.push(c);
+            }
+            out.push(b')');
+        }
+    }
+}
+
+pub (crate) fn write_engine_data(value : &EngineValue) -> Vec<u8>
+{
+    let mut out = b"\n\n".to_vec();
+    write_engine_value(&mut out, value, 0);
+    out.push(b'\n');
+    out
+}
+
+// runs are only stored as overrides on top of the default style sheet
+fn read_text_run(style : Option<&EngineValue>, fallback : &TextRun, fonts : &[String]) -> TextRun
+{
+    let mut run = fallback.clone();
+    let Some(style) = style else { return run };
+    if let Some(font) = style.get("Font").and_then(|x| x.number()).and_then(|x| fonts.get(x as usize))
+    {
+        run.font = font.clone();
+    }
+    if let Some(size) = style.get("FontSize").and_then(|x| x.number())
+    {
+        run.size = size as f32;
+    }
+    // stored as argb
+    let color = style.path(&["FillColor", "Values"]).map(|x| x.array()).unwrap_or(&[]);
+    if color.len() == 4
+    {
+        let c : Vec<_> = color.iter().map(|x| x.number().unwrap_or(0.0) as f32).collect();
+        run.color = [c[1], c[2], c[3], c[0]];
+    }
+    run
+}
+
+pub (crate) fn text_layer_from_psd(psd : &PsdText, offset : [f32; 2]) -> TextLayer
+{
+    let engine = match desc_get(&psd.desc, "EngineData")
+    {
+        Some(DescItem::tdta(data)) => parse_engine_data(data),
+        _ => None,
+    };
+    let engine_text = engine.as_ref().and_then(|x| x.path(&["EngineDict", "Editor", "Text"]));
+    let text = match (desc_get(&psd.desc, "Txt "), engine_text)
+    {
+        (Some(DescItem::TEXT(text)), _) => text.clone(),
+        (_, Some(EngineValue::Text(text))) => text.clone(),
+        _ => "".to_string(),
+    };
+    // photoshop ends every text with a line break, and uses carriage returns for them
+    let text = text.strip_suffix('\r').unwrap_or(&text).replace('\r', "\n");
+    
+    let mut runs = vec!();
+    if let Some(engine) = &engine
+    {
+        let fonts : Vec<_> = engine.path(&["ResourceDict", "FontSet"]).map(|x| x.array()).unwrap_or(&[]).iter()
+            .map(|x| match x.get("Name") { Some(EngineValue::Text(name)) => name.clone(), _ => "".to_string() }).collect();
+        let default = TextRun { length : 0, font : fonts.first().cloned().unwrap_or_default(), size : 12.0, color : [0.0, 0.0, 0.0, 1.0] };
+        let sheets = engine.path(&["ResourceDict", "StyleSheetSet"]).map(|x| x.array()).unwrap_or(&[]);
+        let default = read_text_run(sheets.first().and_then(|x| x.get("StyleSheetData")), &default, &fonts);
+        
+        let style_run = engine.path(&["EngineDict", "StyleRun"]);
+        let lengths = style_run.and_then(|x| x.get("RunLengthArray")).map(|x| x.array()).unwrap_or(&[]);
+        let styles = style_run.and_then(|x| x.get("RunArray")).map(|x| x.array()).unwrap_or(&[]);
+        for (length, style) in lengths.iter().zip(styles.iter())
+        {
+            let mut run = read_text_run(style.path(&["StyleSheet", "StyleSheetData"]), &default, &fonts);
+            run.length = length.number().unwrap_or(0.0) as usize;
+            runs.push(run);
+        }
+    }
+    
+    let t = psd.transform.map(|x| x as f32);
+    let mut ret = TextLayer { text : "".to_string(), runs, transform : [t[0], t[1], t[2], t[3], t[4] - offset[0], t[5] - offset[1]], edited : false, psd_block : psd.raw.clone() };
+    // drops the run length of the trailing line break
+    ret.set_text(&text);
+    ret
+}
+
+// the TySh block to write back out; the original one, with edits and moves applied to it
+pub (crate) fn type_tool_block(text : &TextLayer, offset : [f32; 2]) -> Result<Option<Vec<u8>>, String>
+{
+    let Ok(psd) = read_type_tool(&mut Cursor::new(&text.psd_block[..]), text.psd_block.len() as u64) else { return Ok(None) };
+    let mut raw = psd.raw.clone();
+    let t = text.transform;
+    for (i, x) in [t[0], t[1], t[2], t[3], t[4] + offset[0], t[5] + offset[1]].iter().enumerate()
+    {
+        raw[2 + i * 8..10 + i * 8].copy_from_slice(&(*x as f64).to_be_bytes());
+    }
+    if !text.edited
+    {
+        return Ok(Some(raw));
+    }
+    
+    let ps_text = text.text.replace('\n', "\r") + "\r";
+    let mut lengths : Vec<_> = text.runs.iter().map(|x| x.length).collect();
+    if let Some(last) = lengths.last_mut()
+    {
+        *last += 1;
+    }
+    let total = ps_text.encode_utf16().count();
+    
+    let mut desc = psd.desc.clone();
+    for (key, item) in desc.1.iter_mut()
+    {
+        match (key.as_str(), item)
+        {
+            ("Txt ", DescItem::TEXT(x)) => *x = ps_text.clone(),
+            ("EngineData", DescItem::tdta(data)) =>
+            {
+                let Some(mut engine) = parse_engine_data(data) else { continue };
+                if let Some(EngineValue::Text(x)) = engine.path_mut(&["EngineDict", "Editor", "Text"])
+                {
+                    *x = ps_text.clone();
+                }
+                let run_lengths = [(&["EngineDict", "StyleRun"], lengths.clone()), (&["EngineDict", "ParagraphRun"], vec!(total))];
+                for (path, lengths) in run_lengths
+                {
+                    if let Some(run) = engine.path_mut(path)
+                    {
+                        if let Some(EngineValue::Array(styles)) = run.get_mut("RunArray")
+                        {
+                            styles.truncate(lengths.len());
+                        }
+                        if let Some(x) = run.get_mut("RunLengthArray")
+                        {
+                            *x = EngineValue::Array(lengths.iter().map(|&x| EngineValue::Int(x as i64)).collect());
+                        }
+                    }
+                }
+                *data = write_engine_data(&engine);
+            }
+            _ => {}
+        }
+    }
+    let mut desc_data = vec!();
+    write_descriptor(&mut desc_data, &desc)?;
+    raw.splice(psd.desc_range.0..psd.desc_range.1, desc_data);
+    Ok(Some(raw))
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    #[test]
+    pub fn test_engine_data()
+    {
+        let data = b"\n\n<<\n\t/EngineDict\n\t<<\n\t\t/Editor\n\t\t<<\n\t\t\t/Text (\xFE\xFF\x00H\x00\\(\x00\r)\n\t\t>>\n\t\t/Sizes [ 12 .5 -3.25 ]\n\t\t/On true\n\t\t/Kind /Roman\n\t>>\n>>\n";
+        let value = parse_engine_data(data).unwrap();
+        assert_eq!(value.path(&["EngineDict", "Editor", "Text"]), Some(&EngineValue::Text("H(\r".to_string())));
+        assert_eq!(value.path(&["EngineDict", "Sizes"]).unwrap().array(), &[EngineValue::Int(12), EngineValue::Float(0.5), EngineValue::Float(-3.25)]);
+        assert_eq!(value.path(&["EngineDict", "On"]), Some(&EngineValue::Bool(true)));
+        assert_eq!(value.path(&["EngineDict", "Kind"]), Some(&EngineValue::Name("Roman".to_string())));
+        
+        let written = write_engine_data(&value);
+        assert_eq!(parse_engine_data(&written), Some(value));
+    }
+}
@@ -0,0 +1,193 @@
+# Writes the synthetic-*.psd fixtures: small layered files whose merged composite
+# is worked out here with plain blend math, independent of warpainter's renderer.
+# They stand in until real photoshop-saved files are added next to them.
+import os
+import struct
+
+W, H = 6, 4
+
+def pascal_name(name):
+    data = bytes([len(name)]) + name.encode("ascii")
+    while len(data) % 4 != 0:
+        data += b"\0"
+    return data
+
+def block(key, data):
+    if len(data) % 2 != 0:
+        data += b"\0"
+    return b"8BIM" + key + struct.pack(">I", len(data)) + data
+
+class Layer:
+    def __init__(self, name, rect=(0, 0, H, W), pixels=None, mode=b"norm", opacity=255, hidden=False, blocks=b""):
+        self.name = name
+        self.rect = rect # top, left, bottom, right
+        self.pixels = pixels # function of canvas x, y -> (r, g, b, a)
+        self.mode = mode
+        self.opacity = opacity
+        self.hidden = hidden
+        self.blocks = blocks
+
+    def channels(self):
+        top, left, bottom, right = self.rect
+        planes = [bytearray(), bytearray(), bytearray(), bytearray()]
+        for y in range(top, bottom):
+            for x in range(left, right):
+                px = self.pixels(x, y) if self.pixels else (0, 0, 0, 0)
+                for c in range(4):
+                    planes[c].append(px[c])
+        # raw data, alpha first like photoshop writes it
+        return [(-1, bytes(planes[3])), (0, bytes(planes[0])), (1, bytes(planes[1])), (2, bytes(planes[2]))]
+
+    def record(self):
+        out = struct.pack(">4i", *self.rect)
+        channels = self.channels()
+        out += struct.pack(">H", len(channels))
+        for (id, data) in channels:
+            out += struct.pack(">hI", id, 2 + len(data))
+        flags = 2 if self.hidden else 0
+        out += b"8BIM" + self.mode + bytes([self.opacity, 0, flags, 0])
+        extra = struct.pack(">II", 0, 0) + pascal_name(self.name) + self.blocks
+        out += struct.pack(">I", len(extra)) + extra
+        return out, channels
+
+# photoshop descriptors, only the item types the fixtures need
+def desc_key(key):
+    key = key.encode("ascii")
+    return struct.pack(">I", 0 if len(key) == 4 else len(key)) + key
+
+def descriptor(class_id, items):
+    out = struct.pack(">I", 1) + b"\0\0" + desc_key(class_id) + struct.pack(">I", len(items))
+    for (key, kind, value) in items:
+        out += desc_key(key) + kind
+        if kind == b"bool":
+            out += bytes([value])
+        elif kind == b"enum":
+            out += desc_key(value[0]) + desc_key(value[1])
+        elif kind == b"UntF":
+            out += value[0] + struct.pack(">d", value[1])
+        elif kind == b"doub":
+            out += struct.pack(">d", value)
+        elif kind == b"Objc":
+            out += descriptor(*value)
+    return out
+
+def color_overlay(color, opacity):
+    rgb = ("RGBC", [("Rd  ", b"doub", color[0]), ("Grn ", b"doub", color[1]), ("Bl  ", b"doub", color[2])])
+    fill = ("SoFi", [
+        ("enab", b"bool", True),
+        ("Md  ", b"enum", ("BlnM", "Nrml")),
+        ("Opct", b"UntF", (b"#Prc", opacity)),
+        ("Clr ", b"Objc", rgb),
+    ])
+    effects = descriptor("null", [("Scl ", b"UntF", (b"#Prc", 100.0)), ("masterFXSwitch", b"bool", True), ("SoFi", b"Objc", fill)])
+    return block(b"lfx2", struct.pack(">II", 0, 16) + effects)
+
+def group(name, children, mode=b"pass"):
+    closer = Layer("</Layer group>", rect=(0, 0, 0, 0), blocks=block(b"lsct", struct.pack(">I", 3)))
+    opener = Layer(name, rect=(0, 0, 0, 0), mode=mode, blocks=block(b"lsct", struct.pack(">I", 1) + b"8BIM" + mode))
+    return [closer] + children + [opener]
+
+def write_psd(path, layers, composite):
+    out = b"8BPS" + struct.pack(">H", 1) + b"\0" * 6
+    out += struct.pack(">HIIHH", 3, H, W, 8, 3)
+    out += struct.pack(">I", 0) # color mode data
+    out += struct.pack(">I", 0) # image resources
+
+    records = b""
+    channel_data = b""
+    for layer in layers:
+        record, channels = layer.record()
+        records += record
+        for (_, data) in channels:
+            channel_data += struct.pack(">H", 0) + data
+    info = struct.pack(">h", len(layers)) + records + channel_data
+    if len(info) % 2 != 0:
+        info += b"\0"
+    layer_mask = struct.pack(">I", len(info)) + info + struct.pack(">I", 0)
+    out += struct.pack(">I", len(layer_mask)) + layer_mask
+
+    out += struct.pack(">H", 0)
+    for c in range(3):
+        out += bytes(composite(x, y)[c] for y in range(H) for x in range(W))
+    with open(path, "wb") as f:
+        f.write(out)
+
+def over(bottom, top, alpha, blend):
+    return tuple(round(b + (blend(t / 255, b / 255) * 255 - b) * alpha) for (b, t) in zip(bottom, top))
+
+normal = lambda t, b: t
+
+def luma(rgb):
+    return rgb[0] * 0.3 + rgb[1] * 0.59 + rgb[2] * 0.11
+
+# whole-color modes: pick one of the two colors, so there's no per-channel math
+def over_color(bottom, top, alpha, pick_top):
+    return over(bottom, top, alpha, normal) if pick_top(luma(top), luma(bottom)) else bottom
+multiply = lambda t, b: t * b
+screen = lambda t, b: t + b - t * b
+
+def background(x, y):
+    return (40 * x, 60 * y, 200 - 30 * x, 255)
+
+def spots(x, y):
+    return (250, 200 - 40 * y, 20 * x, 255 if (x + y) % 2 == 0 else 128)
+
+def shade(x, y):
+    return (30 + 40 * y, 220 - 35 * x, 128, 255)
+
+def alpha(px, opacity):
+    return px[3] / 255 * opacity / 255
+
+here = os.path.dirname(os.path.abspath(__file__))
+
+# a half transparent layer, at 60% opacity, over an opaque one
+write_psd(os.path.join(here, "synthetic-normal-opacity.psd"),
+    [Layer("Background", pixels=background), Layer("Spots", pixels=spots, opacity=153)],
+    lambda x, y: over(background(x, y)[:3], spots(x, y)[:3], alpha(spots(x, y), 153), normal))
+
+# multiply, then screen at 50% on top
+def multiply_screen(x, y):
+    color = over(background(x, y)[:3], shade(x, y)[:3], 1.0, multiply)
+    return over(color, spots(x, y)[:3], alpha(spots(x, y), 128), screen)
+
+write_psd(os.path.join(here, "synthetic-multiply-screen.psd"),
+    [Layer("Background", pixels=background), Layer("Shade", pixels=shade, mode=b"mul "), Layer("Spots", pixels=spots, mode=b"scrn", opacity=128)],
+    multiply_screen)
+
+# a pass-through group with a hidden layer, and a layer that only covers part of the canvas
+def small(x, y):
+    return (255, 255, 0, 255)
+
+def grouped(x, y):
+    color = background(x, y)[:3]
+    if 1 <= x < 4 and 1 <= y < 3:
+        color = over(color, small(x, y)[:3], 1.0, multiply)
+    return color
+
+write_psd(os.path.join(here, "synthetic-group-hidden.psd"),
+    [Layer("Background", pixels=background)] + group("Group", [
+        Layer("Hidden", pixels=spots, hidden=True),
+        Layer("Small", rect=(1, 1, 3, 4), pixels=small, mode=b"mul "),
+    ]),
+    grouped)
+
+# darker color, then lighter color at 50%
+def darker_lighter(x, y):
+    color = over_color(background(x, y)[:3], shade(x, y)[:3], 1.0, lambda t, b: t < b)
+    return over_color(color, spots(x, y)[:3], alpha(spots(x, y), 128), lambda t, b: t > b)
+
+write_psd(os.path.join(here, "synthetic-darker-lighter-color.psd"),
+    [Layer("Background", pixels=background), Layer("Shade", pixels=shade, mode=b"dkCl"), Layer("Spots", pixels=spots, mode=b"lgCl", opacity=128)],
+    darker_lighter)
+
+# an invert adjustment layer at 30%, over a layer with a color overlay effect. not 50%, which turns everything gray
+overlay = (0, 128, 255)
+
+def inverted(x, y):
+    color = over(background(x, y)[:3], overlay, alpha(spots(x, y), 255), normal)
+    return over(color, tuple(255 - c for c in color), 77 / 255, normal)
+
+write_psd(os.path.join(here, "synthetic-invert-overlay.psd"),
+    [Layer("Background", pixels=background), Layer("Spots", pixels=spots, blocks=color_overlay(overlay, 100.0)),
+        Layer("Invert", rect=(0, 0, 0, 0), opacity=77, blocks=block(b"nvrt", b""))],
+    inverted)